@@ -1,4 +1,5 @@
 #[derive(Serialize, Deserialize, Eq, PartialEq, Debug, Copy, Clone)]
+#[serde(transparent)]
 pub struct OutputIndex {
     index: u16,
 }
@@ -8,3 +9,9 @@ impl From<OutputIndex> for u16 {
         return x.index;
     }
 }
+
+impl From<u16> for OutputIndex {
+    fn from(index: u16) -> Self {
+        return OutputIndex { index: index };
+    }
+}
@@ -0,0 +1,353 @@
+use std::io;
+use std::thread;
+use std::sync::mpsc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use secp256k1::PublicKey;
+
+use wire::{BinarySD, WireError, Message, MessageConsumerChain};
+use wire::PublicKey as WirePublicKey;
+
+use tokio::prelude::{Sink, Async, AsyncSink, Poll};
+
+// CAPTURE_MAGIC is the beginning of every capture file.
+const CAPTURE_MAGIC: [u8; 4] = [b'l', b'p', b'd', b'c'];
+
+// CAPTURE_VERSION is the version of the capture file format, the reader
+// rejects the file of other version.
+const CAPTURE_VERSION: u8 = 0;
+
+// CAPTURE_QUEUE_SIZE is how many frames wait for the writer, if the writer
+// falls behind further, the recording fails rather than the memory grows.
+const CAPTURE_QUEUE_SIZE: usize = 1024;
+
+/// Who has initiated the session.
+#[derive(Serialize, Deserialize, Clone, Copy, Eq, PartialEq, Debug)]
+pub enum SessionDirection {
+    Outgoing,
+    Incoming,
+}
+
+/// The direction of the frame relative to the local node.
+#[derive(Serialize, Deserialize, Clone, Copy, Eq, PartialEq, Debug)]
+pub enum FrameDirection {
+    Sent,
+    Received,
+}
+
+/// The metadata of the recorded session, it is the beginning of the capture file.
+#[derive(Serialize, Deserialize, Clone, Eq, PartialEq, Debug)]
+pub struct CaptureHeader {
+    magic: [u8; 4],
+    version: u8,
+    pub remote_key: WirePublicKey,
+    pub direction: SessionDirection,
+    // microseconds since the unix epoch
+    pub started: u64,
+}
+
+/// The decrypted frame, the `data` is the serialized message including its type.
+/// The frame is recorded before it is decoded, so the capture keeps
+/// even the message which cannot be decoded.
+#[derive(Serialize, Deserialize, Clone, Eq, PartialEq, Debug)]
+pub struct CaptureFrame {
+    // microseconds since the unix epoch
+    pub timestamp: u64,
+    pub direction: FrameDirection,
+    pub data: Vec<u8>,
+}
+
+impl CaptureFrame {
+    pub fn message(&self) -> Result<Message, WireError> {
+        Message::from_bytes(self.data.as_slice())
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() * 1_000_000 + (d.subsec_micros() as u64))
+        .unwrap_or(0)
+}
+
+/// Writes the capture of the session, attach it to the `Machine`
+/// by `Machine::record` or `BrontideStream::record`.
+///
+/// The capture contains every decrypted message, the secrets of the channels
+/// among them, e.g. the revoked per commitment secrets, keep the file private.
+///
+/// The frames are written by the background thread through the buffer,
+/// the thread flushes it each time it has nothing more to write,
+/// so the reactor never waits for the disk.
+pub struct Recorder {
+    frames: mpsc::SyncSender<CaptureFrame>,
+    writer: thread::JoinHandle<io::Result<()>>,
+}
+
+impl Recorder {
+    pub fn new<W>(writer: W, remote_key: &PublicKey, direction: SessionDirection) -> Result<Self, WireError>
+    where
+        W: io::Write + Send + 'static,
+    {
+        let mut writer = io::BufWriter::new(writer);
+        let header = CaptureHeader {
+            magic: CAPTURE_MAGIC,
+            version: CAPTURE_VERSION,
+            remote_key: remote_key.clone().into(),
+            direction: direction,
+            started: now(),
+        };
+        BinarySD::serialize(&mut writer, &header)?;
+
+        let (frames, queue) = mpsc::sync_channel(CAPTURE_QUEUE_SIZE);
+        let writer = thread::Builder::new()
+            .name("capture".to_owned())
+            .spawn(move || write_frames(writer, queue))?;
+
+        Ok(Recorder {
+            frames: frames,
+            writer: writer,
+        })
+    }
+
+    /// Fails if the writer has failed or falls behind, the frame is not recorded.
+    pub fn record(&mut self, direction: FrameDirection, data: &[u8]) -> Result<(), WireError> {
+        let frame = CaptureFrame {
+            timestamp: now(),
+            direction: direction,
+            data: data.to_vec(),
+        };
+        self.frames.try_send(frame).map_err(|e| {
+            let e = match e {
+                mpsc::TrySendError::Full(_) => io::Error::new(io::ErrorKind::WouldBlock, "the capture writer falls behind"),
+                mpsc::TrySendError::Disconnected(_) => io::Error::new(io::ErrorKind::BrokenPipe, "the capture writer has failed"),
+            };
+            WireError::from(e)
+        })
+    }
+
+    /// Waits until everything recorded is written and flushed.
+    pub fn finish(self) -> Result<(), WireError> {
+        let Recorder { frames, writer } = self;
+        drop(frames);
+        match writer.join() {
+            Ok(result) => result.map_err(WireError::from),
+            Err(_) => Err(WireError::from(io::Error::new(io::ErrorKind::Other, "the capture writer has panicked"))),
+        }
+    }
+}
+
+// the capture is needed most when the node crashes, so the buffer is flushed
+// as soon as the queue is empty, the writer stops when the recorder is dropped
+fn write_frames<W>(writer: io::BufWriter<W>, queue: mpsc::Receiver<CaptureFrame>) -> io::Result<()>
+where
+    W: io::Write,
+{
+    use std::io::Write;
+
+    let to_io = |e: WireError| io::Error::new(io::ErrorKind::Other, e.to_string());
+
+    let mut writer = writer;
+    writer.flush()?;
+    while let Ok(frame) = queue.recv() {
+        BinarySD::serialize(&mut writer, &frame).map_err(to_io)?;
+        while let Ok(frame) = queue.try_recv() {
+            BinarySD::serialize(&mut writer, &frame).map_err(to_io)?;
+        }
+        writer.flush()?;
+    }
+    Ok(())
+}
+
+/// Reads the capture file, iterates over the frames.
+pub struct CaptureReader<R> where R: io::BufRead {
+    reader: R,
+    header: CaptureHeader,
+}
+
+impl<R> CaptureReader<R> where R: io::BufRead {
+    pub fn new(reader: R) -> Result<Self, WireError> {
+        use serde::de::Error;
+
+        let mut reader = reader;
+        let header: CaptureHeader = BinarySD::deserialize(&mut reader)?;
+        if header.magic != CAPTURE_MAGIC {
+            return Err(WireError::custom("not a capture file"));
+        }
+        if header.version != CAPTURE_VERSION {
+            return Err(WireError::custom(format!("unknown capture version: {}", header.version)));
+        }
+
+        Ok(CaptureReader {
+            reader: reader,
+            header: header,
+        })
+    }
+
+    pub fn header(&self) -> &CaptureHeader {
+        &self.header
+    }
+}
+
+impl<R> Iterator for CaptureReader<R> where R: io::BufRead {
+    type Item = Result<CaptureFrame, WireError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.reader.fill_buf() {
+            Ok(buffer) => if buffer.is_empty() {
+                return None;
+            },
+            Err(e) => return Some(Err(WireError::from(e))),
+        };
+
+        Some(BinarySD::deserialize(&mut self.reader))
+    }
+}
+
+/// Collects the messages sent by the consumers during the replay.
+#[derive(Default)]
+pub struct ReplaySink {
+    messages: Vec<Message>,
+}
+
+impl Sink for ReplaySink {
+    type SinkItem = Message;
+    type SinkError = WireError;
+
+    fn start_send(&mut self, item: Self::SinkItem) -> Result<AsyncSink<Self::SinkItem>, Self::SinkError> {
+        self.messages.push(item);
+        Ok(AsyncSink::Ready)
+    }
+
+    fn poll_complete(&mut self) -> Poll<(), Self::SinkError> {
+        Ok(Async::Ready(()))
+    }
+}
+
+/// Feeds the received messages of the capture through the `chain` in the same order,
+/// the message nobody consumes is skipped as the node does. Returns the chain
+/// and the messages it has sent, compare them against the sent frames of the capture.
+pub fn replay<R, C>(capture: CaptureReader<R>, chain: C) -> Result<(C, Vec<Message>), WireError>
+where
+    R: io::BufRead,
+    C: MessageConsumerChain,
+{
+    use tokio::prelude::Future;
+
+    let mut chain = chain;
+    let mut sink = ReplaySink::default();
+    for frame in capture {
+        let frame = frame?;
+        if frame.direction != FrameDirection::Received {
+            continue;
+        }
+
+        let (c, s) = match chain.process(sink, frame.message()?) {
+            Ok(f) => f.wait()?,
+            Err((c, s, _)) => (c, s),
+        };
+        chain = c;
+        sink = s;
+    }
+
+    Ok((chain, sink.messages))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::{Arc, Mutex};
+    use secp256k1::{Secp256k1, SecretKey};
+    use wire::{MessageConsumer, MessageFiltered, Ping, Pong};
+    use tokio::prelude::Future;
+
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl io::Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            io::Write::write(&mut *self.0.lock().unwrap(), buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    struct PingMessage(Ping);
+
+    impl MessageFiltered for PingMessage {
+        fn filter(v: Message) -> Result<Self, Message> {
+            match v {
+                Message::Ping(p) => Ok(PingMessage(p)),
+                v @ _ => Err(v),
+            }
+        }
+    }
+
+    struct PingResponder;
+
+    impl MessageConsumer for PingResponder {
+        type Message = PingMessage;
+
+        fn consume<S>(self, sink: S, message: Self::Message) -> Box<dyn Future<Item=(Self, S), Error=WireError>>
+        where
+            S: Sink<SinkItem=Message, SinkError=WireError> + Send + 'static,
+        {
+            Box::new(sink.send(Message::Pong(Pong::new(&message.0))).map(move |s| (self, s)))
+        }
+    }
+
+    fn serialize(message: &Message) -> Vec<u8> {
+        let mut data = Vec::new();
+        BinarySD::serialize(&mut data, message).unwrap();
+        data
+    }
+
+    #[test]
+    fn record_and_replay() {
+        let context = Secp256k1::new();
+        let secret = SecretKey::from_slice(&context, &[0x11; 32]).unwrap();
+        let remote_key = PublicKey::from_secret_key(&context, &secret).unwrap();
+
+        let buffer = SharedBuffer::default();
+        let mut recorder = Recorder::new(buffer.clone(), &remote_key, SessionDirection::Incoming).unwrap();
+        let ping = Message::Ping(Ping::new(4, 16).unwrap());
+        recorder.record(FrameDirection::Received, serialize(&ping).as_slice()).unwrap();
+        // the frame of unknown even type is kept as it is
+        recorder.record(FrameDirection::Received, &[0, 100]).unwrap();
+        recorder.record(FrameDirection::Sent, serialize(&ping).as_slice()).unwrap();
+        recorder.finish().unwrap();
+
+        let data = buffer.0.lock().unwrap().clone();
+        let capture = CaptureReader::new(data.as_slice()).unwrap();
+        assert_eq!(capture.header().remote_key, WirePublicKey::from(remote_key));
+        assert_eq!(capture.header().direction, SessionDirection::Incoming);
+        let frames = capture.collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(frames.len(), 3);
+        assert_eq!(frames[0].message().unwrap(), ping);
+        assert!(frames[1].message().is_err());
+        assert_eq!(frames[2].direction, FrameDirection::Sent);
+
+        // the undecodable frame fails the replay as it fails the connection
+        let capture = CaptureReader::new(data.as_slice()).unwrap();
+        assert!(replay(capture, (PingResponder, ())).is_err());
+
+        let buffer = SharedBuffer::default();
+        let mut recorder = Recorder::new(buffer.clone(), &remote_key, SessionDirection::Outgoing).unwrap();
+        recorder.record(FrameDirection::Received, serialize(&ping).as_slice()).unwrap();
+        recorder.record(FrameDirection::Received, serialize(&ping).as_slice()).unwrap();
+        recorder.finish().unwrap();
+        let data = buffer.0.lock().unwrap().clone();
+        let capture = CaptureReader::new(data.as_slice()).unwrap();
+        let (_, sent) = replay(capture, (PingResponder, ())).unwrap();
+        assert_eq!(sent.len(), 2);
+        match &sent[0] {
+            &Message::Pong(ref pong) => assert_eq!(pong.length(), 16),
+            m => panic!("unexpected message: {:?}", m),
+        }
+
+        // not a capture file
+        assert!(CaptureReader::new(&[0u8; 64][..]).is_err());
+    }
+}
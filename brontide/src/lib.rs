@@ -10,14 +10,30 @@ extern crate hkdf;
 extern crate hex;
 extern crate crossbeam;
 extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+#[macro_use]
+extern crate log;
+#[macro_use]
+extern crate lazy_static;
 
 extern crate tokio;
+extern crate futures;
 extern crate bytes;
 
 extern crate wire;
+extern crate bincode;
 
 mod machine;
-pub use self::machine::{Machine, HandshakeError, BrontideStream};
+pub use self::machine::{Machine, HandshakeError, HandshakeTimeouts, BrontideStream, IdleTimeout, is_idle_timeout};
+
+pub mod tor;
+
+mod capture;
+pub use self::capture::{
+    Recorder, CaptureReader, CaptureHeader, CaptureFrame, SessionDirection, FrameDirection,
+    ReplaySink, replay,
+};
 
 //#[cfg(test)]
 //mod test_tcp_communication;
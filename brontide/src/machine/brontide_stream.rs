@@ -1,11 +1,17 @@
 use tokio::io;
 use tokio::codec::{Decoder, Framed};
-use tokio::prelude::Future;
+use tokio::prelude::{Future, IntoFuture};
+use tokio::prelude::future::Either;
+use futures::sync::oneshot;
 
-use secp256k1::{PublicKey, SecretKey};
+use secp256k1::PublicKey;
+use wire::{WireError, NodeSigner};
 use std::time::Duration;
+use std::sync::{Arc, Mutex, mpsc};
+use std::{thread, panic};
 
 use super::handshake::{Machine, HandshakeNew, HandshakeError};
+use super::idle_timeout::IdleTimeout;
 
 pub struct BrontideStream<T>
 where
@@ -18,74 +24,115 @@ where
     stream: T,
 }
 
-impl<T> BrontideStream<T>
-where
-    T: io::AsyncRead + io::AsyncWrite,
-{
+/// The time limits of the handshake. The act timeout limits the waiting for
+/// the act from the peer, the initiator waits for act two,
+/// the responder waits for act one and act three.
+#[derive(Clone, Debug)]
+pub struct HandshakeTimeouts {
+    pub act_one: Duration,
+    pub act_two: Duration,
+    pub act_three: Duration,
+    pub handshake: Duration,
+}
+
+impl Default for HandshakeTimeouts {
     // HANDSHAKE_READ_TIMEOUT is a read timeout that will be enforced when
     // waiting for data payloads during the various acts of Brontide. If
     // the remote party fails to deliver the proper payload within this
     // time frame, then we'll fail the connection.
-    fn read_timeout() -> Duration {
-        Duration::new(5, 0)
+    fn default() -> Self {
+        HandshakeTimeouts {
+            act_one: Duration::new(5, 0),
+            act_two: Duration::new(5, 0),
+            act_three: Duration::new(5, 0),
+            handshake: Duration::new(15, 0),
+        }
     }
+}
 
+impl<T> BrontideStream<T>
+where
+    T: io::AsyncRead + io::AsyncWrite,
+{
     pub fn outgoing(
         stream: T,
-        local_secret: SecretKey,
+        local_signer: Arc<dyn NodeSigner>,
+        remote_public: PublicKey,
+    ) -> impl Future<Item = Self, Error = HandshakeError> {
+        Self::outgoing_with_timeouts(stream, local_signer, remote_public, HandshakeTimeouts::default())
+    }
+
+    pub fn outgoing_with_timeouts(
+        stream: T,
+        local_signer: Arc<dyn NodeSigner>,
         remote_public: PublicKey,
+        timeouts: HandshakeTimeouts,
     ) -> impl Future<Item = Self, Error = HandshakeError> {
-        use tokio::prelude::IntoFuture;
+        use tokio::prelude::FutureExt;
 
-        HandshakeNew::new(true, local_secret, remote_public)
-            .map_err(HandshakeError::Crypto)
-            .and_then(|noise| noise.gen_act_one())
+        let act_two_timeout = timeouts.act_two;
+        let blocking = local_signer.is_blocking();
+        HandshakeNew::new(true, local_signer, remote_public)
+            .gen_act_one()
             .into_future()
             .and_then(move |(a, noise)| {
                 io::write_all(stream, a)
                     .map_err(HandshakeError::Io)
                     .map(move |(stream, _)| (noise, stream))
-            }).and_then(|(noise, stream)| {
+            }).and_then(move |(noise, stream)| {
                 io::read_exact(stream, Default::default())
-                    .map_err(HandshakeError::Io)
+                    .timeout(act_two_timeout)
+                    .map_err(|e| HandshakeError::from_timeout(e, HandshakeError::ActTwoTimeout))
                     .and_then(move |(stream, a)| {
-                        let noise = noise.recv_act_two(a)?;
-                        Ok((stream, noise.gen_act_three()?))
+                        signer_step(blocking, move || {
+                            let noise = noise.recv_act_two(a)?;
+                            noise.gen_act_three().map(|(a, noise)| (a, Box::new(noise)))
+                        }).map(move |r| (stream, r))
                     })
             }).and_then(|(stream, (a, noise))| {
                 io::write_all(stream, a)
                     .map_err(HandshakeError::Io)
                     .map(move |(stream, _)| BrontideStream {
-                        noise: Box::new(noise),
+                        noise: noise,
                         stream: stream,
                     })
             })
+            .timeout(timeouts.handshake)
+            .map_err(|e| HandshakeError::from_timeout(e, HandshakeError::Timeout))
     }
 
     pub fn incoming(
         stream: T,
-        local_secret: SecretKey,
+        local_signer: Arc<dyn NodeSigner>,
+    ) -> impl Future<Item = Self, Error = HandshakeError> {
+        Self::incoming_with_timeouts(stream, local_signer, HandshakeTimeouts::default())
+    }
+
+    pub fn incoming_with_timeouts(
+        stream: T,
+        local_signer: Arc<dyn NodeSigner>,
+        timeouts: HandshakeTimeouts,
     ) -> impl Future<Item = Self, Error = HandshakeError> {
         use tokio::prelude::FutureExt;
 
+        let act_three_timeout = timeouts.act_three;
+        let blocking = local_signer.is_blocking();
         io::read_exact(stream, Default::default())
-            .timeout(Self::read_timeout())
-            .map_err(HandshakeError::IoTimeout)
+            .timeout(timeouts.act_one)
+            .map_err(|e| HandshakeError::from_timeout(e, HandshakeError::ActOneTimeout))
             .and_then(move |(stream, a)| {
-                HandshakeNew::new(false, local_secret, PublicKey::new())
-                    .map_err(HandshakeError::Crypto)
-                    .and_then(move |noise| {
-                        let noise = noise.recv_act_one(a)?;
-                        Ok((stream, noise.gen_act_two()?))
-                    })
+                signer_step(blocking, move || {
+                    let noise = HandshakeNew::new(false, local_signer, PublicKey::new()).recv_act_one(a)?;
+                    noise.gen_act_two()
+                }).map(move |r| (stream, r))
             }).and_then(|(stream, (a, noise))| {
                 io::write_all(stream, a)
                     .map_err(HandshakeError::Io)
                     .map(move |(stream, _)| (noise, stream))
-            }).and_then(|(noise, stream)| {
+            }).and_then(move |(noise, stream)| {
                 io::read_exact(stream, Default::default())
-                    .timeout(Self::read_timeout())
-                    .map_err(HandshakeError::IoTimeout)
+                    .timeout(act_three_timeout)
+                    .map_err(|e| HandshakeError::from_timeout(e, HandshakeError::ActThreeTimeout))
                     .and_then(move |(stream, a)| {
                         Ok(BrontideStream {
                             noise: Box::new(noise.recv_act_three(a)?),
@@ -93,15 +140,90 @@ where
                         })
                     })
             })
+            .timeout(timeouts.handshake)
+            .map_err(|e| HandshakeError::from_timeout(e, HandshakeError::Timeout))
     }
 
     pub fn remote_key(&self) -> &PublicKey {
         &self.noise.remote_static()
     }
 
+    /// Starts the capture of the session, see `Machine::record`.
+    pub fn record<W>(&mut self, writer: W) -> Result<(), WireError>
+    where
+        W: std::io::Write + Send + 'static,
+    {
+        self.noise.record(writer)
+    }
+
     pub fn framed(self) -> Framed<T, Box<Machine>> {
         self.noise.framed(self.stream)
     }
+
+    /// The framed connection that fails the read if the peer
+    /// has sent nothing during the `idle` time, see `IdleTimeout`.
+    pub fn framed_with_idle_timeout(self, idle: Duration) -> IdleTimeout<Framed<T, Box<Machine>>> {
+        IdleTimeout::new(self.framed(), idle)
+    }
+}
+
+// SIGNER_QUEUE_SIZE is how many steps of the handshake wait for the blocking signer,
+// if the signer falls behind further, the handshake fails rather than the queue grows.
+const SIGNER_QUEUE_SIZE: usize = 64;
+
+type SignerJob = Box<dyn FnOnce() + Send>;
+
+lazy_static! {
+    // the only thread calling the blocking signer
+    static ref SIGNER_WORKER: Mutex<mpsc::SyncSender<SignerJob>> = {
+        let (sender, receiver) = mpsc::sync_channel::<SignerJob>(SIGNER_QUEUE_SIZE);
+        thread::Builder::new()
+            .name("signer".to_owned())
+            .spawn(move || {
+                for job in receiver {
+                    // the failed step drops its sender, the handshake gets the error
+                    let _ = panic::catch_unwind(panic::AssertUnwindSafe(job));
+                }
+            })
+            .expect("cannot start the signer thread");
+        Mutex::new(sender)
+    };
+}
+
+// The step of the handshake using the node key. The in memory signer is fast,
+// the step runs right here. The blocking signer might be slow or remote,
+// so the step waits in the queue of the signer thread, the reactor goes on
+// serving other connections.
+fn signer_step<F, R>(blocking: bool, step: F) -> impl Future<Item = R, Error = HandshakeError>
+where
+    F: FnOnce() -> Result<R, HandshakeError> + Send + 'static,
+    R: Send + 'static,
+{
+    if !blocking {
+        return Either::A(step().into_future());
+    }
+
+    let (sender, receiver) = oneshot::channel();
+    let job: SignerJob = Box::new(move || sender.send(step()).unwrap_or(()));
+    let queued = match SIGNER_WORKER.lock() {
+        Ok(worker) => worker.try_send(job),
+        Err(e) => e.into_inner().try_send(job),
+    };
+    let queued = queued.map_err(|e| match e {
+        mpsc::TrySendError::Full(_) => HandshakeError::SignerBusy,
+        mpsc::TrySendError::Disconnected(_) => {
+            HandshakeError::Io(std::io::Error::new(std::io::ErrorKind::Other, "the signer thread has failed"))
+        },
+    });
+    Either::B(
+        queued
+            .into_future()
+            .and_then(|()| {
+                receiver
+                    .map_err(|_| HandshakeError::Io(std::io::Error::new(std::io::ErrorKind::Other, "the handshake step has failed")))
+            })
+            .and_then(|r| r)
+    )
 }
 
 impl<T> AsRef<T> for BrontideStream<T>
@@ -121,3 +243,103 @@ where
         &mut self.stream
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::{Read, Write, Error, ErrorKind};
+    use secp256k1::{Secp256k1, SecretKey};
+    use wire::InMemorySigner;
+    use tokio::prelude::{Async, Poll};
+    use tokio::runtime::current_thread;
+
+    // the peer that connects and never speaks
+    struct Silent;
+
+    impl Read for Silent {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+            let _ = buf;
+            Err(Error::from(ErrorKind::WouldBlock))
+        }
+    }
+
+    impl Write for Silent {
+        fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> Result<(), Error> {
+            Ok(())
+        }
+    }
+
+    impl io::AsyncRead for Silent {
+    }
+
+    impl io::AsyncWrite for Silent {
+        fn shutdown(&mut self) -> Poll<(), Error> {
+            Ok(Async::Ready(()))
+        }
+    }
+
+    fn timeouts(act: u64, handshake: u64) -> HandshakeTimeouts {
+        HandshakeTimeouts {
+            act_one: Duration::from_millis(act),
+            act_two: Duration::from_millis(act),
+            act_three: Duration::from_millis(act),
+            handshake: Duration::from_millis(handshake),
+        }
+    }
+
+    fn keys() -> (Arc<dyn NodeSigner>, PublicKey) {
+        let context = Secp256k1::new();
+        let secret = SecretKey::from_slice(&context, &[0x21; 32]).unwrap();
+        let public = PublicKey::from_secret_key(&context, &secret).unwrap();
+        (Arc::new(InMemorySigner::new(secret).unwrap()), public)
+    }
+
+    #[test]
+    fn act_timeout() {
+        let (signer, public) = keys();
+
+        let incoming = BrontideStream::incoming_with_timeouts(Silent, signer.clone(), timeouts(50, 10000));
+        match current_thread::block_on_all(incoming).map(|_| ()) {
+            Err(HandshakeError::ActOneTimeout) => (),
+            r => panic!("unexpected result: {:?}", r),
+        }
+
+        let outgoing = BrontideStream::outgoing_with_timeouts(Silent, signer, public, timeouts(50, 10000));
+        match current_thread::block_on_all(outgoing).map(|_| ()) {
+            Err(HandshakeError::ActTwoTimeout) => (),
+            r => panic!("unexpected result: {:?}", r),
+        }
+    }
+
+    #[test]
+    fn handshake_timeout() {
+        let (signer, _) = keys();
+
+        let incoming = BrontideStream::incoming_with_timeouts(Silent, signer, timeouts(10000, 50));
+        match current_thread::block_on_all(incoming).map(|_| ()) {
+            Err(HandshakeError::Timeout) => (),
+            r => panic!("unexpected result: {:?}", r),
+        }
+    }
+
+    #[test]
+    fn signer_thread() {
+        let name = || thread::current().name().map(ToOwned::to_owned);
+
+        // only the blocking signer leaves the reactor
+        let inline = current_thread::block_on_all(signer_step(false, move || Ok(name()))).unwrap();
+        assert_eq!(inline, name());
+        let worker = current_thread::block_on_all(signer_step(true, move || Ok(name()))).unwrap();
+        assert_eq!(worker.as_ref().map(String::as_str), Some("signer"));
+
+        let failed = signer_step(true, || -> Result<(), HandshakeError> { panic!("the signer has panicked") });
+        assert!(current_thread::block_on_all(failed).is_err());
+        // the thread survives the failed step
+        assert!(current_thread::block_on_all(signer_step(true, || Ok(()))).is_ok());
+    }
+}
@@ -1,8 +1,10 @@
 use std::{io, fmt, error, cell};
-use tokio::timer::timeout;
+use std::sync::Arc;
+use tokio::timer::{self, timeout};
 use secp256k1::{SecretKey, PublicKey, Error as EcdsaError};
 use super::cipher_state::CipherState;
 use super::symmetric_state::{SymmetricState, MAC_SIZE};
+use wire::{NodeSigner, SignerError};
 
 // ecdh performs an ECDH operation between public and private. The returned value is
 // the sha256 of the compressed shared point.
@@ -25,9 +27,41 @@ fn ecdh(pk: &PublicKey, sk: &SecretKey) -> Result<[u8; 32], EcdsaError> {
 #[derive(Debug)]
 pub enum HandshakeError {
     Io(io::Error),
-    IoTimeout(timeout::Error<io::Error>),
     Crypto(EcdsaError),
+    // the node key is not available
+    Signer(SignerError),
+    // too many handshakes wait for the blocking signer
+    SignerBusy,
     UnknownHandshakeVersion(String),
+    // the peer has not sent the act in time
+    ActOneTimeout,
+    ActTwoTimeout,
+    ActThreeTimeout,
+    // the whole handshake has taken too long
+    Timeout,
+    Timer(timer::Error),
+}
+
+impl HandshakeError {
+    // converts the error of the future limited in time, `elapsed` is the error if the time is out
+    pub(crate) fn from_timeout<E>(e: timeout::Error<E>, elapsed: Self) -> Self
+    where
+        E: Into<Self>,
+    {
+        if e.is_elapsed() {
+            elapsed
+        } else if e.is_timer() {
+            HandshakeError::Timer(e.into_timer().unwrap())
+        } else {
+            e.into_inner().unwrap().into()
+        }
+    }
+}
+
+impl From<io::Error> for HandshakeError {
+    fn from(e: io::Error) -> Self {
+        return HandshakeError::Io(e);
+    }
 }
 
 impl error::Error for HandshakeError {
@@ -36,8 +70,9 @@ impl error::Error for HandshakeError {
 
         match self {
             &Io(ref e) => Some(e),
-            &IoTimeout(ref e) => Some(e),
             &Crypto(ref e) => Some(e),
+            &Signer(ref e) => Some(e),
+            &Timer(ref e) => Some(e),
             _ => None,
         }
     }
@@ -49,9 +84,15 @@ impl fmt::Display for HandshakeError {
 
         match self {
             &Io(ref e) => write!(f, "io error: {}", e),
-            &IoTimeout(ref e) => write!(f, "io timeout error: {}", e),
             &Crypto(ref e) => write!(f, "crypto error: {}", e),
+            &Signer(ref e) => write!(f, "signer error: {}", e),
+            &SignerBusy => write!(f, "the signer is busy"),
             &UnknownHandshakeVersion(ref msg) => write!(f, "{}", msg),
+            &ActOneTimeout => write!(f, "act one timeout"),
+            &ActTwoTimeout => write!(f, "act two timeout"),
+            &ActThreeTimeout => write!(f, "act three timeout"),
+            &Timeout => write!(f, "handshake timeout"),
+            &Timer(ref e) => write!(f, "timer error: {}", e),
         }
     }
 }
@@ -217,7 +258,8 @@ static PROTOCOL_NAME: &'static str = "Noise_XK_secp256k1_ChaChaPoly_SHA256";
 
 pub struct HandshakeNew {
     symmetric_state: SymmetricState,
-    local_static: SecretKey,
+    // the node key, it does not leave the signer
+    local_static: Arc<dyn NodeSigner>,
     remote_static: PublicKey,
     pub ephemeral_gen: fn() -> Result<SecretKey, EcdsaError>,
 }
@@ -225,9 +267,9 @@ pub struct HandshakeNew {
 impl HandshakeNew {
     pub fn new(
         initiator: bool,
-        local_secret: SecretKey,
+        local_signer: Arc<dyn NodeSigner>,
         remote_public: PublicKey,
-    ) -> Result<Self, EcdsaError> {
+    ) -> Self {
         use secp256k1::{Secp256k1, constants::SECRET_KEY_SIZE};
 
         let mut symmetric_state = SymmetricState::new(PROTOCOL_NAME);
@@ -235,19 +277,18 @@ impl HandshakeNew {
         if initiator {
             symmetric_state.mix_hash(&remote_public.serialize());
         } else {
-            let local_pub = PublicKey::from_secret_key(&Secp256k1::new(), &local_secret)?;
-            symmetric_state.mix_hash(&local_pub.serialize());
+            symmetric_state.mix_hash(&local_signer.node_id().serialize());
         }
 
-        Ok(HandshakeNew {
+        HandshakeNew {
             symmetric_state: symmetric_state,
-            local_static: local_secret,
+            local_static: local_signer,
             remote_static: remote_public,
             ephemeral_gen: || {
                 let sk: [u8; SECRET_KEY_SIZE] = rand::random();
                 SecretKey::from_slice(&Secp256k1::new(), &sk)
             },
-        })
+        }
     }
 
     // gen_act_one generates the initial packet (act one) to be sent from initiator
@@ -302,7 +343,7 @@ impl HandshakeNew {
         self.symmetric_state.mix_hash(&remote_ephemeral.serialize());
 
         // es
-        let s = ecdh(&remote_ephemeral, &self.local_static).map_err(HandshakeError::Crypto)?;
+        let s = self.local_static.ecdh(&remote_ephemeral).map_err(HandshakeError::Signer)?;
         self.symmetric_state.mix_key(&s);
 
         // If the initiator doesn't know our static key, then this operation
@@ -421,11 +462,9 @@ impl Handshake {
     //
     //    -> s, se
     pub fn gen_act_three(mut self) -> Result<(ActThree, Machine), HandshakeError> {
-        use secp256k1::{Secp256k1, constants::PUBLIC_KEY_SIZE};
+        use secp256k1::constants::PUBLIC_KEY_SIZE;
 
-        let local_static_pub =
-            PublicKey::from_secret_key(&Secp256k1::new(), &self.base.local_static)
-                .map_err(HandshakeError::Crypto)?;
+        let local_static_pub = self.base.local_static.node_id();
         let our_pubkey = local_static_pub.serialize();
         let mut cipher_text = Vec::with_capacity(PUBLIC_KEY_SIZE);
         let tag = self
@@ -434,8 +473,8 @@ impl Handshake {
             .encrypt_and_hash(&our_pubkey, &mut cipher_text)
             .map_err(HandshakeError::Io)?;
 
-        let s = ecdh(&self.remote_ephemeral, &self.base.local_static)
-            .map_err(HandshakeError::Crypto)?;
+        let s = self.base.local_static.ecdh(&self.remote_ephemeral)
+            .map_err(HandshakeError::Signer)?;
         self.base.symmetric_state.mix_key(&s);
 
         let auth_payload = self
@@ -502,24 +541,33 @@ impl Handshake {
             send_cipher: send,
             recv_cipher: recv,
             remote_static: self.base.remote_static,
+            initiator: !swap,
             #[cfg(test)]
             chaining_key: chaining_key,
             message_buffer: cell::RefCell::new([0; std::u16::MAX as usize]),
+            recorder: None,
+            gossip_filter: None,
         }
     }
 }
 
 use bytes::BytesMut;
-use wire::{BinarySD, WireError};
+use wire::{BinarySD, WireError, Message, GossipView, GossipFilter};
 use serde::{Serialize, de::DeserializeOwned};
+use ::capture::{Recorder, SessionDirection, FrameDirection};
 
 pub struct Machine {
     send_cipher: CipherState,
     recv_cipher: CipherState,
     remote_static: PublicKey,
+    initiator: bool,
     #[cfg(test)]
     chaining_key: [u8; 32],
     message_buffer: cell::RefCell<[u8; std::u16::MAX as usize]>,
+    // writes the decrypted frames if the capture is enabled
+    recorder: Option<Recorder>,
+    // the gossip messages which are not new are skipped before decoding
+    gossip_filter: Option<Arc<dyn GossipFilter>>,
 }
 
 impl fmt::Debug for Machine {
@@ -550,6 +598,39 @@ impl Machine {
         &self.remote_static
     }
 
+    pub fn initiator(&self) -> bool {
+        self.initiator
+    }
+
+    /// Starts the capture of the session, every decrypted frame is written
+    /// to the `writer` in both directions. Read it by `CaptureReader`.
+    pub fn record<W>(&mut self, writer: W) -> Result<(), WireError>
+    where
+        W: io::Write + Send + 'static,
+    {
+        let direction = if self.initiator { SessionDirection::Outgoing } else { SessionDirection::Incoming };
+        self.recorder = Some(Recorder::new(writer, &self.remote_static, direction)?);
+        Ok(())
+    }
+
+    // The capture is diagnostic only, if it fails the recording stops, the session goes on.
+    fn capture(&mut self, direction: FrameDirection, length: usize) {
+        let result = match self.recorder {
+            Some(ref mut recorder) => recorder.record(direction, &self.message_buffer.borrow()[..length]),
+            None => return,
+        };
+        if let Err(e) = result {
+            warn!("capture failed, stop recording: {}", e);
+            self.recorder = None;
+        }
+    }
+
+    /// Skips the received gossip messages which are not new for the `filter`,
+    /// they are neither decoded nor passed further.
+    pub fn filter_gossip(&mut self, filter: Arc<dyn GossipFilter>) {
+        self.gossip_filter = Some(filter);
+    }
+
     pub fn write<T>(&mut self, item: T, dst: &mut BytesMut) -> Result<(), WireError>
     where
         T: Serialize,
@@ -567,6 +648,8 @@ impl Machine {
             panic!(ERR_MAX_MESSAGE_LENGTH_EXCEEDED);
         }
 
+        self.capture(FrameDirection::Sent, length);
+
         let mut length_buffer = [0; LENGTH_HEADER_SIZE];
         BinarySD::serialize(&mut length_buffer.as_mut(), &(length as u16))?;
 
@@ -591,6 +674,36 @@ impl Machine {
     where
         T: DeserializeOwned,
     {
+        match self.read_frame(src)? {
+            Some(length) => BinarySD::deserialize(&self.message_buffer.borrow()[..length]).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Reads the message, the message of unknown even type is the distinct error,
+    /// see `Message::unknown_even_type`. The gossip message rejected by the gossip filter
+    /// is skipped, the next frame is read instead.
+    pub fn read_message(&mut self, src: &mut BytesMut) -> Result<Option<Message>, WireError> {
+        while let Some(length) = self.read_frame(src)? {
+            let buffer = self.message_buffer.borrow();
+            let data = &buffer[..length];
+            if let Some(ref filter) = self.gossip_filter {
+                // the malformed gossip goes to the decoder, it reports the error
+                if let Ok(Some(view)) = GossipView::new(data) {
+                    if !filter.is_new(&view) {
+                        continue;
+                    }
+                }
+            }
+            return Message::from_bytes(data).map(Some);
+        }
+        Ok(None)
+    }
+
+    // Decrypts the frame into the message buffer and returns the length of the plaintext.
+    // The message might end with the tlv stream that is read until the end of the input,
+    // so the caller should deserialize exactly this length.
+    fn read_frame(&mut self, src: &mut BytesMut) -> Result<Option<usize>, WireError> {
         use chacha20_poly1305_aead::DecryptError;
         use serde::ser::Error;
 
@@ -637,7 +750,9 @@ impl Machine {
                         DecryptError::TagMismatch => WireError::custom("tag"),
                     })?;
 
-                BinarySD::deserialize(self.message_buffer.borrow().as_ref()).map(Some)
+                self.capture(FrameDirection::Received, length);
+
+                Ok(Some(length))
             }
         }
     }
@@ -657,3 +772,112 @@ impl Machine {
         self.chaining_key.clone()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use secp256k1::Secp256k1;
+    use wire::{InMemorySigner, Ping};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::thread;
+    use std::time::Duration;
+
+    // nothing is new for this filter
+    struct KnowsEverything;
+
+    impl GossipFilter for KnowsEverything {
+        fn is_new(&self, view: &GossipView) -> bool {
+            let _ = view;
+            false
+        }
+    }
+
+    fn machines() -> (Machine, Machine) {
+        let context = Secp256k1::new();
+        let local = SecretKey::from_slice(&context, &[0x11; 32]).unwrap();
+        let remote = SecretKey::from_slice(&context, &[0x21; 32]).unwrap();
+        let remote_public = PublicKey::from_secret_key(&context, &remote).unwrap();
+
+        let initiator = HandshakeNew::new(true, Arc::new(InMemorySigner::new(local).unwrap()), remote_public);
+        let responder = HandshakeNew::new(false, Arc::new(InMemorySigner::new(remote).unwrap()), PublicKey::new());
+        let (act_one, initiator) = initiator.gen_act_one().unwrap();
+        let (act_two, responder) = responder.recv_act_one(act_one).unwrap().gen_act_two().unwrap();
+        let (act_three, initiator) = initiator.recv_act_two(act_two).unwrap().gen_act_three().unwrap();
+        (initiator, responder.recv_act_three(act_three).unwrap())
+    }
+
+    fn update_channel() -> Message {
+        // the signature, the chain hash, the short channel id, the timestamp, the flags,
+        // the time lock delta, the htlc minimum, the base fee and the fee rate
+        let mut data = vec![1, 2];
+        data.extend_from_slice(&[1; 64]);
+        data.extend_from_slice(&[0; 32 + 8 + 4 + 2 + 2 + 8 + 4 + 4]);
+        Message::from_bytes(&data).unwrap()
+    }
+
+    #[test]
+    fn skip_known_gossip() {
+        let (mut initiator, mut responder) = machines();
+        let mut buffer = BytesMut::new();
+
+        initiator.write(update_channel(), &mut buffer).unwrap();
+        initiator.write(Message::Ping(Ping::new(4, 4).unwrap()), &mut buffer).unwrap();
+        match responder.read_message(&mut buffer).unwrap() {
+            Some(Message::UpdateChannel(_)) => (),
+            m => panic!("unexpected message: {:?}", m),
+        }
+
+        // the update is skipped, the ping behind it is decoded
+        responder.filter_gossip(Arc::new(KnowsEverything));
+        initiator.write(update_channel(), &mut buffer).unwrap();
+        match responder.read_message(&mut buffer).unwrap() {
+            Some(Message::Ping(_)) => (),
+            m => panic!("unexpected message: {:?}", m),
+        }
+        assert!(responder.read_message(&mut buffer).unwrap().is_none());
+        assert!(buffer.is_empty());
+    }
+
+    // the writer which fails once it is broken
+    struct Breakable(Arc<AtomicBool>);
+
+    impl io::Write for Breakable {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if self.0.load(Ordering::SeqCst) {
+                Err(io::Error::new(io::ErrorKind::Other, "broken"))
+            } else {
+                Ok(buf.len())
+            }
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn capture_failure() {
+        let (mut initiator, mut responder) = machines();
+        let broken = Arc::new(AtomicBool::new(false));
+        initiator.record(Breakable(broken.clone())).unwrap();
+        responder.record(Breakable(broken.clone())).unwrap();
+        broken.store(true, Ordering::SeqCst);
+
+        // the session goes on without the capture, the writer fails
+        // in the background, so the recording stops some frames later
+        let mut buffer = BytesMut::new();
+        for _ in 0..100 {
+            initiator.write(Message::Ping(Ping::new(4, 4).unwrap()), &mut buffer).unwrap();
+            match responder.read_message(&mut buffer).unwrap() {
+                Some(Message::Ping(_)) => (),
+                m => panic!("unexpected message: {:?}", m),
+            }
+            if initiator.recorder.is_none() && responder.recorder.is_none() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert!(initiator.recorder.is_none());
+        assert!(responder.recorder.is_none());
+    }
+}
@@ -0,0 +1,135 @@
+use tokio::prelude::{Stream, Sink, Future, Async, AsyncSink, Poll};
+use tokio::timer::Delay;
+
+use wire::WireError;
+
+use std::io;
+use std::time::{Duration, Instant};
+
+use std::{fmt, error};
+
+// The error produced when the peer is silent for too long, it is wrapped
+// in the io error of kind `TimedOut`, see `is_idle_timeout`.
+#[derive(Debug)]
+struct IdleError;
+
+impl fmt::Display for IdleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "the peer is idle")
+    }
+}
+
+impl error::Error for IdleError {
+}
+
+/// Tells if the read has failed because the peer was idle. The stream still
+/// can be polled after the error, so the peer layer may ping the peer
+/// instead of disconnecting it.
+pub fn is_idle_timeout(e: &WireError) -> bool {
+    use bincode::ErrorKind;
+
+    match &**e {
+        &ErrorKind::Io(ref e) => {
+            e.kind() == io::ErrorKind::TimedOut && e.get_ref().and_then(|e| e.downcast_ref::<IdleError>()).is_some()
+        },
+        _ => false,
+    }
+}
+
+/// Wraps the framed connection, the read fails if nothing is received
+/// during the `idle` time. Every received message restarts the time.
+pub struct IdleTimeout<S> {
+    inner: S,
+    idle: Duration,
+    delay: Delay,
+}
+
+impl<S> IdleTimeout<S> {
+    pub fn new(inner: S, idle: Duration) -> Self {
+        IdleTimeout {
+            inner: inner,
+            idle: idle,
+            delay: Delay::new(Instant::now() + idle),
+        }
+    }
+
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+
+    fn restart(&mut self) {
+        self.delay.reset(Instant::now() + self.idle);
+    }
+}
+
+impl<S> Stream for IdleTimeout<S> where S: Stream<Error=WireError> {
+    type Item = S::Item;
+    type Error = WireError;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        match self.inner.poll() {
+            Ok(Async::NotReady) => (),
+            r @ _ => {
+                self.restart();
+                return r;
+            },
+        }
+
+        match self.delay.poll() {
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+            Ok(Async::Ready(())) => {
+                self.restart();
+                Err(WireError::from(io::Error::new(io::ErrorKind::TimedOut, IdleError)))
+            },
+            Err(e) => Err(WireError::from(io::Error::new(io::ErrorKind::Other, e.to_string()))),
+        }
+    }
+}
+
+impl<S> Sink for IdleTimeout<S> where S: Sink<SinkError=WireError> {
+    type SinkItem = S::SinkItem;
+    type SinkError = WireError;
+
+    fn start_send(&mut self, item: Self::SinkItem) -> Result<AsyncSink<Self::SinkItem>, Self::SinkError> {
+        self.inner.start_send(item)
+    }
+
+    fn poll_complete(&mut self) -> Poll<(), Self::SinkError> {
+        self.inner.poll_complete()
+    }
+
+    fn close(&mut self) -> Poll<(), Self::SinkError> {
+        self.inner.close()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tokio::prelude::stream;
+    use tokio::runtime::current_thread;
+
+    #[test]
+    fn idle() {
+        let silent = stream::poll_fn(|| -> Poll<Option<()>, WireError> { Ok(Async::NotReady) });
+        let idle = IdleTimeout::new(silent, Duration::from_millis(50));
+        let (e, idle) = current_thread::block_on_all(idle.into_future()).err().unwrap();
+        assert!(is_idle_timeout(&e));
+
+        // the stream is still usable, the time is restarted
+        let (e, _) = current_thread::block_on_all(idle.into_future()).err().unwrap();
+        assert!(is_idle_timeout(&e));
+
+        let error = WireError::from(io::Error::new(io::ErrorKind::TimedOut, "other"));
+        assert!(!is_idle_timeout(&error));
+
+        // the same text is not the same error
+        let error = WireError::from(io::Error::new(io::ErrorKind::TimedOut, IdleError.to_string()));
+        assert!(!is_idle_timeout(&error));
+    }
+}
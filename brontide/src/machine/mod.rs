@@ -1,10 +1,12 @@
 mod brontide_stream;
 mod cipher_state;
 mod handshake;
+mod idle_timeout;
 mod serde;
 mod symmetric_state;
 #[cfg(test)]
 mod test_bolt0008;
 
-pub use self::brontide_stream::BrontideStream;
+pub use self::brontide_stream::{BrontideStream, HandshakeTimeouts};
+pub use self::idle_timeout::{IdleTimeout, is_idle_timeout};
 pub use self::handshake::{HandshakeError, Machine};
@@ -18,6 +18,6 @@ impl Decoder for Box<Machine> {
     type Error = WireError;
 
     fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
-        self.read(src)
+        self.read_message(src)
     }
 }
@@ -3,6 +3,8 @@ use hex;
 use std::error::Error;
 use std::collections::HashMap;
 use super::handshake::HandshakeNew;
+use wire::InMemorySigner;
+use std::sync::Arc;
 
 #[test]
 fn test_bolt0008() {
@@ -40,7 +42,7 @@ fn test_bolt0008_internal() -> Result<(), Box<Error>> {
         "036360e856310ce5d294e8be33fc807077dc56ac80d95d9cd4ddbd21325eff73f7"
     );
 
-    let mut machine = HandshakeNew::new(true, ls_priv, rs_pub)?;
+    let mut machine = HandshakeNew::new(true, Arc::new(InMemorySigner::new(ls_priv)?), rs_pub);
     machine.ephemeral_gen = || -> Result<SecretKey, CryptoError> {
         let sk = SecretKey::from_slice(
             &Secp256k1::new(),
@@ -59,7 +61,7 @@ fn test_bolt0008_internal() -> Result<(), Box<Error>> {
 
     assert_eq!(hex::encode(&act_one.bytes[..]), "00036360e856310ce5d294e8be33fc807077dc56ac80d95d9cd4ddbd21325eff73f70df6086551151f58b8afe6c195782c6a");
 
-    let mut responder_machine = HandshakeNew::new(false, rs_priv, ls_pub)?;
+    let mut responder_machine = HandshakeNew::new(false, Arc::new(InMemorySigner::new(rs_priv)?), ls_pub);
     responder_machine.ephemeral_gen = || -> Result<SecretKey, CryptoError> {
         let sk = SecretKey::from_slice(
             &Secp256k1::new(),
@@ -3,12 +3,17 @@ extern crate secp256k1;
 extern crate brontide;
 extern crate tokio;
 extern crate hex;
+extern crate wire;
 
-use secp256k1::{SecretKey, PublicKey, Secp256k1};
+use secp256k1::{SecretKey, Secp256k1};
 use secp256k1::constants::SECRET_KEY_SIZE;
 
 use tokio::net;
 
+use wire::{NodeSigner, InMemorySigner};
+
+use std::sync::Arc;
+
 fn main() {
     use tokio::prelude::{Stream, Future};
 
@@ -17,16 +22,17 @@ fn main() {
         SecretKey::from_slice(&Secp256k1::new(), &local_priv_bytes).unwrap()
     };
 
-    let public = PublicKey::from_secret_key(&Secp256k1::new(), &secret).unwrap();
+    let signer: Arc<dyn NodeSigner> = Arc::new(InMemorySigner::new(secret).unwrap());
+    let public = signer.node_id();
     let address_str = "127.0.0.1:10020";
     println!("{}@{}", hex::encode(&public.serialize()[..]), address_str);
 
     let listener = net::TcpListener::bind(&address_str.parse().unwrap()).ok().unwrap();
 
-    let node_secret = secret.clone();
+    let node_signer = signer.clone();
     let node = listener.incoming()
         .for_each(move |stream| {
-            let connection = brontide::BrontideStream::incoming(stream, node_secret)
+            let connection = brontide::BrontideStream::incoming(stream, node_signer.clone())
                 .map(|stream| println!("new connection: {}@{}", hex::encode(&stream.remote_key().serialize()[..]), stream.as_ref().peer_addr().unwrap()))
                 .map_err(|e| println!("handshake error: {:?}", e));
             tokio::spawn(connection);
@@ -34,13 +40,13 @@ fn main() {
         })
         .map_err(|e| println!("error: {:?}", e));
 
-    let outgoing_secret = secret.clone();
+    let outgoing_signer = signer.clone();
     let outgoing = net::TcpStream::connect(&"127.0.0.1:10000".parse().unwrap())
         .and_then(move |stream| {
             use secp256k1::PublicKey;
             use secp256k1::Secp256k1;
             let public = PublicKey::from_slice(&Secp256k1::new(), &hex::decode("02bb358785cba705f6339f1eca6a8209e33afc80c9207d99a90a6fbb538c668929").unwrap()).unwrap().into();
-            let connection = brontide::BrontideStream::outgoing(stream, outgoing_secret, public)
+            let connection = brontide::BrontideStream::outgoing(stream, outgoing_signer, public)
                 .map(|stream| println!("outgoing connection: {}", stream.as_ref().peer_addr().unwrap()))
                 .map_err(|e| println!("handshake error: {:?}", e));
             tokio::spawn(connection);
@@ -0,0 +1,276 @@
+use tokio::net::TcpStream;
+use tokio::codec::{Framed, LinesCodec, Decoder};
+use tokio::prelude::{Future, Stream, Sink, IntoFuture};
+use tokio::prelude::future::{loop_fn, Loop};
+
+use std::net::SocketAddr;
+use std::fs;
+
+use wire::Address;
+
+use super::TorError;
+
+type ControlStream = Framed<TcpStream, LinesCodec>;
+
+// sends the command, reads the reply, the result is the text of the lines of the reply,
+// the data of the `250+` line follows it, a line per line of the data
+fn command(stream: ControlStream, line: String) -> impl Future<Item = (ControlStream, Vec<String>), Error = TorError> {
+    // the codec terminates the line by `\n`, the protocol wants `\r\n`
+    stream.send(format!("{}\r", line))
+        .map_err(TorError::Io)
+        .and_then(|stream| {
+            loop_fn((stream, Vec::new(), false), |(stream, lines, data)| {
+                stream.into_future()
+                    .map_err(|(e, _)| TorError::Io(e))
+                    .and_then(move |(line, stream)| {
+                        let mut lines = lines;
+                        let line = line.ok_or(TorError::ControlProtocol("the connection is closed".to_owned()))?;
+                        let line = line.trim_right_matches('\r');
+                        // the data ends with the `.` line, the data line beginning
+                        // with `.` has one more
+                        if data {
+                            if line == "." {
+                                return Ok(Loop::Continue((stream, lines, false)));
+                            }
+                            let line = if line.starts_with('.') { &line[1..] } else { line };
+                            lines.push(line.to_owned());
+                            return Ok(Loop::Continue((stream, lines, true)));
+                        }
+                        if line.len() < 4 || !line.is_char_boundary(4) {
+                            return Err(TorError::ControlProtocol(format!("malformed reply: {}", line)));
+                        }
+                        if !line.starts_with('2') {
+                            return Err(TorError::Control(line.to_owned()));
+                        }
+                        lines.push(line[4..].to_owned());
+                        // `250-` is followed by the next line, `250+` by the data,
+                        // `250 ` is the last one
+                        match &line[3..4] {
+                            " " => Ok(Loop::Break((stream, lines))),
+                            "-" => Ok(Loop::Continue((stream, lines, false))),
+                            "+" => Ok(Loop::Continue((stream, lines, true))),
+                            _ => Err(TorError::ControlProtocol(format!("malformed reply: {}", line))),
+                        }
+                    })
+            })
+        })
+}
+
+// the value of the `key` in the line of space separated `KEY=value` pairs,
+// the value may be quoted
+fn value(line: &str, key: &str) -> Option<String> {
+    let prefix = format!("{}=", key);
+    let start = line.find(prefix.as_str())? + prefix.len();
+    let rest = &line[start..];
+    if rest.starts_with('"') {
+        let end = rest[1..].find('"')?;
+        Some(rest[1..(end + 1)].replace("\\\\", "\\"))
+    } else {
+        Some(rest.split(' ').next().unwrap_or("").to_owned())
+    }
+}
+
+// chooses the authentication by the reply of `PROTOCOLINFO`
+fn authenticate_command(protocol_info: &[String], password: Option<&String>) -> Result<String, TorError> {
+    let auth = protocol_info.iter()
+        .find(|line| line.starts_with("AUTH "))
+        .ok_or(TorError::ControlProtocol("no authentication methods".to_owned()))?;
+    let methods = value(auth, "METHODS").unwrap_or_default();
+    let methods = methods.split(',').collect::<Vec<_>>();
+
+    if methods.contains(&"NULL") {
+        Ok("AUTHENTICATE".to_owned())
+    } else if let (true, Some(password)) = (methods.contains(&"HASHEDPASSWORD"), password) {
+        let escaped = password.replace('\\', "\\\\").replace('"', "\\\"");
+        Ok(format!("AUTHENTICATE \"{}\"", escaped))
+    } else if methods.contains(&"COOKIE") {
+        let path = value(auth, "COOKIEFILE")
+            .ok_or(TorError::ControlProtocol("no cookie file".to_owned()))?;
+        let cookie = fs::read(path).map_err(TorError::Io)?;
+        Ok(format!("AUTHENTICATE {}", ::hex::encode(cookie)))
+    } else {
+        Err(TorError::Control(format!("unsupported authentication methods: {:?}", methods)))
+    }
+}
+
+/// The onion service published through the control connection.
+#[derive(Clone, Debug)]
+pub struct OnionService {
+    // put it in the `AnnouncementNode`
+    pub address: Address,
+    pub service_id: String,
+    // pass it to `TorControl::add_onion` next time to keep the address
+    pub private_key: String,
+}
+
+/// The authenticated connection to the tor control port. The onion services
+/// it has published are removed by tor when the connection is closed.
+pub struct TorControl {
+    stream: ControlStream,
+}
+
+impl TorControl {
+    /// The `password` is needed if tor is configured by `HashedControlPassword`,
+    /// the cookie authentication and no authentication work without it.
+    pub fn connect(address: &SocketAddr, password: Option<String>) -> impl Future<Item = Self, Error = TorError> {
+        TcpStream::connect(address)
+            .map_err(TorError::Io)
+            .and_then(|stream| command(LinesCodec::new().framed(stream), "PROTOCOLINFO 1".to_owned()))
+            .and_then(move |(stream, lines)| {
+                authenticate_command(lines.as_slice(), password.as_ref())
+                    .into_future()
+                    .and_then(move |line| command(stream, line))
+            })
+            .map(|(stream, _)| TorControl {
+                stream: stream,
+            })
+    }
+
+    /// Publishes the v3 onion service, its `port` is forwarded to the `target`
+    /// where we listen for the incoming connections. The `private_key` of
+    /// the previously published service keeps the same onion address.
+    pub fn add_onion(
+        self,
+        port: u16,
+        target: SocketAddr,
+        private_key: Option<String>,
+    ) -> impl Future<Item = (Self, OnionService), Error = TorError> {
+        let key = match &private_key {
+            &Some(ref key) => format!("ED25519-V3:{}", key),
+            &None => "NEW:ED25519-V3".to_owned(),
+        };
+        let line = format!("ADD_ONION {} Port={},{}", key, port, target);
+        command(self.stream, line)
+            .and_then(move |(stream, lines)| {
+                let service_id = lines.iter()
+                    .filter_map(|line| value(line, "ServiceID"))
+                    .next()
+                    .ok_or(TorError::ControlProtocol("no service id".to_owned()))?;
+                // tor does not send back the key we have provided
+                let private_key = lines.iter()
+                    .filter_map(|line| value(line, "PrivateKey"))
+                    .map(|key| key.trim_left_matches("ED25519-V3:").to_owned())
+                    .next()
+                    .or(private_key)
+                    .ok_or(TorError::ControlProtocol("no private key".to_owned()))?;
+                let address = format!("{}.onion:{}", service_id, port)
+                    .parse::<Address>()
+                    .map_err(TorError::ControlProtocol)?;
+                let service = OnionService {
+                    address: address,
+                    service_id: service_id,
+                    private_key: private_key,
+                };
+                Ok((TorControl { stream: stream }, service))
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    use tokio::runtime::current_thread;
+
+    use wire::{AnnouncementNodeData, Color, NodeAlias, BinarySD};
+    use wire::PublicKey as WirePublicKey;
+    use secp256k1::{Secp256k1, SecretKey, PublicKey};
+
+    const SERVICE_ID: &'static str = "2gzyxa5ihm7nsggfxnu52rck2vv4rvmdlkiu3zzui5du4xyclen53wid";
+
+    // the stand-in tor, answers the commands in order
+    fn control(replies: Vec<(String, String)>) -> (SocketAddr, thread::JoinHandle<()>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let handle = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut writer = stream;
+            for (expected, reply) in replies {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                assert_eq!(line, format!("{}\r\n", expected));
+                writer.write_all(reply.as_bytes()).unwrap();
+            }
+        });
+        (address, handle)
+    }
+
+    #[test]
+    fn add_onion() {
+        let (address, handle) = control(vec![
+            (
+                "PROTOCOLINFO 1".to_owned(),
+                "250-PROTOCOLINFO 1\r\n250-AUTH METHODS=HASHEDPASSWORD\r\n250-VERSION Tor=\"0.4.8.9\"\r\n250 OK\r\n".to_owned(),
+            ),
+            ("AUTHENTICATE \"pass\\\"word\"".to_owned(), "250 OK\r\n".to_owned()),
+            (
+                "ADD_ONION NEW:ED25519-V3 Port=9735,127.0.0.1:10100".to_owned(),
+                format!("250-ServiceID={}\r\n250-PrivateKey=ED25519-V3:a2V5\r\n250 OK\r\n", SERVICE_ID),
+            ),
+        ]);
+
+        let task = TorControl::connect(&address, Some("pass\"word".to_owned()))
+            .and_then(|control| control.add_onion(9735, "127.0.0.1:10100".parse().unwrap(), None));
+        let (_control, service) = current_thread::block_on_all(task).unwrap();
+        handle.join().unwrap();
+
+        assert_eq!(service.service_id.as_str(), SERVICE_ID);
+        assert_eq!(service.private_key.as_str(), "a2V5");
+        assert_eq!(service.address.to_string(), format!("{}.onion:9735", SERVICE_ID));
+
+        // announce the onion address
+        let context = Secp256k1::new();
+        let secret = SecretKey::from_slice(&context, &[0x21; 32]).unwrap();
+        let node_id = WirePublicKey::from(PublicKey::from_secret_key(&context, &secret).unwrap());
+        let alias = NodeAlias::try_from_string(format!("{:32}", "lpd")).unwrap();
+        let data = AnnouncementNodeData::new(node_id, 1, Color::default(), alias)
+            .with_address(service.address.clone());
+        let mut bytes = Vec::new();
+        BinarySD::serialize(&mut bytes, &data).unwrap();
+        let restored: AnnouncementNodeData = BinarySD::deserialize(bytes.as_slice()).unwrap();
+        assert_eq!(restored.address.0, vec![service.address]);
+    }
+
+    #[test]
+    fn rejected() {
+        let (address, handle) = control(vec![
+            ("PROTOCOLINFO 1".to_owned(), "250-PROTOCOLINFO 1\r\n250-AUTH METHODS=NULL\r\n250 OK\r\n".to_owned()),
+            ("AUTHENTICATE".to_owned(), "515 Authentication failed\r\n".to_owned()),
+        ]);
+
+        match current_thread::block_on_all(TorControl::connect(&address, None)).map(|_| ()) {
+            Err(TorError::Control(ref reply)) if reply.starts_with("515") => (),
+            r => panic!("unexpected result: {:?}", r),
+        }
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn data_reply() {
+        let (address, handle) = control(vec![
+            (
+                "GETINFO config-text".to_owned(),
+                "250+config-text=\r\nControlPort 9051\r\n..hidden\r\n.\r\n250-version=0.4.8.9\r\n250 OK\r\n".to_owned(),
+            ),
+            ("GETINFO version".to_owned(), "250?version\r\n".to_owned()),
+        ]);
+
+        let task = TcpStream::connect(&address)
+            .map_err(TorError::Io)
+            .and_then(|stream| command(LinesCodec::new().framed(stream), "GETINFO config-text".to_owned()))
+            .and_then(|(stream, lines)| {
+                assert_eq!(lines, vec!["config-text=", "ControlPort 9051", ".hidden", "version=0.4.8.9", "OK"]);
+                command(stream, "GETINFO version".to_owned())
+            });
+        match current_thread::block_on_all(task).map(|_| ()) {
+            Err(TorError::ControlProtocol(_)) => (),
+            r => panic!("unexpected result: {:?}", r),
+        }
+        handle.join().unwrap();
+    }
+}
@@ -0,0 +1,179 @@
+mod socks5;
+mod control;
+
+pub use self::socks5::{handshake as socks5_handshake, onion_host};
+pub use self::control::{TorControl, OnionService};
+
+use tokio::net::TcpStream;
+use tokio::prelude::{Future, IntoFuture, FutureExt};
+use tokio::prelude::future::Either;
+use tokio::timer::{self, timeout};
+
+use secp256k1::PublicKey;
+
+use std::{io, fmt, error};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use wire::{Address, NodeSigner};
+
+use super::{BrontideStream, HandshakeError};
+
+#[derive(Debug)]
+pub enum TorError {
+    Io(io::Error),
+    // the reply code of the SOCKS5 proxy
+    Socks(u8),
+    // the malformed reply of the SOCKS5 proxy
+    SocksProtocol(String),
+    // the proxy has not connected in time
+    Timeout,
+    Timer(timer::Error),
+    // the error reply of the control port
+    Control(String),
+    // the malformed or incomplete reply of the control port
+    ControlProtocol(String),
+    UnsupportedAddress(Address),
+    Handshake(HandshakeError),
+}
+
+impl TorError {
+    fn from_timeout(e: timeout::Error<TorError>) -> Self {
+        if e.is_elapsed() {
+            TorError::Timeout
+        } else if e.is_timer() {
+            TorError::Timer(e.into_timer().unwrap())
+        } else {
+            e.into_inner().unwrap()
+        }
+    }
+}
+
+impl error::Error for TorError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        use self::TorError::*;
+
+        match self {
+            &Io(ref e) => Some(e),
+            &Timer(ref e) => Some(e),
+            &Handshake(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for TorError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::TorError::*;
+
+        match self {
+            &Io(ref e) => write!(f, "io error: {}", e),
+            &Socks(code) => write!(f, "socks5 proxy has failed the request, code: {}", code),
+            &SocksProtocol(ref msg) => write!(f, "socks5 protocol error: {}", msg),
+            &Timeout => write!(f, "socks5 proxy has not connected in time"),
+            &Timer(ref e) => write!(f, "timer error: {}", e),
+            &Control(ref msg) => write!(f, "tor control error: {}", msg),
+            &ControlProtocol(ref msg) => write!(f, "tor control protocol error: {}", msg),
+            &UnsupportedAddress(ref address) => write!(f, "cannot connect to the address: {}", address),
+            &Handshake(ref e) => write!(f, "handshake error: {}", e),
+        }
+    }
+}
+
+/// How to reach the peers. The onion addresses always go through the proxy,
+/// the clearnet addresses do if `clearnet` is set, otherwise they are dialed directly.
+#[derive(Clone, Debug)]
+pub struct TorConfig {
+    pub socks: SocketAddr,
+    pub clearnet: bool,
+    // the limit of the connection through the proxy, tor builds the circuit meanwhile
+    pub timeout: Duration,
+}
+
+impl TorConfig {
+    pub fn new(socks: SocketAddr) -> Self {
+        TorConfig {
+            socks: socks,
+            clearnet: false,
+            timeout: Duration::new(60, 0),
+        }
+    }
+
+    pub fn with_clearnet(self) -> Self {
+        let mut s = self;
+        s.clearnet = true;
+        s
+    }
+
+    pub fn with_timeout(self, timeout: Duration) -> Self {
+        let mut s = self;
+        s.timeout = timeout;
+        s
+    }
+
+    /// Establishes the tcp connection to the `address`.
+    pub fn connect(&self, address: &Address) -> impl Future<Item = TcpStream, Error = TorError> {
+        let address = address.clone();
+        if onion_host(&address).is_some() || self.clearnet {
+            Either::A(
+                TcpStream::connect(&self.socks)
+                    .map_err(TorError::Io)
+                    .and_then(move |stream| socks5_handshake(stream, &address))
+                    .timeout(self.timeout)
+                    .map_err(TorError::from_timeout)
+            )
+        } else {
+            Either::B(
+                address.into_socket_address()
+                    .map_err(TorError::UnsupportedAddress)
+                    .into_future()
+                    .and_then(|address| TcpStream::connect(&address).map_err(TorError::Io))
+            )
+        }
+    }
+
+    /// The same as `BrontideStream::outgoing`, but the `address` may be the onion address.
+    pub fn outgoing(
+        &self,
+        address: &Address,
+        local_signer: Arc<dyn NodeSigner>,
+        remote_public: PublicKey,
+    ) -> impl Future<Item = BrontideStream<TcpStream>, Error = TorError> {
+        self.connect(address)
+            .and_then(move |stream| {
+                BrontideStream::outgoing(stream, local_signer, remote_public)
+                    .map_err(TorError::Handshake)
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::net::TcpListener;
+    use std::thread;
+
+    use tokio::runtime::current_thread;
+
+    #[test]
+    fn proxy_timeout() {
+        // the proxy accepts the connection, but never answers
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let socks = listener.local_addr().unwrap();
+        let handle = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            thread::sleep(Duration::from_millis(500));
+            drop(stream);
+        });
+
+        let config = TorConfig::new(socks).with_timeout(Duration::from_millis(50));
+        let address: Address = "2gzyxa5ihm7nsggfxnu52rck2vv4rvmdlkiu3zzui5du4xyclen53wid.onion:9735".parse().unwrap();
+        match current_thread::block_on_all(config.connect(&address)).map(|_| ()) {
+            Err(TorError::Timeout) => (),
+            r => panic!("unexpected result: {:?}", r),
+        }
+        handle.join().unwrap();
+    }
+}
@@ -0,0 +1,212 @@
+use tokio::io;
+use tokio::prelude::{Future, IntoFuture};
+use tokio::prelude::future::Either;
+
+use wire::Address;
+
+use super::TorError;
+
+// SOCKS_VERSION is the version of the protocol, the only one supported.
+const SOCKS_VERSION: u8 = 5;
+
+// NO_AUTHENTICATION is the only authentication method we offer,
+// tor does not require any.
+const NO_AUTHENTICATION: u8 = 0;
+
+// COMMAND_CONNECT asks the proxy to establish the tcp connection.
+const COMMAND_CONNECT: u8 = 1;
+
+// the types of the address in the request and in the reply
+const ADDRESS_IPV4: u8 = 1;
+const ADDRESS_DOMAIN: u8 = 3;
+const ADDRESS_IPV6: u8 = 4;
+
+/// The host of the onion address, `<base32>.onion`.
+pub fn onion_host(address: &Address) -> Option<String> {
+    match address {
+        &Address::TorV2(_) | &Address::TorV3(_) => {
+            let s = address.to_string();
+            s.rsplitn(2, ':').nth(1).map(ToOwned::to_owned)
+        },
+        _ => None,
+    }
+}
+
+fn request(address: &Address) -> Result<Vec<u8>, TorError> {
+    let mut request = vec![SOCKS_VERSION, COMMAND_CONNECT, 0];
+    let port = match address {
+        &Address::IpV4(ref v) => {
+            request.push(ADDRESS_IPV4);
+            request.extend_from_slice(&v.ip[..]);
+            v.port
+        },
+        &Address::IpV6(ref v) => {
+            request.push(ADDRESS_IPV6);
+            request.extend_from_slice(&v.ip[..]);
+            v.port
+        },
+        &Address::TorV2(ref v) => {
+            request.push(ADDRESS_DOMAIN);
+            let host = onion_host(address).unwrap();
+            request.push(host.len() as u8);
+            request.extend_from_slice(host.as_bytes());
+            v.port
+        },
+        &Address::TorV3(ref v) => {
+            request.push(ADDRESS_DOMAIN);
+            let host = onion_host(address).unwrap();
+            request.push(host.len() as u8);
+            request.extend_from_slice(host.as_bytes());
+            v.port
+        },
+        &Address::None => return Err(TorError::UnsupportedAddress(address.clone())),
+    };
+    request.extend_from_slice(&[(port >> 8) as u8, port as u8]);
+    Ok(request)
+}
+
+// checks the beginning of the reply, returns the type of the bound address
+fn check_reply(head: [u8; 4]) -> Result<u8, TorError> {
+    if head[0] != SOCKS_VERSION {
+        return Err(TorError::SocksProtocol(format!("unexpected socks version: {}", head[0])));
+    }
+    if head[1] != 0 {
+        return Err(TorError::Socks(head[1]));
+    }
+    match head[3] {
+        t @ ADDRESS_IPV4 | t @ ADDRESS_DOMAIN | t @ ADDRESS_IPV6 => Ok(t),
+        t @ _ => Err(TorError::SocksProtocol(format!("unknown address type: {}", t))),
+    }
+}
+
+/// Asks the SOCKS5 proxy at the other end of the `stream` to connect
+/// to the `address`, the stream is connected to the `address` after that.
+pub fn handshake<T>(stream: T, address: &Address) -> impl Future<Item = T, Error = TorError>
+where
+    T: io::AsyncRead + io::AsyncWrite,
+{
+    request(address)
+        .into_future()
+        .and_then(move |request| {
+            io::write_all(stream, [SOCKS_VERSION, 1, NO_AUTHENTICATION])
+                .map_err(TorError::Io)
+                .map(move |(stream, _)| (stream, request))
+        }).and_then(|(stream, request)| {
+            io::read_exact(stream, [0; 2])
+                .map_err(TorError::Io)
+                .and_then(move |(stream, reply)| {
+                    if reply == [SOCKS_VERSION, NO_AUTHENTICATION] {
+                        Ok((stream, request))
+                    } else {
+                        Err(TorError::SocksProtocol(format!("authentication is not accepted: {:?}", reply)))
+                    }
+                })
+        }).and_then(|(stream, request)| {
+            io::write_all(stream, request).map_err(TorError::Io)
+        }).and_then(|(stream, _)| {
+            io::read_exact(stream, [0; 4])
+                .map_err(TorError::Io)
+                .and_then(|(stream, head)| check_reply(head).map(|t| (stream, t)))
+        }).and_then(|(stream, address_type)| {
+            // the size of the bound address, the domain is prefixed by its length
+            match address_type {
+                ADDRESS_DOMAIN => Either::A(
+                    io::read_exact(stream, [0; 1])
+                        .map_err(TorError::Io)
+                        .map(|(stream, length)| (stream, length[0] as usize))
+                ),
+                ADDRESS_IPV4 => Either::B(Ok((stream, 4)).into_future()),
+                _ => Either::B(Ok((stream, 16)).into_future()),
+            }
+        }).and_then(|(stream, size)| {
+            // the bound address and port are not needed
+            io::read_exact(stream, vec![0; size + 2])
+                .map_err(TorError::Io)
+                .map(|(stream, _)| stream)
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    use tokio::net::TcpStream;
+    use tokio::runtime::current_thread;
+
+    // the stand-in proxy, accepts one connection to the `expected` domain and echoes
+    fn proxy(expected: Vec<u8>) -> (u16, thread::JoinHandle<()>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let handle = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut greeting = [0; 3];
+            stream.read_exact(&mut greeting).unwrap();
+            assert_eq!(greeting, [5, 1, 0]);
+            stream.write_all(&[5, 0]).unwrap();
+
+            let mut request = vec![0; expected.len()];
+            stream.read_exact(request.as_mut_slice()).unwrap();
+            assert_eq!(request, expected);
+            // bound to some domain
+            stream.write_all(&[5, 0, 0, 3, 4, b'h', b'o', b's', b't', 0x24, 0x07]).unwrap();
+
+            let mut data = [0; 5];
+            stream.read_exact(&mut data).unwrap();
+            stream.write_all(&data).unwrap();
+        });
+        (port, handle)
+    }
+
+    #[test]
+    fn onion() {
+        let address: Address = "2gzyxa5ihm7nsggfxnu52rck2vv4rvmdlkiu3zzui5du4xyclen53wid.onion:9735".parse().unwrap();
+        let host = onion_host(&address).unwrap();
+        assert_eq!(host.as_str(), "2gzyxa5ihm7nsggfxnu52rck2vv4rvmdlkiu3zzui5du4xyclen53wid.onion");
+
+        let mut expected = vec![5, 1, 0, 3, host.len() as u8];
+        expected.extend_from_slice(host.as_bytes());
+        expected.extend_from_slice(&[0x26, 0x07]);
+        let (port, handle) = proxy(expected);
+
+        let proxy_address = format!("127.0.0.1:{}", port).parse().unwrap();
+        let task = TcpStream::connect(&proxy_address)
+            .map_err(TorError::Io)
+            .and_then(move |stream| handshake(stream, &address))
+            .and_then(|stream| io::write_all(stream, *b"hello").map_err(TorError::Io))
+            .and_then(|(stream, _)| io::read_exact(stream, [0; 5]).map_err(TorError::Io))
+            .map(|(_, data)| data);
+        assert_eq!(&current_thread::block_on_all(task).unwrap(), b"hello");
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn request_failed() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let handle = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut buffer = [0; 3 + 4 + 4 + 2];
+            stream.read_exact(&mut buffer[..3]).unwrap();
+            stream.write_all(&[5, 0]).unwrap();
+            stream.read_exact(&mut buffer[3..]).unwrap();
+            // host unreachable
+            stream.write_all(&[5, 4, 0, 1, 0, 0, 0, 0, 0, 0]).unwrap();
+        });
+
+        let proxy_address = format!("127.0.0.1:{}", port).parse().unwrap();
+        let task = TcpStream::connect(&proxy_address)
+            .map_err(TorError::Io)
+            .and_then(|stream| handshake(stream, &Address::localhost(9735)));
+        match current_thread::block_on_all(task).map(|_| ()) {
+            Err(TorError::Socks(4)) => (),
+            r => panic!("unexpected result: {:?}", r),
+        }
+        handle.join().unwrap();
+
+        assert!(request(&Address::None).is_err());
+    }
+}
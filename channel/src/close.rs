@@ -0,0 +1,258 @@
+use secp256k1::{PublicKey, SecretKey, Signature, Secp256k1, Message, Error as Secp256k1Error};
+use bitcoin::util::hash::{Sha256dHash};
+use bitcoin::blockdata::script::{Script};
+use bitcoin::blockdata::transaction::{Transaction, TxIn, TxOut};
+use bip69;
+use tools::{spending_witness_2x2_multisig, funding_sig_hash};
+
+// The weight of the closing transaction without outputs: the version, the funding input,
+// the output count and the locktime, the segwit marker and the 2-of-2 witness with
+// the signatures of the maximal size
+pub const BASE_CLOSING_WEIGHT: i64 = 428;
+
+/// Whether `shutdown` may carry the script: P2PKH, P2SH, P2WPKH or P2WSH.
+pub fn is_valid_shutdown_script(script: &[u8]) -> bool {
+    match script.len() {
+        // OP_DUP OP_HASH160 <20 bytes> OP_EQUALVERIFY OP_CHECKSIG
+        25 => script[0] == 0x76 && script[1] == 0xa9 && script[2] == 0x14 && script[23] == 0x88 && script[24] == 0xac,
+        // OP_HASH160 <20 bytes> OP_EQUAL
+        23 => script[0] == 0xa9 && script[1] == 0x14 && script[22] == 0x87,
+        // OP_0 <20 bytes>
+        22 => script[0] == 0x00 && script[1] == 0x14,
+        // OP_0 <32 bytes>
+        34 => script[0] == 0x00 && script[1] == 0x20,
+        _ => false,
+    }
+}
+
+/// The transaction spending the funding output to the scripts given by `shutdown`.
+/// Both sides build the same transaction, the outputs are in BIP69 order.
+pub struct ClosingTx {
+    pub funding_amount: i64,
+    pub local_funding_pubkey: PublicKey,
+    pub remote_funding_pubkey: PublicKey,
+
+    pub funding_tx_id: Sha256dHash,
+    pub funding_output_index: u32,
+
+    // the dust limit of the signer
+    pub dust_limit_satoshi: i64,
+
+    pub to_local_msat: i64,
+    pub to_remote_msat: i64,
+
+    pub local_script: Script,
+    pub remote_script: Script,
+
+    pub fee: i64,
+    pub local_pays_fee: bool,
+}
+
+impl ClosingTx {
+    // The outputs in satoshi rounded down, the funder pays the fee
+    fn amounts(&self) -> (i64, i64) {
+        let (to_local, to_remote) = (self.to_local_msat / 1000, self.to_remote_msat / 1000);
+        if self.local_pays_fee {
+            (to_local - self.fee, to_remote)
+        } else {
+            (to_local, to_remote - self.fee)
+        }
+    }
+
+    pub fn get_tx(&self) -> Transaction {
+        let mut tx = Transaction{
+            version: 2,
+            input: vec![TxIn{
+                prev_hash: self.funding_tx_id,
+                prev_index: self.funding_output_index,
+                sequence: 0xffffffff,
+                script_sig: Script::new(),
+                witness: vec![]
+            }],
+            output: vec![
+            ],
+            lock_time: 0
+        };
+
+        // The outputs below the dust limit go to the fee
+        let (to_local, to_remote) = self.amounts();
+        if to_local >= self.dust_limit_satoshi {
+            tx.output.push(TxOut{
+                value: to_local as u64,
+                script_pubkey: self.local_script.clone(),
+            });
+        }
+        if to_remote >= self.dust_limit_satoshi {
+            tx.output.push(TxOut{
+                value: to_remote as u64,
+                script_pubkey: self.remote_script.clone(),
+            });
+        }
+
+        bip69::reorder_tx(&mut tx);
+        tx
+    }
+
+    /// The weight of the signed transaction, it is known before signing.
+    pub fn weight(&self) -> i64 {
+        let outputs = self.get_tx().output.iter()
+            .map(|o| (8 + 1 + o.script_pubkey.len() as i64) * 4)
+            .sum::<i64>();
+        BASE_CLOSING_WEIGHT + outputs
+    }
+
+    fn sig_hash(&self, tx: &Transaction) -> Message {
+        funding_sig_hash(tx, &self.local_funding_pubkey, &self.remote_funding_pubkey, self.funding_amount as u64)
+    }
+
+    pub fn sign(&self, priv_key: &SecretKey) -> Signature {
+        let sec = Secp256k1::new();
+        let tx = self.get_tx();
+        sec.sign(&self.sig_hash(&tx), priv_key).unwrap()
+    }
+
+    // checks the signature of the counterparty by its funding key
+    pub fn verify(&self, remote_sig: &Signature) -> Result<(), Secp256k1Error> {
+        let sec = Secp256k1::new();
+        let tx = self.get_tx();
+        sec.verify(&self.sig_hash(&tx), remote_sig, &self.remote_funding_pubkey)
+    }
+
+    // The transaction ready to broadcast by either side
+    pub fn signed_tx(&self, local_sig: &Signature, remote_sig: &Signature) -> Transaction {
+        let mut tx = self.get_tx();
+        tx.input[0].witness = spending_witness_2x2_multisig(
+            &self.local_funding_pubkey,
+            &self.remote_funding_pubkey,
+            local_sig,
+            remote_sig,
+        );
+        tx
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use spec_example::get_example;
+    use tools::{v0_p2wpkh, p2pkh, new_2x2_wsh_lock_script};
+    use close::{ClosingTx, is_valid_shutdown_script};
+    use bitcoin::network::serialize::RawEncoder;
+    use bitcoin::network::encodable::ConsensusEncodable;
+    use bitcoin::blockdata::transaction::Transaction;
+
+    fn get_closing_tx(fee: i64) -> ClosingTx {
+        let ex = get_example();
+        ClosingTx {
+            funding_amount: ex.funding_amount_satoshi,
+            local_funding_pubkey: ex.local_funding_pubkey,
+            remote_funding_pubkey: ex.remote_funding_pubkey,
+            funding_tx_id: ex.funding_tx_id,
+            funding_output_index: ex.funding_output_index as u32,
+            dust_limit_satoshi: ex.local_dust_limit_satoshi,
+            to_local_msat: 6988000000,
+            to_remote_msat: 3000000000,
+            local_script: p2pkh(&ex.localpubkey),
+            remote_script: v0_p2wpkh(&ex.remotepubkey),
+            fee: fee,
+            local_pays_fee: true,
+        }
+    }
+
+    // the same transaction seen by the counterparty
+    fn swap(closing_tx: ClosingTx) -> ClosingTx {
+        ClosingTx {
+            local_funding_pubkey: closing_tx.remote_funding_pubkey,
+            remote_funding_pubkey: closing_tx.local_funding_pubkey,
+            to_local_msat: closing_tx.to_remote_msat,
+            to_remote_msat: closing_tx.to_local_msat,
+            local_script: closing_tx.remote_script,
+            remote_script: closing_tx.local_script,
+            local_pays_fee: !closing_tx.local_pays_fee,
+            ..closing_tx
+        }
+    }
+
+    fn size(tx: &Transaction) -> usize {
+        let mut data = vec![];
+        tx.consensus_encode(&mut RawEncoder::new(&mut data)).unwrap();
+        data.len()
+    }
+
+    #[test]
+    fn test_shutdown_scripts() {
+        let ex = get_example();
+        assert!(is_valid_shutdown_script(&p2pkh(&ex.localpubkey).data()));
+        assert!(is_valid_shutdown_script(&v0_p2wpkh(&ex.localpubkey).data()));
+        let wsh = new_2x2_wsh_lock_script(&ex.local_funding_pubkey.serialize(), &ex.remote_funding_pubkey.serialize());
+        assert!(is_valid_shutdown_script(&wsh.data()));
+        let sh = wsh.to_p2sh();
+        assert!(is_valid_shutdown_script(&sh.data()));
+
+        // the empty, the truncated and the future segwit version
+        assert!(!is_valid_shutdown_script(&[]));
+        assert!(!is_valid_shutdown_script(&wsh.data()[..33]));
+        let mut v1 = wsh.data();
+        v1[0] = 0x51;
+        assert!(!is_valid_shutdown_script(&v1));
+    }
+
+    #[test]
+    fn test_closing_tx() {
+        let tx = get_closing_tx(1000).get_tx();
+        assert_eq!(tx.version, 2);
+        assert_eq!(tx.lock_time, 0);
+        assert_eq!(tx.input[0].sequence, 0xffffffff);
+
+        // the funder pays the fee, the outputs are ordered by the amount
+        assert_eq!(tx.output.len(), 2);
+        assert_eq!(tx.output[0].value, 3000000);
+        assert_eq!(tx.output[1].value, 6987000);
+        assert_eq!(tx.output[0].script_pubkey, v0_p2wpkh(&get_example().remotepubkey));
+
+        // both sides build the same transaction
+        assert_eq!(swap(get_closing_tx(1000)).get_tx(), tx);
+    }
+
+    #[test]
+    fn test_closing_tx_dust_trimmed() {
+        let mut closing_tx = get_closing_tx(1000);
+        closing_tx.to_local_msat = 1545999;
+        closing_tx.to_remote_msat = 9998454001;
+        let tx = closing_tx.get_tx();
+        assert_eq!(tx.output.len(), 1);
+        assert_eq!(tx.output[0].value, 9998454);
+
+        // the output exactly at the dust limit remains
+        closing_tx.to_local_msat = 1546000;
+        assert_eq!(closing_tx.get_tx().output.len(), 2);
+
+        // the fee greater than the funder output
+        closing_tx.fee = 2000;
+        assert_eq!(closing_tx.get_tx().output.len(), 1);
+    }
+
+    #[test]
+    fn test_closing_tx_signatures() {
+        let ex = get_example();
+        let closing_tx = get_closing_tx(1000);
+        let local_sig = closing_tx.sign(&ex.local_funding_privkey);
+        let remote = swap(get_closing_tx(1000));
+        let remote_sig = remote.sign(&ex.internal.remote_funding_privkey);
+
+        assert!(closing_tx.verify(&remote_sig).is_ok());
+        assert!(remote.verify(&local_sig).is_ok());
+        assert!(closing_tx.verify(&local_sig).is_err());
+        assert!(get_closing_tx(1001).verify(&remote_sig).is_err());
+
+        // the witness is the same whoever assembles it
+        let tx = closing_tx.signed_tx(&local_sig, &remote_sig);
+        assert_eq!(tx, remote.signed_tx(&remote_sig, &local_sig));
+
+        // the estimation covers the signatures of the maximal size
+        let mut stripped = tx.clone();
+        stripped.input[0].witness = vec![];
+        let weight = size(&stripped) * 3 + size(&tx);
+        assert!(weight as i64 <= closing_tx.weight());
+        assert!(weight as i64 + 8 >= closing_tx.weight());
+    }
+}
@@ -1,16 +1,17 @@
-use secp256k1::{PublicKey, SecretKey, Signature, Secp256k1, Message};
+use secp256k1::{PublicKey, SecretKey, Signature, Secp256k1, Message, Error as Secp256k1Error};
 use bitcoin::util::hash::{Sha256dHash};
 use bitcoin::blockdata::script::{Script};
 use bitcoin::blockdata::transaction::{Transaction, TxIn, TxOut};
 use bitcoin::util::bip143;
 use bip69;
-use tools::{get_sequence, get_locktime, accepted_htlc, offered_htlc, to_local_script, v0_p2wpkh, new_2x2_multisig};
+use tools::{get_sequence, get_locktime, accepted_htlc, offered_htlc, to_local_script, v0_p2wpkh, spending_witness_2x2_multisig, funding_sig_hash};
 
 pub const HTLC_TIMEOUT_WEIGHT: i64 = 663;
 pub const HTLC_SUCCESS_WEIGHT: i64 = 703;
 pub const BASE_COMMITMENT_WEIGHT: i64 = 724;
 pub const PER_HTLC_COMMITMENT_WEIGHT: i64 = 172;
 
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
 pub enum HTLCDirection {
     Accepted,
     Offered,
@@ -49,10 +50,124 @@ pub struct CommitTx {
     pub funding_output_index: u32,
 
     pub htlcs: Vec<HTLC>,
+
+    // the funder pays the fee, it is the owner of the transaction or the counterparty
+    pub local_pays_fee: bool,
+}
+
+// The second stage transaction spending the htlc output of the commitment transaction,
+// HTLC-success for the accepted htlc and HTLC-timeout for the offered one
+pub struct HtlcTx {
+    // the position of the htlc in `CommitTx::htlcs`
+    pub htlc_index: usize,
+    pub direction: HTLCDirection,
+    pub tx: Transaction,
+    // the witness script and the amount in satoshi of the spent output
+    pub htlc_script: Script,
+    pub amount: u64,
+}
+
+impl HtlcTx {
+    fn sig_hash(&self) -> Message {
+        let tx_sig_hash = bip143::SighashComponents::new(&self.tx)
+            .sighash_all(
+                &self.tx.input[0],
+                &self.htlc_script,
+                self.amount
+            );
+        Message::from(tx_sig_hash.data())
+    }
+
+    // signs by the htlc key of the local or the remote side of the commitment transaction
+    pub fn sign(&self, htlc_priv_key: &SecretKey) -> Signature {
+        let sec = Secp256k1::new();
+        // TODO(mkl): maybe do not use unwrap
+        sec.sign(&self.sig_hash(), htlc_priv_key).unwrap()
+    }
+
+    pub fn verify(&self, sig: &Signature, htlc_pubkey: &PublicKey) -> Result<(), Secp256k1Error> {
+        let sec = Secp256k1::new();
+        sec.verify(&self.sig_hash(), sig, htlc_pubkey)
+    }
+
+    // The transaction ready to broadcast by the owner of the commitment transaction,
+    // the HTLC-success requires the payment preimage
+    pub fn signed_tx(&self, local_sig: &Signature, remote_sig: &Signature, payment_preimage: Option<[u8; 32]>) -> Transaction {
+        let sec = Secp256k1::new();
+
+        let mut local_sig_ser = local_sig.serialize_der(&sec);
+        local_sig_ser.push(1);
+        let mut remote_sig_ser = remote_sig.serialize_der(&sec);
+        remote_sig_ser.push(1);
+
+        let mut tx = self.tx.clone();
+        tx.input[0].witness = vec![
+            // Empy element due to a bug(now a consensus feature) of OP_CHECKMULTISIG
+            vec![],
+            remote_sig_ser,
+            local_sig_ser,
+            payment_preimage.map(|p| p.to_vec()).unwrap_or_default(),
+            self.htlc_script.data(),
+        ];
+        tx
+    }
 }
 
 impl CommitTx {
     pub fn get_tx(&self) -> Transaction {
+        self.build().0
+    }
+
+    // The second stage transactions for untrimmed htlcs in the order of the htlc outputs,
+    // the htlc signatures of `commitment_signed` are in this order
+    pub fn get_htlc_txs(&self) -> Vec<HtlcTx> {
+        let (commit_tx, htlc_outputs) = self.build();
+        let commit_tx_id = commit_tx.txid();
+
+        htlc_outputs.into_iter()
+            .map(|(htlc_index, output_index)| {
+                let h = &self.htlcs[htlc_index];
+                let (fee, lock_time) = match h.direction {
+                    HTLCDirection::Accepted => (self.htlc_success_fee(), 0),
+                    HTLCDirection::Offered => (self.htlc_timeout_fee(), h.expiry as u32),
+                };
+                let amount = h.amount_msat / 1000;
+
+                let tx = Transaction{
+                    version: 2,
+                    input: vec![TxIn{
+                        prev_hash: commit_tx_id,
+                        prev_index: output_index,
+                        sequence: 0,
+                        script_sig: Script::new(),
+                        witness: vec![]
+                    }],
+                    output: vec![TxOut{
+                        value: (amount - fee) as u64,
+                        script_pubkey: to_local_script(&self.local_delayedpubkey, self.local_delay as u64, &self.local_revocation_pubkey).to_v0_p2wsh(),
+                    }],
+                    lock_time: lock_time,
+                };
+                HtlcTx{
+                    htlc_index: htlc_index,
+                    direction: h.direction,
+                    tx: tx,
+                    htlc_script: self.htlc_script(h),
+                    amount: amount as u64,
+                }
+            })
+            .collect()
+    }
+
+    fn htlc_script(&self, h: &HTLC) -> Script {
+        match h.direction {
+            HTLCDirection::Accepted => accepted_htlc(&self.local_revocation_pubkey, &self.remote_htlc_pubkey, &self.local_htlc_pubkey, h.payment_hash, h.expiry as u32),
+            HTLCDirection::Offered => offered_htlc(&self.local_revocation_pubkey, &self.remote_htlc_pubkey, &self.local_htlc_pubkey, h.payment_hash),
+        }
+    }
+
+    // Returns the transaction and the pairs of the htlc index and its output index
+    fn build(&self) -> (Transaction, Vec<(usize, u32)>) {
         let sequence = get_sequence(self.obscured_commit_number);
         let locktime = get_locktime(self.obscured_commit_number);
 
@@ -70,28 +185,25 @@ impl CommitTx {
             lock_time: locktime as u32
         };
 
-        let mut weight: i64 = BASE_COMMITMENT_WEIGHT;
-        for h in &self.htlcs {
+        let mut untrimmed = vec![];
+        for (i, h) in self.htlcs.iter().enumerate() {
             if self.is_htlc_trimmed(h) {
                 continue
             }
-            weight += PER_HTLC_COMMITMENT_WEIGHT;
-            let lock_script = match h.direction {
-                HTLCDirection::Accepted => accepted_htlc(&self.local_revocation_pubkey, &self.remote_htlc_pubkey, &self.local_htlc_pubkey, h.payment_hash, h.expiry as u32),
-                HTLCDirection::Offered => offered_htlc(&self.local_revocation_pubkey, &self.remote_htlc_pubkey, &self.local_htlc_pubkey, h.payment_hash),
-            };
+            untrimmed.push(i);
             tx.output.push(TxOut{
                 value: (h.amount_msat / 1000) as u64,
-                script_pubkey: lock_script.to_v0_p2wsh(),
+                script_pubkey: self.htlc_script(h).to_v0_p2wsh(),
             })
         }
 
-        let base_fee = (weight * self.local_feerate_per_kw) / 1000;
-        // TODO(mkl): who pays it
         // TODO(mkl): what happens if it is negative
-        // Assume that local pays fee
-        let mut to_local = (self.to_local_msat / 1000) - base_fee;
-        let mut to_remote = self.to_remote_msat / 1000;
+        let base_fee = self.fee();
+        let (mut to_local, mut to_remote) = if self.local_pays_fee {
+            ((self.to_local_msat / 1000) - base_fee, self.to_remote_msat / 1000)
+        } else {
+            (self.to_local_msat / 1000, (self.to_remote_msat / 1000) - base_fee)
+        };
 
         if to_local < 0 {
             to_local = 0;
@@ -119,9 +231,31 @@ impl CommitTx {
             });
         }
 
-        bip69::reorder_tx(&mut tx);
+        let htlc_count = untrimmed.len();
+        let reordering = bip69::reorder_tx(&mut tx);
+
+        // The htlc outputs are first before the reordering. Identical outputs are interchangeable,
+        // but their second stage transactions are not, those are ordered by the expiry
+        let positions = reordering.outputs.iter()
+            .enumerate()
+            .filter(|&(_, &original)| (original as usize) < htlc_count)
+            .map(|(position, _)| position as u32);
+        untrimmed.sort_by(|&i, &j| {
+            let (hi, hj) = (&self.htlcs[i], &self.htlcs[j]);
+            let amount_ordering = (hi.amount_msat / 1000).cmp(&(hj.amount_msat / 1000));
+            let script_ordering = self.htlc_script(hi).to_v0_p2wsh().data().cmp(&self.htlc_script(hj).to_v0_p2wsh().data());
+            amount_ordering.then(script_ordering).then(hi.expiry.cmp(&hj.expiry))
+        });
+        let htlc_outputs = untrimmed.into_iter().zip(positions).collect();
+
+        return (tx, htlc_outputs);
+    }
 
-        return tx;
+    /// The fee of the transaction in satoshi, the trimmed htlcs do not add weight.
+    pub fn fee(&self) -> i64 {
+        let untrimmed = self.htlcs.iter().filter(|h| !self.is_htlc_trimmed(h)).count() as i64;
+        let weight = BASE_COMMITMENT_WEIGHT + untrimmed * PER_HTLC_COMMITMENT_WEIGHT;
+        return (weight * self.local_feerate_per_kw) / 1000;
     }
 
     fn htlc_timeout_fee(&self) -> i64 {
@@ -140,37 +274,47 @@ impl CommitTx {
         return (h.amount_msat / 1000) < required;
     }
 
+    fn sig_hash(&self, tx: &Transaction) -> Message {
+        funding_sig_hash(tx, &self.local_funding_pubkey, &self.remote_funding_pubkey, self.funding_amount as u64)
+    }
+
     pub fn sign(&self, priv_key: &SecretKey) -> Signature {
         let sec = Secp256k1::new();
         let tx = self.get_tx();
 
-        let funding_lock_script = new_2x2_multisig(
-            &self.local_funding_pubkey.serialize(),
-            &self.remote_funding_pubkey.serialize()
-        );
-        let tx_sig_hash = bip143::SighashComponents::new(&tx)
-            .sighash_all(
-                &tx.input[0],
-                &funding_lock_script,
-                self.funding_amount as u64
-            );
         // TODO(mkl): maybe do not use unwrap
         let sig = sec.sign(
-            &Message::from(tx_sig_hash.data()),
+            &self.sig_hash(&tx),
             priv_key
         ).unwrap();
         sig
     }
 
-    // TODO(mkl): add signature validation
+    // checks the signature of the counterparty by its funding key
+    pub fn verify(&self, remote_sig: &Signature) -> Result<(), Secp256k1Error> {
+        let sec = Secp256k1::new();
+        let tx = self.get_tx();
+        sec.verify(&self.sig_hash(&tx), remote_sig, &self.remote_funding_pubkey)
+    }
 
+    // The transaction ready to broadcast by the owner
+    pub fn signed_tx(&self, local_sig: &Signature, remote_sig: &Signature) -> Transaction {
+        let mut tx = self.get_tx();
+        tx.input[0].witness = spending_witness_2x2_multisig(
+            &self.local_funding_pubkey,
+            &self.remote_funding_pubkey,
+            local_sig,
+            remote_sig,
+        );
+        tx
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use spec_example::get_example;
-    use tools::{s2tx, assert_tx_eq, spending_witness_2x2_multisig};
-    use commit::CommitTx;
+    use tools::{s2tx, s2sig, assert_tx_eq, spending_witness_2x2_multisig, sha256};
+    use commit::{CommitTx, HTLC, HTLCDirection};
     use secp256k1::Secp256k1;
     use hex;
 
@@ -206,6 +350,8 @@ mod tests {
             funding_output_index: ex.funding_output_index as u32,
 
             htlcs: vec![],
+
+            local_pays_fee: true,
         };
 
         // Validate that transaction without witness is correct
@@ -263,6 +409,8 @@ mod tests {
             funding_output_index: ex.funding_output_index as u32,
 
             htlcs: vec![],
+
+            local_pays_fee: true,
         };
 
         for h in &ex.htlcs {
@@ -410,4 +558,337 @@ mod tests {
         let tx = commit_tx.get_tx();
         assert_tx_eq(&tx, &example_tx, true);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_verify_and_signed_tx() {
+        // name: commitment tx with all five HTLCs untrimmed (minimum feerate)
+        let example_tx = s2tx("02000000000101bef67e4e2fb9ddeeb3461973cd4c62abb35050b1add772995b820b584a488489000000000038b02b8007e80300000000000022002052bfef0479d7b293c27e0f1eb294bea154c63a3294ef092c19af51409bce0e2ad007000000000000220020403d394747cae42e98ff01734ad5c08f82ba123d3d9a620abda88989651e2ab5d007000000000000220020748eba944fedc8827f6b06bc44678f93c0f9e6078b35c6331ed31e75f8ce0c2db80b000000000000220020c20b5d1f8584fd90443e7b7b720136174fa4b9333c261d04dbbd012635c0f419a00f0000000000002200208c48d15160397c9731df9bc3b236656efb6665fbfe92b4a6878e88a499f741c4c0c62d0000000000160014ccf1af2f2aabee14bb40fa3851ab2301de843110e0a06a00000000002200204adb4e2f00643db396dd120d4e7dc17625f5f2c11a40d857accc862d6b7dd80e04004730440220275b0c325a5e9355650dc30c0eccfbc7efb23987c24b556b9dfdd40effca18d202206caceb2c067836c51f296740c7ae807ffcbfbf1dd3a0d56b6de9a5b247985f060147304402204fd4928835db1ccdfc40f5c78ce9bd65249b16348df81f0c44328dcdefc97d630220194d3869c38bc732dd87d13d2958015e2fc16829e74cd4377f84d215c0b7060601475221023da092f6980e58d2c037173180e9a465476026ee50f96695963e8efe436f54eb21030e9f7b623d2ccc7c9bd44d66d5ce21ce504c0acf6385a132cec6d3c39fa711c152ae3e195220");
+        let ex = get_example();
+        let commit_tx = get_base_commit_tx(0);
+
+        let remote_sig = s2sig("304402204fd4928835db1ccdfc40f5c78ce9bd65249b16348df81f0c44328dcdefc97d630220194d3869c38bc732dd87d13d2958015e2fc16829e74cd4377f84d215c0b70606");
+        assert!(commit_tx.verify(&remote_sig).is_ok());
+
+        // signed by the wrong key or for another transaction
+        let local_sig = commit_tx.sign(&ex.local_funding_privkey);
+        assert!(commit_tx.verify(&local_sig).is_err());
+        assert!(get_base_commit_tx(647).verify(&remote_sig).is_err());
+
+        let tx = commit_tx.signed_tx(&local_sig, &remote_sig);
+        assert_tx_eq(&tx, &example_tx, false);
+    }
+
+    // Checks the second stage transactions of the spec example, the expected values are
+    // the remote htlc signature, the local htlc signature and the signed transaction
+    fn assert_htlc_txs(commit_tx: &CommitTx, expected: &[(&str, &str, &str)]) {
+        let ex = get_example();
+        let ctx = Secp256k1::new();
+
+        let htlc_txs = commit_tx.get_htlc_txs();
+        assert_eq!(htlc_txs.len(), expected.len());
+        for (htlc_tx, &(remote_sig_hex, local_sig_hex, tx_hex)) in htlc_txs.iter().zip(expected.iter()) {
+            let remote_sig = htlc_tx.sign(&ex.internal.remote_privkey);
+            assert_eq!(hex::encode(remote_sig.serialize_der(&ctx)), remote_sig_hex);
+            assert!(htlc_tx.verify(&remote_sig, &ex.remotepubkey).is_ok());
+
+            let local_sig = htlc_tx.sign(&ex.local_privkey);
+            assert_eq!(hex::encode(local_sig.serialize_der(&ctx)), local_sig_hex);
+            assert!(htlc_tx.verify(&local_sig, &ex.localpubkey).is_ok());
+            assert!(htlc_tx.verify(&local_sig, &ex.remotepubkey).is_err());
+
+            let payment_preimage = match htlc_tx.direction {
+                HTLCDirection::Accepted => Some(ex.htlcs[htlc_tx.htlc_index].payment_preimage),
+                HTLCDirection::Offered => None,
+            };
+            let tx = htlc_tx.signed_tx(&local_sig, &remote_sig, payment_preimage);
+            assert_tx_eq(&tx, &s2tx(tx_hex), false);
+        }
+    }
+
+    #[test]
+    fn test_htlc_txs_with_all_five_htlcs_untrimmed_minimum_feerate() {
+        // name: commitment tx with all five HTLCs untrimmed (minimum feerate)
+        let commit_tx = get_base_commit_tx(0);
+        assert_htlc_txs(&commit_tx, &[
+            // htlc_success_tx (htlc #0)
+            (
+                "304402206a6e59f18764a5bf8d4fa45eebc591566689441229c918b480fb2af8cc6a4aeb02205248f273be447684b33e3c8d1d85a8e0ca9fa0bae9ae33f0527ada9c162919a6",
+                "304402207cb324fa0de88f452ffa9389678127ebcf4cabe1dd848b8e076c1a1962bf34720220116ed922b12311bd602d67e60d2529917f21c5b82f25ff6506c0f87886b4dfd5",
+                "020000000001018154ecccf11a5fb56c39654c4deb4d2296f83c69268280b94d021370c94e219700000000000000000001e8030000000000002200204adb4e2f00643db396dd120d4e7dc17625f5f2c11a40d857accc862d6b7dd80e050047304402206a6e59f18764a5bf8d4fa45eebc591566689441229c918b480fb2af8cc6a4aeb02205248f273be447684b33e3c8d1d85a8e0ca9fa0bae9ae33f0527ada9c162919a60147304402207cb324fa0de88f452ffa9389678127ebcf4cabe1dd848b8e076c1a1962bf34720220116ed922b12311bd602d67e60d2529917f21c5b82f25ff6506c0f87886b4dfd5012000000000000000000000000000000000000000000000000000000000000000008a76a91414011f7254d96b819c76986c277d115efce6f7b58763ac67210394854aa6eab5b2a8122cc726e9dded053a2184d88256816826d6231c068d4a5b7c8201208763a914b8bcb07f6344b42ab04250c86a6e8b75d3fdbbc688527c21030d417a46946384f88d5f3337267c5e579765875dc4daca813e21734b140639e752ae677502f401b175ac686800000000",
+            ),
+            // htlc_timeout_tx (htlc #2)
+            (
+                "3045022100d5275b3619953cb0c3b5aa577f04bc512380e60fa551762ce3d7a1bb7401cff9022037237ab0dac3fe100cde094e82e2bed9ba0ed1bb40154b48e56aa70f259e608b",
+                "3045022100c89172099507ff50f4c925e6c5150e871fb6e83dd73ff9fbb72f6ce829a9633f02203a63821d9162e99f9be712a68f9e589483994feae2661e4546cd5b6cec007be5",
+                "020000000001018154ecccf11a5fb56c39654c4deb4d2296f83c69268280b94d021370c94e219701000000000000000001d0070000000000002200204adb4e2f00643db396dd120d4e7dc17625f5f2c11a40d857accc862d6b7dd80e0500483045022100d5275b3619953cb0c3b5aa577f04bc512380e60fa551762ce3d7a1bb7401cff9022037237ab0dac3fe100cde094e82e2bed9ba0ed1bb40154b48e56aa70f259e608b01483045022100c89172099507ff50f4c925e6c5150e871fb6e83dd73ff9fbb72f6ce829a9633f02203a63821d9162e99f9be712a68f9e589483994feae2661e4546cd5b6cec007be501008576a91414011f7254d96b819c76986c277d115efce6f7b58763ac67210394854aa6eab5b2a8122cc726e9dded053a2184d88256816826d6231c068d4a5b7c820120876475527c21030d417a46946384f88d5f3337267c5e579765875dc4daca813e21734b140639e752ae67a914b43e1b38138a41b37f7cd9a1d274bc63e3a9b5d188ac6868f6010000",
+            ),
+            // htlc_success_tx (htlc #1)
+            (
+                "304402201b63ec807771baf4fdff523c644080de17f1da478989308ad13a58b51db91d360220568939d38c9ce295adba15665fa68f51d967e8ed14a007b751540a80b325f202",
+                "3045022100def389deab09cee69eaa1ec14d9428770e45bcbe9feb46468ecf481371165c2f022015d2e3c46600b2ebba8dcc899768874cc6851fd1ecb3fffd15db1cc3de7e10da",
+                "020000000001018154ecccf11a5fb56c39654c4deb4d2296f83c69268280b94d021370c94e219702000000000000000001d0070000000000002200204adb4e2f00643db396dd120d4e7dc17625f5f2c11a40d857accc862d6b7dd80e050047304402201b63ec807771baf4fdff523c644080de17f1da478989308ad13a58b51db91d360220568939d38c9ce295adba15665fa68f51d967e8ed14a007b751540a80b325f20201483045022100def389deab09cee69eaa1ec14d9428770e45bcbe9feb46468ecf481371165c2f022015d2e3c46600b2ebba8dcc899768874cc6851fd1ecb3fffd15db1cc3de7e10da012001010101010101010101010101010101010101010101010101010101010101018a76a91414011f7254d96b819c76986c277d115efce6f7b58763ac67210394854aa6eab5b2a8122cc726e9dded053a2184d88256816826d6231c068d4a5b7c8201208763a9144b6b2e5444c2639cc0fb7bcea5afba3f3cdce23988527c21030d417a46946384f88d5f3337267c5e579765875dc4daca813e21734b140639e752ae677502f501b175ac686800000000",
+            ),
+            // htlc_timeout_tx (htlc #3)
+            (
+                "3045022100daee1808f9861b6c3ecd14f7b707eca02dd6bdfc714ba2f33bc8cdba507bb182022026654bf8863af77d74f51f4e0b62d461a019561bb12acb120d3f7195d148a554",
+                "30440220643aacb19bbb72bd2b635bc3f7375481f5981bace78cdd8319b2988ffcc6704202203d27784ec8ad51ed3bd517a05525a5139bb0b755dd719e0054332d186ac08727",
+                "020000000001018154ecccf11a5fb56c39654c4deb4d2296f83c69268280b94d021370c94e219703000000000000000001b80b0000000000002200204adb4e2f00643db396dd120d4e7dc17625f5f2c11a40d857accc862d6b7dd80e0500483045022100daee1808f9861b6c3ecd14f7b707eca02dd6bdfc714ba2f33bc8cdba507bb182022026654bf8863af77d74f51f4e0b62d461a019561bb12acb120d3f7195d148a554014730440220643aacb19bbb72bd2b635bc3f7375481f5981bace78cdd8319b2988ffcc6704202203d27784ec8ad51ed3bd517a05525a5139bb0b755dd719e0054332d186ac0872701008576a91414011f7254d96b819c76986c277d115efce6f7b58763ac67210394854aa6eab5b2a8122cc726e9dded053a2184d88256816826d6231c068d4a5b7c820120876475527c21030d417a46946384f88d5f3337267c5e579765875dc4daca813e21734b140639e752ae67a9148a486ff2e31d6158bf39e2608864d63fefd09d5b88ac6868f7010000",
+            ),
+            // htlc_success_tx (htlc #4)
+            (
+                "304402207e0410e45454b0978a623f36a10626ef17b27d9ad44e2760f98cfa3efb37924f0220220bd8acd43ecaa916a80bd4f919c495a2c58982ce7c8625153f8596692a801d",
+                "30440220549e80b4496803cbc4a1d09d46df50109f546d43fbbf86cd90b174b1484acd5402205f12a4f995cb9bded597eabfee195a285986aa6d93ae5bb72507ebc6a4e2349e",
+                "020000000001018154ecccf11a5fb56c39654c4deb4d2296f83c69268280b94d021370c94e219704000000000000000001a00f0000000000002200204adb4e2f00643db396dd120d4e7dc17625f5f2c11a40d857accc862d6b7dd80e050047304402207e0410e45454b0978a623f36a10626ef17b27d9ad44e2760f98cfa3efb37924f0220220bd8acd43ecaa916a80bd4f919c495a2c58982ce7c8625153f8596692a801d014730440220549e80b4496803cbc4a1d09d46df50109f546d43fbbf86cd90b174b1484acd5402205f12a4f995cb9bded597eabfee195a285986aa6d93ae5bb72507ebc6a4e2349e012004040404040404040404040404040404040404040404040404040404040404048a76a91414011f7254d96b819c76986c277d115efce6f7b58763ac67210394854aa6eab5b2a8122cc726e9dded053a2184d88256816826d6231c068d4a5b7c8201208763a91418bc1a114ccf9c052d3d23e28d3b0a9d1227434288527c21030d417a46946384f88d5f3337267c5e579765875dc4daca813e21734b140639e752ae677502f801b175ac686800000000",
+            ),
+        ]);
+    }
+
+    #[test]
+    fn test_htlc_txs_with_seven_outputs_untrimmed_maximum_feerate() {
+        // name: commitment tx with seven outputs untrimmed (maximum feerate)
+        let commit_tx = get_base_commit_tx(647);
+        assert_htlc_txs(&commit_tx, &[
+            // htlc_success_tx (htlc #0)
+            (
+                "30440220385a5afe75632f50128cbb029ee95c80156b5b4744beddc729ad339c9ca432c802202ba5f48550cad3379ac75b9b4fedb86a35baa6947f16ba5037fb8b11ab343740",
+                "304402205999590b8a79fa346e003a68fd40366397119b2b0cdf37b149968d6bc6fbcc4702202b1e1fb5ab7864931caed4e732c359e0fe3d86a548b557be2246efb1708d579a",
+                "020000000001018323148ce2419f21ca3d6780053747715832e18ac780931a514b187768882bb60000000000000000000122020000000000002200204adb4e2f00643db396dd120d4e7dc17625f5f2c11a40d857accc862d6b7dd80e05004730440220385a5afe75632f50128cbb029ee95c80156b5b4744beddc729ad339c9ca432c802202ba5f48550cad3379ac75b9b4fedb86a35baa6947f16ba5037fb8b11ab3437400147304402205999590b8a79fa346e003a68fd40366397119b2b0cdf37b149968d6bc6fbcc4702202b1e1fb5ab7864931caed4e732c359e0fe3d86a548b557be2246efb1708d579a012000000000000000000000000000000000000000000000000000000000000000008a76a91414011f7254d96b819c76986c277d115efce6f7b58763ac67210394854aa6eab5b2a8122cc726e9dded053a2184d88256816826d6231c068d4a5b7c8201208763a914b8bcb07f6344b42ab04250c86a6e8b75d3fdbbc688527c21030d417a46946384f88d5f3337267c5e579765875dc4daca813e21734b140639e752ae677502f401b175ac686800000000",
+            ),
+            // htlc_timeout_tx (htlc #2)
+            (
+                "304402207ceb6678d4db33d2401fdc409959e57c16a6cb97a30261d9c61f29b8c58d34b90220084b4a17b4ca0e86f2d798b3698ca52de5621f2ce86f80bed79afa66874511b0",
+                "304402207ff03eb0127fc7c6cae49cc29e2a586b98d1e8969cf4a17dfa50b9c2647720b902205e2ecfda2252956c0ca32f175080e75e4e390e433feb1f8ce9f2ba55648a1dac",
+                "020000000001018323148ce2419f21ca3d6780053747715832e18ac780931a514b187768882bb60100000000000000000124060000000000002200204adb4e2f00643db396dd120d4e7dc17625f5f2c11a40d857accc862d6b7dd80e050047304402207ceb6678d4db33d2401fdc409959e57c16a6cb97a30261d9c61f29b8c58d34b90220084b4a17b4ca0e86f2d798b3698ca52de5621f2ce86f80bed79afa66874511b00147304402207ff03eb0127fc7c6cae49cc29e2a586b98d1e8969cf4a17dfa50b9c2647720b902205e2ecfda2252956c0ca32f175080e75e4e390e433feb1f8ce9f2ba55648a1dac01008576a91414011f7254d96b819c76986c277d115efce6f7b58763ac67210394854aa6eab5b2a8122cc726e9dded053a2184d88256816826d6231c068d4a5b7c820120876475527c21030d417a46946384f88d5f3337267c5e579765875dc4daca813e21734b140639e752ae67a914b43e1b38138a41b37f7cd9a1d274bc63e3a9b5d188ac6868f6010000",
+            ),
+            // htlc_success_tx (htlc #1)
+            (
+                "304402206a401b29a0dff0d18ec903502c13d83e7ec019450113f4a7655a4ce40d1f65ba0220217723a084e727b6ca0cc8b6c69c014a7e4a01fcdcba3e3993f462a3c574d833",
+                "3045022100d50d067ca625d54e62df533a8f9291736678d0b86c28a61bb2a80cf42e702d6e02202373dde7e00218eacdafb9415fe0e1071beec1857d1af3c6a201a44cbc47c877",
+                "020000000001018323148ce2419f21ca3d6780053747715832e18ac780931a514b187768882bb6020000000000000000010a060000000000002200204adb4e2f00643db396dd120d4e7dc17625f5f2c11a40d857accc862d6b7dd80e050047304402206a401b29a0dff0d18ec903502c13d83e7ec019450113f4a7655a4ce40d1f65ba0220217723a084e727b6ca0cc8b6c69c014a7e4a01fcdcba3e3993f462a3c574d83301483045022100d50d067ca625d54e62df533a8f9291736678d0b86c28a61bb2a80cf42e702d6e02202373dde7e00218eacdafb9415fe0e1071beec1857d1af3c6a201a44cbc47c877012001010101010101010101010101010101010101010101010101010101010101018a76a91414011f7254d96b819c76986c277d115efce6f7b58763ac67210394854aa6eab5b2a8122cc726e9dded053a2184d88256816826d6231c068d4a5b7c8201208763a9144b6b2e5444c2639cc0fb7bcea5afba3f3cdce23988527c21030d417a46946384f88d5f3337267c5e579765875dc4daca813e21734b140639e752ae677502f501b175ac686800000000",
+            ),
+            // htlc_timeout_tx (htlc #3)
+            (
+                "30450221009b1c987ba599ee3bde1dbca776b85481d70a78b681a8d84206723e2795c7cac002207aac84ad910f8598c4d1c0ea2e3399cf6627a4e3e90131315bc9f038451ce39d",
+                "3045022100db9dc65291077a52728c622987e9895b7241d4394d6dcb916d7600a3e8728c22022036ee3ee717ba0bb5c45ee84bc7bbf85c0f90f26ae4e4a25a6b4241afa8a3f1cb",
+                "020000000001018323148ce2419f21ca3d6780053747715832e18ac780931a514b187768882bb6030000000000000000010c0a0000000000002200204adb4e2f00643db396dd120d4e7dc17625f5f2c11a40d857accc862d6b7dd80e05004830450221009b1c987ba599ee3bde1dbca776b85481d70a78b681a8d84206723e2795c7cac002207aac84ad910f8598c4d1c0ea2e3399cf6627a4e3e90131315bc9f038451ce39d01483045022100db9dc65291077a52728c622987e9895b7241d4394d6dcb916d7600a3e8728c22022036ee3ee717ba0bb5c45ee84bc7bbf85c0f90f26ae4e4a25a6b4241afa8a3f1cb01008576a91414011f7254d96b819c76986c277d115efce6f7b58763ac67210394854aa6eab5b2a8122cc726e9dded053a2184d88256816826d6231c068d4a5b7c820120876475527c21030d417a46946384f88d5f3337267c5e579765875dc4daca813e21734b140639e752ae67a9148a486ff2e31d6158bf39e2608864d63fefd09d5b88ac6868f7010000",
+            ),
+            // htlc_success_tx (htlc #4)
+            (
+                "3045022100cc28030b59f0914f45b84caa983b6f8effa900c952310708c2b5b00781117022022027ba2ccdf94d03c6d48b327f183f6e28c8a214d089b9227f94ac4f85315274f0",
+                "304402202d1a3c0d31200265d2a2def2753ead4959ae20b4083e19553acfffa5dfab60bf022020ede134149504e15b88ab261a066de49848411e15e70f9e6a5462aec2949f8f",
+                "020000000001018323148ce2419f21ca3d6780053747715832e18ac780931a514b187768882bb604000000000000000001da0d0000000000002200204adb4e2f00643db396dd120d4e7dc17625f5f2c11a40d857accc862d6b7dd80e0500483045022100cc28030b59f0914f45b84caa983b6f8effa900c952310708c2b5b00781117022022027ba2ccdf94d03c6d48b327f183f6e28c8a214d089b9227f94ac4f85315274f00147304402202d1a3c0d31200265d2a2def2753ead4959ae20b4083e19553acfffa5dfab60bf022020ede134149504e15b88ab261a066de49848411e15e70f9e6a5462aec2949f8f012004040404040404040404040404040404040404040404040404040404040404048a76a91414011f7254d96b819c76986c277d115efce6f7b58763ac67210394854aa6eab5b2a8122cc726e9dded053a2184d88256816826d6231c068d4a5b7c8201208763a91418bc1a114ccf9c052d3d23e28d3b0a9d1227434288527c21030d417a46946384f88d5f3337267c5e579765875dc4daca813e21734b140639e752ae677502f801b175ac686800000000",
+            ),
+        ]);
+    }
+
+    #[test]
+    fn test_htlc_txs_with_six_outputs_untrimmed_minimum_feerate() {
+        // name: commitment tx with six outputs untrimmed (minimum feerate)
+        let commit_tx = get_base_commit_tx(648);
+        assert_htlc_txs(&commit_tx, &[
+            // htlc_timeout_tx (htlc #2)
+            (
+                "3044022062ef2e77591409d60d7817d9bb1e71d3c4a2931d1a6c7c8307422c84f001a251022022dad9726b0ae3fe92bda745a06f2c00f92342a186d84518588cf65f4dfaada8",
+                "3045022100a4c574f00411dd2f978ca5cdc1b848c311cd7849c087ad2f21a5bce5e8cc5ae90220090ae39a9bce2fb8bc879d7e9f9022df249f41e25e51f1a9bf6447a9eeffc098",
+                "02000000000101579c183eca9e8236a5d7f5dcd79cfec32c497fdc0ec61533cde99ecd436cadd10000000000000000000123060000000000002200204adb4e2f00643db396dd120d4e7dc17625f5f2c11a40d857accc862d6b7dd80e0500473044022062ef2e77591409d60d7817d9bb1e71d3c4a2931d1a6c7c8307422c84f001a251022022dad9726b0ae3fe92bda745a06f2c00f92342a186d84518588cf65f4dfaada801483045022100a4c574f00411dd2f978ca5cdc1b848c311cd7849c087ad2f21a5bce5e8cc5ae90220090ae39a9bce2fb8bc879d7e9f9022df249f41e25e51f1a9bf6447a9eeffc09801008576a91414011f7254d96b819c76986c277d115efce6f7b58763ac67210394854aa6eab5b2a8122cc726e9dded053a2184d88256816826d6231c068d4a5b7c820120876475527c21030d417a46946384f88d5f3337267c5e579765875dc4daca813e21734b140639e752ae67a914b43e1b38138a41b37f7cd9a1d274bc63e3a9b5d188ac6868f6010000",
+            ),
+            // htlc_success_tx (htlc #1)
+            (
+                "3045022100e968cbbb5f402ed389fdc7f6cd2a80ed650bb42c79aeb2a5678444af94f6c78502204b47a1cb24ab5b0b6fe69fe9cfc7dba07b9dd0d8b95f372c1d9435146a88f8d4",
+                "304402207679cf19790bea76a733d2fa0672bd43ab455687a068f815a3d237581f57139a0220683a1a799e102071c206b207735ca80f627ab83d6616b4bcd017c5d79ef3e7d0",
+                "02000000000101579c183eca9e8236a5d7f5dcd79cfec32c497fdc0ec61533cde99ecd436cadd10100000000000000000109060000000000002200204adb4e2f00643db396dd120d4e7dc17625f5f2c11a40d857accc862d6b7dd80e0500483045022100e968cbbb5f402ed389fdc7f6cd2a80ed650bb42c79aeb2a5678444af94f6c78502204b47a1cb24ab5b0b6fe69fe9cfc7dba07b9dd0d8b95f372c1d9435146a88f8d40147304402207679cf19790bea76a733d2fa0672bd43ab455687a068f815a3d237581f57139a0220683a1a799e102071c206b207735ca80f627ab83d6616b4bcd017c5d79ef3e7d0012001010101010101010101010101010101010101010101010101010101010101018a76a91414011f7254d96b819c76986c277d115efce6f7b58763ac67210394854aa6eab5b2a8122cc726e9dded053a2184d88256816826d6231c068d4a5b7c8201208763a9144b6b2e5444c2639cc0fb7bcea5afba3f3cdce23988527c21030d417a46946384f88d5f3337267c5e579765875dc4daca813e21734b140639e752ae677502f501b175ac686800000000",
+            ),
+            // htlc_timeout_tx (htlc #3)
+            (
+                "3045022100aa91932e305292cf9969cc23502bbf6cef83a5df39c95ad04a707c4f4fed5c7702207099fc0f3a9bfe1e7683c0e9aa5e76c5432eb20693bf4cb182f04d383dc9c8c2",
+                "304402200df76fea718745f3c529bac7fd37923e7309ce38b25c0781e4cf514dd9ef8dc802204172295739dbae9fe0474dcee3608e3433b4b2af3a2e6787108b02f894dcdda3",
+                "02000000000101579c183eca9e8236a5d7f5dcd79cfec32c497fdc0ec61533cde99ecd436cadd1020000000000000000010b0a0000000000002200204adb4e2f00643db396dd120d4e7dc17625f5f2c11a40d857accc862d6b7dd80e0500483045022100aa91932e305292cf9969cc23502bbf6cef83a5df39c95ad04a707c4f4fed5c7702207099fc0f3a9bfe1e7683c0e9aa5e76c5432eb20693bf4cb182f04d383dc9c8c20147304402200df76fea718745f3c529bac7fd37923e7309ce38b25c0781e4cf514dd9ef8dc802204172295739dbae9fe0474dcee3608e3433b4b2af3a2e6787108b02f894dcdda301008576a91414011f7254d96b819c76986c277d115efce6f7b58763ac67210394854aa6eab5b2a8122cc726e9dded053a2184d88256816826d6231c068d4a5b7c820120876475527c21030d417a46946384f88d5f3337267c5e579765875dc4daca813e21734b140639e752ae67a9148a486ff2e31d6158bf39e2608864d63fefd09d5b88ac6868f7010000",
+            ),
+            // htlc_success_tx (htlc #4)
+            (
+                "3044022035cac88040a5bba420b1c4257235d5015309113460bc33f2853cd81ca36e632402202fc94fd3e81e9d34a9d01782a0284f3044370d03d60f3fc041e2da088d2de58f",
+                "304402200daf2eb7afd355b4caf6fb08387b5f031940ea29d1a9f35071288a839c9039e4022067201b562456e7948616c13acb876b386b511599b58ac1d94d127f91c50463a6",
+                "02000000000101579c183eca9e8236a5d7f5dcd79cfec32c497fdc0ec61533cde99ecd436cadd103000000000000000001d90d0000000000002200204adb4e2f00643db396dd120d4e7dc17625f5f2c11a40d857accc862d6b7dd80e0500473044022035cac88040a5bba420b1c4257235d5015309113460bc33f2853cd81ca36e632402202fc94fd3e81e9d34a9d01782a0284f3044370d03d60f3fc041e2da088d2de58f0147304402200daf2eb7afd355b4caf6fb08387b5f031940ea29d1a9f35071288a839c9039e4022067201b562456e7948616c13acb876b386b511599b58ac1d94d127f91c50463a6012004040404040404040404040404040404040404040404040404040404040404048a76a91414011f7254d96b819c76986c277d115efce6f7b58763ac67210394854aa6eab5b2a8122cc726e9dded053a2184d88256816826d6231c068d4a5b7c8201208763a91418bc1a114ccf9c052d3d23e28d3b0a9d1227434288527c21030d417a46946384f88d5f3337267c5e579765875dc4daca813e21734b140639e752ae677502f801b175ac686800000000",
+            ),
+        ]);
+    }
+
+    #[test]
+    fn test_htlc_txs_with_six_outputs_untrimmed_maximum_feerate() {
+        // name: commitment tx with six outputs untrimmed (maximum feerate)
+        let commit_tx = get_base_commit_tx(2069);
+        assert_htlc_txs(&commit_tx, &[
+            // htlc_timeout_tx (htlc #2)
+            (
+                "3045022100d1cf354de41c1369336cf85b225ed033f1f8982a01be503668df756a7e668b66022001254144fb4d0eecc61908fccc3388891ba17c5d7a1a8c62bdd307e5a513f992",
+                "3044022056eb1af429660e45a1b0b66568cb8c4a3aa7e4c9c292d5d6c47f86ebf2c8838f022065c3ac4ebe980ca7a41148569be4ad8751b0a724a41405697ec55035dae66402",
+                "02000000000101ca94a9ad516ebc0c4bdd7b6254871babfa978d5accafb554214137d398bfcf6a0000000000000000000175020000000000002200204adb4e2f00643db396dd120d4e7dc17625f5f2c11a40d857accc862d6b7dd80e0500483045022100d1cf354de41c1369336cf85b225ed033f1f8982a01be503668df756a7e668b66022001254144fb4d0eecc61908fccc3388891ba17c5d7a1a8c62bdd307e5a513f99201473044022056eb1af429660e45a1b0b66568cb8c4a3aa7e4c9c292d5d6c47f86ebf2c8838f022065c3ac4ebe980ca7a41148569be4ad8751b0a724a41405697ec55035dae6640201008576a91414011f7254d96b819c76986c277d115efce6f7b58763ac67210394854aa6eab5b2a8122cc726e9dded053a2184d88256816826d6231c068d4a5b7c820120876475527c21030d417a46946384f88d5f3337267c5e579765875dc4daca813e21734b140639e752ae67a914b43e1b38138a41b37f7cd9a1d274bc63e3a9b5d188ac6868f6010000",
+            ),
+            // htlc_success_tx (htlc #1)
+            (
+                "3045022100d065569dcb94f090345402736385efeb8ea265131804beac06dd84d15dd2d6880220664feb0b4b2eb985fadb6ec7dc58c9334ea88ce599a9be760554a2d4b3b5d9f4",
+                "3045022100914bb232cd4b2690ee3d6cb8c3713c4ac9c4fb925323068d8b07f67c8541f8d9022057152f5f1615b793d2d45aac7518989ae4fe970f28b9b5c77504799d25433f7f",
+                "02000000000101ca94a9ad516ebc0c4bdd7b6254871babfa978d5accafb554214137d398bfcf6a0100000000000000000122020000000000002200204adb4e2f00643db396dd120d4e7dc17625f5f2c11a40d857accc862d6b7dd80e0500483045022100d065569dcb94f090345402736385efeb8ea265131804beac06dd84d15dd2d6880220664feb0b4b2eb985fadb6ec7dc58c9334ea88ce599a9be760554a2d4b3b5d9f401483045022100914bb232cd4b2690ee3d6cb8c3713c4ac9c4fb925323068d8b07f67c8541f8d9022057152f5f1615b793d2d45aac7518989ae4fe970f28b9b5c77504799d25433f7f012001010101010101010101010101010101010101010101010101010101010101018a76a91414011f7254d96b819c76986c277d115efce6f7b58763ac67210394854aa6eab5b2a8122cc726e9dded053a2184d88256816826d6231c068d4a5b7c8201208763a9144b6b2e5444c2639cc0fb7bcea5afba3f3cdce23988527c21030d417a46946384f88d5f3337267c5e579765875dc4daca813e21734b140639e752ae677502f501b175ac686800000000",
+            ),
+            // htlc_timeout_tx (htlc #3)
+            (
+                "3045022100d4e69d363de993684eae7b37853c40722a4c1b4a7b588ad7b5d8a9b5006137a102207a069c628170ee34be5612747051bdcc087466dbaa68d5756ea81c10155aef18",
+                "304402200e362443f7af830b419771e8e1614fc391db3a4eb799989abfc5ab26d6fcd032022039ab0cad1c14dfbe9446bf847965e56fe016e0cbcf719fd18c1bfbf53ecbd9f9",
+                "02000000000101ca94a9ad516ebc0c4bdd7b6254871babfa978d5accafb554214137d398bfcf6a020000000000000000015d060000000000002200204adb4e2f00643db396dd120d4e7dc17625f5f2c11a40d857accc862d6b7dd80e0500483045022100d4e69d363de993684eae7b37853c40722a4c1b4a7b588ad7b5d8a9b5006137a102207a069c628170ee34be5612747051bdcc087466dbaa68d5756ea81c10155aef180147304402200e362443f7af830b419771e8e1614fc391db3a4eb799989abfc5ab26d6fcd032022039ab0cad1c14dfbe9446bf847965e56fe016e0cbcf719fd18c1bfbf53ecbd9f901008576a91414011f7254d96b819c76986c277d115efce6f7b58763ac67210394854aa6eab5b2a8122cc726e9dded053a2184d88256816826d6231c068d4a5b7c820120876475527c21030d417a46946384f88d5f3337267c5e579765875dc4daca813e21734b140639e752ae67a9148a486ff2e31d6158bf39e2608864d63fefd09d5b88ac6868f7010000",
+            ),
+            // htlc_success_tx (htlc #4)
+            (
+                "30450221008ec888e36e4a4b3dc2ed6b823319855b2ae03006ca6ae0d9aa7e24bfc1d6f07102203b0f78885472a67ff4fe5916c0bb669487d659527509516fc3a08e87a2cc0a7c",
+                "304402202c3e14282b84b02705dfd00a6da396c9fe8a8bcb1d3fdb4b20a4feba09440e8b02202b058b39aa9b0c865b22095edcd9ff1f71bbfe20aa4993755e54d042755ed0d5",
+                "02000000000101ca94a9ad516ebc0c4bdd7b6254871babfa978d5accafb554214137d398bfcf6a03000000000000000001f2090000000000002200204adb4e2f00643db396dd120d4e7dc17625f5f2c11a40d857accc862d6b7dd80e05004830450221008ec888e36e4a4b3dc2ed6b823319855b2ae03006ca6ae0d9aa7e24bfc1d6f07102203b0f78885472a67ff4fe5916c0bb669487d659527509516fc3a08e87a2cc0a7c0147304402202c3e14282b84b02705dfd00a6da396c9fe8a8bcb1d3fdb4b20a4feba09440e8b02202b058b39aa9b0c865b22095edcd9ff1f71bbfe20aa4993755e54d042755ed0d5012004040404040404040404040404040404040404040404040404040404040404048a76a91414011f7254d96b819c76986c277d115efce6f7b58763ac67210394854aa6eab5b2a8122cc726e9dded053a2184d88256816826d6231c068d4a5b7c8201208763a91418bc1a114ccf9c052d3d23e28d3b0a9d1227434288527c21030d417a46946384f88d5f3337267c5e579765875dc4daca813e21734b140639e752ae677502f801b175ac686800000000",
+            ),
+        ]);
+    }
+
+    #[test]
+    fn test_htlc_txs_with_five_outputs_untrimmed_minimum_feerate() {
+        // name: commitment tx with five outputs untrimmed (minimum feerate)
+        let commit_tx = get_base_commit_tx(2070);
+        assert_htlc_txs(&commit_tx, &[
+            // htlc_timeout_tx (htlc #2)
+            (
+                "3045022100eed143b1ee4bed5dc3cde40afa5db3e7354cbf9c44054b5f713f729356f08cf7022077161d171c2bbd9badf3c9934de65a4918de03bbac1450f715275f75b103f891",
+                "3045022100a0d043ed533e7fb1911e0553d31a8e2f3e6de19dbc035257f29d747c5e02f1f5022030cd38d8e84282175d49c1ebe0470db3ebd59768cf40780a784e248a43904fb8",
+                "0200000000010140a83ce364747ff277f4d7595d8d15f708418798922c40bc2b056aca5485a2180000000000000000000174020000000000002200204adb4e2f00643db396dd120d4e7dc17625f5f2c11a40d857accc862d6b7dd80e0500483045022100eed143b1ee4bed5dc3cde40afa5db3e7354cbf9c44054b5f713f729356f08cf7022077161d171c2bbd9badf3c9934de65a4918de03bbac1450f715275f75b103f89101483045022100a0d043ed533e7fb1911e0553d31a8e2f3e6de19dbc035257f29d747c5e02f1f5022030cd38d8e84282175d49c1ebe0470db3ebd59768cf40780a784e248a43904fb801008576a91414011f7254d96b819c76986c277d115efce6f7b58763ac67210394854aa6eab5b2a8122cc726e9dded053a2184d88256816826d6231c068d4a5b7c820120876475527c21030d417a46946384f88d5f3337267c5e579765875dc4daca813e21734b140639e752ae67a914b43e1b38138a41b37f7cd9a1d274bc63e3a9b5d188ac6868f6010000",
+            ),
+            // htlc_timeout_tx (htlc #3)
+            (
+                "3044022071e9357619fd8d29a411dc053b326a5224c5d11268070e88ecb981b174747c7a02202b763ae29a9d0732fa8836dd8597439460b50472183f420021b768981b4f7cf6",
+                "3045022100adb1d679f65f96178b59f23ed37d3b70443118f345224a07ecb043eee2acc157022034d24524fe857144a3bcfff3065a9994d0a6ec5f11c681e49431d573e242612d",
+                "0200000000010140a83ce364747ff277f4d7595d8d15f708418798922c40bc2b056aca5485a218010000000000000000015c060000000000002200204adb4e2f00643db396dd120d4e7dc17625f5f2c11a40d857accc862d6b7dd80e0500473044022071e9357619fd8d29a411dc053b326a5224c5d11268070e88ecb981b174747c7a02202b763ae29a9d0732fa8836dd8597439460b50472183f420021b768981b4f7cf601483045022100adb1d679f65f96178b59f23ed37d3b70443118f345224a07ecb043eee2acc157022034d24524fe857144a3bcfff3065a9994d0a6ec5f11c681e49431d573e242612d01008576a91414011f7254d96b819c76986c277d115efce6f7b58763ac67210394854aa6eab5b2a8122cc726e9dded053a2184d88256816826d6231c068d4a5b7c820120876475527c21030d417a46946384f88d5f3337267c5e579765875dc4daca813e21734b140639e752ae67a9148a486ff2e31d6158bf39e2608864d63fefd09d5b88ac6868f7010000",
+            ),
+            // htlc_success_tx (htlc #4)
+            (
+                "3045022100c9458a4d2cbb741705577deb0a890e5cb90ee141be0400d3162e533727c9cb2102206edcf765c5dc5e5f9b976ea8149bf8607b5a0efb30691138e1231302b640d2a4",
+                "304402200831422aa4e1ee6d55e0b894201770a8f8817a189356f2d70be76633ffa6a6f602200dd1b84a4855dc6727dd46c98daae43dfc70889d1ba7ef0087529a57c06e5e04",
+                "0200000000010140a83ce364747ff277f4d7595d8d15f708418798922c40bc2b056aca5485a21802000000000000000001f1090000000000002200204adb4e2f00643db396dd120d4e7dc17625f5f2c11a40d857accc862d6b7dd80e0500483045022100c9458a4d2cbb741705577deb0a890e5cb90ee141be0400d3162e533727c9cb2102206edcf765c5dc5e5f9b976ea8149bf8607b5a0efb30691138e1231302b640d2a40147304402200831422aa4e1ee6d55e0b894201770a8f8817a189356f2d70be76633ffa6a6f602200dd1b84a4855dc6727dd46c98daae43dfc70889d1ba7ef0087529a57c06e5e04012004040404040404040404040404040404040404040404040404040404040404048a76a91414011f7254d96b819c76986c277d115efce6f7b58763ac67210394854aa6eab5b2a8122cc726e9dded053a2184d88256816826d6231c068d4a5b7c8201208763a91418bc1a114ccf9c052d3d23e28d3b0a9d1227434288527c21030d417a46946384f88d5f3337267c5e579765875dc4daca813e21734b140639e752ae677502f801b175ac686800000000",
+            ),
+        ]);
+    }
+
+    #[test]
+    fn test_htlc_txs_with_five_outputs_untrimmed_maximum_feerate() {
+        // name: commitment tx with five outputs untrimmed (maximum feerate)
+        let commit_tx = get_base_commit_tx(2194);
+        assert_htlc_txs(&commit_tx, &[
+            // htlc_timeout_tx (htlc #2)
+            (
+                "30450221009ed2f0a67f99e29c3c8cf45c08207b765980697781bb727fe0b1416de0e7622902206052684229bc171419ed290f4b615c943f819c0262414e43c5b91dcf72ddcf44",
+                "3044022004ad5f04ae69c71b3b141d4db9d0d4c38d84009fb3cfeeae6efdad414487a9a0022042d3fe1388c1ff517d1da7fb4025663d372c14728ed52dc88608363450ff6a2f",
+                "02000000000101fb824d4e4dafc0f567789dee3a6bce8d411fe80f5563d8cdfdcc7d7e4447d43a0000000000000000000122020000000000002200204adb4e2f00643db396dd120d4e7dc17625f5f2c11a40d857accc862d6b7dd80e05004830450221009ed2f0a67f99e29c3c8cf45c08207b765980697781bb727fe0b1416de0e7622902206052684229bc171419ed290f4b615c943f819c0262414e43c5b91dcf72ddcf4401473044022004ad5f04ae69c71b3b141d4db9d0d4c38d84009fb3cfeeae6efdad414487a9a0022042d3fe1388c1ff517d1da7fb4025663d372c14728ed52dc88608363450ff6a2f01008576a91414011f7254d96b819c76986c277d115efce6f7b58763ac67210394854aa6eab5b2a8122cc726e9dded053a2184d88256816826d6231c068d4a5b7c820120876475527c21030d417a46946384f88d5f3337267c5e579765875dc4daca813e21734b140639e752ae67a914b43e1b38138a41b37f7cd9a1d274bc63e3a9b5d188ac6868f6010000",
+            ),
+            // htlc_timeout_tx (htlc #3)
+            (
+                "30440220155d3b90c67c33a8321996a9be5b82431b0c126613be751d400669da9d5c696702204318448bcd48824439d2c6a70be6e5747446be47ff45977cf41672bdc9b6b12d",
+                "304402201707050c870c1f77cc3ed58d6d71bf281de239e9eabd8ef0955bad0d7fe38dcc02204d36d80d0019b3a71e646a08fa4a5607761d341ae8be371946ebe437c289c915",
+                "02000000000101fb824d4e4dafc0f567789dee3a6bce8d411fe80f5563d8cdfdcc7d7e4447d43a010000000000000000010a060000000000002200204adb4e2f00643db396dd120d4e7dc17625f5f2c11a40d857accc862d6b7dd80e05004730440220155d3b90c67c33a8321996a9be5b82431b0c126613be751d400669da9d5c696702204318448bcd48824439d2c6a70be6e5747446be47ff45977cf41672bdc9b6b12d0147304402201707050c870c1f77cc3ed58d6d71bf281de239e9eabd8ef0955bad0d7fe38dcc02204d36d80d0019b3a71e646a08fa4a5607761d341ae8be371946ebe437c289c91501008576a91414011f7254d96b819c76986c277d115efce6f7b58763ac67210394854aa6eab5b2a8122cc726e9dded053a2184d88256816826d6231c068d4a5b7c820120876475527c21030d417a46946384f88d5f3337267c5e579765875dc4daca813e21734b140639e752ae67a9148a486ff2e31d6158bf39e2608864d63fefd09d5b88ac6868f7010000",
+            ),
+            // htlc_success_tx (htlc #4)
+            (
+                "3045022100a12a9a473ece548584aabdd051779025a5ed4077c4b7aa376ec7a0b1645e5a48022039490b333f53b5b3e2ddde1d809e492cba2b3e5fc3a436cd3ffb4cd3d500fa5a",
+                "3045022100ff200bc934ab26ce9a559e998ceb0aee53bc40368e114ab9d3054d9960546e2802202496856ca163ac12c143110b6b3ac9d598df7254f2e17b3b94c3ab5301f4c3b0",
+                "02000000000101fb824d4e4dafc0f567789dee3a6bce8d411fe80f5563d8cdfdcc7d7e4447d43a020000000000000000019a090000000000002200204adb4e2f00643db396dd120d4e7dc17625f5f2c11a40d857accc862d6b7dd80e0500483045022100a12a9a473ece548584aabdd051779025a5ed4077c4b7aa376ec7a0b1645e5a48022039490b333f53b5b3e2ddde1d809e492cba2b3e5fc3a436cd3ffb4cd3d500fa5a01483045022100ff200bc934ab26ce9a559e998ceb0aee53bc40368e114ab9d3054d9960546e2802202496856ca163ac12c143110b6b3ac9d598df7254f2e17b3b94c3ab5301f4c3b0012004040404040404040404040404040404040404040404040404040404040404048a76a91414011f7254d96b819c76986c277d115efce6f7b58763ac67210394854aa6eab5b2a8122cc726e9dded053a2184d88256816826d6231c068d4a5b7c8201208763a91418bc1a114ccf9c052d3d23e28d3b0a9d1227434288527c21030d417a46946384f88d5f3337267c5e579765875dc4daca813e21734b140639e752ae677502f801b175ac686800000000",
+            ),
+        ]);
+    }
+
+    #[test]
+    fn test_htlc_txs_with_four_outputs_untrimmed_minimum_feerate() {
+        // name: commitment tx with four outputs untrimmed (minimum feerate)
+        let commit_tx = get_base_commit_tx(2195);
+        assert_htlc_txs(&commit_tx, &[
+            // htlc_timeout_tx (htlc #3)
+            (
+                "3045022100a8a78fa1016a5c5c3704f2e8908715a3cef66723fb95f3132ec4d2d05cd84fb4022025ac49287b0861ec21932405f5600cbce94313dbde0e6c5d5af1b3366d8afbfc",
+                "3045022100be6ae1977fd7b630a53623f3f25c542317ccfc2b971782802a4f1ef538eb22b402207edc4d0408f8f38fd3c7365d1cfc26511b7cd2d4fecd8b005fba3cd5bc704390",
+                "020000000001014e16c488fa158431c1a82e8f661240ec0a71ba0ce92f2721a6538c510226ad5c0000000000000000000109060000000000002200204adb4e2f00643db396dd120d4e7dc17625f5f2c11a40d857accc862d6b7dd80e0500483045022100a8a78fa1016a5c5c3704f2e8908715a3cef66723fb95f3132ec4d2d05cd84fb4022025ac49287b0861ec21932405f5600cbce94313dbde0e6c5d5af1b3366d8afbfc01483045022100be6ae1977fd7b630a53623f3f25c542317ccfc2b971782802a4f1ef538eb22b402207edc4d0408f8f38fd3c7365d1cfc26511b7cd2d4fecd8b005fba3cd5bc70439001008576a91414011f7254d96b819c76986c277d115efce6f7b58763ac67210394854aa6eab5b2a8122cc726e9dded053a2184d88256816826d6231c068d4a5b7c820120876475527c21030d417a46946384f88d5f3337267c5e579765875dc4daca813e21734b140639e752ae67a9148a486ff2e31d6158bf39e2608864d63fefd09d5b88ac6868f7010000",
+            ),
+            // htlc_success_tx (htlc #4)
+            (
+                "3045022100e769cb156aa2f7515d126cef7a69968629620ce82afcaa9e210969de6850df4602200b16b3f3486a229a48aadde520dbee31ae340dbadaffae74fbb56681fef27b92",
+                "30440220665b9cb4a978c09d1ca8977a534999bc8a49da624d0c5439451dd69cde1a003d022070eae0620f01f3c1bd029cc1488da13fb40fdab76f396ccd335479a11c5276d8",
+                "020000000001014e16c488fa158431c1a82e8f661240ec0a71ba0ce92f2721a6538c510226ad5c0100000000000000000199090000000000002200204adb4e2f00643db396dd120d4e7dc17625f5f2c11a40d857accc862d6b7dd80e0500483045022100e769cb156aa2f7515d126cef7a69968629620ce82afcaa9e210969de6850df4602200b16b3f3486a229a48aadde520dbee31ae340dbadaffae74fbb56681fef27b92014730440220665b9cb4a978c09d1ca8977a534999bc8a49da624d0c5439451dd69cde1a003d022070eae0620f01f3c1bd029cc1488da13fb40fdab76f396ccd335479a11c5276d8012004040404040404040404040404040404040404040404040404040404040404048a76a91414011f7254d96b819c76986c277d115efce6f7b58763ac67210394854aa6eab5b2a8122cc726e9dded053a2184d88256816826d6231c068d4a5b7c8201208763a91418bc1a114ccf9c052d3d23e28d3b0a9d1227434288527c21030d417a46946384f88d5f3337267c5e579765875dc4daca813e21734b140639e752ae677502f801b175ac686800000000",
+            ),
+        ]);
+    }
+
+    #[test]
+    fn test_htlc_txs_with_four_outputs_untrimmed_maximum_feerate() {
+        // name: commitment tx with four outputs untrimmed (maximum feerate)
+        let commit_tx = get_base_commit_tx(3702);
+        assert_htlc_txs(&commit_tx, &[
+            // htlc_timeout_tx (htlc #3)
+            (
+                "3045022100dfb73b4fe961b31a859b2bb1f4f15cabab9265016dd0272323dc6a9e85885c54022059a7b87c02861ee70662907f25ce11597d7b68d3399443a831ae40e777b76bdb",
+                "304402202765b9c9ece4f127fa5407faf66da4c5ce2719cdbe47cd3175fc7d48b482e43d02205605125925e07bad1e41c618a4b434d72c88a164981c4b8af5eaf4ee9142ec3a",
+                "02000000000101b8de11eb51c22498fe39722c7227b6e55ff1a94146cf638458cb9bc6a060d3a30000000000000000000122020000000000002200204adb4e2f00643db396dd120d4e7dc17625f5f2c11a40d857accc862d6b7dd80e0500483045022100dfb73b4fe961b31a859b2bb1f4f15cabab9265016dd0272323dc6a9e85885c54022059a7b87c02861ee70662907f25ce11597d7b68d3399443a831ae40e777b76bdb0147304402202765b9c9ece4f127fa5407faf66da4c5ce2719cdbe47cd3175fc7d48b482e43d02205605125925e07bad1e41c618a4b434d72c88a164981c4b8af5eaf4ee9142ec3a01008576a91414011f7254d96b819c76986c277d115efce6f7b58763ac67210394854aa6eab5b2a8122cc726e9dded053a2184d88256816826d6231c068d4a5b7c820120876475527c21030d417a46946384f88d5f3337267c5e579765875dc4daca813e21734b140639e752ae67a9148a486ff2e31d6158bf39e2608864d63fefd09d5b88ac6868f7010000",
+            ),
+            // htlc_success_tx (htlc #4)
+            (
+                "3045022100ea9dc2a7c3c3640334dab733bb4e036e32a3106dc707b24227874fa4f7da746802204d672f7ac0fe765931a8df10b81e53a3242dd32bd9dc9331eb4a596da87954e9",
+                "30440220048a41c660c4841693de037d00a407810389f4574b3286afb7bc392a438fa3f802200401d71fa87c64fe621b49ac07e3bf85157ac680acb977124da28652cc7f1a5c",
+                "02000000000101b8de11eb51c22498fe39722c7227b6e55ff1a94146cf638458cb9bc6a060d3a30100000000000000000176050000000000002200204adb4e2f00643db396dd120d4e7dc17625f5f2c11a40d857accc862d6b7dd80e0500483045022100ea9dc2a7c3c3640334dab733bb4e036e32a3106dc707b24227874fa4f7da746802204d672f7ac0fe765931a8df10b81e53a3242dd32bd9dc9331eb4a596da87954e9014730440220048a41c660c4841693de037d00a407810389f4574b3286afb7bc392a438fa3f802200401d71fa87c64fe621b49ac07e3bf85157ac680acb977124da28652cc7f1a5c012004040404040404040404040404040404040404040404040404040404040404048a76a91414011f7254d96b819c76986c277d115efce6f7b58763ac67210394854aa6eab5b2a8122cc726e9dded053a2184d88256816826d6231c068d4a5b7c8201208763a91418bc1a114ccf9c052d3d23e28d3b0a9d1227434288527c21030d417a46946384f88d5f3337267c5e579765875dc4daca813e21734b140639e752ae677502f801b175ac686800000000",
+            ),
+        ]);
+    }
+
+    #[test]
+    fn test_htlc_txs_with_three_outputs_untrimmed_minimum_feerate() {
+        // name: commitment tx with three outputs untrimmed (minimum feerate)
+        let commit_tx = get_base_commit_tx(3703);
+        assert_htlc_txs(&commit_tx, &[
+            // htlc_success_tx (htlc #4)
+            (
+                "3044022044f65cf833afdcb9d18795ca93f7230005777662539815b8a601eeb3e57129a902206a4bf3e53392affbba52640627defa8dc8af61c958c9e827b2798ab45828abdd",
+                "3045022100b94d931a811b32eeb885c28ddcf999ae1981893b21dd1329929543fe87ce793002206370107fdd151c5f2384f9ceb71b3107c69c74c8ed5a28a94a4ab2d27d3b0724",
+                "020000000001011c076aa7fb3d7460d10df69432c904227ea84bbf3134d4ceee5fb0f135ef206d0000000000000000000175050000000000002200204adb4e2f00643db396dd120d4e7dc17625f5f2c11a40d857accc862d6b7dd80e0500473044022044f65cf833afdcb9d18795ca93f7230005777662539815b8a601eeb3e57129a902206a4bf3e53392affbba52640627defa8dc8af61c958c9e827b2798ab45828abdd01483045022100b94d931a811b32eeb885c28ddcf999ae1981893b21dd1329929543fe87ce793002206370107fdd151c5f2384f9ceb71b3107c69c74c8ed5a28a94a4ab2d27d3b0724012004040404040404040404040404040404040404040404040404040404040404048a76a91414011f7254d96b819c76986c277d115efce6f7b58763ac67210394854aa6eab5b2a8122cc726e9dded053a2184d88256816826d6231c068d4a5b7c8201208763a91418bc1a114ccf9c052d3d23e28d3b0a9d1227434288527c21030d417a46946384f88d5f3337267c5e579765875dc4daca813e21734b140639e752ae677502f801b175ac686800000000",
+            ),
+        ]);
+    }
+
+    #[test]
+    fn test_htlc_txs_with_three_outputs_untrimmed_maximum_feerate() {
+        // name: commitment tx with three outputs untrimmed (maximum feerate)
+        let commit_tx = get_base_commit_tx(4914);
+        assert_htlc_txs(&commit_tx, &[
+            // htlc_success_tx (htlc #4)
+            (
+                "3045022100fcb38506bfa11c02874092a843d0cc0a8613c23b639832564a5f69020cb0f6ba02206508b9e91eaa001425c190c68ee5f887e1ad5b1b314002e74db9dbd9e42dbecf",
+                "304502210086e76b460ddd3cea10525fba298405d3fe11383e56966a5091811368362f689a02200f72ee75657915e0ede89c28709acd113ede9e1b7be520e3bc5cda425ecd6e68",
+                "0200000000010110a3fdcbcd5db477cd3ad465e7f501ffa8c437e8301f00a6061138590add757f0000000000000000000122020000000000002200204adb4e2f00643db396dd120d4e7dc17625f5f2c11a40d857accc862d6b7dd80e0500483045022100fcb38506bfa11c02874092a843d0cc0a8613c23b639832564a5f69020cb0f6ba02206508b9e91eaa001425c190c68ee5f887e1ad5b1b314002e74db9dbd9e42dbecf0148304502210086e76b460ddd3cea10525fba298405d3fe11383e56966a5091811368362f689a02200f72ee75657915e0ede89c28709acd113ede9e1b7be520e3bc5cda425ecd6e68012004040404040404040404040404040404040404040404040404040404040404048a76a91414011f7254d96b819c76986c277d115efce6f7b58763ac67210394854aa6eab5b2a8122cc726e9dded053a2184d88256816826d6231c068d4a5b7c8201208763a91418bc1a114ccf9c052d3d23e28d3b0a9d1227434288527c21030d417a46946384f88d5f3337267c5e579765875dc4daca813e21734b140639e752ae677502f801b175ac686800000000",
+            ),
+        ]);
+    }
+
+    #[test]
+    fn test_htlc_txs_with_identical_offered_htlcs() {
+        // two offered htlcs with the same amount and preimage,
+        // their outputs are identical, but the HTLC-timeout transactions are ordered by the expiry
+        let mut commit_tx = get_base_commit_tx(253);
+        let payment_hash = sha256(&[5; 32]);
+        commit_tx.htlcs = vec![
+            HTLC{ direction: HTLCDirection::Accepted, amount_msat: 2000000, expiry: 501, payment_hash: sha256(&[1; 32]) },
+            HTLC{ direction: HTLCDirection::Offered, amount_msat: 5000000, expiry: 506, payment_hash: payment_hash },
+            HTLC{ direction: HTLCDirection::Offered, amount_msat: 5000000, expiry: 505, payment_hash: payment_hash },
+        ];
+
+        let tx = commit_tx.get_tx();
+        let htlc_txs = commit_tx.get_htlc_txs();
+        assert_eq!(htlc_txs.len(), 3);
+        assert_eq!(htlc_txs.iter().map(|h| h.htlc_index).collect::<Vec<_>>(), vec![0, 2, 1]);
+        assert_eq!(htlc_txs.iter().map(|h| h.tx.lock_time).collect::<Vec<_>>(), vec![0, 505, 506]);
+        for (i, htlc_tx) in htlc_txs.iter().enumerate() {
+            let output = htlc_tx.tx.input[0].prev_index as usize;
+            assert_eq!(tx.output[output].script_pubkey, htlc_tx.htlc_script.to_v0_p2wsh());
+            assert_eq!(tx.output[output].value, htlc_tx.amount);
+            if i > 0 {
+                assert!(htlc_txs[i - 1].tx.input[0].prev_index < htlc_tx.tx.input[0].prev_index);
+            }
+        }
+    }
+}
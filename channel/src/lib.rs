@@ -2,9 +2,16 @@ extern crate bitcoin;
 extern crate hex;
 extern crate secp256k1;
 extern crate crypto;
+extern crate wire;
+extern crate shachain;
+
+#[cfg(test)]
+extern crate rand;
 
 pub mod bip69;
 pub mod tools;
 pub mod commit;
 pub mod spec_example;
-pub mod derivation;
\ No newline at end of file
+pub mod derivation;
+pub mod state;
+pub mod close;
@@ -0,0 +1,2062 @@
+use secp256k1::{Secp256k1, SecretKey, PublicKey, Signature};
+use secp256k1::Error as Secp256k1Error;
+use bitcoin::util::hash::Sha256dHash;
+use bitcoin::blockdata::transaction::Transaction;
+use bitcoin::blockdata::script::Script;
+
+use wire::{
+    Message, ChannelId, HtlcId, MilliSatoshi, Satoshi, SatoshiPerKiloWeight, CsvDelay, Hash256,
+    OnionBlob, OpaqueReason, FailureCode, ChannelKeys, ChannelPrivateKeys, OpenChannel, AcceptChannel,
+    FundingCreated, FundingSigned, FundingLocked, UpdateAddHtlc, UpdateFulfillHtlc, UpdateFailHtlc,
+    UpdateFailMalformedHtlc, UpdateFee, CommitmentSigned, RevokeAndAck, ShutdownChannel, ClosingNegotiation,
+    FeeRange, ReestablishChannel,
+};
+use wire::PublicKey as LpdPublicKey;
+use wire::Signature as LpdSignature;
+
+use shachain::{Sha256Hash, LeafIndex};
+use shachain::producer_tree::ProducerTree;
+use shachain::store_tree::StoreTree;
+
+use commit::{CommitTx, HTLC, HTLCDirection};
+use close::{ClosingTx, is_valid_shutdown_script};
+use derivation::{derive_pubkey, derive_privkey, derive_revocation_pubkey};
+use tools::{get_obscuring_number, sha256};
+
+use std::collections::VecDeque;
+use std::{cmp, mem, fmt, error};
+
+// MAX_CLTV_EXPIRY is the least `cltv_expiry` that is rejected,
+// the greater values are timestamps rather than block heights.
+const MAX_CLTV_EXPIRY: u32 = 500000000;
+
+// FEERATE_FLOOR is the least feerate of the commitment, the transaction
+// paying less does not propagate.
+const FEERATE_FLOOR: u32 = 253;
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum Side {
+    Local,
+    Remote,
+}
+
+impl Side {
+    fn other(self) -> Self {
+        match self {
+            Side::Local => Side::Remote,
+            Side::Remote => Side::Local,
+        }
+    }
+}
+
+/// The limits the node announces in `open_channel` or `accept_channel`,
+/// the counterparty must respect them.
+#[derive(Clone, Debug)]
+pub struct ChannelParameters {
+    // the commitment transaction of the node does not have outputs below it
+    pub dust_limit: Satoshi,
+    // the total of the htlcs offered to the node
+    pub max_htlc_value_in_flight: MilliSatoshi,
+    // the counterparty must keep it on its side
+    pub channel_reserve: Satoshi,
+    pub htlc_minimum: MilliSatoshi,
+    // the delay of the `to_local` output of the counterparty
+    pub csv_delay: CsvDelay,
+    // the count of the htlcs offered to the node
+    pub max_accepted_htlc_number: u16,
+}
+
+impl ChannelParameters {
+    pub fn open(open_channel: &OpenChannel) -> Self {
+        ChannelParameters {
+            dust_limit: open_channel.dust_limit,
+            max_htlc_value_in_flight: open_channel.max_in_flight,
+            channel_reserve: open_channel.channel_reserve,
+            htlc_minimum: open_channel.htlc_minimum,
+            csv_delay: open_channel.csv_delay,
+            max_accepted_htlc_number: open_channel.max_accepted_htlc_number,
+        }
+    }
+
+    pub fn accept(accept_channel: &AcceptChannel) -> Self {
+        ChannelParameters {
+            dust_limit: accept_channel.dust_limit,
+            max_htlc_value_in_flight: accept_channel.max_htlc_value_in_flight,
+            channel_reserve: accept_channel.chanel_reserve,
+            htlc_minimum: accept_channel.htlc_minimum,
+            csv_delay: accept_channel.csv_delay,
+            max_accepted_htlc_number: accept_channel.max_accepted_htlc_number,
+        }
+    }
+}
+
+/// Everything agreed during the channel establishment.
+pub struct ChannelSetup {
+    pub funder: Side,
+    pub funding: Satoshi,
+    pub push: MilliSatoshi,
+    pub feerate: SatoshiPerKiloWeight,
+    pub funding_txid: [u8; 32],
+    pub funding_output_index: u16,
+    pub local_parameters: ChannelParameters,
+    pub remote_parameters: ChannelParameters,
+    // the first per commitment secret is ignored, the secrets are derived from the seed
+    pub local_keys: ChannelPrivateKeys,
+    pub local_seed: [u8; 32],
+    pub remote_keys: ChannelKeys,
+}
+
+impl ChannelSetup {
+    /// The node has received `open_channel`, answered `accept_channel`
+    /// and received `funding_created`.
+    pub fn fundee(
+        open_channel: &OpenChannel,
+        accept_channel: &AcceptChannel,
+        funding_created: &FundingCreated,
+        local_keys: ChannelPrivateKeys,
+        local_seed: [u8; 32],
+    ) -> Self {
+        ChannelSetup {
+            funder: Side::Remote,
+            funding: open_channel.funding,
+            push: open_channel.push,
+            feerate: open_channel.fee,
+            funding_txid: funding_created.funding_txid.into(),
+            funding_output_index: funding_created.output_index.into(),
+            local_parameters: ChannelParameters::accept(accept_channel),
+            remote_parameters: ChannelParameters::open(open_channel),
+            local_keys: local_keys,
+            local_seed: local_seed,
+            remote_keys: open_channel.keys.clone(),
+        }
+    }
+}
+
+/// Happens to the htlcs, the caller should forward or settle them,
+/// or to the channel itself.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub enum ChannelEvent {
+    /// The htlc offered by the counterparty is irrevocably committed.
+    HtlcReceived(UpdateAddHtlc),
+    /// The htlc offered by the node is irrevocably removed.
+    HtlcFulfilled(UpdateFulfillHtlc),
+    HtlcFailed(UpdateFailHtlc),
+    HtlcFailedMalformed(UpdateFailMalformedHtlc),
+    /// The feerate proposed by the funder is irrevocably committed.
+    FeeUpdated(UpdateFee),
+    /// The counterparty has sent `shutdown`, the node should answer by `shutdown` too.
+    ShutdownReceived,
+    /// The fee is agreed, the closing transaction is signed by both sides and ready to broadcast.
+    Closed(Transaction),
+}
+
+/// The messages to send to the counterparty and the events for the node.
+#[derive(Default, Debug)]
+pub struct Outcome {
+    pub messages: Vec<Message>,
+    pub events: Vec<ChannelEvent>,
+}
+
+impl Outcome {
+    fn message(message: Message) -> Self {
+        Outcome {
+            messages: vec![message],
+            events: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Eq, PartialEq)]
+pub enum ChannelError {
+    Crypto(Secp256k1Error),
+    WrongChannel,
+    UnexpectedMessage,
+    // the counterparty has not sent `funding_locked`
+    NotLocked,
+    HtlcIdOutOfOrder,
+    HtlcBelowMinimum,
+    ExpiryTooFar,
+    TooManyHtlcs,
+    MaxInFlightExceeded,
+    CannotAfford,
+    // `update_fee` is sent by the fundee
+    FeeByFundee,
+    // the feerate of `update_fee` is below the floor
+    FeerateTooLow,
+    // the htlc is not irrevocably committed
+    UnknownHtlc,
+    HtlcAlreadyRemoved,
+    WrongPreimage,
+    NotBadOnion,
+    NoUpdates,
+    AwaitingRevocation,
+    UnexpectedRevocation,
+    WrongRevocation,
+    // the counterparty has not signed the local commitment
+    NotSigned,
+    // the `commitment_signed` of the counterparty includes no updates
+    EmptyCommitment,
+    // the signature of the counterparty does not match the local commitment of the number
+    WrongCommitmentSignature(u64),
+    // the signatures of the second stage transactions do not match the htlc outputs
+    WrongHtlcSignatureCount,
+    WrongHtlcSignature,
+    // no htlcs are added after `shutdown`
+    ShuttingDown,
+    InvalidShutdownScript,
+    // the updates must be signed before `shutdown`
+    UpdatesPending,
+    WrongClosingFee,
+    // the fees acceptable by both sides do not overlap
+    FeeRangeMismatch,
+    WrongClosingSignature,
+    // only `channel_reestablish` is expected after the reconnection
+    NotReestablished,
+    // the commitment numbers of the counterparty do not match the ones the node knows
+    WrongReestablish,
+    // the counterparty has proved the node lost its latest state,
+    // the local commitment is revoked and must not be broadcast
+    DataLoss,
+}
+
+impl error::Error for ChannelError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            &ChannelError::Crypto(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for ChannelError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::ChannelError::*;
+
+        match self {
+            &Crypto(ref e) => write!(f, "crypto error: {}", e),
+            &WrongChannel => write!(f, "the message is for another channel"),
+            &UnexpectedMessage => write!(f, "unexpected message"),
+            &NotLocked => write!(f, "the funding is not locked"),
+            &HtlcIdOutOfOrder => write!(f, "the htlc id is not the next one"),
+            &HtlcBelowMinimum => write!(f, "the htlc amount is below the minimum"),
+            &ExpiryTooFar => write!(f, "the htlc expiry is not a block height"),
+            &TooManyHtlcs => write!(f, "too many htlcs offered"),
+            &MaxInFlightExceeded => write!(f, "the htlcs in flight exceed the maximum"),
+            &CannotAfford => write!(f, "the sender cannot afford the htlc"),
+            &FeeByFundee => write!(f, "update_fee is sent by the fundee"),
+            &FeerateTooLow => write!(f, "the feerate of update_fee is below {} sat/kw", FEERATE_FLOOR),
+            &UnknownHtlc => write!(f, "the htlc is not committed"),
+            &HtlcAlreadyRemoved => write!(f, "the htlc is already removed"),
+            &WrongPreimage => write!(f, "the preimage does not match the payment hash"),
+            &NotBadOnion => write!(f, "the failure code has no BADONION flag"),
+            &NoUpdates => write!(f, "nothing to sign"),
+            &AwaitingRevocation => write!(f, "the previous commitment is not revoked yet"),
+            &UnexpectedRevocation => write!(f, "no commitment to revoke"),
+            &WrongRevocation => write!(f, "the secret does not match the commitment"),
+            &NotSigned => write!(f, "the local commitment is not signed"),
+            &EmptyCommitment => write!(f, "the commitment includes no updates"),
+            &WrongCommitmentSignature(number) => write!(f, "the signature does not match the local commitment {}", number),
+            &WrongHtlcSignatureCount => write!(f, "the number of htlc signatures does not match the htlc outputs"),
+            &WrongHtlcSignature => write!(f, "the htlc signature does not match the htlc transaction"),
+            &ShuttingDown => write!(f, "the channel is shutting down"),
+            &InvalidShutdownScript => write!(f, "the shutdown script is not P2PKH, P2SH, P2WPKH or P2WSH"),
+            &UpdatesPending => write!(f, "the updates are not signed"),
+            &WrongClosingFee => write!(f, "the closing fee is out of its range"),
+            &FeeRangeMismatch => write!(f, "no closing fee is acceptable by both sides"),
+            &WrongClosingSignature => write!(f, "the signature does not match the closing transaction"),
+            &NotReestablished => write!(f, "the channel is not reestablished"),
+            &WrongReestablish => write!(f, "the commitment numbers do not match"),
+            &DataLoss => write!(f, "the local state is outdated, the counterparty is ahead"),
+        }
+    }
+}
+
+/// The secret of the local commitment `number`, the point of the first one
+/// goes to `open_channel` or `accept_channel`.
+pub fn per_commitment_secret(seed: &[u8; 32], number: u64) -> Result<SecretKey, Secp256k1Error> {
+    let secret = commitment_secret(seed, number);
+    SecretKey::from_slice(&Secp256k1::new(), &secret[..])
+}
+
+fn commitment_secret(seed: &[u8; 32], number: u64) -> [u8; 32] {
+    ProducerTree::new(Sha256Hash::from(seed.clone())).leaf(LeafIndex::new(number)).into()
+}
+
+fn commitment_point(seed: &[u8; 32], number: u64) -> Result<PublicKey, ChannelError> {
+    let secret = per_commitment_secret(seed, number).map_err(ChannelError::Crypto)?;
+    PublicKey::from_secret_key(&Secp256k1::new(), &secret).map_err(ChannelError::Crypto)
+}
+
+#[derive(Clone, Debug)]
+enum Update {
+    Add(UpdateAddHtlc),
+    Fulfill(UpdateFulfillHtlc),
+    Fail(UpdateFailHtlc),
+    FailMalformed(UpdateFailMalformedHtlc),
+    Fee(UpdateFee),
+}
+
+impl Update {
+    fn message(&self) -> Message {
+        match self {
+            &Update::Add(ref m) => Message::UpdateAddHtlc(m.clone()),
+            &Update::Fulfill(ref m) => Message::UpdateFulfillHtlc(m.clone()),
+            &Update::Fail(ref m) => Message::UpdateFailHtlc(m.clone()),
+            &Update::FailMalformed(ref m) => Message::UpdateFailMalformedHtlc(m.clone()),
+            &Update::Fee(ref m) => Message::UpdateFee(m.clone()),
+        }
+    }
+
+    // the id of the htlc the update removes
+    fn removes(&self) -> Option<HtlcId> {
+        match self {
+            &Update::Add(_) | &Update::Fee(_) => None,
+            &Update::Fulfill(ref m) => Some(m.id),
+            &Update::Fail(ref m) => Some(m.id()),
+            &Update::FailMalformed(ref m) => Some(m.id()),
+        }
+    }
+}
+
+// the updates proposed by one side, the ones irrevocably committed are dropped
+#[derive(Default)]
+struct UpdateLog {
+    // the index of the first update in the queue
+    offset: u64,
+    updates: VecDeque<Update>,
+}
+
+impl UpdateLog {
+    fn len(&self) -> u64 {
+        self.offset + self.updates.len() as u64
+    }
+
+    fn push(&mut self, update: Update) {
+        self.updates.push_back(update)
+    }
+
+    fn pop(&mut self) -> Option<Update> {
+        self.updates.pop_back()
+    }
+
+    fn range(&self, from: u64, to: u64) -> impl Iterator<Item=&Update> {
+        self.updates.iter().skip((from - self.offset) as usize).take((to - from) as usize)
+    }
+
+    fn compact(&mut self, to: u64) {
+        while self.offset < to {
+            self.updates.pop_front();
+            self.offset += 1;
+        }
+    }
+
+    fn removes(&self, id: HtlcId) -> bool {
+        self.updates.iter().any(|u| u.removes() == Some(id))
+    }
+}
+
+#[derive(Clone, Debug)]
+struct CommittedHtlc {
+    offered_by: Side,
+    id: HtlcId,
+    amount: u64,
+    payment_hash: [u8; 32],
+    expiry: u32,
+}
+
+// the content of the commitment transaction of one side,
+// the balances are named relative to the node, not to the owner
+#[derive(Clone, Debug)]
+struct Commitment {
+    number: u64,
+    // the count of the updates of each side it includes
+    local_updates: u64,
+    remote_updates: u64,
+    local_msat: u64,
+    remote_msat: u64,
+    htlcs: Vec<CommittedHtlc>,
+    feerate: SatoshiPerKiloWeight,
+    // the per commitment point of the owner
+    point: PublicKey,
+}
+
+impl Commitment {
+    fn balance(&self, side: Side) -> u64 {
+        match side {
+            Side::Local => self.local_msat,
+            Side::Remote => self.remote_msat,
+        }
+    }
+
+    fn balance_mut(&mut self, side: Side) -> &mut u64 {
+        match side {
+            Side::Local => &mut self.local_msat,
+            Side::Remote => &mut self.remote_msat,
+        }
+    }
+
+    fn htlc(&self, offered_by: Side, id: HtlcId) -> Option<&CommittedHtlc> {
+        self.htlcs.iter().find(|h| h.offered_by == offered_by && h.id == id)
+    }
+
+    fn remove(&mut self, offered_by: Side, id: HtlcId) -> Result<CommittedHtlc, ChannelError> {
+        let position = self.htlcs.iter()
+            .position(|h| h.offered_by == offered_by && h.id == id)
+            .ok_or(ChannelError::UnknownHtlc)?;
+        Ok(self.htlcs.remove(position))
+    }
+
+    fn apply(&mut self, proposer: Side, update: &Update) -> Result<(), ChannelError> {
+        match update {
+            &Update::Add(ref m) => {
+                let amount = u64::from(m.amount);
+                {
+                    let balance = self.balance_mut(proposer);
+                    *balance = balance.checked_sub(amount).ok_or(ChannelError::CannotAfford)?;
+                }
+                self.htlcs.push(CommittedHtlc {
+                    offered_by: proposer,
+                    id: m.id,
+                    amount: amount,
+                    payment_hash: m.payment.into(),
+                    expiry: m.expiry,
+                });
+            },
+            &Update::Fulfill(ref m) => {
+                let htlc = self.remove(proposer.other(), m.id)?;
+                *self.balance_mut(proposer) += htlc.amount;
+            },
+            &Update::Fail(ref m) => {
+                let htlc = self.remove(proposer.other(), m.id())?;
+                *self.balance_mut(proposer.other()) += htlc.amount;
+            },
+            &Update::FailMalformed(ref m) => {
+                let htlc = self.remove(proposer.other(), m.id())?;
+                *self.balance_mut(proposer.other()) += htlc.amount;
+            },
+            &Update::Fee(ref m) => self.feerate = m.fee(),
+        }
+        Ok(())
+    }
+}
+
+/// The normal operation of the channel after the funding, BOLT 2.
+/// Takes the messages of the counterparty and the commands of the node,
+/// and tells what to send and what has happened, it does no I/O.
+pub struct Channel {
+    channel_id: ChannelId,
+    funder: Side,
+    funding: Satoshi,
+    funding_txid: Sha256dHash,
+    funding_output_index: u16,
+    obscuring_factor: u64,
+
+    local_parameters: ChannelParameters,
+    remote_parameters: ChannelParameters,
+    local_keys: ChannelPrivateKeys,
+    local_points: ChannelKeys,
+    local_seed: [u8; 32],
+    remote_keys: ChannelKeys,
+    remote_secrets: StoreTree,
+    remote_locked: bool,
+
+    // the latest commitments of each side
+    local_commitment: Commitment,
+    remote_commitment: Commitment,
+    // the remote commitment preceding the latest one, until it is revoked
+    remote_previous: Option<Commitment>,
+    // the point for the next remote commitment
+    remote_next_point: Option<PublicKey>,
+    // the signature of the latest local commitment by the counterparty,
+    // comes with `funding_created` or `funding_signed` for the first one
+    local_signature: Option<Signature>,
+
+    local_updates: UpdateLog,
+    remote_updates: UpdateLog,
+    next_local_htlc_id: HtlcId,
+    next_remote_htlc_id: HtlcId,
+
+    // the scripts of `shutdown` sent and received, the closing transaction pays to them
+    local_shutdown: Option<Script>,
+    remote_shutdown: Option<Script>,
+    // the fee of the latest `closing_signed` sent by the node
+    closing_fee: Option<u64>,
+    closed: bool,
+
+    // the connection is lost, `channel_reestablish` is awaited
+    disconnected: bool,
+    // `commitment_signed` is sent after the latest `revoke_and_ack`,
+    // they are retransmitted in the same order
+    signed_last: bool,
+    // the point of the latest remote commitment, the counterparty has proved it is ahead
+    data_loss: Option<PublicKey>,
+}
+
+impl Channel {
+    pub fn new(setup: ChannelSetup) -> Result<Self, ChannelError> {
+        let local_points = ChannelKeys::new(&setup.local_keys).map_err(ChannelError::Crypto)?;
+        let obscuring_factor = {
+            let (funder, fundee) = match setup.funder {
+                Side::Local => (&local_points, &setup.remote_keys),
+                Side::Remote => (&setup.remote_keys, &local_points),
+            };
+            get_obscuring_number(
+                &funder.payment().as_ref().serialize()[..],
+                &fundee.payment().as_ref().serialize()[..],
+            )
+        };
+
+        let push = u64::from(setup.push);
+        let funder_msat = u64::from(MilliSatoshi::from(setup.funding))
+            .checked_sub(push)
+            .ok_or(ChannelError::CannotAfford)?;
+        let (local_msat, remote_msat) = match setup.funder {
+            Side::Local => (funder_msat, push),
+            Side::Remote => (push, funder_msat),
+        };
+        let feerate = setup.feerate;
+        let commitment = |point| Commitment {
+            number: 0,
+            local_updates: 0,
+            remote_updates: 0,
+            local_msat: local_msat,
+            remote_msat: remote_msat,
+            htlcs: Vec::new(),
+            feerate: feerate,
+            point: point,
+        };
+
+        // the funding txid xor the output index in big endian
+        let mut channel_id = setup.funding_txid;
+        channel_id[30] ^= (setup.funding_output_index >> 8) as u8;
+        channel_id[31] ^= (setup.funding_output_index & 0xff) as u8;
+
+        Ok(Channel {
+            channel_id: ChannelId::from(channel_id),
+            funder: setup.funder,
+            funding: setup.funding,
+            funding_txid: Sha256dHash::from(&setup.funding_txid[..]),
+            funding_output_index: setup.funding_output_index,
+            obscuring_factor: obscuring_factor,
+            local_parameters: setup.local_parameters,
+            remote_parameters: setup.remote_parameters,
+            local_keys: setup.local_keys,
+            local_points: local_points,
+            local_commitment: commitment(commitment_point(&setup.local_seed, 0)?),
+            remote_commitment: commitment(setup.remote_keys.first_per_commitment().as_ref().clone()),
+            local_seed: setup.local_seed,
+            remote_keys: setup.remote_keys,
+            remote_secrets: StoreTree::new(),
+            remote_locked: false,
+            remote_previous: None,
+            remote_next_point: None,
+            local_signature: None,
+            local_updates: UpdateLog::default(),
+            remote_updates: UpdateLog::default(),
+            next_local_htlc_id: HtlcId::new(),
+            next_remote_htlc_id: HtlcId::new(),
+            local_shutdown: None,
+            remote_shutdown: None,
+            closing_fee: None,
+            closed: false,
+            disconnected: false,
+            signed_last: false,
+            data_loss: None,
+        })
+    }
+
+    pub fn channel_id(&self) -> ChannelId {
+        self.channel_id
+    }
+
+    /// The local and the remote balance in the latest local commitment.
+    pub fn balances(&self) -> (MilliSatoshi, MilliSatoshi) {
+        let c = &self.local_commitment;
+        (MilliSatoshi::from(c.local_msat), MilliSatoshi::from(c.remote_msat))
+    }
+
+    /// The numbers of the latest local and remote commitments.
+    pub fn commitment_numbers(&self) -> (u64, u64) {
+        (self.local_commitment.number, self.remote_commitment.number)
+    }
+
+    pub fn local_commitment_tx(&self) -> Transaction {
+        self.commit_tx(Side::Local, &self.local_commitment).get_tx()
+    }
+
+    pub fn remote_commitment_tx(&self) -> Transaction {
+        self.commit_tx(Side::Remote, &self.remote_commitment).get_tx()
+    }
+
+    /// The signature of the latest remote commitment, `funding_created`
+    /// and `funding_signed` carry it for the first one.
+    pub fn sign_remote_commitment(&self) -> Signature {
+        self.commit_tx(Side::Remote, &self.remote_commitment)
+            .sign(self.local_keys.funding_sk().as_ref())
+    }
+
+    /// The signatures of the HTLC-success and HTLC-timeout transactions of the latest remote
+    /// commitment in the order of its htlc outputs, `commitment_signed` carries them.
+    pub fn sign_remote_htlcs(&self) -> Vec<Signature> {
+        let commitment = &self.remote_commitment;
+        let htlc_sk = derive_privkey(self.local_keys.htlc_sk().as_ref(), &commitment.point);
+        self.commit_tx(Side::Remote, commitment).get_htlc_txs()
+            .iter()
+            .map(|htlc_tx| htlc_tx.sign(&htlc_sk))
+            .collect()
+    }
+
+    /// The latest local commitment signed by both sides, the node broadcasts it
+    /// to close the channel unilaterally.
+    pub fn signed_local_commitment_tx(&self) -> Result<Transaction, ChannelError> {
+        if self.data_loss.is_some() {
+            return Err(ChannelError::DataLoss);
+        }
+        let remote_signature = self.local_signature.as_ref().ok_or(ChannelError::NotSigned)?;
+        let commit_tx = self.commit_tx(Side::Local, &self.local_commitment);
+        let local_signature = commit_tx.sign(self.local_keys.funding_sk().as_ref());
+        Ok(commit_tx.signed_tx(&local_signature, remote_signature))
+    }
+
+    /// Tells the point of the second local commitment, the funding is confirmed.
+    pub fn funding_locked(&self) -> Result<FundingLocked, ChannelError> {
+        Ok(FundingLocked {
+            channel_id: self.channel_id,
+            next_per_commitment_point: LpdPublicKey::from(commitment_point(&self.local_seed, 1)?),
+        })
+    }
+
+    /// The counterparty has sent `funding_locked`, the htlcs can be offered.
+    pub fn is_locked(&self) -> bool {
+        self.remote_locked
+    }
+
+    /// Processes the message of the counterparty, the error means the channel should be failed.
+    pub fn receive(&mut self, message: Message) -> Result<Outcome, ChannelError> {
+        match message {
+            Message::ReestablishChannel(m) => self.received_reestablish(m),
+            _ if self.disconnected => Err(ChannelError::NotReestablished),
+            Message::FundingCreated(m) => self.received_funding_created(m),
+            Message::FundingSigned(m) => self.received_funding_signed(m),
+            Message::FundingLocked(m) => self.received_funding_locked(m),
+            Message::UpdateAddHtlc(m) => self.received_add(m),
+            Message::UpdateFulfillHtlc(m) => self.received_fulfill(m),
+            Message::UpdateFailHtlc(m) => self.received_fail(m),
+            Message::UpdateFailMalformedHtlc(m) => self.received_fail_malformed(m),
+            Message::UpdateFee(m) => self.received_update_fee(m),
+            Message::CommitmentSigned(m) => self.received_commitment_signed(m),
+            Message::RevokeAndAck(m) => self.received_revoke_and_ack(m),
+            Message::ShutdownChannel(m) => self.received_shutdown(m),
+            Message::ClosingNegotiation(m) => self.received_closing_signed(m),
+            _ => Err(ChannelError::UnexpectedMessage),
+        }
+    }
+
+    /// Offers the htlc, it goes to the remote commitment with the next `sign`.
+    pub fn add_htlc(
+        &mut self,
+        amount: MilliSatoshi,
+        payment_hash: Hash256,
+        expiry: u32,
+        onion_blob: OnionBlob,
+    ) -> Result<(HtlcId, Outcome), ChannelError> {
+        if !self.remote_locked {
+            return Err(ChannelError::NotLocked);
+        }
+        if self.local_shutdown.is_some() || self.remote_shutdown.is_some() {
+            return Err(ChannelError::ShuttingDown);
+        }
+        if u64::from(amount) == 0 || amount < self.remote_parameters.htlc_minimum {
+            return Err(ChannelError::HtlcBelowMinimum);
+        }
+        if expiry >= MAX_CLTV_EXPIRY {
+            return Err(ChannelError::ExpiryTooFar);
+        }
+
+        let id = self.next_local_htlc_id;
+        let add = UpdateAddHtlc {
+            channel_id: self.channel_id,
+            id: id,
+            amount: amount,
+            payment: payment_hash,
+            expiry: expiry,
+            onion_blob: onion_blob,
+        };
+        self.local_updates.push(Update::Add(add.clone()));
+        if let Err(e) = self.check_offered(Side::Local) {
+            self.local_updates.pop();
+            return Err(e);
+        }
+        self.next_local_htlc_id = id.next();
+        Ok((id, Outcome::message(Message::UpdateAddHtlc(add))))
+    }
+
+    pub fn fulfill_htlc(&mut self, id: HtlcId, payment_preimage: [u8; 32]) -> Result<Outcome, ChannelError> {
+        if sha256(&payment_preimage) != self.committed_htlc(Side::Remote, id)?.payment_hash {
+            return Err(ChannelError::WrongPreimage);
+        }
+
+        let fulfill = UpdateFulfillHtlc {
+            channel_id: self.channel_id,
+            id: id,
+            payment_preimage: payment_preimage,
+        };
+        self.local_updates.push(Update::Fulfill(fulfill.clone()));
+        Ok(Outcome::message(Message::UpdateFulfillHtlc(fulfill)))
+    }
+
+    pub fn fail_htlc(&mut self, id: HtlcId, reason: OpaqueReason) -> Result<Outcome, ChannelError> {
+        self.committed_htlc(Side::Remote, id)?;
+
+        let fail = UpdateFailHtlc::new(self.channel_id, id, reason);
+        self.local_updates.push(Update::Fail(fail.clone()));
+        Ok(Outcome::message(Message::UpdateFailHtlc(fail)))
+    }
+
+    pub fn fail_malformed_htlc(
+        &mut self,
+        id: HtlcId,
+        sha256_of_onion: Hash256,
+        failure_code: FailureCode,
+    ) -> Result<Outcome, ChannelError> {
+        self.committed_htlc(Side::Remote, id)?;
+
+        let fail = UpdateFailMalformedHtlc::new(self.channel_id, id, sha256_of_onion, failure_code)
+            .ok_or(ChannelError::NotBadOnion)?;
+        self.local_updates.push(Update::FailMalformed(fail.clone()));
+        Ok(Outcome::message(Message::UpdateFailMalformedHtlc(fail)))
+    }
+
+    /// The funder proposes the new feerate of the commitments, it applies with the next `sign`.
+    pub fn update_fee(&mut self, feerate: SatoshiPerKiloWeight) -> Result<Outcome, ChannelError> {
+        if self.funder != Side::Local {
+            return Err(ChannelError::FeeByFundee);
+        }
+        if u32::from(feerate) < FEERATE_FLOOR {
+            return Err(ChannelError::FeerateTooLow);
+        }
+
+        let update_fee = UpdateFee::new(self.channel_id, feerate);
+        self.local_updates.push(Update::Fee(update_fee.clone()));
+        if let Err(e) = self.check_offered(Side::Local) {
+            self.local_updates.pop();
+            return Err(e);
+        }
+        Ok(Outcome::message(Message::UpdateFee(update_fee)))
+    }
+
+    /// Signs the remote commitment with all updates sent and acknowledged so far.
+    pub fn sign(&mut self) -> Result<Outcome, ChannelError> {
+        if self.remote_previous.is_some() {
+            return Err(ChannelError::AwaitingRevocation);
+        }
+        if !self.has_changes_for_remote() {
+            return Err(ChannelError::NoUpdates);
+        }
+        self.sign_next().map(Outcome::message)
+    }
+
+    /// Forgets the updates of the counterparty it has not signed, they are lost with the connection,
+    /// the channel awaits `channel_reestablish` then. The updates of the node are retransmitted.
+    pub fn disconnect(&mut self) {
+        while self.remote_updates.len() > self.local_commitment.remote_updates {
+            if let Some(Update::Add(m)) = self.remote_updates.pop() {
+                self.next_remote_htlc_id = m.id;
+            }
+        }
+        // the fee negotiation restarts
+        if !self.closed {
+            self.closing_fee = None;
+        }
+        self.disconnected = true;
+    }
+
+    /// The first message after the reconnection.
+    pub fn reestablish(&self) -> Result<ReestablishChannel, ChannelError> {
+        let next_revocation = self.remote_acked().number;
+        let last_secret = match next_revocation {
+            0 => [0; 32],
+            n => self.remote_secrets.lookup(LeafIndex::new(n - 1))
+                .map_err(|_| ChannelError::WrongRevocation)?
+                .into(),
+        };
+        Ok(ReestablishChannel::new(
+            self.channel_id,
+            self.local_commitment.number + 1,
+            next_revocation,
+            last_secret,
+            LpdPublicKey::from(self.local_commitment.point.clone()),
+        ))
+    }
+
+    /// The point of the latest commitment of the counterparty, if it has proved
+    /// the node lost its state. The funds are swept with it once the counterparty broadcasts.
+    pub fn data_loss_point(&self) -> Option<&PublicKey> {
+        self.data_loss.as_ref()
+    }
+
+    /// Starts the mutual close, the channel closes once no htlcs remain.
+    /// The closing transaction pays the local balance to the `script`.
+    pub fn shutdown(&mut self, script: Vec<u8>) -> Result<Outcome, ChannelError> {
+        if self.local_shutdown.is_some() {
+            return Err(ChannelError::ShuttingDown);
+        }
+        if !is_valid_shutdown_script(&script) {
+            return Err(ChannelError::InvalidShutdownScript);
+        }
+        if self.local_updates.len() > self.remote_commitment.local_updates {
+            return Err(ChannelError::UpdatesPending);
+        }
+
+        self.local_shutdown = Some(Script::from(script.clone()));
+        let mut outcome = Outcome::message(Message::ShutdownChannel(ShutdownChannel {
+            channel_id: self.channel_id,
+            script: script,
+        }));
+        outcome.messages.extend(self.start_closing());
+        Ok(outcome)
+    }
+
+    fn check_channel(&self, channel_id: &ChannelId) -> Result<(), ChannelError> {
+        if channel_id != &self.channel_id {
+            return Err(ChannelError::WrongChannel);
+        }
+        Ok(())
+    }
+
+    // the latest remote commitment whose predecessor is revoked
+    fn remote_acked(&self) -> &Commitment {
+        self.remote_previous.as_ref().unwrap_or(&self.remote_commitment)
+    }
+
+    fn has_changes_for_remote(&self) -> bool {
+        self.local_updates.len() > self.remote_commitment.local_updates
+            || self.local_commitment.remote_updates > self.remote_commitment.remote_updates
+    }
+
+    // applies the updates up to the given counts to the `base`, the number and the point remain
+    fn next_commitment(&self, base: &Commitment, local_to: u64, remote_to: u64) -> Result<Commitment, ChannelError> {
+        let mut commitment = base.clone();
+        for update in self.local_updates.range(base.local_updates, local_to) {
+            commitment.apply(Side::Local, update)?;
+        }
+        for update in self.remote_updates.range(base.remote_updates, remote_to) {
+            commitment.apply(Side::Remote, update)?;
+        }
+        commitment.local_updates = local_to;
+        commitment.remote_updates = remote_to;
+        Ok(commitment)
+    }
+
+    fn commit_tx(&self, owner: Side, commitment: &Commitment) -> CommitTx {
+        let (owner_keys, other_keys, owner_parameters, other_parameters) = match owner {
+            Side::Local => (&self.local_points, &self.remote_keys, &self.local_parameters, &self.remote_parameters),
+            Side::Remote => (&self.remote_keys, &self.local_points, &self.remote_parameters, &self.local_parameters),
+        };
+        let point = &commitment.point;
+
+        CommitTx {
+            funding_amount: u64::from(self.funding) as i64,
+            local_funding_pubkey: owner_keys.funding().as_ref().clone(),
+            remote_funding_pubkey: other_keys.funding().as_ref().clone(),
+
+            local_feerate_per_kw: u32::from(commitment.feerate) as i64,
+            dust_limit_satoshi: u64::from(owner_parameters.dust_limit) as i64,
+
+            to_local_msat: commitment.balance(owner) as i64,
+            to_remote_msat: commitment.balance(owner.other()) as i64,
+
+            obscured_commit_number: commitment.number ^ self.obscuring_factor,
+
+            local_htlc_pubkey: derive_pubkey(owner_keys.htlc().as_ref(), point),
+            remote_htlc_pubkey: derive_pubkey(other_keys.htlc().as_ref(), point),
+
+            local_revocation_pubkey: derive_revocation_pubkey(other_keys.revocation().as_ref(), point),
+            local_delayedpubkey: derive_pubkey(owner_keys.delayed_payment().as_ref(), point),
+            local_delay: u16::from(other_parameters.csv_delay) as u64,
+
+            remotepubkey: derive_pubkey(other_keys.payment().as_ref(), point),
+
+            funding_tx_id: self.funding_txid,
+            funding_output_index: self.funding_output_index as u32,
+
+            htlcs: commitment.htlcs.iter()
+                .map(|h| HTLC {
+                    direction: if h.offered_by == owner { HTLCDirection::Offered } else { HTLCDirection::Accepted },
+                    amount_msat: h.amount as i64,
+                    expiry: h.expiry as i32,
+                    payment_hash: h.payment_hash,
+                })
+                .collect(),
+
+            local_pays_fee: self.funder == owner,
+        }
+    }
+
+    // checks the htlcs offered by the `offerer` against the limits of the counterparty,
+    // every pending update is applied to the commitment of the counterparty
+    fn check_offered(&self, offerer: Side) -> Result<(), ChannelError> {
+        let receiver = offerer.other();
+        let (base, parameters) = match receiver {
+            Side::Local => (&self.local_commitment, &self.local_parameters),
+            Side::Remote => (&self.remote_commitment, &self.remote_parameters),
+        };
+        let projected = self.next_commitment(base, self.local_updates.len(), self.remote_updates.len())?;
+
+        let offered = projected.htlcs.iter().filter(|h| h.offered_by == offerer);
+        let (count, total) = offered.fold((0, 0), |(count, total), h| (count + 1, total + h.amount));
+        if count > parameters.max_accepted_htlc_number as u64 {
+            return Err(ChannelError::TooManyHtlcs);
+        }
+        if total > u64::from(parameters.max_htlc_value_in_flight) {
+            return Err(ChannelError::MaxInFlightExceeded);
+        }
+
+        let mut required = u64::from(MilliSatoshi::from(parameters.channel_reserve));
+        if self.funder == offerer {
+            required += self.commit_tx(receiver, &projected).fee() as u64 * 1000;
+        }
+        if projected.balance(offerer) < required {
+            return Err(ChannelError::CannotAfford);
+        }
+        Ok(())
+    }
+
+    // the htlc irrevocably committed, and not being removed
+    fn committed_htlc(&self, offered_by: Side, id: HtlcId) -> Result<&CommittedHtlc, ChannelError> {
+        let removals = match offered_by {
+            Side::Local => &self.remote_updates,
+            Side::Remote => &self.local_updates,
+        };
+        if removals.removes(id) {
+            return Err(ChannelError::HtlcAlreadyRemoved);
+        }
+        match (self.local_commitment.htlc(offered_by, id), self.remote_acked().htlc(offered_by, id)) {
+            (Some(htlc), Some(_)) => Ok(htlc),
+            _ => Err(ChannelError::UnknownHtlc),
+        }
+    }
+
+    fn sign_next(&mut self) -> Result<Message, ChannelError> {
+        let point = self.remote_next_point.clone().ok_or(ChannelError::NotLocked)?;
+        let mut commitment = self.next_commitment(
+            &self.remote_commitment,
+            self.local_updates.len(),
+            self.local_commitment.remote_updates,
+        )?;
+        commitment.number += 1;
+        commitment.point = point;
+
+        self.remote_next_point = None;
+        self.remote_previous = Some(mem::replace(&mut self.remote_commitment, commitment));
+        self.signed_last = true;
+        Ok(self.commitment_signed())
+    }
+
+    // the signatures are deterministic, the retransmitted message is the same
+    fn commitment_signed(&self) -> Message {
+        Message::CommitmentSigned(CommitmentSigned {
+            channel_id: self.channel_id,
+            signature: self.sign_remote_commitment().into(),
+            htlc_signatures: self.sign_remote_htlcs().into_iter().map(Into::into).collect(),
+        })
+    }
+
+    // revokes the local commitment `number`
+    fn revoke_and_ack(&self, number: u64) -> Result<Message, ChannelError> {
+        Ok(Message::RevokeAndAck(RevokeAndAck {
+            channel_id: self.channel_id,
+            revocation_preimage: commitment_secret(&self.local_seed, number),
+            next_per_commitment_point: LpdPublicKey::from(commitment_point(&self.local_seed, number + 2)?),
+        }))
+    }
+
+    // rebuilds the local commitment and checks the signature of the counterparty by its funding key
+    fn verify_commitment_signature(&self, commitment: &Commitment, signature: &LpdSignature) -> Result<(), ChannelError> {
+        self.commit_tx(Side::Local, commitment)
+            .verify(signature.as_ref())
+            .map_err(|_| ChannelError::WrongCommitmentSignature(commitment.number))
+    }
+
+    // both sides have sent `shutdown` and both commitments are the same and have no htlcs
+    fn is_closing(&self) -> bool {
+        self.local_shutdown.is_some() && self.remote_shutdown.is_some()
+            && self.local_commitment.htlcs.is_empty() && self.remote_commitment.htlcs.is_empty()
+            && self.remote_previous.is_none() && !self.has_changes_for_remote()
+            && self.remote_updates.len() == self.local_commitment.remote_updates
+    }
+
+    // the funder proposes the first fee
+    fn start_closing(&mut self) -> Option<Message> {
+        if self.funder != Side::Local || self.closing_fee.is_some() || !self.is_closing() {
+            return None;
+        }
+        let (fee, _) = self.closing_fees();
+        Some(self.sign_closing(fee))
+    }
+
+    fn closing_tx(&self, fee: u64, dust_limit: Satoshi) -> ClosingTx {
+        let commitment = &self.local_commitment;
+        ClosingTx {
+            funding_amount: u64::from(self.funding) as i64,
+            local_funding_pubkey: self.local_points.funding().as_ref().clone(),
+            remote_funding_pubkey: self.remote_keys.funding().as_ref().clone(),
+
+            funding_tx_id: self.funding_txid,
+            funding_output_index: self.funding_output_index as u32,
+
+            dust_limit_satoshi: u64::from(dust_limit) as i64,
+
+            to_local_msat: commitment.local_msat as i64,
+            to_remote_msat: commitment.remote_msat as i64,
+
+            local_script: self.local_shutdown.clone().unwrap_or_else(Script::new),
+            remote_script: self.remote_shutdown.clone().unwrap_or_else(Script::new),
+
+            fee: fee as i64,
+            local_pays_fee: self.funder == Side::Local,
+        }
+    }
+
+    // the fee of the closing transaction at the feerate of the channel, and the fees the node accepts,
+    // at most the fee of the latest commitment the funder would pay anyway
+    fn closing_fees(&self) -> (u64, FeeRange) {
+        let weight = self.closing_tx(0, self.local_parameters.dust_limit).weight() as u64;
+        let max = self.commit_tx(Side::Local, &self.local_commitment).fee() as u64;
+        let fee = cmp::min(weight * u32::from(self.local_commitment.feerate) as u64 / 1000, max);
+        (fee, FeeRange { min: Satoshi::from(fee / 2), max: Satoshi::from(max) })
+    }
+
+    // each side signs the closing transaction with its own dust limit
+    fn sign_closing(&mut self, fee: u64) -> Message {
+        let (_, fee_range) = self.closing_fees();
+        let signature = self.closing_tx(fee, self.local_parameters.dust_limit)
+            .sign(self.local_keys.funding_sk().as_ref());
+        self.closing_fee = Some(fee);
+        Message::ClosingNegotiation(ClosingNegotiation::new(
+            self.channel_id,
+            Satoshi::from(fee),
+            signature.into(),
+            Some(fee_range),
+        ))
+    }
+
+    // the counterparty signs the second stage transactions of the local commitment by its htlc key
+    fn verify_htlc_signatures(&self, commitment: &Commitment, signatures: &[LpdSignature]) -> Result<(), ChannelError> {
+        let commit_tx = self.commit_tx(Side::Local, commitment);
+        let htlc_txs = commit_tx.get_htlc_txs();
+        if htlc_txs.len() != signatures.len() {
+            return Err(ChannelError::WrongHtlcSignatureCount);
+        }
+        for (htlc_tx, signature) in htlc_txs.iter().zip(signatures.iter()) {
+            htlc_tx.verify(signature.as_ref(), &commit_tx.remote_htlc_pubkey)
+                .map_err(|_| ChannelError::WrongHtlcSignature)?;
+        }
+        Ok(())
+    }
+
+    // the updates committed in both latest unrevoked commitments are irrevocable,
+    // tells about the ones of the counterparty and drops them
+    fn lock_in(&mut self, events: &mut Vec<ChannelEvent>) {
+        let (local_locked, remote_locked) = {
+            let acked = self.remote_acked();
+            (
+                cmp::min(self.local_commitment.local_updates, acked.local_updates),
+                cmp::min(self.local_commitment.remote_updates, acked.remote_updates),
+            )
+        };
+
+        for update in self.remote_updates.range(self.remote_updates.offset, remote_locked) {
+            events.push(match update {
+                &Update::Add(ref m) => ChannelEvent::HtlcReceived(m.clone()),
+                &Update::Fulfill(ref m) => ChannelEvent::HtlcFulfilled(m.clone()),
+                &Update::Fail(ref m) => ChannelEvent::HtlcFailed(m.clone()),
+                &Update::FailMalformed(ref m) => ChannelEvent::HtlcFailedMalformed(m.clone()),
+                &Update::Fee(ref m) => ChannelEvent::FeeUpdated(m.clone()),
+            });
+        }
+        self.local_updates.compact(local_locked);
+        self.remote_updates.compact(remote_locked);
+    }
+
+    // the fundee receives the signature of the first local commitment and signs the remote one
+    fn received_funding_created(&mut self, m: FundingCreated) -> Result<Outcome, ChannelError> {
+        if self.funder != Side::Remote || self.local_signature.is_some() {
+            return Err(ChannelError::UnexpectedMessage);
+        }
+        let funding_txid: [u8; 32] = m.funding_txid.into();
+        let output_index: u16 = m.output_index.into();
+        if Sha256dHash::from(&funding_txid[..]) != self.funding_txid || output_index != self.funding_output_index {
+            return Err(ChannelError::WrongChannel);
+        }
+        self.verify_commitment_signature(&self.local_commitment, &m.signature)?;
+
+        self.local_signature = Some(m.signature.as_ref().clone());
+        Ok(Outcome::message(Message::FundingSigned(FundingSigned {
+            channel_id: self.channel_id,
+            signature: self.sign_remote_commitment().into(),
+        })))
+    }
+
+    fn received_funding_signed(&mut self, m: FundingSigned) -> Result<Outcome, ChannelError> {
+        self.check_channel(&m.channel_id)?;
+        if self.funder != Side::Local || self.local_signature.is_some() {
+            return Err(ChannelError::UnexpectedMessage);
+        }
+        self.verify_commitment_signature(&self.local_commitment, &m.signature)?;
+
+        self.local_signature = Some(m.signature.as_ref().clone());
+        Ok(Outcome::default())
+    }
+
+    fn received_funding_locked(&mut self, m: FundingLocked) -> Result<Outcome, ChannelError> {
+        self.check_channel(&m.channel_id)?;
+        // retransmitted after the reconnection, it is redundant, but harmless
+        if self.remote_locked {
+            return Ok(Outcome::default());
+        }
+        // the funding must not be locked before the first commitment can be broadcast
+        if self.local_signature.is_none() {
+            return Err(ChannelError::NotSigned);
+        }
+
+        self.remote_locked = true;
+        self.remote_next_point = Some(m.next_per_commitment_point.into());
+        Ok(Outcome::default())
+    }
+
+    fn received_add(&mut self, m: UpdateAddHtlc) -> Result<Outcome, ChannelError> {
+        self.check_channel(&m.channel_id)?;
+        if !self.remote_locked {
+            return Err(ChannelError::NotLocked);
+        }
+        if m.id != self.next_remote_htlc_id {
+            return Err(ChannelError::HtlcIdOutOfOrder);
+        }
+        if u64::from(m.amount) == 0 || m.amount < self.local_parameters.htlc_minimum {
+            return Err(ChannelError::HtlcBelowMinimum);
+        }
+        if m.expiry >= MAX_CLTV_EXPIRY {
+            return Err(ChannelError::ExpiryTooFar);
+        }
+
+        let id = m.id;
+        self.remote_updates.push(Update::Add(m));
+        if let Err(e) = self.check_offered(Side::Remote) {
+            self.remote_updates.pop();
+            return Err(e);
+        }
+        self.next_remote_htlc_id = id.next();
+        Ok(Outcome::default())
+    }
+
+    fn received_fulfill(&mut self, m: UpdateFulfillHtlc) -> Result<Outcome, ChannelError> {
+        self.check_channel(&m.channel_id)?;
+        if sha256(&m.payment_preimage) != self.committed_htlc(Side::Local, m.id)?.payment_hash {
+            return Err(ChannelError::WrongPreimage);
+        }
+
+        self.remote_updates.push(Update::Fulfill(m));
+        Ok(Outcome::default())
+    }
+
+    fn received_fail(&mut self, m: UpdateFailHtlc) -> Result<Outcome, ChannelError> {
+        self.check_channel(m.channel_id())?;
+        self.committed_htlc(Side::Local, m.id())?;
+
+        self.remote_updates.push(Update::Fail(m));
+        Ok(Outcome::default())
+    }
+
+    fn received_fail_malformed(&mut self, m: UpdateFailMalformedHtlc) -> Result<Outcome, ChannelError> {
+        self.check_channel(m.channel_id())?;
+        if !m.failure_code().is_bad_onion() {
+            return Err(ChannelError::NotBadOnion);
+        }
+        self.committed_htlc(Side::Local, m.id())?;
+
+        self.remote_updates.push(Update::FailMalformed(m));
+        Ok(Outcome::default())
+    }
+
+    // the funder must afford the fee at the new feerate in the local commitment
+    fn received_update_fee(&mut self, m: UpdateFee) -> Result<Outcome, ChannelError> {
+        self.check_channel(m.channel_id())?;
+        if self.funder != Side::Remote {
+            return Err(ChannelError::FeeByFundee);
+        }
+        if u32::from(m.fee()) < FEERATE_FLOOR {
+            return Err(ChannelError::FeerateTooLow);
+        }
+
+        self.remote_updates.push(Update::Fee(m));
+        if let Err(e) = self.check_offered(Side::Remote) {
+            self.remote_updates.pop();
+            return Err(e);
+        }
+        Ok(Outcome::default())
+    }
+
+    fn received_commitment_signed(&mut self, m: CommitmentSigned) -> Result<Outcome, ChannelError> {
+        self.check_channel(&m.channel_id)?;
+
+        // the updates of the node are included once the counterparty has acknowledged them
+        let local_to = self.remote_acked().local_updates;
+        let remote_to = self.remote_updates.len();
+        if local_to == self.local_commitment.local_updates && remote_to == self.local_commitment.remote_updates {
+            return Err(ChannelError::EmptyCommitment);
+        }
+        let mut commitment = self.next_commitment(&self.local_commitment, local_to, remote_to)?;
+        commitment.number += 1;
+        commitment.point = commitment_point(&self.local_seed, commitment.number)?;
+        self.verify_commitment_signature(&commitment, &m.signature)?;
+        self.verify_htlc_signatures(&commitment, &m.htlc_signatures)?;
+
+        let revoke_and_ack = self.revoke_and_ack(commitment.number - 1)?;
+        self.local_commitment = commitment;
+        self.local_signature = Some(m.signature.as_ref().clone());
+        self.signed_last = false;
+
+        let mut outcome = Outcome::message(revoke_and_ack);
+        self.lock_in(&mut outcome.events);
+        if self.remote_previous.is_none() && self.has_changes_for_remote() {
+            outcome.messages.push(self.sign_next()?);
+        }
+        outcome.messages.extend(self.start_closing());
+        Ok(outcome)
+    }
+
+    fn received_revoke_and_ack(&mut self, m: RevokeAndAck) -> Result<Outcome, ChannelError> {
+        self.check_channel(&m.channel_id)?;
+        {
+            let previous = self.remote_previous.as_ref().ok_or(ChannelError::UnexpectedRevocation)?;
+            let ctx = Secp256k1::new();
+            let secret = SecretKey::from_slice(&ctx, &m.revocation_preimage[..])
+                .map_err(|_| ChannelError::WrongRevocation)?;
+            let point = PublicKey::from_secret_key(&ctx, &secret).map_err(ChannelError::Crypto)?;
+            if point != previous.point {
+                return Err(ChannelError::WrongRevocation);
+            }
+        }
+        // the store checks the secret is derivable from the next ones
+        self.remote_secrets.add_leaf(Sha256Hash::from(m.revocation_preimage))
+            .map_err(|_| ChannelError::WrongRevocation)?;
+
+        self.remote_previous = None;
+        self.remote_next_point = Some(m.next_per_commitment_point.into());
+
+        let mut outcome = Outcome::default();
+        self.lock_in(&mut outcome.events);
+        if self.has_changes_for_remote() {
+            outcome.messages.push(self.sign_next()?);
+        }
+        outcome.messages.extend(self.start_closing());
+        Ok(outcome)
+    }
+
+    fn received_shutdown(&mut self, m: ShutdownChannel) -> Result<Outcome, ChannelError> {
+        self.check_channel(&m.channel_id)?;
+        if self.remote_shutdown.is_some() {
+            return Err(ChannelError::UnexpectedMessage);
+        }
+        if !is_valid_shutdown_script(&m.script) {
+            return Err(ChannelError::InvalidShutdownScript);
+        }
+        if self.remote_updates.len() > self.local_commitment.remote_updates {
+            return Err(ChannelError::UpdatesPending);
+        }
+
+        self.remote_shutdown = Some(Script::from(m.script));
+        let mut outcome = Outcome::default();
+        if self.local_shutdown.is_none() {
+            outcome.events.push(ChannelEvent::ShutdownReceived);
+        }
+        outcome.messages.extend(self.start_closing());
+        Ok(outcome)
+    }
+
+    // accepts the fee if the node has proposed it or it is acceptable, otherwise proposes
+    // the fee within the ranges of both sides, or halfway to the fee of the legacy counterparty
+    fn received_closing_signed(&mut self, m: ClosingNegotiation) -> Result<Outcome, ChannelError> {
+        self.check_channel(&m.channel_id)?;
+        if self.closed || !self.is_closing() || (self.funder == Side::Local && self.closing_fee.is_none()) {
+            return Err(ChannelError::UnexpectedMessage);
+        }
+        let fee = u64::from(m.fee);
+        let remote_range = m.fee_range().map_err(|_| ChannelError::WrongClosingFee)?;
+        if let Some(ref range) = remote_range {
+            if fee < u64::from(range.min) || fee > u64::from(range.max) {
+                return Err(ChannelError::WrongClosingFee);
+            }
+        }
+        let closing_tx = self.closing_tx(fee, self.remote_parameters.dust_limit);
+        closing_tx.verify(m.signature.as_ref()).map_err(|_| ChannelError::WrongClosingSignature)?;
+
+        let (proposed, local_range) = self.closing_fees();
+        let last = self.closing_fee.unwrap_or(proposed);
+        let (min, max) = match remote_range {
+            Some(range) => (
+                cmp::max(u64::from(local_range.min), u64::from(range.min)),
+                cmp::min(u64::from(local_range.max), u64::from(range.max)),
+            ),
+            None => (u64::from(local_range.min), u64::from(local_range.max)),
+        };
+        if min > max {
+            return Err(ChannelError::FeeRangeMismatch);
+        }
+
+        let mut outcome = Outcome::default();
+        if self.closing_fee != Some(fee) {
+            let next = if fee >= min && fee <= max {
+                fee
+            } else if remote_range.is_some() {
+                cmp::min(cmp::max(last, min), max)
+            } else {
+                let next = cmp::min(cmp::max((last + fee) / 2, min), max);
+                if next == last {
+                    return Err(ChannelError::FeeRangeMismatch);
+                }
+                next
+            };
+            outcome.messages.push(self.sign_closing(next));
+            if next != fee {
+                return Ok(outcome);
+            }
+        }
+
+        let local_signature = closing_tx.sign(self.local_keys.funding_sk().as_ref());
+        self.closed = true;
+        outcome.events.push(ChannelEvent::Closed(closing_tx.signed_tx(&local_signature, m.signature.as_ref())));
+        Ok(outcome)
+    }
+
+    // retransmits what the counterparty has missed, `revoke_and_ack` and `commitment_signed`
+    // in the order they were sent, then the updates it has forgotten
+    fn received_reestablish(&mut self, m: ReestablishChannel) -> Result<Outcome, ChannelError> {
+        self.check_channel(&m.channel_id)?;
+        if !self.disconnected {
+            return Err(ChannelError::UnexpectedMessage);
+        }
+        let local_number = self.local_commitment.number;
+        let remote_number = self.remote_commitment.number;
+        let next_commitment = m.next_local_commitment_number;
+        let next_revocation = m.next_remote_revocation_number;
+
+        // the counterparty knows the secret of the revocation the node has not sent yet,
+        // its data is newer, the local commitment may be revoked
+        let expected_secret = match next_revocation {
+            0 => [0; 32],
+            n => commitment_secret(&self.local_seed, n - 1),
+        };
+        if m.last_remote_commit_secret != expected_secret {
+            return Err(ChannelError::WrongRevocation);
+        }
+        if next_revocation > local_number {
+            self.data_loss = Some(m.local_unrevoked_commit_point.into());
+            return Err(ChannelError::DataLoss);
+        }
+
+        let revoke_and_ack = if next_revocation == local_number {
+            None
+        } else if next_revocation + 1 == local_number {
+            Some(self.revoke_and_ack(next_revocation)?)
+        } else {
+            return Err(ChannelError::WrongReestablish);
+        };
+        let commitment_signed = if next_commitment == remote_number + 1 {
+            Vec::new()
+        } else if self.remote_previous.is_some() && next_commitment == remote_number {
+            // the updates it includes are forgotten as well
+            let from = self.remote_acked().local_updates;
+            let mut messages = self.local_updates.range(from, self.remote_commitment.local_updates)
+                .map(Update::message)
+                .collect::<Vec<_>>();
+            messages.push(self.commitment_signed());
+            messages
+        } else {
+            return Err(ChannelError::WrongReestablish);
+        };
+
+        self.disconnected = false;
+        let mut outcome = Outcome::default();
+        if self.remote_locked && next_commitment == 1 && local_number == 0 {
+            outcome.messages.push(Message::FundingLocked(self.funding_locked()?));
+        }
+        if self.signed_last {
+            outcome.messages.extend(revoke_and_ack);
+            outcome.messages.extend(commitment_signed);
+        } else {
+            outcome.messages.extend(commitment_signed);
+            outcome.messages.extend(revoke_and_ack);
+        }
+        if let Some(ref script) = self.local_shutdown {
+            outcome.messages.push(Message::ShutdownChannel(ShutdownChannel {
+                channel_id: self.channel_id,
+                script: script.data(),
+            }));
+        }
+        outcome.messages.extend(
+            self.local_updates.range(self.remote_commitment.local_updates, self.local_updates.len())
+                .map(Update::message)
+        );
+        outcome.messages.extend(self.start_closing());
+        Ok(outcome)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wire::{FundingTxid, OutputIndex};
+
+    use rand;
+
+    fn parameters() -> ChannelParameters {
+        ChannelParameters {
+            dust_limit: Satoshi::from(546),
+            max_htlc_value_in_flight: MilliSatoshi::from(5000000000),
+            channel_reserve: Satoshi::from(10000),
+            htlc_minimum: MilliSatoshi::from(1000),
+            csv_delay: CsvDelay::from(144),
+            max_accepted_htlc_number: 3,
+        }
+    }
+
+    // the funder and the fundee of 0.1 btc channel, 0.03 btc is pushed, nothing is signed yet
+    fn unsigned_pair() -> (Channel, Channel) {
+        unsigned_pair_with(&rand::random(), &rand::random())
+    }
+
+    fn unsigned_pair_with(keys: &(ChannelPrivateKeys, ChannelPrivateKeys), seeds: &([u8; 32], [u8; 32])) -> (Channel, Channel) {
+        let public = |keys: &ChannelPrivateKeys, seed: &[u8; 32]| {
+            let keys = keys.clone().with_first_per_commitment(per_commitment_secret(seed, 0).unwrap().into());
+            ChannelKeys::new(&keys).unwrap()
+        };
+        let setup = |funder: Side, local: &ChannelPrivateKeys, seed: &[u8; 32], remote: ChannelKeys| ChannelSetup {
+            funder: funder,
+            funding: Satoshi::from(10000000),
+            push: MilliSatoshi::from(3000000000),
+            feerate: SatoshiPerKiloWeight::from(15000),
+            funding_txid: [0x8a; 32],
+            funding_output_index: 1,
+            local_parameters: parameters(),
+            remote_parameters: parameters(),
+            local_keys: local.clone(),
+            local_seed: seed.clone(),
+            remote_keys: remote,
+        };
+
+        let a = setup(Side::Local, &keys.0, &seeds.0, public(&keys.1, &seeds.1));
+        let b = setup(Side::Remote, &keys.1, &seeds.1, public(&keys.0, &seeds.0));
+        let a = Channel::new(a).unwrap();
+        let b = Channel::new(b).unwrap();
+        assert_eq!(a.remote_commitment_tx(), b.local_commitment_tx());
+        assert_eq!(b.remote_commitment_tx(), a.local_commitment_tx());
+        (a, b)
+    }
+
+    fn funding_created(a: &Channel) -> FundingCreated {
+        FundingCreated {
+            temporary_channel_id: ChannelId::all(),
+            funding_txid: FundingTxid::from([0x8a; 32]),
+            output_index: OutputIndex::from(1),
+            signature: a.sign_remote_commitment().into(),
+        }
+    }
+
+    // the funding is signed and locked
+    fn pair() -> (Channel, Channel) {
+        lock(unsigned_pair())
+    }
+
+    fn lock((mut a, mut b): (Channel, Channel)) -> (Channel, Channel) {
+        let messages = b.receive(Message::FundingCreated(funding_created(&a))).unwrap().messages;
+        exchange(&mut a, &mut b, messages, vec![]);
+
+        b.receive(Message::FundingLocked(a.funding_locked().unwrap())).unwrap();
+        a.receive(Message::FundingLocked(b.funding_locked().unwrap())).unwrap();
+        (a, b)
+    }
+
+    // delivers the messages in order in both directions until both are silent, returns the events
+    fn exchange(a: &mut Channel, b: &mut Channel, to_a: Vec<Message>, to_b: Vec<Message>) -> (Vec<ChannelEvent>, Vec<ChannelEvent>) {
+        exchange_limited(a, b, to_a, to_b, usize::max_value())
+    }
+
+    // the same, but the connection drops after `limit` messages, the rest is lost
+    fn exchange_limited(
+        a: &mut Channel,
+        b: &mut Channel,
+        to_a: Vec<Message>,
+        to_b: Vec<Message>,
+        limit: usize,
+    ) -> (Vec<ChannelEvent>, Vec<ChannelEvent>) {
+        let mut events = (Vec::new(), Vec::new());
+        let mut to_a = to_a.into_iter().collect::<VecDeque<_>>();
+        let mut to_b = to_b.into_iter().collect::<VecDeque<_>>();
+        let mut delivered = 0;
+        while (!to_a.is_empty() || !to_b.is_empty()) && delivered < limit {
+            if let Some(message) = to_b.pop_front() {
+                let outcome = b.receive(message).unwrap();
+                to_a.extend(outcome.messages);
+                events.1.extend(outcome.events);
+                delivered += 1;
+            }
+            if delivered == limit {
+                break;
+            }
+            if let Some(message) = to_a.pop_front() {
+                let outcome = a.receive(message).unwrap();
+                to_b.extend(outcome.messages);
+                events.0.extend(outcome.events);
+                delivered += 1;
+            }
+        }
+        events
+    }
+
+    // both sides have lost the connection, returns `channel_reestablish` to each
+    fn reconnect(a: &mut Channel, b: &mut Channel) -> (Vec<Message>, Vec<Message>) {
+        a.disconnect();
+        b.disconnect();
+        (
+            vec![Message::ReestablishChannel(b.reestablish().unwrap())],
+            vec![Message::ReestablishChannel(a.reestablish().unwrap())],
+        )
+    }
+
+    fn add(a: &mut Channel, amount: u64, preimage: &[u8; 32]) -> (HtlcId, Vec<Message>) {
+        let onion = OnionBlob::from_vec(vec![0; 1366]).unwrap();
+        let (id, outcome) = a.add_htlc(MilliSatoshi::from(amount), Hash256::from(sha256(preimage)), 500, onion).unwrap();
+        (id, outcome.messages)
+    }
+
+    fn sign(a: &mut Channel, messages: Vec<Message>) -> Vec<Message> {
+        let mut messages = messages;
+        messages.extend(a.sign().unwrap().messages);
+        messages
+    }
+
+    fn assert_consistent(a: &Channel, b: &Channel) {
+        assert_eq!(a.remote_commitment_tx(), b.local_commitment_tx());
+        assert_eq!(b.remote_commitment_tx(), a.local_commitment_tx());
+    }
+
+    #[test]
+    fn fulfill() {
+        let (mut a, mut b) = pair();
+        let preimage = [0x11; 32];
+
+        let (id, messages) = add(&mut a, 2000000000, &preimage);
+        let messages = sign(&mut a, messages);
+        let (a_events, b_events) = exchange(&mut a, &mut b, vec![], messages);
+        assert!(a_events.is_empty());
+        match b_events.as_slice() {
+            &[ChannelEvent::HtlcReceived(ref m)] => assert_eq!(m.id, id),
+            e => panic!("unexpected events: {:?}", e),
+        }
+        assert_eq!(a.commitment_numbers(), (1, 1));
+        assert_eq!(b.balances(), (MilliSatoshi::from(3000000000), MilliSatoshi::from(5000000000)));
+        assert_consistent(&a, &b);
+
+        assert_eq!(b.fulfill_htlc(id, [0x22; 32]).err(), Some(ChannelError::WrongPreimage));
+        let messages = b.fulfill_htlc(id, preimage).unwrap().messages;
+        assert_eq!(b.fulfill_htlc(id, preimage).err(), Some(ChannelError::HtlcAlreadyRemoved));
+        let messages = sign(&mut b, messages);
+        let (b_events, a_events) = exchange(&mut b, &mut a, vec![], messages);
+        assert!(b_events.is_empty());
+        match a_events.as_slice() {
+            &[ChannelEvent::HtlcFulfilled(ref m)] => assert_eq!(m.payment_preimage, preimage),
+            e => panic!("unexpected events: {:?}", e),
+        }
+        assert_eq!(a.commitment_numbers(), (2, 2));
+        assert_eq!(a.balances(), (MilliSatoshi::from(5000000000), MilliSatoshi::from(5000000000)));
+        assert_consistent(&a, &b);
+        assert_eq!(b.sign().err(), Some(ChannelError::NoUpdates));
+    }
+
+    #[test]
+    fn fail() {
+        let (mut a, mut b) = pair();
+
+        let (id, messages) = add(&mut a, 1000000, &[0x11; 32]);
+        let messages = sign(&mut a, messages);
+        exchange(&mut a, &mut b, vec![], messages);
+
+        let messages = b.fail_htlc(id, OpaqueReason::from(vec![1, 2, 3])).unwrap().messages;
+        let messages = sign(&mut b, messages);
+        let (_, a_events) = exchange(&mut b, &mut a, vec![], messages);
+        match a_events.as_slice() {
+            &[ChannelEvent::HtlcFailed(ref m)] => assert_eq!(m.id(), id),
+            e => panic!("unexpected events: {:?}", e),
+        }
+        assert_eq!(a.balances(), (MilliSatoshi::from(7000000000), MilliSatoshi::from(3000000000)));
+        assert_consistent(&a, &b);
+    }
+
+    #[test]
+    fn concurrent() {
+        let (mut a, mut b) = pair();
+
+        // both offer and sign at once, the updates cross
+        let (a_id, a_messages) = add(&mut a, 1000000000, &[0x11; 32]);
+        let (b_id, b_messages) = add(&mut b, 1000000000, &[0x22; 32]);
+        let a_messages = sign(&mut a, a_messages);
+        let b_messages = sign(&mut b, b_messages);
+        assert_eq!(a.sign().err(), Some(ChannelError::AwaitingRevocation));
+
+        let (a_events, b_events) = exchange(&mut a, &mut b, b_messages, a_messages);
+        match a_events.as_slice() {
+            &[ChannelEvent::HtlcReceived(ref m)] => assert_eq!(m.id, b_id),
+            e => panic!("unexpected events: {:?}", e),
+        }
+        match b_events.as_slice() {
+            &[ChannelEvent::HtlcReceived(ref m)] => assert_eq!(m.id, a_id),
+            e => panic!("unexpected events: {:?}", e),
+        }
+        assert_eq!(a.balances(), (MilliSatoshi::from(6000000000), MilliSatoshi::from(2000000000)));
+        assert_consistent(&a, &b);
+    }
+
+    #[test]
+    fn validation() {
+        let (mut a, mut b) = pair();
+        let onion = || OnionBlob::from_vec(vec![0; 1366]).unwrap();
+        let channel_id = a.channel_id();
+        let update = |id: HtlcId, amount: u64, expiry: u32| Message::UpdateAddHtlc(UpdateAddHtlc {
+            channel_id: channel_id,
+            id: id,
+            amount: MilliSatoshi::from(amount),
+            payment: Hash256::from([0x11; 32]),
+            expiry: expiry,
+            onion_blob: onion(),
+        });
+
+        // the signature is correct, but the commitment is the same
+        let empty = CommitmentSigned {
+            channel_id: channel_id,
+            signature: a.sign_remote_commitment().into(),
+            htlc_signatures: vec![],
+        };
+        assert_eq!(b.receive(Message::CommitmentSigned(empty)).err(), Some(ChannelError::EmptyCommitment));
+        assert_eq!(b.commitment_numbers(), (0, 0));
+
+        let first = HtlcId::new();
+        assert_eq!(b.receive(update(first.next(), 1000000, 500)).err(), Some(ChannelError::HtlcIdOutOfOrder));
+        assert_eq!(b.receive(update(first, 999, 500)).err(), Some(ChannelError::HtlcBelowMinimum));
+        assert_eq!(b.receive(update(first, 1000000, 500000000)).err(), Some(ChannelError::ExpiryTooFar));
+        assert_eq!(b.receive(update(first, 6000000000, 500)).err(), Some(ChannelError::MaxInFlightExceeded));
+        // the fundee would go below the reserve
+        assert_eq!(a.receive(update(first, 2995000000, 500)).err(), Some(ChannelError::CannotAfford));
+        let amount = MilliSatoshi::from(2995000000);
+        assert_eq!(b.add_htlc(amount, Hash256::from([0x11; 32]), 500, onion()).err(), Some(ChannelError::CannotAfford));
+
+        let mut id = first;
+        for _ in 0..3 {
+            b.receive(update(id, 1000000, 500)).unwrap();
+            id = id.next();
+        }
+        assert_eq!(b.receive(update(id, 1000000, 500)).err(), Some(ChannelError::TooManyHtlcs));
+
+        // nothing is committed yet
+        let fulfill = UpdateFulfillHtlc {
+            channel_id: channel_id,
+            id: first,
+            payment_preimage: [0x11; 32],
+        };
+        assert_eq!(a.receive(Message::UpdateFulfillHtlc(fulfill)).err(), Some(ChannelError::UnknownHtlc));
+        let revoke_and_ack = RevokeAndAck {
+            channel_id: channel_id,
+            revocation_preimage: [0x11; 32],
+            next_per_commitment_point: LpdPublicKey::from(commitment_point(&[0x11; 32], 0).unwrap()),
+        };
+        assert_eq!(a.receive(Message::RevokeAndAck(revoke_and_ack.clone())).err(), Some(ChannelError::UnexpectedRevocation));
+
+        // the secret does not match the commitment
+        a.add_htlc(MilliSatoshi::from(1000000), Hash256::from([0x11; 32]), 500, onion()).unwrap();
+        a.sign().unwrap();
+        assert_eq!(a.receive(Message::RevokeAndAck(revoke_and_ack)).err(), Some(ChannelError::WrongRevocation));
+
+        let wrong_channel = FundingLocked {
+            channel_id: ChannelId::all(),
+            next_per_commitment_point: LpdPublicKey::from(commitment_point(&[0x11; 32], 0).unwrap()),
+        };
+        assert_eq!(a.receive(Message::FundingLocked(wrong_channel)).err(), Some(ChannelError::WrongChannel));
+    }
+
+    #[test]
+    fn update_fee() {
+        let (mut a, mut b) = pair();
+        let channel_id = a.channel_id();
+        // the funding is 0.1 btc, no htlcs
+        let fee_paid = |tx: Transaction| 10000000 - tx.output.iter().map(|o| o.value).sum::<u64>();
+        let before = fee_paid(b.local_commitment_tx());
+
+        let messages = a.update_fee(SatoshiPerKiloWeight::from(30000)).unwrap().messages;
+        let messages = sign(&mut a, messages);
+        let (_, b_events) = exchange(&mut a, &mut b, vec![], messages);
+        match b_events.as_slice() {
+            &[ChannelEvent::FeeUpdated(ref m)] => assert_eq!(m.fee(), SatoshiPerKiloWeight::from(30000)),
+            e => panic!("unexpected events: {:?}", e),
+        }
+        assert_eq!(a.commitment_numbers(), (1, 1));
+        assert_consistent(&a, &b);
+        // the weight is the same, the funder pays twice as much
+        assert_eq!(fee_paid(b.local_commitment_tx()), before * 2);
+        assert_eq!(fee_paid(a.local_commitment_tx()), before * 2);
+
+        // the funding_locked retransmitted after the reconnection is ignored
+        let outcome = b.receive(Message::FundingLocked(a.funding_locked().unwrap())).unwrap();
+        assert!(outcome.messages.is_empty() && outcome.events.is_empty());
+
+        let fee = |feerate: u32| Message::UpdateFee(UpdateFee::new(channel_id, SatoshiPerKiloWeight::from(feerate)));
+        assert_eq!(b.update_fee(SatoshiPerKiloWeight::from(30000)).err(), Some(ChannelError::FeeByFundee));
+        assert_eq!(a.receive(fee(30000)).err(), Some(ChannelError::FeeByFundee));
+        assert_eq!(a.update_fee(SatoshiPerKiloWeight::from(252)).err(), Some(ChannelError::FeerateTooLow));
+        assert_eq!(b.receive(fee(252)).err(), Some(ChannelError::FeerateTooLow));
+        // the funder cannot pay the fee and keep the reserve
+        assert_eq!(a.update_fee(SatoshiPerKiloWeight::from(10000000)).err(), Some(ChannelError::CannotAfford));
+        assert_eq!(b.receive(fee(10000000)).err(), Some(ChannelError::CannotAfford));
+        assert!(!a.has_changes_for_remote());
+        assert_eq!(b.remote_updates.len(), b.local_commitment.remote_updates);
+    }
+
+    #[test]
+    fn htlc_signatures() {
+        let (mut a, mut b) = pair();
+
+        // untrimmed, each one has the second stage transaction
+        let (_, mut messages) = add(&mut a, 100000000, &[0x11; 32]);
+        messages.extend(add(&mut a, 200000000, &[0x22; 32]).1);
+        for message in messages {
+            b.receive(message).unwrap();
+        }
+        let commitment_signed = match a.sign().unwrap().messages.pop() {
+            Some(Message::CommitmentSigned(m)) => m,
+            m => panic!("unexpected message: {:?}", m),
+        };
+        assert_eq!(commitment_signed.htlc_signatures.len(), 2);
+
+        let tampered = |htlc_signatures: Vec<LpdSignature>| Message::CommitmentSigned(CommitmentSigned {
+            channel_id: commitment_signed.channel_id,
+            signature: commitment_signed.signature.clone(),
+            htlc_signatures: htlc_signatures,
+        });
+        let signatures = commitment_signed.htlc_signatures.clone();
+        let swapped = vec![signatures[1].clone(), signatures[0].clone()];
+        assert_eq!(b.receive(tampered(swapped)).err(), Some(ChannelError::WrongHtlcSignature));
+        let missing = vec![signatures[0].clone()];
+        assert_eq!(b.receive(tampered(missing)).err(), Some(ChannelError::WrongHtlcSignatureCount));
+
+        exchange(&mut a, &mut b, vec![], vec![Message::CommitmentSigned(commitment_signed)]);
+        assert_eq!(b.commitment_numbers(), (1, 1));
+        assert_consistent(&a, &b);
+    }
+
+    #[test]
+    fn commitment_signatures() {
+        let (mut a, mut b) = unsigned_pair();
+        assert_eq!(b.signed_local_commitment_tx().err(), Some(ChannelError::NotSigned));
+        let funding_locked = a.funding_locked().unwrap();
+        assert_eq!(b.receive(Message::FundingLocked(funding_locked)).err(), Some(ChannelError::NotSigned));
+
+        // the funder signs its own commitment instead of the one of the fundee
+        let mut wrong = funding_created(&a);
+        wrong.signature = a.commit_tx(Side::Local, &a.local_commitment).sign(a.local_keys.funding_sk().as_ref()).into();
+        assert_eq!(b.receive(Message::FundingCreated(wrong)).err(), Some(ChannelError::WrongCommitmentSignature(0)));
+        let mut wrong = funding_created(&a);
+        wrong.output_index = OutputIndex::from(0);
+        assert_eq!(b.receive(Message::FundingCreated(wrong)).err(), Some(ChannelError::WrongChannel));
+        assert_eq!(a.receive(Message::FundingCreated(funding_created(&a))).err(), Some(ChannelError::UnexpectedMessage));
+
+        let funding_signed = match b.receive(Message::FundingCreated(funding_created(&a))).unwrap().messages.pop() {
+            Some(Message::FundingSigned(m)) => m,
+            m => panic!("unexpected message: {:?}", m),
+        };
+        let wrong = FundingSigned {
+            channel_id: funding_signed.channel_id,
+            signature: b.commit_tx(Side::Local, &b.local_commitment).sign(b.local_keys.funding_sk().as_ref()).into(),
+        };
+        assert_eq!(a.receive(Message::FundingSigned(wrong)).err(), Some(ChannelError::WrongCommitmentSignature(0)));
+        a.receive(Message::FundingSigned(funding_signed)).unwrap();
+
+        // both can broadcast the first commitment
+        for channel in &[&a, &b] {
+            let tx = channel.signed_local_commitment_tx().unwrap();
+            assert_eq!(tx.input[0].witness.len(), 4);
+            let mut unsigned = tx.clone();
+            unsigned.input[0].witness = vec![];
+            assert_eq!(unsigned, channel.local_commitment_tx());
+        }
+
+        b.receive(Message::FundingLocked(a.funding_locked().unwrap())).unwrap();
+        a.receive(Message::FundingLocked(b.funding_locked().unwrap())).unwrap();
+        let (_, messages) = add(&mut a, 100000000, &[0x11; 32]);
+        for message in messages {
+            b.receive(message).unwrap();
+        }
+        // the signature of the previous commitment
+        let stale = a.sign_remote_commitment();
+        let commitment_signed = match a.sign().unwrap().messages.pop() {
+            Some(Message::CommitmentSigned(m)) => m,
+            m => panic!("unexpected message: {:?}", m),
+        };
+        let wrong = CommitmentSigned {
+            channel_id: commitment_signed.channel_id,
+            signature: stale.into(),
+            htlc_signatures: commitment_signed.htlc_signatures.clone(),
+        };
+        assert_eq!(b.receive(Message::CommitmentSigned(wrong)).err(), Some(ChannelError::WrongCommitmentSignature(1)));
+        assert_eq!(b.commitment_numbers(), (0, 0));
+
+        exchange(&mut a, &mut b, vec![], vec![Message::CommitmentSigned(commitment_signed)]);
+        let tx = b.signed_local_commitment_tx().unwrap();
+        assert_eq!(tx.output.len(), 3);
+        assert_consistent(&a, &b);
+    }
+
+    fn p2wpkh(byte: u8) -> Vec<u8> {
+        let mut script = vec![0x00, 0x14];
+        script.extend_from_slice(&[byte; 20]);
+        script
+    }
+
+    // both sides send `shutdown`, returns what the funder answers
+    fn shutdown(a: &mut Channel, b: &mut Channel) -> Vec<Message> {
+        let mut to_a = Vec::new();
+        for message in a.shutdown(p2wpkh(0xaa)).unwrap().messages {
+            let outcome = b.receive(message).unwrap();
+            assert_eq!(outcome.events, vec![ChannelEvent::ShutdownReceived]);
+            to_a.extend(outcome.messages);
+        }
+        to_a.extend(b.shutdown(p2wpkh(0xbb)).unwrap().messages);
+        to_a.into_iter().flat_map(|message| a.receive(message).unwrap().messages).collect()
+    }
+
+    fn closing_tx(events: &[ChannelEvent]) -> Option<Transaction> {
+        events.iter()
+            .filter_map(|event| match event {
+                &ChannelEvent::Closed(ref tx) => Some(tx.clone()),
+                _ => None,
+            })
+            .next()
+    }
+
+    // `closing_signed` of the funder unaware of the fee range
+    fn legacy_closing_signed(a: &Channel, fee: u64) -> Message {
+        let signature = a.closing_tx(fee, a.local_parameters.dust_limit).sign(a.local_keys.funding_sk().as_ref());
+        Message::ClosingNegotiation(ClosingNegotiation::new(a.channel_id, Satoshi::from(fee), signature.into(), None))
+    }
+
+    fn proposed_fee(messages: &[Message]) -> u64 {
+        match messages {
+            &[Message::ClosingNegotiation(ref m)] => {
+                assert!(m.fee_range().unwrap().is_some());
+                u64::from(m.fee)
+            },
+            m => panic!("unexpected messages: {:?}", m),
+        }
+    }
+
+    #[test]
+    fn mutual_close() {
+        let (mut a, mut b) = pair();
+        let preimage = [0x11; 32];
+        let onion = || OnionBlob::from_vec(vec![0; 1366]).unwrap();
+
+        let (id, messages) = add(&mut a, 1000000000, &preimage);
+        let messages = sign(&mut a, messages);
+        exchange(&mut a, &mut b, vec![], messages);
+
+        // the htlc remains, no new ones are offered
+        assert!(shutdown(&mut a, &mut b).is_empty());
+        let add_htlc = |c: &mut Channel| c.add_htlc(MilliSatoshi::from(1000000), Hash256::from([0x11; 32]), 500, onion()).err();
+        assert_eq!(add_htlc(&mut a), Some(ChannelError::ShuttingDown));
+        assert_eq!(add_htlc(&mut b), Some(ChannelError::ShuttingDown));
+        assert_eq!(a.shutdown(p2wpkh(0xaa)).err(), Some(ChannelError::ShuttingDown));
+
+        // the funder proposes the fee once the htlc is removed, the fundee accepts it
+        let messages = b.fulfill_htlc(id, preimage).unwrap().messages;
+        let messages = sign(&mut b, messages);
+        let (b_events, a_events) = exchange(&mut b, &mut a, vec![], messages);
+        let tx = closing_tx(&a_events).unwrap();
+        assert_eq!(closing_tx(&b_events), Some(tx.clone()));
+
+        // 676 weight at 15000 satoshi per kw
+        assert_eq!(tx.output.len(), 2);
+        assert_eq!(tx.output[0].value, 4000000);
+        assert_eq!(tx.output[0].script_pubkey, Script::from(p2wpkh(0xbb)));
+        assert_eq!(tx.output[1].value, 6000000 - 10140);
+        assert_eq!(tx.output[1].script_pubkey, Script::from(p2wpkh(0xaa)));
+        assert_eq!(tx.input[0].witness.len(), 4);
+
+        let repeated = legacy_closing_signed(&a, 10140);
+        assert_eq!(b.receive(repeated).err(), Some(ChannelError::UnexpectedMessage));
+    }
+
+    #[test]
+    fn legacy_closing_negotiation() {
+        // the fundee counters halfway, but not beyond its range
+        let (mut a, mut b) = pair();
+        assert_eq!(proposed_fee(&shutdown(&mut a, &mut b)), 10140);
+        let messages = b.receive(legacy_closing_signed(&a, 3000)).unwrap().messages;
+        assert_eq!(proposed_fee(&messages), (10140 + 3000) / 2);
+        let outcome = b.receive(legacy_closing_signed(&a, 6000)).unwrap();
+        assert_eq!(proposed_fee(&outcome.messages), 6000);
+        let tx = closing_tx(&outcome.events).unwrap();
+        assert_eq!(tx.output.iter().map(|o| o.value).sum::<u64>(), 10000000 - 6000);
+
+        let (mut a, mut b) = pair();
+        shutdown(&mut a, &mut b);
+        let messages = b.receive(legacy_closing_signed(&a, 20000)).unwrap().messages;
+        // at most the fee of the commitment, 724 weight
+        assert_eq!(proposed_fee(&messages), 10860);
+        assert_eq!(b.receive(legacy_closing_signed(&a, 12000)).err(), Some(ChannelError::FeeRangeMismatch));
+    }
+
+    #[test]
+    fn closing_validation() {
+        let (mut a, mut b) = pair();
+        assert_eq!(b.receive(legacy_closing_signed(&a, 10000)).err(), Some(ChannelError::UnexpectedMessage));
+        assert_eq!(a.shutdown(vec![0x51, 0x20]).err(), Some(ChannelError::InvalidShutdownScript));
+        let invalid = ShutdownChannel {
+            channel_id: a.channel_id(),
+            script: vec![0x6a],
+        };
+        assert_eq!(b.receive(Message::ShutdownChannel(invalid)).err(), Some(ChannelError::InvalidShutdownScript));
+
+        // the update is not signed
+        let (_, messages) = add(&mut a, 1000000, &[0x11; 32]);
+        assert_eq!(a.shutdown(p2wpkh(0xaa)).err(), Some(ChannelError::UpdatesPending));
+        let messages = sign(&mut a, messages);
+        exchange(&mut a, &mut b, vec![], messages);
+        assert!(a.shutdown(p2wpkh(0xaa)).is_ok());
+
+        let (mut a, mut b) = pair();
+        shutdown(&mut a, &mut b);
+        let signature = |c: &Channel, fee: u64| -> LpdSignature {
+            c.closing_tx(fee, c.local_parameters.dust_limit)
+                .sign(c.local_keys.funding_sk().as_ref())
+                .into()
+        };
+        let closing_signed = |fee: u64, signature: LpdSignature, min: u64, max: u64| {
+            let fee_range = FeeRange { min: Satoshi::from(min), max: Satoshi::from(max) };
+            Message::ClosingNegotiation(ClosingNegotiation::new(a.channel_id(), Satoshi::from(fee), signature, Some(fee_range)))
+        };
+        let m = closing_signed(10000, signature(&b, 10000), 5000, 10860);
+        assert_eq!(b.receive(m).err(), Some(ChannelError::WrongClosingSignature));
+        let m = closing_signed(10000, signature(&a, 10000), 10001, 10860);
+        assert_eq!(b.receive(m).err(), Some(ChannelError::WrongClosingFee));
+        let m = closing_signed(20000, signature(&a, 20000), 20000, 30000);
+        assert_eq!(b.receive(m).err(), Some(ChannelError::FeeRangeMismatch));
+
+        // the fundee proposes its fee within the overlap
+        let m = closing_signed(4000, signature(&a, 4000), 1000, 11000);
+        assert_eq!(proposed_fee(&b.receive(m).unwrap().messages), 10140);
+    }
+
+    // the payment of 0.01 btc is interrupted after each message, both sides retransmit
+    // what the other has missed, nothing is lost or processed twice
+    #[test]
+    fn reestablish() {
+        let preimage = [0x11; 32];
+        // the update, the signature, the revocation and the signature, the revocation
+        for limit in 0..6 {
+            let (mut a, mut b) = pair();
+            let (id, messages) = add(&mut a, 1000000000, &preimage);
+            let messages = sign(&mut a, messages);
+            let (mut a_events, mut b_events) = exchange_limited(&mut a, &mut b, vec![], messages, limit);
+            let (to_a, to_b) = reconnect(&mut a, &mut b);
+            let events = exchange(&mut a, &mut b, to_a, to_b);
+            a_events.extend(events.0);
+            b_events.extend(events.1);
+            assert!(a_events.is_empty());
+            match b_events.as_slice() {
+                &[ChannelEvent::HtlcReceived(ref m)] => assert_eq!(m.id, id),
+                e => panic!("unexpected events at {}: {:?}", limit, e),
+            }
+            assert_eq!(a.commitment_numbers(), (1, 1));
+            assert_consistent(&a, &b);
+
+            let messages = b.fulfill_htlc(id, preimage).unwrap().messages;
+            let messages = sign(&mut b, messages);
+            let (mut b_events, mut a_events) = exchange_limited(&mut b, &mut a, vec![], messages, limit);
+            let (to_b, to_a) = reconnect(&mut b, &mut a);
+            let events = exchange(&mut b, &mut a, to_b, to_a);
+            b_events.extend(events.0);
+            a_events.extend(events.1);
+            assert!(b_events.is_empty());
+            match a_events.as_slice() {
+                &[ChannelEvent::HtlcFulfilled(ref m)] => assert_eq!(m.payment_preimage, preimage),
+                e => panic!("unexpected events at {}: {:?}", limit, e),
+            }
+            assert_eq!(a.commitment_numbers(), (2, 2));
+            assert_eq!(a.balances(), (MilliSatoshi::from(6000000000), MilliSatoshi::from(4000000000)));
+            assert_consistent(&a, &b);
+            assert!(a.signed_local_commitment_tx().is_ok());
+            assert!(b.signed_local_commitment_tx().is_ok());
+        }
+    }
+
+    #[test]
+    fn reestablish_unsigned_updates() {
+        let (mut a, mut b) = pair();
+
+        // the counterparty forgets the update it has no signature for, the update is sent again
+        let (id, messages) = add(&mut a, 1000000000, &[0x11; 32]);
+        exchange(&mut a, &mut b, vec![], messages);
+        let (to_a, to_b) = reconnect(&mut a, &mut b);
+        let fulfill = UpdateFulfillHtlc {
+            channel_id: a.channel_id(),
+            id: id,
+            payment_preimage: [0x11; 32],
+        };
+        assert_eq!(a.receive(Message::UpdateFulfillHtlc(fulfill)).err(), Some(ChannelError::NotReestablished));
+        let (_, b_events) = exchange(&mut a, &mut b, to_a, to_b);
+        assert!(b_events.is_empty());
+        let reestablish = Message::ReestablishChannel(a.reestablish().unwrap());
+        assert_eq!(b.receive(reestablish).err(), Some(ChannelError::UnexpectedMessage));
+
+        // the id is the same
+        let messages = a.sign().unwrap().messages;
+        let (_, b_events) = exchange(&mut a, &mut b, vec![], messages);
+        match b_events.as_slice() {
+            &[ChannelEvent::HtlcReceived(ref m)] => assert_eq!(m.id, id),
+            e => panic!("unexpected events: {:?}", e),
+        }
+        assert_consistent(&a, &b);
+    }
+
+    #[test]
+    fn data_loss_protect() {
+        let keys = rand::random();
+        let seeds = rand::random();
+        let (mut a, mut b) = lock(unsigned_pair_with(&keys, &seeds));
+        // the fundee restored from the backup made right after the funding
+        let (_, mut stale) = lock(unsigned_pair_with(&keys, &seeds));
+
+        let (_, messages) = add(&mut a, 1000000000, &[0x11; 32]);
+        let messages = sign(&mut a, messages);
+        exchange(&mut a, &mut b, vec![], messages);
+
+        // the counterparty proves it has got the revocation of the first commitment
+        a.disconnect();
+        stale.disconnect();
+        let reestablish = Message::ReestablishChannel(a.reestablish().unwrap());
+        assert_eq!(stale.receive(reestablish).err(), Some(ChannelError::DataLoss));
+        assert_eq!(stale.signed_local_commitment_tx().err(), Some(ChannelError::DataLoss));
+        assert_eq!(stale.data_loss_point(), Some(&a.local_commitment.point));
+
+        // the counterparty does not expect such numbers
+        let reestablish = Message::ReestablishChannel(stale.reestablish().unwrap());
+        assert_eq!(a.receive(reestablish).err(), Some(ChannelError::WrongReestablish));
+        assert!(a.signed_local_commitment_tx().is_ok());
+
+        // the claim is not proved by the secret
+        b.disconnect();
+        let point = LpdPublicKey::from(a.local_commitment.point.clone());
+        let forged = ReestablishChannel::new(a.channel_id(), 2, 2, [0x11; 32], point);
+        assert_eq!(b.receive(Message::ReestablishChannel(forged)).err(), Some(ChannelError::WrongRevocation));
+        assert!(b.data_loss_point().is_none());
+    }
+}
@@ -8,8 +8,9 @@ use bitcoin::blockdata::opcodes::All::*;
 
 use bitcoin::network::encodable::{ConsensusDecodable};
 use bitcoin::network::serialize::{RawDecoder};
+use bitcoin::util::bip143;
 
-use secp256k1::{Secp256k1, SecretKey, PublicKey, Signature};
+use secp256k1::{Secp256k1, SecretKey, PublicKey, Signature, Message};
 
 use crypto::sha2::Sha256;
 use crypto::digest::Digest;
@@ -351,6 +352,18 @@ pub fn spending_witness_2x2_multisig(pk1: &PublicKey, pk2: &PublicKey, sig1: &Si
     witness
 }
 
+// The message both sides sign to spend the funding output by the first input of the transaction
+pub fn funding_sig_hash(tx: &Transaction, pk1: &PublicKey, pk2: &PublicKey, funding_amount: u64) -> Message {
+    let funding_lock_script = new_2x2_multisig(&pk1.serialize(), &pk2.serialize());
+    let tx_sig_hash = bip143::SighashComponents::new(tx)
+        .sighash_all(
+            &tx.input[0],
+            &funding_lock_script,
+            funding_amount
+        );
+    Message::from(tx_sig_hash.data())
+}
+
 
 #[cfg(test)]
 mod tests {
@@ -1,9 +1,17 @@
 
-#[derive(Serialize, Deserialize, Eq, PartialEq, Copy, Clone)]
+#[derive(Eq, PartialEq, Hash, Copy, Clone)]
 pub struct Hash256 {
     data: [u8; 32],
 }
 
+impl From<[u8; 32]> for Hash256 {
+    fn from(data: [u8; 32]) -> Self {
+        Hash256 {
+            data: data,
+        }
+    }
+}
+
 impl From<Hash256> for [u8; 32] {
     fn from(h: Hash256) -> Self {
         return h.data;
@@ -20,6 +28,48 @@ impl Hash256 {
     };
 }
 
+mod serde {
+    use super::Hash256;
+
+    use serde::Serialize;
+    use serde::Serializer;
+    use serde::Deserialize;
+    use serde::Deserializer;
+    use serde::de::Error;
+
+    use hex::encode;
+    use hex::decode;
+
+    // the human readable representation is hex string,
+    // the binary representation is the same as of the array
+    impl Serialize for Hash256 {
+        fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error> where S: Serializer {
+            if serializer.is_human_readable() {
+                serializer.serialize_str(encode(&self.data[..]).as_str())
+            } else {
+                self.data.serialize(serializer)
+            }
+        }
+    }
+
+    impl<'de> Deserialize<'de> for Hash256 {
+        fn deserialize<D>(deserializer: D) -> Result<Self, D::Error> where D: Deserializer<'de> {
+            if deserializer.is_human_readable() {
+                let string = String::deserialize(deserializer)?;
+                let data = decode(string.as_str()).map_err(D::Error::custom)?;
+                if data.len() != 32 {
+                    return Err(D::Error::custom(format!("wrong length: {}", data.len())));
+                }
+                let mut hash = Hash256 { data: [0; 32] };
+                hash.data.copy_from_slice(data.as_slice());
+                Ok(hash)
+            } else {
+                <[u8; 32]>::deserialize(deserializer).map(Hash256::from)
+            }
+        }
+    }
+}
+
 mod debug {
     use super::Hash256;
 
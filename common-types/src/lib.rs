@@ -1,8 +1,6 @@
 #![forbid(unsafe_code)]
 
 extern crate serde;
-#[macro_use]
-extern crate serde_derive;
 extern crate rand;
 extern crate sha2;
 #[macro_use]
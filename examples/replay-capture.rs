@@ -0,0 +1,47 @@
+// replays the capture of the session written by `BrontideStream::record`,
+// run the node with `LPD_CAPTURE=<directory>` to write the capture,
+// then `cargo run --example replay-capture -- <file>`
+
+extern crate brontide;
+extern crate wire;
+extern crate routing;
+extern crate hex;
+
+use std::env;
+use std::fs;
+use std::io;
+
+use brontide::{CaptureReader, FrameDirection, replay};
+use wire::JsonSD;
+use routing::Graph;
+
+fn main() {
+    let path = env::args().nth(1).expect("usage: replay-capture <file>");
+    let open = || CaptureReader::new(io::BufReader::new(fs::File::open(&path).unwrap())).unwrap();
+
+    let capture = open();
+    let header = capture.header().clone();
+    println!("remote: {}, {:?}", hex::encode(&header.remote_key.as_ref().serialize()[..]), header.direction);
+    for frame in capture {
+        let frame = frame.unwrap();
+        let arrow = match frame.direction {
+            FrameDirection::Sent => "->",
+            FrameDirection::Received => "<-",
+        };
+        match frame.message() {
+            Ok(message) => println!("{} {} {}", frame.timestamp, arrow, JsonSD::to_string(&message).unwrap()),
+            Err(e) => println!("{} {} cannot decode {}: {}", frame.timestamp, arrow, hex::encode(&frame.data), e),
+        }
+    }
+
+    // the received messages go through the consumers again, so the bug reproduces offline
+    match replay(open(), (Graph::new(), ())) {
+        Ok(((mut graph, ()), sent)) => {
+            for message in sent {
+                println!("replay -> {}", JsonSD::to_string(&message).unwrap());
+            }
+            graph.enumerate_nodes();
+        },
+        Err(e) => println!("replay failed: {:?}", e),
+    }
+}
@@ -0,0 +1,260 @@
+//! Bech32 as defined in BIP 173, without the limit of 90 characters,
+//! the invoice is usually longer. BOLT 12 uses the same alphabet without the checksum.
+
+use std::fmt;
+use std::error;
+
+// CHARSET is the alphabet, the index of the character is the value of the word
+const CHARSET: &'static [u8; 32] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+
+// GENERATOR is the coefficients of the checksum generator polynomial
+const GENERATOR: [u32; 5] = [0x3b6a57b2, 0x26508e6d, 0x1ea119fa, 0x3d4233dd, 0x2a1462b3];
+
+// CHECKSUM_SIZE is the number of words of the checksum
+const CHECKSUM_SIZE: usize = 6;
+
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub enum Bech32Error {
+    MissingSeparator,
+    EmptyHumanReadablePart,
+    MixedCase,
+    InvalidCharacter(char),
+    TooShort,
+    InvalidChecksum,
+}
+
+impl error::Error for Bech32Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        None
+    }
+}
+
+impl fmt::Display for Bech32Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::Bech32Error::*;
+
+        match self {
+            &MissingSeparator => write!(f, "the separator `1` is missing"),
+            &EmptyHumanReadablePart => write!(f, "the human readable part is empty"),
+            &MixedCase => write!(f, "both upper and lower case characters are used"),
+            &InvalidCharacter(c) => write!(f, "invalid character: {:?}", c),
+            &TooShort => write!(f, "the data is shorter than the checksum"),
+            &InvalidChecksum => write!(f, "invalid checksum"),
+        }
+    }
+}
+
+fn polymod(values: &[u8]) -> u32 {
+    values.iter().fold(1u32, |checksum, &value| {
+        let top = checksum >> 25;
+        let checksum = ((checksum & 0x1ffffff) << 5) ^ (value as u32);
+        (0..5).fold(checksum, |checksum, i| {
+            if (top >> i) & 1 == 1 { checksum ^ GENERATOR[i] } else { checksum }
+        })
+    })
+}
+
+fn expand(hrp: &str) -> Vec<u8> {
+    let mut expanded = hrp.bytes().map(|b| b >> 5).collect::<Vec<_>>();
+    expanded.push(0);
+    expanded.extend(hrp.bytes().map(|b| b & 0x1f));
+    expanded
+}
+
+fn checksum(hrp: &str, words: &[u8]) -> Vec<u8> {
+    let mut values = expand(hrp);
+    values.extend_from_slice(words);
+    values.extend_from_slice(&[0; CHECKSUM_SIZE]);
+    let checksum = polymod(values.as_slice()) ^ 1;
+    (0..CHECKSUM_SIZE)
+        .map(|i| ((checksum >> (5 * (CHECKSUM_SIZE - 1 - i))) & 0x1f) as u8)
+        .collect()
+}
+
+/// Encodes the 5-bit words, the human readable part should be lower case.
+pub fn encode(hrp: &str, words: &[u8]) -> String {
+    let mut result = String::with_capacity(hrp.len() + 1 + words.len() + CHECKSUM_SIZE);
+    result.push_str(hrp);
+    result.push('1');
+    for &word in words.iter().chain(checksum(hrp, words).iter()) {
+        result.push(CHARSET[word as usize] as char);
+    }
+    result
+}
+
+/// Returns the human readable part in lower case and the 5-bit words without the checksum.
+pub fn decode(s: &str) -> Result<(String, Vec<u8>), Bech32Error> {
+    use self::Bech32Error::*;
+
+    let (hrp, words) = split(s)?;
+    if words.len() < CHECKSUM_SIZE {
+        return Err(TooShort);
+    }
+
+    let mut values = expand(hrp.as_str());
+    values.extend_from_slice(words.as_slice());
+    if polymod(values.as_slice()) != 1 {
+        return Err(InvalidChecksum);
+    }
+
+    let length = words.len() - CHECKSUM_SIZE;
+    Ok((hrp, words[..length].to_vec()))
+}
+
+/// Encodes the 5-bit words without the checksum, as BOLT 12 strings are encoded.
+pub fn encode_without_checksum(hrp: &str, words: &[u8]) -> String {
+    let mut result = String::with_capacity(hrp.len() + 1 + words.len());
+    result.push_str(hrp);
+    result.push('1');
+    for &word in words {
+        result.push(CHARSET[word as usize] as char);
+    }
+    result
+}
+
+/// Decodes the string without the checksum, the string might be split
+/// by `+` followed by optional whitespace, the parts are concatenated.
+pub fn decode_without_checksum(s: &str) -> Result<(String, Vec<u8>), Bech32Error> {
+    let mut parts = s.split('+');
+    let mut joined = parts.next().unwrap_or("").to_owned();
+    for part in parts {
+        let part = part.trim_start();
+        if joined.is_empty() || part.is_empty() {
+            return Err(Bech32Error::InvalidCharacter('+'));
+        }
+        joined.push_str(part);
+    }
+
+    split(joined.as_str())
+}
+
+// the human readable part in lower case and all the words
+fn split(s: &str) -> Result<(String, Vec<u8>), Bech32Error> {
+    use self::Bech32Error::*;
+
+    let lower = s.chars().any(|c| c.is_ascii_lowercase());
+    let upper = s.chars().any(|c| c.is_ascii_uppercase());
+    if lower && upper {
+        return Err(MixedCase);
+    }
+
+    let s = s.to_ascii_lowercase();
+    let position = s.rfind('1').ok_or(MissingSeparator)?;
+    if position == 0 {
+        return Err(EmptyHumanReadablePart);
+    }
+    let (hrp, data) = (&s[..position], &s[(position + 1)..]);
+    if let Some(c) = hrp.chars().find(|&c| c < '!' || c > '~') {
+        return Err(InvalidCharacter(c));
+    }
+
+    let words = data.chars()
+        .map(|c| {
+            CHARSET.iter().position(|&a| a as char == c)
+                .map(|position| position as u8)
+                .ok_or(InvalidCharacter(c))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok((hrp.to_owned(), words))
+}
+
+/// Regroups the bytes into 5-bit words, the last word is padded with zeros.
+pub fn to_words(data: &[u8]) -> Vec<u8> {
+    let mut words = Vec::with_capacity((data.len() * 8 + 4) / 5);
+    let (mut buffer, mut bits) = (0u16, 0);
+    for &byte in data {
+        buffer = (buffer << 8) | (byte as u16);
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            words.push(((buffer >> bits) & 0x1f) as u8);
+        }
+    }
+    if bits > 0 {
+        words.push(((buffer << (5 - bits)) & 0x1f) as u8);
+    }
+    words
+}
+
+/// Regroups the 5-bit words into bytes, the incomplete last byte is dropped.
+pub fn from_words(words: &[u8]) -> Vec<u8> {
+    let mut data = Vec::with_capacity(words.len() * 5 / 8);
+    let (mut buffer, mut bits) = (0u16, 0);
+    for &word in words {
+        buffer = (buffer << 5) | (word as u16);
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            data.push((buffer >> bits) as u8);
+        }
+    }
+    data
+}
+
+/// Regroups the 5-bit words into bytes, the last byte is padded with zeros.
+/// It is how the data is hashed for the signature.
+pub fn from_words_padded(words: &[u8]) -> Vec<u8> {
+    let mut data = from_words(words);
+    let bits = (words.len() * 5) % 8;
+    if bits > 0 {
+        let last = words.iter().fold(0u16, |acc, &word| (acc << 5) | (word as u16));
+        data.push((last << (8 - bits)) as u8);
+    }
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bip173() {
+        let valid = vec![
+            "A12UEL5L",
+            "a12uel5l",
+            "an83characterlonghumanreadablepartthatcontainsthenumber1andtheexcludedcharactersbio1tt5tgs",
+            "abcdef1qpzry9x8gf2tvdw0s3jn54khce6mua7lmqqqxw",
+            "split1checkupstagehandshakeupstreamerranterredcaperred2y9e3w",
+            "?1ezyfcl",
+        ];
+        for s in valid {
+            let (hrp, words) = decode(s).unwrap();
+            assert_eq!(encode(hrp.as_str(), words.as_slice()), s.to_ascii_lowercase());
+        }
+
+        assert_eq!(decode("pzry9x0s0muk"), Err(Bech32Error::MissingSeparator));
+        assert_eq!(decode("1pzry9x0s0muk"), Err(Bech32Error::EmptyHumanReadablePart));
+        assert_eq!(decode("x1b4n0q5v"), Err(Bech32Error::InvalidCharacter('b')));
+        assert_eq!(decode("li1dgmt3"), Err(Bech32Error::TooShort));
+        assert_eq!(decode("A1G7SGD8"), Err(Bech32Error::InvalidChecksum));
+        assert_eq!(decode("a12UEL5L"), Err(Bech32Error::MixedCase));
+    }
+
+    #[test]
+    fn without_checksum() {
+        let s = "lno1pgx9getnwss8vetrw3hhyuckyypwa3eyt44h6txtxquqh7lz5djge4afgfjn7k4rgrkuag0jsd5xvxg";
+        let (hrp, words) = decode_without_checksum(s).unwrap();
+        assert_eq!(hrp, "lno");
+        assert_eq!(encode_without_checksum(hrp.as_str(), words.as_slice()), s);
+
+        let split = "lno1pgx9getnwss8vetrw3hhyucky+ypwa3eyt44h6txtxquqh7lz5djge4afgfjn7k4rgrkuag+  \n jsd5xvxg";
+        assert_eq!(decode_without_checksum(split).unwrap(), (hrp.clone(), words.clone()));
+        assert_eq!(decode_without_checksum(s.to_uppercase().as_str()).unwrap(), (hrp, words));
+
+        assert!(decode_without_checksum(format!("{}+", s).as_str()).is_err());
+        assert!(decode_without_checksum(format!("+{}", s).as_str()).is_err());
+        assert!(decode_without_checksum("lno1pgx9getnwss8vetrw3hhyucky++ypwa3eyt44").is_err());
+        assert!(decode_without_checksum("lno1pgx9getnwss8vetrw3hhyucky+ypwa3eyt44h6txtxquqh7lZ5djge4afgfjn7k4rgrkuag0jsd5xvxg").is_err());
+    }
+
+    #[test]
+    fn words() {
+        let data = vec![0xff, 0x00, 0xab];
+        let words = to_words(data.as_slice());
+        assert_eq!(words, vec![31, 28, 0, 10, 22]);
+        assert_eq!(from_words(words.as_slice()), data);
+        assert_eq!(from_words_padded(words.as_slice()), vec![0xff, 0x00, 0xab, 0x00]);
+        assert_eq!(from_words_padded(&[1]), vec![0x08]);
+    }
+}
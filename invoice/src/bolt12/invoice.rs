@@ -0,0 +1,484 @@
+use wire::TlvStream;
+use wire::TlvKnownTypes;
+use wire::Hash256;
+use wire::MilliSatoshi;
+use wire::PublicKey;
+use wire::SecretKey;
+use wire::RawFeatureVector;
+use wire::BlindedPath;
+use wire::BlindedPayInfo;
+
+use super::Bolt12Error;
+use super::SIGNATURE_TYPES;
+use super::encode;
+use super::decode;
+use super::check_ranges;
+use super::read_list;
+use super::write_list;
+use super::read_value;
+use super::read_tu64;
+use super::read_features;
+use super::merkle;
+use super::offer::Offer;
+use super::invoice_request::InvoiceRequestTlv;
+use super::invoice_request::InvoiceRequest;
+
+use std::fmt;
+use std::str::FromStr;
+use std::ops::RangeInclusive;
+
+// PREFIX is the human readable part of the invoice string
+const PREFIX: &'static str = "lni";
+
+// MESSAGE_NAME is the part of the tag of the signed message
+const MESSAGE_NAME: &'static str = "invoice";
+
+// DEFAULT_RELATIVE_EXPIRY is the lifetime of the invoice in seconds
+// if the invoice does not specify it
+const DEFAULT_RELATIVE_EXPIRY: u64 = 7200;
+
+// REQUEST_RANGES are the types of the records of the invoice request, the invoice mirrors them
+const REQUEST_RANGES: [RangeInclusive<u64>; 2] = [0..=159, 1_000_000_000..=2_999_999_999];
+
+/// The types of the records of the invoice,
+/// the records of the invoice request are mirrored as is.
+#[derive(Default, Clone, Eq, PartialEq, Debug)]
+pub struct InvoiceTlv;
+
+impl InvoiceTlv {
+    pub const PATHS: u64 = 160;
+    pub const BLINDED_PAY: u64 = 162;
+    pub const CREATED_AT: u64 = 164;
+    pub const RELATIVE_EXPIRY: u64 = 166;
+    pub const PAYMENT_HASH: u64 = 168;
+    pub const AMOUNT: u64 = 170;
+    pub const FALLBACKS: u64 = 172;
+    pub const FEATURES: u64 = 174;
+    pub const NODE_ID: u64 = 176;
+    pub const SIGNATURE: u64 = 240;
+}
+
+impl TlvKnownTypes for InvoiceTlv {
+    const KNOWN: &'static [u64] = &[
+        Self::PATHS,
+        Self::BLINDED_PAY,
+        Self::CREATED_AT,
+        Self::RELATIVE_EXPIRY,
+        Self::PAYMENT_HASH,
+        Self::AMOUNT,
+        Self::FALLBACKS,
+        Self::FEATURES,
+        Self::NODE_ID,
+        Self::SIGNATURE,
+    ];
+
+    fn is_known(t: u64) -> bool {
+        Self::KNOWN.contains(&t) || InvoiceRequestTlv::is_known(t)
+    }
+}
+
+/// The on chain address to pay if the payment fails,
+/// the `version` is the segwit version.
+#[derive(Serialize, Deserialize, Clone, Eq, PartialEq, Debug)]
+pub struct FallbackAddress {
+    pub version: u8,
+    pub address: Vec<u8>,
+}
+
+impl FallbackAddress {
+    // the reader ignores the address of unknown version or of invalid length
+    fn is_valid(&self) -> bool {
+        self.version <= 16 && self.address.len() >= 2 && self.address.len() <= 40
+    }
+}
+
+/// The invoice is the response to the invoice request, signed by the issuer.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct UnsignedBolt12Invoice {
+    stream: TlvStream<InvoiceTlv>,
+}
+
+impl UnsignedBolt12Invoice {
+    /// Each path to the node is paired with its fees and limits,
+    /// `created_at` is seconds since the unix epoch.
+    pub fn new(
+        request: &InvoiceRequest,
+        paths: Vec<(BlindedPath, BlindedPayInfo)>,
+        created_at: u64,
+        payment_hash: Hash256,
+        amount: MilliSatoshi,
+        node_id: PublicKey,
+    ) -> Self {
+        let mut stream = TlvStream::new();
+        for (t, value) in request.stream().iter().filter(|&(t, _)| !SIGNATURE_TYPES.contains(&t)) {
+            stream.insert(t, value.to_vec());
+        }
+        let (paths, pay_info): (Vec<_>, Vec<_>) = paths.into_iter().unzip();
+        stream.insert(InvoiceTlv::PATHS, write_list(paths.as_slice()));
+        stream.insert(InvoiceTlv::BLINDED_PAY, write_list(pay_info.as_slice()));
+        stream.insert_tu64(InvoiceTlv::CREATED_AT, created_at);
+        stream.insert_value(InvoiceTlv::PAYMENT_HASH, &payment_hash).unwrap();
+        stream.insert_tu64(InvoiceTlv::AMOUNT, amount.into());
+        stream.insert_value(InvoiceTlv::NODE_ID, &node_id).unwrap();
+        UnsignedBolt12Invoice {
+            stream: stream,
+        }
+    }
+
+    /// Seconds since the creation, the default is two hours.
+    pub fn with_relative_expiry(self, relative_expiry: u32) -> Self {
+        let mut s = self;
+        s.stream.insert_tu64(InvoiceTlv::RELATIVE_EXPIRY, relative_expiry as u64);
+        s
+    }
+
+    pub fn with_fallbacks(self, fallbacks: Vec<FallbackAddress>) -> Self {
+        let mut s = self;
+        s.stream.insert(InvoiceTlv::FALLBACKS, write_list(fallbacks.as_slice()));
+        s
+    }
+
+    pub fn with_features(self, features: RawFeatureVector) -> Self {
+        let mut s = self;
+        s.stream.insert(InvoiceTlv::FEATURES, features.into());
+        s
+    }
+
+    /// The `secret_key` should match the node id,
+    /// the result is checked as the payer does.
+    /// The schnorr signing is not constant time, see the `schnorr` module,
+    /// do not sign with the long-term node key, use the key made for the offer.
+    pub fn sign(self, secret_key: &SecretKey) -> Result<Bolt12Invoice, Bolt12Error> {
+        let signature = merkle::sign(&self.stream, MESSAGE_NAME, secret_key)?;
+        let mut stream = self.stream;
+        stream.insert(InvoiceTlv::SIGNATURE, signature.to_vec());
+        Bolt12Invoice::from_stream(stream)
+    }
+}
+
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct Bolt12Invoice {
+    stream: TlvStream<InvoiceTlv>,
+    node_id: PublicKey,
+}
+
+impl Bolt12Invoice {
+    /// Checks the invoice as the payer does, except it does not check
+    /// that the invoice responds our request, see `verify_request`.
+    pub fn from_stream(stream: TlvStream<InvoiceTlv>) -> Result<Self, Bolt12Error> {
+        check_ranges(&stream, &[0..=239, SIGNATURE_TYPES, 1_000_000_000..=3_999_999_999])?;
+
+        let node_id = read_value(&stream, InvoiceTlv::NODE_ID, "invoice_node_id")?
+            .ok_or(Bolt12Error::MissingField("invoice_node_id"))?;
+        {
+            let signature = stream.get(InvoiceTlv::SIGNATURE)
+                .ok_or(Bolt12Error::MissingField("signature"))?;
+            merkle::verify(&stream, MESSAGE_NAME, signature, &node_id)?;
+        }
+
+        let invoice = Bolt12Invoice {
+            stream: stream,
+            node_id: node_id,
+        };
+        invoice.validate()?;
+        Ok(invoice)
+    }
+
+    fn validate(&self) -> Result<(), Bolt12Error> {
+        read_features(&self.stream, InvoiceTlv::FEATURES)?;
+        read_tu64(&self.stream, InvoiceTlv::RELATIVE_EXPIRY, "invoice_relative_expiry")?;
+        read_tu64(&self.stream, InvoiceTlv::CREATED_AT, "invoice_created_at")?
+            .ok_or(Bolt12Error::MissingField("invoice_created_at"))?;
+        read_tu64(&self.stream, InvoiceTlv::AMOUNT, "invoice_amount")?
+            .ok_or(Bolt12Error::MissingField("invoice_amount"))?;
+        read_value::<_, Hash256>(&self.stream, InvoiceTlv::PAYMENT_HASH, "invoice_payment_hash")?
+            .ok_or(Bolt12Error::MissingField("invoice_payment_hash"))?;
+        self.read_fallbacks()?;
+
+        let paths = self.read_paths()?;
+        if paths.is_empty() {
+            return Err(Bolt12Error::MissingField("invoice_paths"));
+        }
+
+        // the node should be the issuer of the offer
+        let mut offer_stream = TlvStream::new();
+        for (t, value) in self.stream.iter().filter(|&(t, _)| Offer::contains_type(t)) {
+            offer_stream.insert(t, value.to_vec());
+        }
+        let offer = Offer::from_stream(offer_stream)?;
+        let valid_node_id = match offer.issuer_id() {
+            Some(issuer_id) => issuer_id == self.node_id,
+            None => offer.paths().iter()
+                .filter_map(|path| path.hops.last())
+                .any(|hop| hop.blinded_node_id == self.node_id),
+        };
+        if !valid_node_id {
+            return Err(Bolt12Error::InvalidField("invoice_node_id"));
+        }
+
+        Ok(())
+    }
+
+    fn read_paths(&self) -> Result<Vec<(BlindedPath, BlindedPayInfo)>, Bolt12Error> {
+        let paths = match self.stream.get(InvoiceTlv::PATHS) {
+            None => return Ok(Vec::new()),
+            Some(data) => read_list::<BlindedPath>(data)
+                .map_err(|_| Bolt12Error::InvalidField("invoice_paths"))?,
+        };
+        let pay_info = self.stream.get(InvoiceTlv::BLINDED_PAY)
+            .ok_or(Bolt12Error::MissingField("invoice_blindedpay"))
+            .and_then(|data| {
+                read_list::<BlindedPayInfo>(data).map_err(|_| Bolt12Error::InvalidField("invoice_blindedpay"))
+            })?;
+        if paths.len() != pay_info.len() {
+            return Err(Bolt12Error::InvalidField("invoice_blindedpay"));
+        }
+        Ok(paths.into_iter().zip(pay_info.into_iter()).collect())
+    }
+
+    fn read_fallbacks(&self) -> Result<Vec<FallbackAddress>, Bolt12Error> {
+        match self.stream.get(InvoiceTlv::FALLBACKS) {
+            None => Ok(Vec::new()),
+            Some(data) => read_list(data).map_err(|_| Bolt12Error::InvalidField("invoice_fallbacks")),
+        }
+    }
+
+    /// Checks that the invoice is the response to the `request`,
+    /// all records of the request should be mirrored.
+    pub fn verify_request(&self, request: &InvoiceRequest) -> Result<(), Bolt12Error> {
+        let mirrored = |t: u64| REQUEST_RANGES.iter().any(|range| range.contains(&t));
+        let expected = request.stream().iter().filter(|&(t, _)| mirrored(t));
+        let actual = self.stream.iter().filter(|&(t, _)| mirrored(t));
+
+        // both are ordered by type, so the first difference is the mismatch
+        let mut expected = expected;
+        let mut actual = actual;
+        loop {
+            match (expected.next(), actual.next()) {
+                (None, None) => break,
+                (Some((t, _)), None) | (None, Some((t, _))) => return Err(Bolt12Error::RequestMismatch(t)),
+                (Some((t, a)), Some((u, b))) => {
+                    if t != u {
+                        return Err(Bolt12Error::RequestMismatch(t.min(u)));
+                    }
+                    if a != b {
+                        return Err(Bolt12Error::RequestMismatch(t));
+                    }
+                },
+            }
+        }
+
+        match request.amount() {
+            Some(amount) if amount != self.amount() => Err(Bolt12Error::InvalidField("invoice_amount")),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn stream(&self) -> &TlvStream<InvoiceTlv> {
+        &self.stream
+    }
+
+    pub fn paths(&self) -> Vec<(BlindedPath, BlindedPayInfo)> {
+        self.read_paths().unwrap_or_default()
+    }
+
+    /// Seconds since the unix epoch.
+    pub fn created_at(&self) -> u64 {
+        // checked on construction
+        self.stream.get_tu64(InvoiceTlv::CREATED_AT).unwrap().unwrap()
+    }
+
+    /// Seconds since the creation.
+    pub fn relative_expiry(&self) -> u64 {
+        self.stream.get_tu64(InvoiceTlv::RELATIVE_EXPIRY).ok().and_then(|x| x)
+            .unwrap_or(DEFAULT_RELATIVE_EXPIRY)
+    }
+
+    /// Whether the invoice is expired at the `now` seconds since the unix epoch.
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.created_at().saturating_add(self.relative_expiry()) <= now
+    }
+
+    pub fn payment_hash(&self) -> Hash256 {
+        self.stream.get_value(InvoiceTlv::PAYMENT_HASH).unwrap().unwrap()
+    }
+
+    pub fn amount(&self) -> MilliSatoshi {
+        MilliSatoshi::from(self.stream.get_tu64(InvoiceTlv::AMOUNT).unwrap().unwrap())
+    }
+
+    /// Only the addresses of known versions.
+    pub fn fallbacks(&self) -> Vec<FallbackAddress> {
+        self.read_fallbacks().unwrap_or_default()
+            .into_iter()
+            .filter(FallbackAddress::is_valid)
+            .collect()
+    }
+
+    pub fn features(&self) -> RawFeatureVector {
+        read_features(&self.stream, InvoiceTlv::FEATURES).unwrap_or_default()
+    }
+
+    pub fn node_id(&self) -> &PublicKey {
+        &self.node_id
+    }
+}
+
+impl FromStr for Bolt12Invoice {
+    type Err = Bolt12Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Bolt12Invoice::from_stream(decode(PREFIX, s)?)
+    }
+}
+
+impl fmt::Display for Bolt12Invoice {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", encode(PREFIX, &self.stream))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::OfferAmount;
+    use wire::BlindedHop;
+    use secp256k1::Secp256k1;
+    use secp256k1;
+    use rand;
+
+    fn key_pair(byte: u8) -> (SecretKey, PublicKey) {
+        let context = Secp256k1::new();
+        let secret_key = secp256k1::SecretKey::from_slice(&context, &[byte; 32]).unwrap();
+        let public_key = secp256k1::PublicKey::from_secret_key(&context, &secret_key).unwrap();
+        (SecretKey::from(secret_key), PublicKey::from(public_key))
+    }
+
+    fn path() -> (BlindedPath, BlindedPayInfo) {
+        let path = BlindedPath {
+            introduction_node_id: rand::random(),
+            blinding_point: rand::random(),
+            hops: vec![
+                BlindedHop {
+                    blinded_node_id: rand::random(),
+                    encrypted_recipient_data: vec![0; 32],
+                },
+            ],
+        };
+        let pay_info = BlindedPayInfo {
+            base_fee: 1000,
+            fee_rate: 100,
+            time_lock_delta: 144,
+            htlc_minimum: MilliSatoshi::from(1),
+            htlc_maximum: MilliSatoshi::from(1_000_000_000),
+            features: RawFeatureVector::new(),
+        };
+        (path, pay_info)
+    }
+
+    #[test]
+    fn flow() {
+        let (issuer_key, issuer_id) = key_pair(0x41);
+        let (payer_key, payer_id) = key_pair(0x42);
+
+        let offer = Offer::new("coffee".to_owned(), issuer_id.clone())
+            .with_amount(OfferAmount::Bitcoin(MilliSatoshi::from(1000)));
+        let request = offer.to_string().parse::<Offer>().unwrap()
+            .request_invoice(vec![7; 16], payer_id)
+            .with_amount(MilliSatoshi::from(1500))
+            .sign(&payer_key)
+            .unwrap();
+        let request = request.to_string().parse::<InvoiceRequest>().unwrap();
+
+        let fallback = FallbackAddress {
+            version: 0,
+            address: vec![0x75; 20],
+        };
+        let unknown_fallback = FallbackAddress {
+            version: 17,
+            address: vec![0x75; 20],
+        };
+        let invoice = UnsignedBolt12Invoice::new(
+            &request,
+            vec![path(), path()],
+            1_700_000_000,
+            Hash256::from([1; 32]),
+            MilliSatoshi::from(1500),
+            issuer_id.clone(),
+        )
+            .with_relative_expiry(60)
+            .with_fallbacks(vec![fallback.clone(), unknown_fallback])
+            .sign(&issuer_key)
+            .unwrap();
+
+        let restored = invoice.to_string().parse::<Bolt12Invoice>().unwrap();
+        assert_eq!(restored, invoice);
+        restored.verify_request(&request).unwrap();
+        assert_eq!(restored.node_id(), &issuer_id);
+        assert_eq!(restored.amount(), MilliSatoshi::from(1500));
+        assert_eq!(restored.payment_hash(), Hash256::from([1; 32]));
+        assert_eq!(restored.paths().len(), 2);
+        assert_eq!(restored.fallbacks(), vec![fallback]);
+        assert!(!restored.is_expired(1_700_000_059));
+        assert!(restored.is_expired(1_700_000_060));
+
+        // the invoice for another request
+        let other = offer.request_invoice(vec![8; 16], key_pair(0x43).1)
+            .sign(&key_pair(0x43).0)
+            .unwrap();
+        match restored.verify_request(&other) {
+            Err(Bolt12Error::RequestMismatch(0)) => (),
+            r => panic!("unexpected: {:?}", r),
+        }
+    }
+
+    #[test]
+    fn invalid() {
+        let (issuer_key, issuer_id) = key_pair(0x41);
+        let (payer_key, payer_id) = key_pair(0x42);
+
+        let request = Offer::new("coffee".to_owned(), issuer_id.clone())
+            .request_invoice(vec![7; 16], payer_id.clone())
+            .with_amount(MilliSatoshi::from(1500))
+            .sign(&payer_key)
+            .unwrap();
+        let unsigned = |node_id: PublicKey, paths| UnsignedBolt12Invoice::new(
+            &request,
+            paths,
+            1_700_000_000,
+            Hash256::from([1; 32]),
+            MilliSatoshi::from(1500),
+            node_id,
+        );
+
+        // no paths
+        match unsigned(issuer_id.clone(), vec![]).sign(&issuer_key) {
+            Err(Bolt12Error::MissingField("invoice_paths")) => (),
+            r => panic!("unexpected: {:?}", r),
+        }
+
+        // the node is not the issuer
+        match unsigned(payer_id, vec![path()]).sign(&payer_key) {
+            Err(Bolt12Error::InvalidField("invoice_node_id")) => (),
+            r => panic!("unexpected: {:?}", r),
+        }
+
+        // the number of paths differs from the number of pay infos
+        let mut stream = unsigned(issuer_id.clone(), vec![path(), path()]).stream;
+        let (_, pay_info) = path();
+        stream.insert(InvoiceTlv::BLINDED_PAY, write_list(vec![pay_info].as_slice()));
+        match (UnsignedBolt12Invoice { stream: stream }).sign(&issuer_key) {
+            Err(Bolt12Error::InvalidField("invoice_blindedpay")) => (),
+            r => panic!("unexpected: {:?}", r),
+        }
+
+        // the record is changed after signing
+        let invoice = unsigned(issuer_id, vec![path()]).sign(&issuer_key).unwrap();
+        let mut stream = invoice.stream().clone();
+        stream.insert(InvoiceTlv::AMOUNT, vec![1]);
+        match Bolt12Invoice::from_stream(stream) {
+            Err(Bolt12Error::InvalidSignature) => (),
+            r => panic!("unexpected: {:?}", r),
+        }
+    }
+}
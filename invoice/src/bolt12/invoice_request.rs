@@ -0,0 +1,343 @@
+use wire::TlvStream;
+use wire::TlvKnownTypes;
+use wire::Hash256;
+use wire::MilliSatoshi;
+use wire::PublicKey;
+use wire::SecretKey;
+use wire::RawFeatureVector;
+
+use super::Bolt12Error;
+use super::SIGNATURE_TYPES;
+use super::encode;
+use super::decode;
+use super::check_ranges;
+use super::read_string;
+use super::read_value;
+use super::read_tu64;
+use super::read_features;
+use super::merkle;
+use super::offer::OfferTlv;
+use super::offer::OfferAmount;
+use super::offer::Offer;
+
+use std::fmt;
+use std::str::FromStr;
+
+// PREFIX is the human readable part of the invoice request string
+const PREFIX: &'static str = "lnr";
+
+// MESSAGE_NAME is the part of the tag of the signed message
+const MESSAGE_NAME: &'static str = "invoice_request";
+
+/// The types of the records of the invoice request,
+/// the records of the offer are mirrored as is.
+#[derive(Default, Clone, Eq, PartialEq, Debug)]
+pub struct InvoiceRequestTlv;
+
+impl InvoiceRequestTlv {
+    pub const METADATA: u64 = 0;
+    pub const CHAIN: u64 = 80;
+    pub const AMOUNT: u64 = 82;
+    pub const FEATURES: u64 = 84;
+    pub const QUANTITY: u64 = 86;
+    pub const PAYER_ID: u64 = 88;
+    pub const PAYER_NOTE: u64 = 89;
+    pub const SIGNATURE: u64 = 240;
+}
+
+impl TlvKnownTypes for InvoiceRequestTlv {
+    const KNOWN: &'static [u64] = &[
+        Self::METADATA,
+        Self::CHAIN,
+        Self::AMOUNT,
+        Self::FEATURES,
+        Self::QUANTITY,
+        Self::PAYER_ID,
+        Self::PAYER_NOTE,
+        Self::SIGNATURE,
+    ];
+
+    fn is_known(t: u64) -> bool {
+        Self::KNOWN.contains(&t) || OfferTlv::is_known(t)
+    }
+}
+
+/// The request is built from the offer, it is signed by the payer.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct UnsignedInvoiceRequest {
+    stream: TlvStream<InvoiceRequestTlv>,
+}
+
+impl UnsignedInvoiceRequest {
+    /// The `metadata` should be unique for each request,
+    /// it is the nonce that hides the payer id.
+    pub fn new(offer: &Offer, metadata: Vec<u8>, payer_id: PublicKey) -> Self {
+        let mut stream = TlvStream::new();
+        for (t, value) in offer.stream().iter() {
+            stream.insert(t, value.to_vec());
+        }
+        stream.insert(InvoiceRequestTlv::METADATA, metadata);
+        stream.insert_value(InvoiceRequestTlv::PAYER_ID, &payer_id).unwrap();
+        UnsignedInvoiceRequest {
+            stream: stream,
+        }
+    }
+
+    /// Required if the offer is for any amount.
+    pub fn with_amount(self, amount: MilliSatoshi) -> Self {
+        let mut s = self;
+        s.stream.insert_tu64(InvoiceRequestTlv::AMOUNT, amount.into());
+        s
+    }
+
+    /// The bitcoin chain is implied.
+    pub fn with_chain(self, chain: Hash256) -> Self {
+        let mut s = self;
+        s.stream.insert_value(InvoiceRequestTlv::CHAIN, &chain).unwrap();
+        s
+    }
+
+    pub fn with_features(self, features: RawFeatureVector) -> Self {
+        let mut s = self;
+        s.stream.insert(InvoiceRequestTlv::FEATURES, features.into());
+        s
+    }
+
+    /// Required if the offer has the maximal quantity.
+    pub fn with_quantity(self, quantity: u64) -> Self {
+        let mut s = self;
+        s.stream.insert_tu64(InvoiceRequestTlv::QUANTITY, quantity);
+        s
+    }
+
+    pub fn with_payer_note(self, payer_note: String) -> Self {
+        let mut s = self;
+        s.stream.insert(InvoiceRequestTlv::PAYER_NOTE, payer_note.into_bytes());
+        s
+    }
+
+    /// The `secret_key` should match the payer id,
+    /// the result is checked as the issuer does.
+    /// The schnorr signing is not constant time, see the `schnorr` module,
+    /// the payer id should be the key made for this request.
+    pub fn sign(self, secret_key: &SecretKey) -> Result<InvoiceRequest, Bolt12Error> {
+        let signature = merkle::sign(&self.stream, MESSAGE_NAME, secret_key)?;
+        let mut stream = self.stream;
+        stream.insert(InvoiceRequestTlv::SIGNATURE, signature.to_vec());
+        InvoiceRequest::from_stream(stream)
+    }
+}
+
+/// The request for the invoice for the offer, signed by the payer.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct InvoiceRequest {
+    stream: TlvStream<InvoiceRequestTlv>,
+    offer: Offer,
+    payer_id: PublicKey,
+}
+
+impl InvoiceRequest {
+    /// Checks the request as the issuer does, except it does not check
+    /// that the offer is issued by us, the caller should check the `offer`.
+    pub fn from_stream(stream: TlvStream<InvoiceRequestTlv>) -> Result<Self, Bolt12Error> {
+        check_ranges(&stream, &[0..=159, SIGNATURE_TYPES, 1_000_000_000..=2_999_999_999])?;
+
+        if stream.get(InvoiceRequestTlv::METADATA).is_none() {
+            return Err(Bolt12Error::MissingField("invreq_metadata"));
+        }
+        let payer_id = read_value(&stream, InvoiceRequestTlv::PAYER_ID, "invreq_payer_id")?
+            .ok_or(Bolt12Error::MissingField("invreq_payer_id"))?;
+        {
+            let signature = stream.get(InvoiceRequestTlv::SIGNATURE)
+                .ok_or(Bolt12Error::MissingField("signature"))?;
+            merkle::verify(&stream, MESSAGE_NAME, signature, &payer_id)?;
+        }
+
+        // the offer is the subset of the records
+        let mut offer_stream = TlvStream::new();
+        for (t, value) in stream.iter().filter(|&(t, _)| Offer::contains_type(t)) {
+            offer_stream.insert(t, value.to_vec());
+        }
+        let request = InvoiceRequest {
+            stream: stream,
+            offer: Offer::from_stream(offer_stream)?,
+            payer_id: payer_id,
+        };
+        request.validate()?;
+        Ok(request)
+    }
+
+    fn validate(&self) -> Result<(), Bolt12Error> {
+        read_features(&self.stream, InvoiceRequestTlv::FEATURES)?;
+        read_string(&self.stream, InvoiceRequestTlv::PAYER_NOTE, "invreq_payer_note")?;
+
+        let chain = self.read_chain()?;
+        if !self.offer.chains().contains(&chain) {
+            return Err(Bolt12Error::InvalidField("invreq_chain"));
+        }
+
+        let quantity = read_tu64(&self.stream, InvoiceRequestTlv::QUANTITY, "invreq_quantity")?;
+        match (self.offer.quantity_max(), quantity) {
+            (None, None) => (),
+            (Some(_), None) => return Err(Bolt12Error::MissingField("invreq_quantity")),
+            (None, Some(_)) => return Err(Bolt12Error::InvalidField("invreq_quantity")),
+            (Some(max), Some(quantity)) => {
+                if quantity == 0 || (max != 0 && quantity > max) {
+                    return Err(Bolt12Error::InvalidField("invreq_quantity"));
+                }
+            },
+        }
+
+        let amount = read_tu64(&self.stream, InvoiceRequestTlv::AMOUNT, "invreq_amount")?;
+        match (self.offer.amount(), amount) {
+            (None, None) => return Err(Bolt12Error::MissingField("invreq_amount")),
+            (Some(OfferAmount::Bitcoin(expected)), Some(amount)) => {
+                let expected = u64::from(expected).checked_mul(quantity.unwrap_or(1));
+                if expected.map(|expected| amount < expected).unwrap_or(true) {
+                    return Err(Bolt12Error::InvalidField("invreq_amount"));
+                }
+            },
+            _ => (),
+        }
+
+        Ok(())
+    }
+
+    fn read_chain(&self) -> Result<Hash256, Bolt12Error> {
+        read_value(&self.stream, InvoiceRequestTlv::CHAIN, "invreq_chain")
+            .map(|chain| chain.unwrap_or(Hash256::BITCOIN_CHAIN_HASH))
+    }
+
+    pub fn stream(&self) -> &TlvStream<InvoiceRequestTlv> {
+        &self.stream
+    }
+
+    /// The offer the request is for.
+    pub fn offer(&self) -> &Offer {
+        &self.offer
+    }
+
+    pub fn metadata(&self) -> &[u8] {
+        self.stream.get(InvoiceRequestTlv::METADATA).unwrap_or(&[])
+    }
+
+    pub fn payer_id(&self) -> &PublicKey {
+        &self.payer_id
+    }
+
+    pub fn chain(&self) -> Hash256 {
+        self.read_chain().unwrap_or(Hash256::BITCOIN_CHAIN_HASH)
+    }
+
+    /// `None` means the amount of the offer.
+    pub fn amount(&self) -> Option<MilliSatoshi> {
+        self.stream.get_tu64(InvoiceRequestTlv::AMOUNT).ok().and_then(|x| x).map(MilliSatoshi::from)
+    }
+
+    pub fn features(&self) -> RawFeatureVector {
+        read_features(&self.stream, InvoiceRequestTlv::FEATURES).unwrap_or_default()
+    }
+
+    pub fn quantity(&self) -> Option<u64> {
+        self.stream.get_tu64(InvoiceRequestTlv::QUANTITY).ok().and_then(|x| x)
+    }
+
+    pub fn payer_note(&self) -> Option<String> {
+        read_string(&self.stream, InvoiceRequestTlv::PAYER_NOTE, "invreq_payer_note").ok().and_then(|x| x)
+    }
+}
+
+impl FromStr for InvoiceRequest {
+    type Err = Bolt12Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        InvoiceRequest::from_stream(decode(PREFIX, s)?)
+    }
+}
+
+impl fmt::Display for InvoiceRequest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", encode(PREFIX, &self.stream))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wire::BinarySD;
+    use secp256k1::Secp256k1;
+    use secp256k1;
+    use hex;
+
+    #[test]
+    fn spec() {
+        let s = "lnr1qqyqqqqqqqqqqqqqqcp4256ypqqkgzshgysy6ct5dpjk6ct5d93kzmpq23ex2ct5d9ek293pqthvwfzadd7jejes8q9lh\
+                 c4rvjxd022zv5l44g6qah82ru5rdpnpjkppqvjx204vgdzgsqpvcp4mldl3plscny0rt707gvpdh6ndydfacz43euzqhrurageg3n\
+                 7kafgsek6gz3e9w52parv8gs2hlxzk95tzeswywffxlkeyhml0hh46kndmwf4m6xma3tkq2lu04qz3slje2rfthc89vss";
+        let request = s.parse::<InvoiceRequest>().unwrap();
+        assert_eq!(request.metadata(), &[0; 8][..]);
+        assert_eq!(request.amount(), None);
+        assert_eq!(request.chain(), Hash256::BITCOIN_CHAIN_HASH);
+        assert_eq!(
+            request.offer().amount(),
+            Some(OfferAmount::Currency { iso4217: "USD".to_owned(), amount: 100 })
+        );
+        assert_eq!(request.offer().description(), Some("A Mathematical Treatise".to_owned()));
+        let payer_id = hex::decode("0324653eac434488002cc06bbfb7f10fe18991e35f9fe4302dbea6d2353dc0ab1c").unwrap();
+        assert_eq!(request.payer_id(), &BinarySD::deserialize::<PublicKey, _>(payer_id.as_slice()).unwrap());
+        assert_eq!(request.to_string(), s);
+
+        // the signature covers the note
+        let mut stream = request.stream().clone();
+        stream.insert(InvoiceRequestTlv::PAYER_NOTE, b"note".to_vec());
+        match InvoiceRequest::from_stream(stream) {
+            Err(Bolt12Error::InvalidSignature) => (),
+            r => panic!("unexpected: {:?}", r),
+        }
+    }
+
+    #[test]
+    fn sign() {
+        let context = Secp256k1::new();
+        let issuer_key = secp256k1::SecretKey::from_slice(&context, &[0x41; 32]).unwrap();
+        let payer_key = secp256k1::SecretKey::from_slice(&context, &[0x42; 32]).unwrap();
+        let issuer_id = PublicKey::from(secp256k1::PublicKey::from_secret_key(&context, &issuer_key).unwrap());
+        let payer_id = PublicKey::from(secp256k1::PublicKey::from_secret_key(&context, &payer_key).unwrap());
+
+        let offer = Offer::new("coffee".to_owned(), issuer_id)
+            .with_amount(OfferAmount::Bitcoin(MilliSatoshi::from(1000)))
+            .with_quantity_max(5);
+
+        // the quantity is required
+        match offer.request_invoice(vec![1; 16], payer_id.clone()).sign(&SecretKey::from(payer_key.clone())) {
+            Err(Bolt12Error::MissingField("invreq_quantity")) => (),
+            r => panic!("unexpected: {:?}", r),
+        }
+
+        // the amount is less than the price
+        let request = offer.request_invoice(vec![1; 16], payer_id.clone())
+            .with_quantity(2)
+            .with_amount(MilliSatoshi::from(1999));
+        match request.sign(&SecretKey::from(payer_key.clone())) {
+            Err(Bolt12Error::InvalidField("invreq_amount")) => (),
+            r => panic!("unexpected: {:?}", r),
+        }
+
+        // signed by the wrong key
+        let request = offer.request_invoice(vec![1; 16], payer_id.clone()).with_quantity(2);
+        match request.clone().sign(&SecretKey::from(issuer_key)) {
+            Err(Bolt12Error::InvalidSignature) => (),
+            r => panic!("unexpected: {:?}", r),
+        }
+
+        let request = request
+            .with_payer_note("thanks".to_owned())
+            .sign(&SecretKey::from(payer_key))
+            .unwrap();
+        let restored = request.to_string().parse::<InvoiceRequest>().unwrap();
+        assert_eq!(restored, request);
+        assert_eq!(restored.offer(), &offer);
+        assert_eq!(restored.quantity(), Some(2));
+        assert_eq!(restored.payer_note(), Some("thanks".to_owned()));
+    }
+}
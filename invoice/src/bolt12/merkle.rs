@@ -0,0 +1,151 @@
+use wire::BinarySD;
+use wire::BigSize;
+use wire::TlvStream;
+use wire::TlvKnownTypes;
+use wire::PublicKey;
+use wire::SecretKey;
+
+use super::Bolt12Error;
+use super::SIGNATURE_TYPES;
+use super::super::schnorr;
+use super::super::schnorr::tagged_hash;
+
+fn big_size(value: u64) -> Vec<u8> {
+    let mut data = Vec::with_capacity(9);
+    BinarySD::serialize(&mut data, &BigSize::from(value)).unwrap();
+    data
+}
+
+// the inner node, the order of the children does not matter
+fn branch(a: [u8; 32], b: [u8; 32]) -> [u8; 32] {
+    let (lesser, greater) = if a < b { (a, b) } else { (b, a) };
+    tagged_hash(b"LnBranch", &[&lesser[..], &greater[..]])
+}
+
+/// The root of the merkle tree of the records, except the signature records.
+/// Each leaf is paired with the nonce leaf, it is the hash of the type
+/// tagged by the first record, so the sibling does not reveal the neighbour record.
+/// Returns `None` if there is no record to sign.
+pub fn root<K>(stream: &TlvStream<K>) -> Option<[u8; 32]> where K: TlvKnownTypes {
+    let records = stream.iter()
+        .filter(|&(t, _)| !SIGNATURE_TYPES.contains(&t))
+        .map(|(t, value)| {
+            let mut record = big_size(t);
+            record.extend(big_size(value.len() as u64));
+            record.extend_from_slice(value);
+            (t, record)
+        })
+        .collect::<Vec<_>>();
+
+    let nonce_tag = {
+        let &(_, ref first) = records.first()?;
+        let mut tag = b"LnNonce".to_vec();
+        tag.extend_from_slice(first.as_slice());
+        tag
+    };
+
+    let mut level = records.iter()
+        .map(|&(t, ref record)| {
+            let leaf = tagged_hash(b"LnLeaf", &[record.as_slice()]);
+            let nonce = tagged_hash(nonce_tag.as_slice(), &[big_size(t).as_slice()]);
+            branch(leaf, nonce)
+        })
+        .collect::<Vec<_>>();
+
+    // the odd node is promoted to the next level,
+    // so the lower order leaves are deeper
+    while level.len() > 1 {
+        level = level.chunks(2)
+            .map(|pair| if pair.len() == 2 { branch(pair[0], pair[1]) } else { pair[0] })
+            .collect();
+    }
+
+    level.first().cloned()
+}
+
+// the message to sign, tagged by the name of the message and of the field
+fn message(message_name: &str, field_name: &str, root: &[u8; 32]) -> [u8; 32] {
+    let tag = format!("lightning{}{}", message_name, field_name);
+    tagged_hash(tag.as_bytes(), &[&root[..]])
+}
+
+pub fn sign<K>(
+    stream: &TlvStream<K>,
+    message_name: &str,
+    secret_key: &SecretKey,
+) -> Result<[u8; 64], Bolt12Error>
+where
+    K: TlvKnownTypes,
+{
+    use rand;
+
+    let root = root(stream).ok_or(Bolt12Error::MissingField("signature"))?;
+    let aux: [u8; 32] = rand::random();
+    schnorr::sign(secret_key.as_ref(), &message(message_name, "signature", &root), &aux)
+        .map_err(Bolt12Error::from)
+}
+
+pub fn verify<K>(
+    stream: &TlvStream<K>,
+    message_name: &str,
+    signature: &[u8],
+    public_key: &PublicKey,
+) -> Result<(), Bolt12Error>
+where
+    K: TlvKnownTypes,
+{
+    let root = root(stream).ok_or(Bolt12Error::MissingField("signature"))?;
+    if signature.len() != 64 {
+        return Err(Bolt12Error::InvalidField("signature"));
+    }
+    let mut array = [0; 64];
+    array.copy_from_slice(signature);
+    if schnorr::verify(public_key.as_ref(), &message(message_name, "signature", &root), &array) {
+        Ok(())
+    } else {
+        Err(Bolt12Error::InvalidSignature)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wire::NoKnownTypes;
+    use hex;
+
+    #[derive(Clone, Eq, PartialEq, Debug)]
+    struct AllTypes;
+
+    impl TlvKnownTypes for AllTypes {
+        const KNOWN: &'static [u64] = &[];
+
+        fn is_known(_: u64) -> bool {
+            true
+        }
+    }
+
+    #[test]
+    fn spec_invoice_request() {
+        let data = hex::decode(
+            "0008000000000000000006035553440801640a1741204d617468656d61746963616c205472656174697365\
+             162102eec7245d6b7d2ccb30380bfbe2a3648cd7a942653f5aa340edcea1f283686619\
+             58210324653eac434488002cc06bbfb7f10fe18991e35f9fe4302dbea6d2353dc0ab1c\
+             f040b8f83ea3288cfd6ea510cdb481472575141e8d8744157f98562d162cc1c472526fdb24befefbdebab4dbb726bbd1b7d8aec057f8fa805187e5950d2bbe0e5642"
+        ).unwrap();
+        let stream: TlvStream<AllTypes> = BinarySD::deserialize(data.as_slice()).unwrap();
+        assert_eq!(
+            hex::encode(&root(&stream).unwrap()[..]),
+            "608407c18ad9a94d9ea2bcdbe170b6c20c462a7833a197621c916f78cf18e624"
+        );
+
+        let payer_id: PublicKey = BinarySD::deserialize(&data[(data.len() - 66 - 33)..(data.len() - 66)]).unwrap();
+        let signature = stream.get(240).unwrap();
+        verify(&stream, "invoice_request", signature, &payer_id).unwrap();
+        assert!(verify(&stream, "invoice", signature, &payer_id).is_err());
+    }
+
+    #[test]
+    fn empty() {
+        assert_eq!(root(&TlvStream::<NoKnownTypes>::new()), None);
+    }
+}
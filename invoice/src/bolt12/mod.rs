@@ -0,0 +1,187 @@
+//! BOLT 12 offers. Each message is the TLV stream, it is kept as is,
+//! because the signature covers all the records, including unknown odd.
+
+use wire::BinarySD;
+use wire::WireError;
+use wire::TlvStream;
+use wire::TlvKnownTypes;
+use wire::FeatureError;
+use wire::RawFeatureVector;
+use wire::Secp256k1Error;
+
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+
+use super::bech32;
+use super::bech32::Bech32Error;
+
+use std::fmt;
+use std::error;
+use std::ops::RangeInclusive;
+
+mod merkle;
+mod offer;
+mod invoice_request;
+mod invoice;
+
+pub use self::offer::OfferTlv;
+pub use self::offer::OfferAmount;
+pub use self::offer::Offer;
+pub use self::invoice_request::InvoiceRequestTlv;
+pub use self::invoice_request::UnsignedInvoiceRequest;
+pub use self::invoice_request::InvoiceRequest;
+pub use self::invoice::InvoiceTlv;
+pub use self::invoice::FallbackAddress;
+pub use self::invoice::UnsignedBolt12Invoice;
+pub use self::invoice::Bolt12Invoice;
+
+// SIGNATURE_TYPES are reserved for the signatures, they are not signed
+const SIGNATURE_TYPES: RangeInclusive<u64> = 240..=1000;
+
+#[derive(Debug)]
+pub enum Bolt12Error {
+    Bech32(Bech32Error),
+    WrongPrefix(String),
+    Wire(WireError),
+    UnexpectedType(u64),
+    MissingField(&'static str),
+    InvalidField(&'static str),
+    Feature(FeatureError),
+    InvalidSignature,
+    Secp256k1(Secp256k1Error),
+    /// The invoice does not mirror the record of the request.
+    RequestMismatch(u64),
+}
+
+impl From<Bech32Error> for Bolt12Error {
+    fn from(e: Bech32Error) -> Self {
+        return Bolt12Error::Bech32(e);
+    }
+}
+
+impl From<WireError> for Bolt12Error {
+    fn from(e: WireError) -> Self {
+        return Bolt12Error::Wire(e);
+    }
+}
+
+impl From<FeatureError> for Bolt12Error {
+    fn from(e: FeatureError) -> Self {
+        return Bolt12Error::Feature(e);
+    }
+}
+
+impl From<Secp256k1Error> for Bolt12Error {
+    fn from(e: Secp256k1Error) -> Self {
+        return Bolt12Error::Secp256k1(e);
+    }
+}
+
+impl error::Error for Bolt12Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        None
+    }
+}
+
+impl fmt::Display for Bolt12Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::Bolt12Error::*;
+
+        match self {
+            &Bech32(ref e) => write!(f, "bech32: {}", e),
+            &WrongPrefix(ref hrp) => write!(f, "unexpected prefix: {:?}", hrp),
+            &Wire(ref e) => write!(f, "tlv stream: {}", e),
+            &UnexpectedType(t) => write!(f, "tlv type: {} is not allowed in the message", t),
+            &MissingField(name) => write!(f, "the field `{}` is missing", name),
+            &InvalidField(name) => write!(f, "the field `{}` is invalid", name),
+            &Feature(ref e) => write!(f, "features: {}", e),
+            &InvalidSignature => write!(f, "invalid signature"),
+            &Secp256k1(ref e) => write!(f, "secp256k1: {}", e),
+            &RequestMismatch(t) => write!(f, "tlv type: {} differs from the invoice request", t),
+        }
+    }
+}
+
+// the bytes of the stream as bech32 without the checksum
+fn encode<K>(hrp: &str, stream: &TlvStream<K>) -> String where K: TlvKnownTypes {
+    let mut data = Vec::new();
+    BinarySD::serialize(&mut data, stream).unwrap();
+    bech32::encode_without_checksum(hrp, bech32::to_words(data.as_slice()).as_slice())
+}
+
+fn decode<K>(hrp: &str, s: &str) -> Result<TlvStream<K>, Bolt12Error> where K: TlvKnownTypes {
+    let (actual, words) = bech32::decode_without_checksum(s)?;
+    if actual != hrp {
+        return Err(Bolt12Error::WrongPrefix(actual));
+    }
+    let data = bech32::from_words(words.as_slice());
+    BinarySD::deserialize(data.as_slice()).map_err(Bolt12Error::from)
+}
+
+// each type of the stream should be in one of the ranges
+fn check_ranges<K>(stream: &TlvStream<K>, ranges: &[RangeInclusive<u64>]) -> Result<(), Bolt12Error>
+where
+    K: TlvKnownTypes,
+{
+    match stream.iter().find(|&(t, _)| !ranges.iter().any(|range| range.contains(&t))) {
+        Some((t, _)) => Err(Bolt12Error::UnexpectedType(t)),
+        None => Ok(()),
+    }
+}
+
+// the sequence of the values until the end of the record
+fn read_list<T>(data: &[u8]) -> Result<Vec<T>, WireError> where T: DeserializeOwned {
+    let mut data = data;
+    let mut list = Vec::new();
+    while !data.is_empty() {
+        list.push(BinarySD::deserialize(&mut data)?);
+    }
+    Ok(list)
+}
+
+fn write_list<T>(list: &[T]) -> Vec<u8> where T: Serialize {
+    let mut data = Vec::new();
+    for item in list {
+        BinarySD::serialize(&mut data, item).unwrap();
+    }
+    data
+}
+
+fn read_string<K>(stream: &TlvStream<K>, t: u64, name: &'static str) -> Result<Option<String>, Bolt12Error>
+where
+    K: TlvKnownTypes,
+{
+    match stream.get(t) {
+        None => Ok(None),
+        Some(data) => String::from_utf8(data.to_vec()).map(Some).map_err(|_| Bolt12Error::InvalidField(name)),
+    }
+}
+
+fn read_value<K, T>(stream: &TlvStream<K>, t: u64, name: &'static str) -> Result<Option<T>, Bolt12Error>
+where
+    K: TlvKnownTypes,
+    T: DeserializeOwned,
+{
+    stream.get_value(t).map_err(|_| Bolt12Error::InvalidField(name))
+}
+
+fn read_tu64<K>(stream: &TlvStream<K>, t: u64, name: &'static str) -> Result<Option<u64>, Bolt12Error>
+where
+    K: TlvKnownTypes,
+{
+    stream.get_tu64(t).map_err(|_| Bolt12Error::InvalidField(name))
+}
+
+// the reader should not accept the unknown required feature
+fn read_features<K>(stream: &TlvStream<K>, t: u64) -> Result<RawFeatureVector, Bolt12Error>
+where
+    K: TlvKnownTypes,
+{
+    let features = stream.get(t)
+        .map(|data| RawFeatureVector::from(data.to_vec()))
+        .unwrap_or(RawFeatureVector::new());
+    match features.iter().find(|feature_bit| !feature_bit.is_known() && feature_bit.is_required()) {
+        Some(feature_bit) => Err(FeatureError::UnknownRequired(feature_bit.clone()).into()),
+        None => Ok(features),
+    }
+}
@@ -0,0 +1,389 @@
+use wire::TlvStream;
+use wire::TlvKnownTypes;
+use wire::Hash256;
+use wire::MilliSatoshi;
+use wire::PublicKey;
+use wire::RawFeatureVector;
+use wire::BlindedPath;
+
+use super::Bolt12Error;
+use super::encode;
+use super::decode;
+use super::check_ranges;
+use super::read_list;
+use super::write_list;
+use super::read_string;
+use super::read_value;
+use super::read_tu64;
+use super::read_features;
+use super::invoice_request::UnsignedInvoiceRequest;
+
+use std::fmt;
+use std::str::FromStr;
+use std::ops::RangeInclusive;
+
+// PREFIX is the human readable part of the offer string
+const PREFIX: &'static str = "lno";
+
+// RANGES are the types of the records of the offer
+const RANGES: [RangeInclusive<u64>; 2] = [1..=79, 1_000_000_000..=1_999_999_999];
+
+/// The types of the records of the offer,
+/// the invoice request and the invoice mirror them.
+#[derive(Default, Clone, Eq, PartialEq, Debug)]
+pub struct OfferTlv;
+
+impl OfferTlv {
+    pub const CHAINS: u64 = 2;
+    pub const METADATA: u64 = 4;
+    pub const CURRENCY: u64 = 6;
+    pub const AMOUNT: u64 = 8;
+    pub const DESCRIPTION: u64 = 10;
+    pub const FEATURES: u64 = 12;
+    pub const ABSOLUTE_EXPIRY: u64 = 14;
+    pub const PATHS: u64 = 16;
+    pub const ISSUER: u64 = 18;
+    pub const QUANTITY_MAX: u64 = 20;
+    pub const ISSUER_ID: u64 = 22;
+}
+
+impl TlvKnownTypes for OfferTlv {
+    const KNOWN: &'static [u64] = &[
+        Self::CHAINS,
+        Self::METADATA,
+        Self::CURRENCY,
+        Self::AMOUNT,
+        Self::DESCRIPTION,
+        Self::FEATURES,
+        Self::ABSOLUTE_EXPIRY,
+        Self::PATHS,
+        Self::ISSUER,
+        Self::QUANTITY_MAX,
+        Self::ISSUER_ID,
+    ];
+
+    // unknown odd records are kept, the invoice request mirrors them
+    fn is_known(t: u64) -> bool {
+        Self::KNOWN.contains(&t) || t % 2 == 1
+    }
+}
+
+/// The amount is either in bitcoin or in the currency, e.g. `USD`,
+/// then it is in the minor unit of the currency, e.g. cents.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub enum OfferAmount {
+    Bitcoin(MilliSatoshi),
+    Currency {
+        iso4217: String,
+        amount: u64,
+    },
+}
+
+/// The reusable payment code, it needs no signature,
+/// so it can be created without the running node.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct Offer {
+    stream: TlvStream<OfferTlv>,
+}
+
+impl Offer {
+    /// The offer is for any amount until `with_amount` is called.
+    pub fn new(description: String, issuer_id: PublicKey) -> Self {
+        let mut stream = TlvStream::new();
+        stream.insert(OfferTlv::DESCRIPTION, description.into_bytes());
+        stream.insert_value(OfferTlv::ISSUER_ID, &issuer_id).unwrap();
+        Offer {
+            stream: stream,
+        }
+    }
+
+    pub fn with_amount(self, amount: OfferAmount) -> Self {
+        let mut s = self;
+        match amount {
+            OfferAmount::Bitcoin(amount) => {
+                s.stream.remove(OfferTlv::CURRENCY);
+                s.stream.insert_tu64(OfferTlv::AMOUNT, amount.into());
+            },
+            OfferAmount::Currency { iso4217: iso4217, amount: amount } => {
+                s.stream.insert(OfferTlv::CURRENCY, iso4217.into_bytes());
+                s.stream.insert_tu64(OfferTlv::AMOUNT, amount);
+            },
+        }
+        s
+    }
+
+    /// The default is the bitcoin chain only.
+    pub fn with_chains(self, chains: Vec<Hash256>) -> Self {
+        let mut s = self;
+        s.stream.insert(OfferTlv::CHAINS, write_list(chains.as_slice()));
+        s
+    }
+
+    /// The data for the issuer, e.g. to recognize the offer without storing it.
+    pub fn with_metadata(self, metadata: Vec<u8>) -> Self {
+        let mut s = self;
+        s.stream.insert(OfferTlv::METADATA, metadata);
+        s
+    }
+
+    pub fn with_features(self, features: RawFeatureVector) -> Self {
+        let mut s = self;
+        s.stream.insert(OfferTlv::FEATURES, features.into());
+        s
+    }
+
+    /// Seconds since the unix epoch.
+    pub fn with_absolute_expiry(self, absolute_expiry: u64) -> Self {
+        let mut s = self;
+        s.stream.insert_tu64(OfferTlv::ABSOLUTE_EXPIRY, absolute_expiry);
+        s
+    }
+
+    pub fn with_paths(self, paths: Vec<BlindedPath>) -> Self {
+        let mut s = self;
+        s.stream.insert(OfferTlv::PATHS, write_list(paths.as_slice()));
+        s
+    }
+
+    pub fn with_issuer(self, issuer: String) -> Self {
+        let mut s = self;
+        s.stream.insert(OfferTlv::ISSUER, issuer.into_bytes());
+        s
+    }
+
+    /// Zero means unlimited quantity.
+    pub fn with_quantity_max(self, quantity_max: u64) -> Self {
+        let mut s = self;
+        s.stream.insert_tu64(OfferTlv::QUANTITY_MAX, quantity_max);
+        s
+    }
+
+    /// Whether the record of the type belongs to the offer,
+    /// the invoice request and the invoice mirror such records.
+    pub fn contains_type(t: u64) -> bool {
+        RANGES.iter().any(|range| range.contains(&t))
+    }
+
+    /// Checks the offer as the reader does.
+    pub fn from_stream(stream: TlvStream<OfferTlv>) -> Result<Self, Bolt12Error> {
+        check_ranges(&stream, &RANGES)?;
+        let offer = Offer {
+            stream: stream,
+        };
+        offer.validate()?;
+        Ok(offer)
+    }
+
+    fn validate(&self) -> Result<(), Bolt12Error> {
+        self.read_chains()?;
+        read_features(&self.stream, OfferTlv::FEATURES)?;
+        read_tu64(&self.stream, OfferTlv::ABSOLUTE_EXPIRY, "offer_absolute_expiry")?;
+        read_tu64(&self.stream, OfferTlv::QUANTITY_MAX, "offer_quantity_max")?;
+        read_string(&self.stream, OfferTlv::ISSUER, "offer_issuer")?;
+        let amount = self.read_amount()?;
+        let description = read_string(&self.stream, OfferTlv::DESCRIPTION, "offer_description")?;
+        if amount.is_some() && description.is_none() {
+            return Err(Bolt12Error::MissingField("offer_description"));
+        }
+        let issuer_id = read_value::<_, PublicKey>(&self.stream, OfferTlv::ISSUER_ID, "offer_issuer_id")?;
+        let paths = self.read_paths()?;
+        if issuer_id.is_none() && paths.is_empty() {
+            return Err(Bolt12Error::MissingField("offer_issuer_id"));
+        }
+        Ok(())
+    }
+
+    fn read_chains(&self) -> Result<Vec<Hash256>, Bolt12Error> {
+        match self.stream.get(OfferTlv::CHAINS) {
+            None => Ok(vec![Hash256::BITCOIN_CHAIN_HASH]),
+            Some(data) => read_list(data).map_err(|_| Bolt12Error::InvalidField("offer_chains")),
+        }
+    }
+
+    fn read_amount(&self) -> Result<Option<OfferAmount>, Bolt12Error> {
+        let amount = read_tu64(&self.stream, OfferTlv::AMOUNT, "offer_amount")?;
+        let currency = read_string(&self.stream, OfferTlv::CURRENCY, "offer_currency")?;
+        match (amount, currency) {
+            (None, None) => Ok(None),
+            (None, Some(_)) => Err(Bolt12Error::MissingField("offer_amount")),
+            (Some(amount), None) => Ok(Some(OfferAmount::Bitcoin(MilliSatoshi::from(amount)))),
+            (Some(amount), Some(iso4217)) => {
+                if iso4217.len() != 3 {
+                    return Err(Bolt12Error::InvalidField("offer_currency"));
+                }
+                Ok(Some(OfferAmount::Currency {
+                    iso4217: iso4217,
+                    amount: amount,
+                }))
+            },
+        }
+    }
+
+    fn read_paths(&self) -> Result<Vec<BlindedPath>, Bolt12Error> {
+        match self.stream.get(OfferTlv::PATHS) {
+            None => Ok(Vec::new()),
+            Some(data) => match read_list::<BlindedPath>(data) {
+                Ok(ref paths) if paths.is_empty() => Err(Bolt12Error::InvalidField("offer_paths")),
+                Ok(paths) => Ok(paths),
+                Err(_) => Err(Bolt12Error::InvalidField("offer_paths")),
+            },
+        }
+    }
+
+    pub fn stream(&self) -> &TlvStream<OfferTlv> {
+        &self.stream
+    }
+
+    pub fn chains(&self) -> Vec<Hash256> {
+        self.read_chains().unwrap_or_default()
+    }
+
+    pub fn metadata(&self) -> Option<&[u8]> {
+        self.stream.get(OfferTlv::METADATA)
+    }
+
+    /// `None` means the payer chooses the amount.
+    pub fn amount(&self) -> Option<OfferAmount> {
+        self.read_amount().ok().and_then(|x| x)
+    }
+
+    pub fn description(&self) -> Option<String> {
+        read_string(&self.stream, OfferTlv::DESCRIPTION, "offer_description").ok().and_then(|x| x)
+    }
+
+    pub fn features(&self) -> RawFeatureVector {
+        read_features(&self.stream, OfferTlv::FEATURES).unwrap_or_default()
+    }
+
+    pub fn absolute_expiry(&self) -> Option<u64> {
+        self.stream.get_tu64(OfferTlv::ABSOLUTE_EXPIRY).ok().and_then(|x| x)
+    }
+
+    pub fn paths(&self) -> Vec<BlindedPath> {
+        self.read_paths().unwrap_or_default()
+    }
+
+    pub fn issuer(&self) -> Option<String> {
+        read_string(&self.stream, OfferTlv::ISSUER, "offer_issuer").ok().and_then(|x| x)
+    }
+
+    pub fn quantity_max(&self) -> Option<u64> {
+        self.stream.get_tu64(OfferTlv::QUANTITY_MAX).ok().and_then(|x| x)
+    }
+
+    /// `None` if the offer is reachable only via blinded paths.
+    pub fn issuer_id(&self) -> Option<PublicKey> {
+        self.stream.get_value(OfferTlv::ISSUER_ID).ok().and_then(|x| x)
+    }
+
+    /// The request mirrors all records of the offer,
+    /// `payer_id` is the key the request will be signed with.
+    pub fn request_invoice(&self, metadata: Vec<u8>, payer_id: PublicKey) -> UnsignedInvoiceRequest {
+        UnsignedInvoiceRequest::new(self, metadata, payer_id)
+    }
+}
+
+impl FromStr for Offer {
+    type Err = Bolt12Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Offer::from_stream(decode(PREFIX, s)?)
+    }
+}
+
+impl fmt::Display for Offer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", encode(PREFIX, &self.stream))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wire::BinarySD;
+    use hex;
+
+    fn issuer_id() -> PublicKey {
+        let data = hex::decode("02eec7245d6b7d2ccb30380bfbe2a3648cd7a942653f5aa340edcea1f283686619").unwrap();
+        BinarySD::deserialize(data.as_slice()).unwrap()
+    }
+
+    #[test]
+    fn minimal() {
+        let s = "lno1pgx9getnwss8vetrw3hhyuckyypwa3eyt44h6txtxquqh7lz5djge4afgfjn7k4rgrkuag0jsd5xvxg";
+        let offer = s.parse::<Offer>().unwrap();
+        assert_eq!(offer.description(), Some("Test vectors".to_owned()));
+        assert_eq!(offer.issuer_id(), Some(issuer_id()));
+        assert_eq!(offer.chains(), vec![Hash256::BITCOIN_CHAIN_HASH]);
+        assert_eq!(offer.amount(), None);
+
+        let built = Offer::new("Test vectors".to_owned(), issuer_id());
+        assert_eq!(built, offer);
+        assert_eq!(built.to_string(), s);
+    }
+
+    #[test]
+    fn offline() {
+        let offer = Offer::new("coffee".to_owned(), issuer_id())
+            .with_amount(OfferAmount::Currency { iso4217: "USD".to_owned(), amount: 250 })
+            .with_issuer("the shop".to_owned())
+            .with_quantity_max(0)
+            .with_absolute_expiry(1_700_000_000)
+            .with_metadata(vec![1, 2, 3]);
+        let restored = offer.to_string().parse::<Offer>().unwrap();
+        assert_eq!(restored, offer);
+        assert_eq!(restored.amount(), Some(OfferAmount::Currency { iso4217: "USD".to_owned(), amount: 250 }));
+        assert_eq!(restored.issuer(), Some("the shop".to_owned()));
+        assert_eq!(restored.quantity_max(), Some(0));
+        assert_eq!(restored.absolute_expiry(), Some(1_700_000_000));
+        assert_eq!(restored.metadata(), Some(&[1, 2, 3][..]));
+    }
+
+    #[test]
+    fn invalid() {
+        // the amount without the description
+        let mut stream = TlvStream::<OfferTlv>::new();
+        stream.insert_tu64(OfferTlv::AMOUNT, 100);
+        stream.insert_value(OfferTlv::ISSUER_ID, &issuer_id()).unwrap();
+        match Offer::from_stream(stream.clone()) {
+            Err(Bolt12Error::MissingField("offer_description")) => (),
+            r => panic!("unexpected: {:?}", r),
+        }
+
+        // neither the issuer id nor the paths
+        stream.insert(OfferTlv::DESCRIPTION, b"test".to_vec());
+        stream.remove(OfferTlv::ISSUER_ID);
+        match Offer::from_stream(stream.clone()) {
+            Err(Bolt12Error::MissingField("offer_issuer_id")) => (),
+            r => panic!("unexpected: {:?}", r),
+        }
+
+        // the odd type out of the offer range
+        stream.insert_value(OfferTlv::ISSUER_ID, &issuer_id()).unwrap();
+        stream.insert(81, vec![]);
+        match Offer::from_stream(stream.clone()) {
+            Err(Bolt12Error::UnexpectedType(81)) => (),
+            r => panic!("unexpected: {:?}", r),
+        }
+
+        // unknown odd type in the range is kept
+        stream.remove(81);
+        stream.insert(33, vec![1]);
+        let offer = Offer::from_stream(stream).unwrap();
+        assert_eq!(offer.to_string().parse::<Offer>().unwrap().stream().get(33), Some(&[1][..]));
+
+        // unknown even type
+        let mut stream = offer.stream.clone();
+        stream.insert(32, vec![]);
+        match (Offer { stream: stream }).to_string().parse::<Offer>() {
+            Err(Bolt12Error::Wire(_)) => (),
+            r => panic!("unexpected: {:?}", r),
+        }
+
+        // the prefix of another message
+        match offer.to_string().replace("lno", "lnr").parse::<Offer>() {
+            Err(Bolt12Error::WrongPrefix(_)) => (),
+            r => panic!("unexpected: {:?}", r),
+        }
+    }
+}
@@ -0,0 +1,307 @@
+use wire::Hash256;
+use wire::PublicKey;
+use wire::ShortChannelId;
+use wire::RawFeatureVector;
+use wire::FeatureBit;
+
+use secp256k1::Secp256k1;
+use secp256k1::PublicKey as Secp256k1PublicKey;
+
+use super::bech32::to_words;
+use super::bech32::from_words;
+
+// HASH_WORDS is the length of 32 bytes field, the only valid
+const HASH_WORDS: usize = 52;
+
+// PUBLIC_KEY_WORDS is the length of the public key field, the only valid
+const PUBLIC_KEY_WORDS: usize = 53;
+
+// ROUTE_HOP_SIZE is the size of the single hop of the route hint in bytes
+const ROUTE_HOP_SIZE: usize = 33 + 8 + 4 + 4 + 2;
+
+/// The on-chain address to use if the payment fails.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub enum Fallback {
+    /// Witness version 0-16 and the witness program.
+    SegWit {
+        version: u8,
+        program: Vec<u8>,
+    },
+    PubKeyHash([u8; 20]),
+    ScriptHash([u8; 20]),
+}
+
+/// The hop of the private route to the payee.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct RouteHop {
+    pub node_id: PublicKey,
+    pub short_channel_id: ShortChannelId,
+    pub base_fee: u32,
+    pub fee_rate: u32,
+    pub time_lock_delta: u16,
+}
+
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub enum TaggedField {
+    PaymentHash(Hash256),
+    PaymentSecret([u8; 32]),
+    Description(String),
+    DescriptionHash(Hash256),
+    /// Seconds since the timestamp.
+    Expiry(u64),
+    MinFinalCltvExpiry(u64),
+    Fallback(Fallback),
+    RouteHint(Vec<RouteHop>),
+    PayeeNodeId(PublicKey),
+    Features(RawFeatureVector),
+    Metadata(Vec<u8>),
+    /// The field this implementation does not know, kept to be signed as is.
+    Unknown {
+        tag: u8,
+        words: Vec<u8>,
+    },
+}
+
+impl TaggedField {
+    pub const PAYMENT_HASH: u8 = 1;
+    pub const ROUTE_HINT: u8 = 3;
+    pub const FEATURES: u8 = 5;
+    pub const EXPIRY: u8 = 6;
+    pub const FALLBACK: u8 = 9;
+    pub const DESCRIPTION: u8 = 13;
+    pub const PAYMENT_SECRET: u8 = 16;
+    pub const PAYEE_NODE_ID: u8 = 19;
+    pub const DESCRIPTION_HASH: u8 = 23;
+    pub const MIN_FINAL_CLTV_EXPIRY: u8 = 24;
+    pub const METADATA: u8 = 27;
+
+    pub fn tag(&self) -> u8 {
+        use self::TaggedField::*;
+
+        match self {
+            &PaymentHash(_) => Self::PAYMENT_HASH,
+            &PaymentSecret(_) => Self::PAYMENT_SECRET,
+            &Description(_) => Self::DESCRIPTION,
+            &DescriptionHash(_) => Self::DESCRIPTION_HASH,
+            &Expiry(_) => Self::EXPIRY,
+            &MinFinalCltvExpiry(_) => Self::MIN_FINAL_CLTV_EXPIRY,
+            &Fallback(_) => Self::FALLBACK,
+            &RouteHint(_) => Self::ROUTE_HINT,
+            &PayeeNodeId(_) => Self::PAYEE_NODE_ID,
+            &Features(_) => Self::FEATURES,
+            &Metadata(_) => Self::METADATA,
+            &Unknown { tag: tag, .. } => tag,
+        }
+    }
+
+    /// The data of the field as 5-bit words, without the tag and the length.
+    pub fn words(&self) -> Vec<u8> {
+        use self::TaggedField::*;
+
+        match self {
+            &PaymentHash(ref hash) | &DescriptionHash(ref hash) => to_words(&<[u8; 32]>::from(*hash)[..]),
+            &PaymentSecret(ref secret) => to_words(&secret[..]),
+            &Description(ref description) => to_words(description.as_bytes()),
+            &Expiry(value) | &MinFinalCltvExpiry(value) => int_to_words(value),
+            &Fallback(ref fallback) => {
+                let (version, data) = match fallback {
+                    &self::Fallback::SegWit { version: version, program: ref program } => (version, program.as_slice()),
+                    &self::Fallback::PubKeyHash(ref hash) => (17, &hash[..]),
+                    &self::Fallback::ScriptHash(ref hash) => (18, &hash[..]),
+                };
+                let mut words = vec![version];
+                words.extend(to_words(data));
+                words
+            },
+            &RouteHint(ref hops) => {
+                let mut data = Vec::with_capacity(hops.len() * ROUTE_HOP_SIZE);
+                for hop in hops {
+                    data.extend_from_slice(&hop.node_id.as_ref().serialize()[..]);
+                    data.extend_from_slice(&be_bytes(u64::from(hop.short_channel_id.clone()), 8));
+                    data.extend_from_slice(&be_bytes(hop.base_fee as u64, 4));
+                    data.extend_from_slice(&be_bytes(hop.fee_rate as u64, 4));
+                    data.extend_from_slice(&be_bytes(hop.time_lock_delta as u64, 2));
+                }
+                to_words(data.as_slice())
+            },
+            &PayeeNodeId(ref node_id) => to_words(&node_id.as_ref().serialize()[..]),
+            &Features(ref features) => features_to_words(features),
+            &Metadata(ref metadata) => to_words(metadata.as_slice()),
+            &Unknown { words: ref words, .. } => words.clone(),
+        }
+    }
+
+    /// Returns `None` if the field is known, but its data is not valid,
+    /// the reader should skip such field.
+    pub fn from_words(tag: u8, words: &[u8]) -> Option<Self> {
+        use self::TaggedField::*;
+
+        let hash = || {
+            if words.len() == HASH_WORDS {
+                let mut hash = [0; 32];
+                hash.copy_from_slice(&from_words(words)[..32]);
+                Some(hash)
+            } else {
+                None
+            }
+        };
+
+        match tag {
+            Self::PAYMENT_HASH => hash().map(Hash256::from).map(PaymentHash),
+            Self::PAYMENT_SECRET => hash().map(PaymentSecret),
+            Self::DESCRIPTION_HASH => hash().map(Hash256::from).map(DescriptionHash),
+            Self::DESCRIPTION => String::from_utf8(from_words(words)).ok().map(Description),
+            Self::EXPIRY => words_to_int(words).map(Expiry),
+            Self::MIN_FINAL_CLTV_EXPIRY => words_to_int(words).map(MinFinalCltvExpiry),
+            Self::FALLBACK => {
+                let (&version, program) = words.split_first()?;
+                let program = from_words(program);
+                let hash = || {
+                    if program.len() == 20 {
+                        let mut hash = [0; 20];
+                        hash.copy_from_slice(program.as_slice());
+                        Some(hash)
+                    } else {
+                        None
+                    }
+                };
+                match version {
+                    0..=16 => Some(Fallback(self::Fallback::SegWit {
+                        version: version,
+                        program: program.clone(),
+                    })),
+                    17 => hash().map(|hash| Fallback(self::Fallback::PubKeyHash(hash))),
+                    18 => hash().map(|hash| Fallback(self::Fallback::ScriptHash(hash))),
+                    // the reader should skip unknown version
+                    _ => None,
+                }
+            },
+            Self::ROUTE_HINT => {
+                let data = from_words(words);
+                if data.is_empty() || data.len() % ROUTE_HOP_SIZE != 0 {
+                    return None;
+                }
+                data.chunks(ROUTE_HOP_SIZE)
+                    .map(|hop| {
+                        Some(RouteHop {
+                            node_id: public_key(&hop[0..33])?,
+                            short_channel_id: ShortChannelId::from(be_int(&hop[33..41])),
+                            base_fee: be_int(&hop[41..45]) as u32,
+                            fee_rate: be_int(&hop[45..49]) as u32,
+                            time_lock_delta: be_int(&hop[49..51]) as u16,
+                        })
+                    })
+                    .collect::<Option<Vec<_>>>()
+                    .map(RouteHint)
+            },
+            Self::PAYEE_NODE_ID => {
+                if words.len() == PUBLIC_KEY_WORDS {
+                    public_key(&from_words(words)[..33]).map(PayeeNodeId)
+                } else {
+                    None
+                }
+            },
+            Self::FEATURES => Some(Features(features_from_words(words))),
+            Self::METADATA => Some(Metadata(from_words(words))),
+            _ => Some(Unknown {
+                tag: tag,
+                words: words.to_vec(),
+            }),
+        }
+    }
+}
+
+fn public_key(data: &[u8]) -> Option<PublicKey> {
+    Secp256k1PublicKey::from_slice(&Secp256k1::new(), data).ok().map(PublicKey::from)
+}
+
+fn be_bytes(value: u64, size: usize) -> Vec<u8> {
+    (0..size).rev().map(|i| (value >> (8 * i)) as u8).collect()
+}
+
+fn be_int(data: &[u8]) -> u64 {
+    data.iter().fold(0, |acc, &b| (acc << 8) | (b as u64))
+}
+
+/// Big endian, without leading zero words.
+pub fn int_to_words(value: u64) -> Vec<u8> {
+    let mut words = Vec::new();
+    let mut value = value;
+    while value != 0 {
+        words.insert(0, (value & 0x1f) as u8);
+        value >>= 5;
+    }
+    words
+}
+
+/// Returns `None` if the value does not fit into 64 bits.
+pub fn words_to_int(words: &[u8]) -> Option<u64> {
+    // 12 words is 60 bits, the 13th word overflows if the leading is not zero
+    let words = &words[words.iter().take_while(|&&w| w == 0).count()..];
+    if words.len() > 13 || (words.len() == 13 && words[0] > 0x0f) {
+        return None;
+    }
+    Some(words.iter().fold(0, |acc, &w| (acc << 5) | (w as u64)))
+}
+
+// the bit `i` is the bit `i % 5` of the word `i / 5` counting from the end
+fn features_to_words(features: &RawFeatureVector) -> Vec<u8> {
+    let bits = features.iter().map(|bit| u16::from(bit.clone()) as usize).collect::<Vec<_>>();
+    match bits.iter().max() {
+        None => Vec::new(),
+        Some(&max) => {
+            let length = max / 5 + 1;
+            let mut words = vec![0u8; length];
+            for bit in bits {
+                words[length - 1 - bit / 5] |= 1 << (bit % 5);
+            }
+            words
+        },
+    }
+}
+
+fn features_from_words(words: &[u8]) -> RawFeatureVector {
+    words.iter().rev().enumerate()
+        .fold(RawFeatureVector::new(), |features, (index, &word)| {
+            (0..5).filter(|bit| word & (1 << bit) != 0)
+                .fold(features, |features, bit| {
+                    features.set_bit(FeatureBit::from((index * 5 + bit) as u16))
+                })
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn integer() {
+        assert_eq!(int_to_words(60), vec![1, 28]);
+        assert_eq!(words_to_int(&[1, 28]), Some(60));
+        assert_eq!(words_to_int(&[0, 0, 1, 28]), Some(60));
+        assert_eq!(words_to_int(&[]), Some(0));
+        assert_eq!(words_to_int(int_to_words(u64::max_value()).as_slice()), Some(u64::max_value()));
+        assert_eq!(words_to_int(&[0x10, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]), None);
+    }
+
+    #[test]
+    fn features() {
+        let features = RawFeatureVector::new()
+            .set_bit(FeatureBit::VarOnionOptinRequired)
+            .set_bit(FeatureBit::PaymentSecretRequired);
+        let words = features_to_words(&features);
+        assert_eq!(words, vec![16, 8, 0]);
+        assert_eq!(features_from_words(words.as_slice()), features);
+    }
+
+    #[test]
+    fn wrong_length() {
+        assert!(TaggedField::from_words(TaggedField::PAYMENT_HASH, &[0; 51]).is_none());
+        assert!(TaggedField::from_words(TaggedField::PAYEE_NODE_ID, &[0; 52]).is_none());
+        assert!(TaggedField::from_words(TaggedField::FALLBACK, &[19, 0]).is_none());
+        assert_eq!(
+            TaggedField::from_words(2, &[1, 2]),
+            Some(TaggedField::Unknown { tag: 2, words: vec![1, 2] })
+        );
+    }
+}
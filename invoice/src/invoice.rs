@@ -0,0 +1,832 @@
+use wire::MilliSatoshi;
+use wire::PublicKey;
+use wire::SecretKey;
+use wire::Hash256;
+use wire::RawFeatureVector;
+use wire::FeatureContext;
+use wire::FeatureError;
+use wire::Secp256k1Error;
+
+use secp256k1::Secp256k1;
+use secp256k1::Message;
+use secp256k1::RecoverableSignature;
+use secp256k1::RecoveryId;
+
+use super::bech32;
+use super::bech32::Bech32Error;
+use super::field::TaggedField;
+use super::field::Fallback;
+use super::field::RouteHop;
+use super::field::int_to_words;
+use super::field::words_to_int;
+
+use std::fmt;
+use std::error;
+use std::str::FromStr;
+
+// TIMESTAMP_WORDS is the length of the timestamp, 35 bits
+const TIMESTAMP_WORDS: usize = 7;
+
+// SIGNATURE_WORDS is the length of the signature, 64 bytes and the recovery id
+const SIGNATURE_WORDS: usize = 104;
+
+// MAX_FIELD_WORDS is the maximal length of the field, the length is encoded in two words
+const MAX_FIELD_WORDS: usize = 1023;
+
+// DEFAULT_EXPIRY is the expiry in seconds if the field `x` is missing
+const DEFAULT_EXPIRY: u64 = 3600;
+
+// DEFAULT_MIN_FINAL_CLTV_EXPIRY is the value if the field `c` is missing
+const DEFAULT_MIN_FINAL_CLTV_EXPIRY: u64 = 18;
+
+// MILLI_SATOSHI_PER_BITCOIN is the amount without the multiplier
+const MILLI_SATOSHI_PER_BITCOIN: u64 = 100_000_000_000;
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum Currency {
+    Bitcoin,
+    Testnet,
+    Regtest,
+    Signet,
+}
+
+impl Currency {
+    // the longer prefix goes first, `bcrt` starts with `bc`
+    const ALL: [Currency; 4] = [Currency::Regtest, Currency::Signet, Currency::Bitcoin, Currency::Testnet];
+
+    pub fn prefix(&self) -> &'static str {
+        use self::Currency::*;
+
+        match self {
+            &Bitcoin => "bc",
+            &Testnet => "tb",
+            &Regtest => "bcrt",
+            &Signet => "tbs",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum InvoiceError {
+    Bech32(Bech32Error),
+    UnknownCurrency(String),
+    InvalidAmount(String),
+    TooShort,
+    InvalidTimestamp,
+    MalformedField(u8),
+    FieldTooLong(u8),
+    MissingPaymentHash,
+    MissingDescription,
+    DescriptionAndHash,
+    DuplicateField(u8),
+    Feature(FeatureError),
+    InvalidSignature,
+    Secp256k1(Secp256k1Error),
+}
+
+impl From<Bech32Error> for InvoiceError {
+    fn from(e: Bech32Error) -> Self {
+        return InvoiceError::Bech32(e);
+    }
+}
+
+impl From<FeatureError> for InvoiceError {
+    fn from(e: FeatureError) -> Self {
+        return InvoiceError::Feature(e);
+    }
+}
+
+impl From<Secp256k1Error> for InvoiceError {
+    fn from(e: Secp256k1Error) -> Self {
+        return InvoiceError::Secp256k1(e);
+    }
+}
+
+impl error::Error for InvoiceError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        None
+    }
+}
+
+impl fmt::Display for InvoiceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::InvoiceError::*;
+
+        match self {
+            &Bech32(ref e) => write!(f, "bech32: {}", e),
+            &UnknownCurrency(ref hrp) => write!(f, "unknown currency in: {:?}", hrp),
+            &InvalidAmount(ref amount) => write!(f, "invalid amount: {:?}", amount),
+            &TooShort => write!(f, "the data is too short to contain the timestamp and the signature"),
+            &InvalidTimestamp => write!(f, "the timestamp does not fit in 35 bits"),
+            &MalformedField(tag) => write!(f, "the field {} overflows the data", tag),
+            &FieldTooLong(tag) => write!(f, "the field {} is longer than {} words", tag, MAX_FIELD_WORDS),
+            &MissingPaymentHash => write!(f, "the payment hash is missing"),
+            &MissingDescription => write!(f, "both the description and the description hash are missing"),
+            &DescriptionAndHash => write!(f, "both the description and the description hash are present"),
+            &DuplicateField(tag) => write!(f, "the field {} is repeated", tag),
+            &Feature(ref e) => write!(f, "features: {}", e),
+            &InvalidSignature => write!(f, "the signature does not match the payee node id"),
+            &Secp256k1(ref e) => write!(f, "secp256k1: {}", e),
+        }
+    }
+}
+
+/// The invoice before it is signed by the payee node.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct UnsignedInvoice {
+    currency: Currency,
+    amount: Option<MilliSatoshi>,
+    timestamp: u64,
+    fields: Vec<TaggedField>,
+}
+
+impl UnsignedInvoice {
+    /// The `timestamp` is seconds since the unix epoch.
+    pub fn new(currency: Currency, timestamp: u64) -> Self {
+        UnsignedInvoice {
+            currency: currency,
+            amount: None,
+            timestamp: timestamp,
+            fields: Vec::new(),
+        }
+    }
+
+    pub fn with_amount(self, amount: MilliSatoshi) -> Self {
+        let mut s = self;
+        s.amount = Some(amount);
+        s
+    }
+
+    /// The fields are written in the order they are added.
+    pub fn with_field(self, field: TaggedField) -> Self {
+        let mut s = self;
+        s.fields.push(field);
+        s
+    }
+
+    // the amount uses the largest multiplier which represents it exactly
+    fn hrp(&self) -> String {
+        let amount = match self.amount {
+            None => String::new(),
+            Some(amount) => {
+                let amount = u64::from(amount);
+                if amount % MILLI_SATOSHI_PER_BITCOIN == 0 {
+                    format!("{}", amount / MILLI_SATOSHI_PER_BITCOIN)
+                } else if amount % 100_000_000 == 0 {
+                    format!("{}m", amount / 100_000_000)
+                } else if amount % 100_000 == 0 {
+                    format!("{}u", amount / 100_000)
+                } else if amount % 100 == 0 {
+                    format!("{}n", amount / 100)
+                } else {
+                    format!("{}p", amount * 10)
+                }
+            },
+        };
+        format!("ln{}{}", self.currency.prefix(), amount)
+    }
+
+    // the timestamp and the fields, without the signature
+    fn words(&self) -> Result<Vec<u8>, InvoiceError> {
+        let timestamp = int_to_words(self.timestamp);
+        if timestamp.len() > TIMESTAMP_WORDS {
+            return Err(InvoiceError::InvalidTimestamp);
+        }
+
+        let mut words = vec![0; TIMESTAMP_WORDS - timestamp.len()];
+        words.extend(timestamp);
+        for field in &self.fields {
+            let data = field.words();
+            if data.len() > MAX_FIELD_WORDS {
+                return Err(InvoiceError::FieldTooLong(field.tag()));
+            }
+            words.push(field.tag());
+            words.push((data.len() >> 5) as u8);
+            words.push((data.len() & 0x1f) as u8);
+            words.extend(data);
+        }
+        Ok(words)
+    }
+
+    pub fn sign(self, secret_key: &SecretKey) -> Result<Invoice, InvoiceError> {
+        let hrp = self.hrp();
+        let mut words = self.words()?;
+
+        let context = Secp256k1::new();
+        let signature = context.sign_recoverable(&message(hrp.as_str(), words.as_slice()), secret_key.as_ref())?;
+        let (recovery_id, data) = signature.serialize_compact(&context);
+        let mut signature = data.to_vec();
+        signature.push(recovery_id.to_i32() as u8);
+        words.extend(bech32::to_words(signature.as_slice()));
+
+        // decode what is just encoded, it checks the invoice is valid
+        Invoice::from_words(hrp, words)
+    }
+}
+
+// the hash of the human readable part and the data is signed
+fn message(hrp: &str, words: &[u8]) -> Message {
+    use sha2::Sha256;
+    use digest::FixedOutput;
+    use digest::Input;
+
+    let mut hasher = Sha256::default();
+    hasher.process(hrp.as_bytes());
+    hasher.process(bech32::from_words_padded(words).as_slice());
+    Message::from_slice(hasher.fixed_result().as_slice()).unwrap()
+}
+
+/// The signed payment request, decoded from the string `ln...`.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct Invoice {
+    unsigned: UnsignedInvoice,
+    payee: PublicKey,
+    // keep the original encoding, the known fields might be encoded differently
+    hrp: String,
+    words: Vec<u8>,
+}
+
+impl Invoice {
+    fn from_words(hrp: String, words: Vec<u8>) -> Result<Self, InvoiceError> {
+        let (currency, amount) = parse_hrp(hrp.as_str())?;
+
+        if words.len() < TIMESTAMP_WORDS + SIGNATURE_WORDS {
+            return Err(InvoiceError::TooShort);
+        }
+        let end = words.len() - SIGNATURE_WORDS;
+        let timestamp = words_to_int(&words[..TIMESTAMP_WORDS]).ok_or(InvoiceError::InvalidTimestamp)?;
+
+        let mut fields = Vec::new();
+        let mut position = TIMESTAMP_WORDS;
+        while position < end {
+            let tag = words[position];
+            if position + 3 > end {
+                return Err(InvoiceError::MalformedField(tag));
+            }
+            let length = ((words[position + 1] as usize) << 5) | (words[position + 2] as usize);
+            let start = position + 3;
+            if start + length > end {
+                return Err(InvoiceError::MalformedField(tag));
+            }
+            // the field is skipped if the data is not valid
+            if let Some(field) = TaggedField::from_words(tag, &words[start..(start + length)]) {
+                fields.push(field);
+            }
+            position = start + length;
+        }
+
+        let unsigned = UnsignedInvoice {
+            currency: currency,
+            amount: amount,
+            timestamp: timestamp,
+            fields: fields,
+        };
+        let payee = recover(hrp.as_str(), &words[..end], &words[end..], unsigned.payee_node_id())?;
+
+        let invoice = Invoice {
+            unsigned: unsigned,
+            payee: payee,
+            hrp: hrp,
+            words: words,
+        };
+
+        if invoice.payment_hash().is_none() {
+            return Err(InvoiceError::MissingPaymentHash);
+        }
+        if invoice.description().is_none() && invoice.description_hash().is_none() {
+            return Err(InvoiceError::MissingDescription);
+        }
+        if invoice.description().is_some() && invoice.description_hash().is_some() {
+            return Err(InvoiceError::DescriptionAndHash);
+        }
+        // the payment hash and the description hash must be unambiguous
+        for &tag in [TaggedField::PAYMENT_HASH, TaggedField::DESCRIPTION_HASH].iter() {
+            if invoice.fields().iter().filter(|field| field.tag() == tag).count() > 1 {
+                return Err(InvoiceError::DuplicateField(tag));
+            }
+        }
+        if let Some(features) = invoice.features() {
+            features.validate(FeatureContext::Invoice)?;
+        }
+
+        Ok(invoice)
+    }
+
+    pub fn currency(&self) -> Currency {
+        self.unsigned.currency
+    }
+
+    pub fn amount(&self) -> Option<MilliSatoshi> {
+        self.unsigned.amount
+    }
+
+    pub fn timestamp(&self) -> u64 {
+        self.unsigned.timestamp
+    }
+
+    /// All valid fields in the order they are encoded, including unknown.
+    pub fn fields(&self) -> &[TaggedField] {
+        self.unsigned.fields.as_slice()
+    }
+
+    /// The node which signed the invoice.
+    pub fn payee(&self) -> &PublicKey {
+        &self.payee
+    }
+
+    pub fn payment_hash(&self) -> Option<Hash256> {
+        self.unsigned.find(|field| match field {
+            &TaggedField::PaymentHash(ref hash) => Some(hash.clone()),
+            _ => None,
+        })
+    }
+
+    pub fn payment_secret(&self) -> Option<[u8; 32]> {
+        self.unsigned.find(|field| match field {
+            &TaggedField::PaymentSecret(ref secret) => Some(secret.clone()),
+            _ => None,
+        })
+    }
+
+    pub fn description(&self) -> Option<&str> {
+        self.unsigned.fields.iter()
+            .filter_map(|field| match field {
+                &TaggedField::Description(ref description) => Some(description.as_str()),
+                _ => None,
+            })
+            .next()
+    }
+
+    pub fn description_hash(&self) -> Option<Hash256> {
+        self.unsigned.find(|field| match field {
+            &TaggedField::DescriptionHash(ref hash) => Some(hash.clone()),
+            _ => None,
+        })
+    }
+
+    /// Seconds since the timestamp.
+    pub fn expiry(&self) -> u64 {
+        self.unsigned.find(|field| match field {
+            &TaggedField::Expiry(expiry) => Some(expiry),
+            _ => None,
+        }).unwrap_or(DEFAULT_EXPIRY)
+    }
+
+    pub fn min_final_cltv_expiry(&self) -> u64 {
+        self.unsigned.find(|field| match field {
+            &TaggedField::MinFinalCltvExpiry(expiry) => Some(expiry),
+            _ => None,
+        }).unwrap_or(DEFAULT_MIN_FINAL_CLTV_EXPIRY)
+    }
+
+    pub fn fallbacks(&self) -> Vec<Fallback> {
+        self.unsigned.fields.iter()
+            .filter_map(|field| match field {
+                &TaggedField::Fallback(ref fallback) => Some(fallback.clone()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Each route hint is a private route to the payee.
+    pub fn route_hints(&self) -> Vec<Vec<RouteHop>> {
+        self.unsigned.fields.iter()
+            .filter_map(|field| match field {
+                &TaggedField::RouteHint(ref hops) => Some(hops.clone()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    pub fn features(&self) -> Option<RawFeatureVector> {
+        self.unsigned.find(|field| match field {
+            &TaggedField::Features(ref features) => Some(features.clone()),
+            _ => None,
+        })
+    }
+}
+
+impl UnsignedInvoice {
+    // the first field of the kind, the rest are ignored
+    fn find<T, F>(&self, f: F) -> Option<T> where F: FnMut(&TaggedField) -> Option<T> {
+        self.fields.iter().filter_map(f).next()
+    }
+
+    fn payee_node_id(&self) -> Option<PublicKey> {
+        self.find(|field| match field {
+            &TaggedField::PayeeNodeId(ref node_id) => Some(node_id.clone()),
+            _ => None,
+        })
+    }
+}
+
+fn parse_hrp(hrp: &str) -> Result<(Currency, Option<MilliSatoshi>), InvoiceError> {
+    let unknown = || InvoiceError::UnknownCurrency(hrp.to_owned());
+    if !hrp.starts_with("ln") {
+        return Err(unknown());
+    }
+    let hrp = &hrp[2..];
+    let currency = Currency::ALL.iter()
+        .find(|currency| hrp.starts_with(currency.prefix()))
+        .cloned()
+        .ok_or_else(unknown)?;
+    let amount = &hrp[currency.prefix().len()..];
+    if amount.is_empty() {
+        return Ok((currency, None));
+    }
+
+    let invalid = || InvoiceError::InvalidAmount(amount.to_owned());
+    let (digits, multiplier) = match amount.chars().last() {
+        Some(c) if c.is_ascii_digit() => (amount, None),
+        Some(c) => (&amount[..(amount.len() - 1)], Some(c)),
+        None => unreachable!(),
+    };
+    if digits.is_empty() || digits.starts_with('0') || !digits.chars().all(|c| c.is_ascii_digit()) {
+        return Err(invalid());
+    }
+    let value = digits.parse::<u64>().map_err(|_| invalid())?;
+    let amount = match multiplier {
+        None => value.checked_mul(MILLI_SATOSHI_PER_BITCOIN),
+        Some('m') => value.checked_mul(100_000_000),
+        Some('u') => value.checked_mul(100_000),
+        Some('n') => value.checked_mul(100),
+        // the last decimal of pico bitcoin amount must be zero
+        Some('p') if value % 10 == 0 => Some(value / 10),
+        _ => None,
+    };
+
+    amount
+        .map(|amount| (currency, Some(MilliSatoshi::from(amount))))
+        .ok_or_else(invalid)
+}
+
+// verifies the signature if the payee node id is known, otherwise recovers it
+fn recover(
+    hrp: &str,
+    words: &[u8],
+    signature: &[u8],
+    payee: Option<PublicKey>,
+) -> Result<PublicKey, InvoiceError> {
+    let context = Secp256k1::new();
+    let signature = bech32::from_words(signature);
+    let recovery_id = RecoveryId::from_i32(signature[64] as i32)?;
+    let signature = RecoverableSignature::from_compact(&context, &signature[..64], recovery_id)?;
+    let message = message(hrp, words);
+    match payee {
+        Some(payee) => {
+            context.verify(&message, &signature.to_standard(&context), payee.as_ref())
+                .map_err(|_| InvoiceError::InvalidSignature)?;
+            Ok(payee)
+        },
+        None => Ok(PublicKey::from(context.recover(&message, &signature)?)),
+    }
+}
+
+impl FromStr for Invoice {
+    type Err = InvoiceError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (hrp, words) = bech32::decode(s)?;
+        Invoice::from_words(hrp, words)
+    }
+}
+
+impl fmt::Display for Invoice {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", bech32::encode(self.hrp.as_str(), self.words.as_slice()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wire::ShortChannelId;
+    use wire::FeatureBit;
+    use secp256k1::SecretKey as Secp256k1SecretKey;
+    use hex;
+
+    const TIMESTAMP: u64 = 1496314658;
+
+    fn hex_32(s: &str) -> [u8; 32] {
+        let mut data = [0; 32];
+        data.copy_from_slice(hex::decode(s).unwrap().as_slice());
+        data
+    }
+
+    fn public_key(s: &str) -> PublicKey {
+        use secp256k1::PublicKey as Secp256k1PublicKey;
+
+        Secp256k1PublicKey::from_slice(&Secp256k1::new(), hex::decode(s).unwrap().as_slice()).unwrap().into()
+    }
+
+    fn secret_key() -> SecretKey {
+        let data = hex::decode("e126f68f7eafcc8b74f54d269fe206be715000f94dac067d1c04a8ca3b2db734").unwrap();
+        Secp256k1SecretKey::from_slice(&Secp256k1::new(), data.as_slice()).unwrap().into()
+    }
+
+    fn payee() -> PublicKey {
+        public_key("03e7156ae33b0a208d0744199163177e909e80176e55d97a2f221ede0f934dd9ad")
+    }
+
+    fn payment_hash() -> TaggedField {
+        TaggedField::PaymentHash(Hash256::from(hex_32("0001020304050607080900010203040506070809000102030405060708090102")))
+    }
+
+    fn payment_secret() -> TaggedField {
+        TaggedField::PaymentSecret([0x11; 32])
+    }
+
+    fn description_hash() -> TaggedField {
+        TaggedField::DescriptionHash(Hash256::from(hex_32("3925b6f67e2c340036ed12093dd44e0368df1b6ea26c53dbe4811f58fd5db8c1")))
+    }
+
+    fn features() -> TaggedField {
+        TaggedField::Features(
+            RawFeatureVector::new()
+                .set_bit(FeatureBit::VarOnionOptinRequired)
+                .set_bit(FeatureBit::PaymentSecretRequired)
+        )
+    }
+
+    fn fallback(version: u8, program: &str) -> TaggedField {
+        let program = hex::decode(program).unwrap();
+        let mut hash = [0; 20];
+        TaggedField::Fallback(match version {
+            17 => {
+                hash.copy_from_slice(program.as_slice());
+                Fallback::PubKeyHash(hash)
+            },
+            18 => {
+                hash.copy_from_slice(program.as_slice());
+                Fallback::ScriptHash(hash)
+            },
+            _ => Fallback::SegWit {
+                version: version,
+                program: program,
+            },
+        })
+    }
+
+    // the test vectors from BOLT 11 with the payment secret and the features
+    fn vectors() -> Vec<(&'static str, UnsignedInvoice)> {
+        let new = || UnsignedInvoice::new(Currency::Bitcoin, TIMESTAMP);
+        vec![
+            (
+                "lnbc1pvjluezsp5zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zygspp5qqqsyqcyq5rqwzqfqqqsyqcyq5rqwzqfqqqsyqcyq5rqwzqfqypqdpl2pkx2ctnv5sxxmmwwd5kgetjypeh2ursdae8g6twvus8g6rfwvs8qun0dfjkxaq9qrsgq357wnc5r2ueh7ck6q93dj32dlqnls087fxdwk8qakdyafkq3yap9us6v52vjjsrvywa6rt52cm9r9zqt8r2t7mlcwspyetp5h2tztugp9lfyql",
+                new()
+                    .with_field(payment_secret())
+                    .with_field(payment_hash())
+                    .with_field(TaggedField::Description("Please consider supporting this project".to_owned()))
+                    .with_field(features()),
+            ),
+            (
+                "lnbc2500u1pvjluezsp5zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zygspp5qqqsyqcyq5rqwzqfqqqsyqcyq5rqwzqfqqqsyqcyq5rqwzqfqypqdq5xysxxatsyp3k7enxv4jsxqzpu9qrsgquk0rl77nj30yxdy8j9vdx85fkpmdla2087ne0xh8nhedh8w27kyke0lp53ut353s06fv3qfegext0eh0ymjpf39tuven09sam30g4vgpfna3rh",
+                new()
+                    .with_amount(MilliSatoshi::from(250_000_000))
+                    .with_field(payment_secret())
+                    .with_field(payment_hash())
+                    .with_field(TaggedField::Description("1 cup coffee".to_owned()))
+                    .with_field(TaggedField::Expiry(60))
+                    .with_field(features()),
+            ),
+            (
+                "lnbc2500u1pvjluezsp5zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zygspp5qqqsyqcyq5rqwzqfqqqsyqcyq5rqwzqfqqqsyqcyq5rqwzqfqypqdpquwpc4curk03c9wlrswe78q4eyqc7d8d0xqzpu9qrsgqhtjpauu9ur7fw2thcl4y9vfvh4m9wlfyz2gem29g5ghe2aak2pm3ps8fdhtceqsaagty2vph7utlgj48u0ged6a337aewvraedendscp573dxr",
+                new()
+                    .with_amount(MilliSatoshi::from(250_000_000))
+                    .with_field(payment_secret())
+                    .with_field(payment_hash())
+                    .with_field(TaggedField::Description("ナンセンス 1杯".to_owned()))
+                    .with_field(TaggedField::Expiry(60))
+                    .with_field(features()),
+            ),
+            (
+                "lnbc20m1pvjluezsp5zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zygspp5qqqsyqcyq5rqwzqfqqqsyqcyq5rqwzqfqqqsyqcyq5rqwzqfqypqhp58yjmdan79s6qqdhdzgynm4zwqd5d7xmw5fk98klysy043l2ahrqs9qrsgq7ea976txfraylvgzuxs8kgcw23ezlrszfnh8r6qtfpr6cxga50aj6txm9rxrydzd06dfeawfk6swupvz4erwnyutnjq7x39ymw6j38gp7ynn44",
+                new()
+                    .with_amount(MilliSatoshi::from(2_000_000_000))
+                    .with_field(payment_secret())
+                    .with_field(payment_hash())
+                    .with_field(description_hash())
+                    .with_field(features()),
+            ),
+            (
+                "lntb20m1pvjluezsp5zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zygshp58yjmdan79s6qqdhdzgynm4zwqd5d7xmw5fk98klysy043l2ahrqspp5qqqsyqcyq5rqwzqfqqqsyqcyq5rqwzqfqqqsyqcyq5rqwzqfqypqfpp3x9et2e20v6pu37c5d9vax37wxq72un989qrsgqdj545axuxtnfemtpwkc45hx9d2ft7x04mt8q7y6t0k2dge9e7h8kpy9p34ytyslj3yu569aalz2xdk8xkd7ltxqld94u8h2esmsmacgpghe9k8",
+                UnsignedInvoice::new(Currency::Testnet, TIMESTAMP)
+                    .with_amount(MilliSatoshi::from(2_000_000_000))
+                    .with_field(payment_secret())
+                    .with_field(description_hash())
+                    .with_field(payment_hash())
+                    .with_field(fallback(17, "3172b5654f6683c8fb146959d347ce303cae4ca7"))
+                    .with_field(features()),
+            ),
+            (
+                "lnbc20m1pvjluezsp5zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zygspp5qqqsyqcyq5rqwzqfqqqsyqcyq5rqwzqfqqqsyqcyq5rqwzqfqypqhp58yjmdan79s6qqdhdzgynm4zwqd5d7xmw5fk98klysy043l2ahrqsfpp3qjmp7lwpagxun9pygexvgpjdc4jdj85fr9yq20q82gphp2nflc7jtzrcazrra7wwgzxqc8u7754cdlpfrmccae92qgzqvzq2ps8pqqqqqqpqqqqq9qqqvpeuqafqxu92d8lr6fvg0r5gv0heeeqgcrqlnm6jhphu9y00rrhy4grqszsvpcgpy9qqqqqqgqqqqq7qqzq9qrsgqdfjcdk6w3ak5pca9hwfwfh63zrrz06wwfya0ydlzpgzxkn5xagsqz7x9j4jwe7yj7vaf2k9lqsdk45kts2fd0fkr28am0u4w95tt2nsq76cqw0",
+                new()
+                    .with_amount(MilliSatoshi::from(2_000_000_000))
+                    .with_field(payment_secret())
+                    .with_field(payment_hash())
+                    .with_field(description_hash())
+                    .with_field(fallback(17, "04b61f7dc1ea0dc99424464cc4064dc564d91e89"))
+                    .with_field(TaggedField::RouteHint(vec![
+                        RouteHop {
+                            node_id: public_key("029e03a901b85534ff1e92c43c74431f7ce72046060fcf7a95c37e148f78c77255"),
+                            short_channel_id: ShortChannelId::from(0x0102030405060708),
+                            base_fee: 1,
+                            fee_rate: 20,
+                            time_lock_delta: 3,
+                        },
+                        RouteHop {
+                            node_id: public_key("039e03a901b85534ff1e92c43c74431f7ce72046060fcf7a95c37e148f78c77255"),
+                            short_channel_id: ShortChannelId::from(0x030405060708090a),
+                            base_fee: 2,
+                            fee_rate: 30,
+                            time_lock_delta: 4,
+                        },
+                    ]))
+                    .with_field(features()),
+            ),
+            (
+                "lnbc20m1pvjluezsp5zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zygshp58yjmdan79s6qqdhdzgynm4zwqd5d7xmw5fk98klysy043l2ahrqspp5qqqsyqcyq5rqwzqfqqqsyqcyq5rqwzqfqqqsyqcyq5rqwzqfqypqfppj3a24vwu6r8ejrss3axul8rxldph2q7z99qrsgqz6qsgww34xlatfj6e3sngrwfy3ytkt29d2qttr8qz2mnedfqysuqypgqex4haa2h8fx3wnypranf3pdwyluftwe680jjcfp438u82xqphf75ym",
+                new()
+                    .with_amount(MilliSatoshi::from(2_000_000_000))
+                    .with_field(payment_secret())
+                    .with_field(description_hash())
+                    .with_field(payment_hash())
+                    .with_field(fallback(18, "8f55563b9a19f321c211e9b9f38cdf686ea07845"))
+                    .with_field(features()),
+            ),
+            (
+                "lnbc20m1pvjluezsp5zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zygshp58yjmdan79s6qqdhdzgynm4zwqd5d7xmw5fk98klysy043l2ahrqspp5qqqsyqcyq5rqwzqfqqqsyqcyq5rqwzqfqqqsyqcyq5rqwzqfqypqfppqw508d6qejxtdg4y5r3zarvary0c5xw7k9qrsgqt29a0wturnys2hhxpner2e3plp6jyj8qx7548zr2z7ptgjjc7hljm98xhjym0dg52sdrvqamxdezkmqg4gdrvwwnf0kv2jdfnl4xatsqmrnsse",
+                new()
+                    .with_amount(MilliSatoshi::from(2_000_000_000))
+                    .with_field(payment_secret())
+                    .with_field(description_hash())
+                    .with_field(payment_hash())
+                    .with_field(fallback(0, "751e76e8199196d454941c45d1b3a323f1433bd6"))
+                    .with_field(features()),
+            ),
+            (
+                "lnbc20m1pvjluezsp5zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zygshp58yjmdan79s6qqdhdzgynm4zwqd5d7xmw5fk98klysy043l2ahrqspp5qqqsyqcyq5rqwzqfqqqsyqcyq5rqwzqfqqqsyqcyq5rqwzqfqypqfp4qrp33g0q5c5txsp9arysrx4k6zdkfs4nce4xj0gdcccefvpysxf3q9qrsgq9vlvyj8cqvq6ggvpwd53jncp9nwc47xlrsnenq2zp70fq83qlgesn4u3uyf4tesfkkwwfg3qs54qe426hp3tz7z6sweqdjg05axsrjqp9yrrwc",
+                new()
+                    .with_amount(MilliSatoshi::from(2_000_000_000))
+                    .with_field(payment_secret())
+                    .with_field(description_hash())
+                    .with_field(payment_hash())
+                    .with_field(fallback(0, "1863143c14c5166804bd19203356da136c985678cd4d27a1b8c6329604903262"))
+                    .with_field(features()),
+            ),
+            (
+                "lnbcrt9678785340p1pvjluezpp5qqqsyqcyq5rqwzqfqqqsyqcyq5rqwzqfqqqsyqcyq5rqwzqfqypqsp5zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zygsdq8w3jhxaqcqzysnp4q0n326hr8v9zprg8gsvezcch06gfaqqhde2aj730yg0durunfhv66mq8q8a04uq9qrsgqcp7k3zc75d0nlac3cusu55vpsxrhkejmw3kzywj7xve0kyc469r5zvdruhpxmxcv2sglqg7lgt0ntu7n677kcty43r53px8ew3hekqsph8apc5",
+                UnsignedInvoice::new(Currency::Regtest, TIMESTAMP)
+                    .with_amount(MilliSatoshi::from(967878534))
+                    .with_field(payment_hash())
+                    .with_field(payment_secret())
+                    .with_field(TaggedField::Description("test".to_owned()))
+                    .with_field(TaggedField::MinFinalCltvExpiry(144))
+                    .with_field(TaggedField::PayeeNodeId(payee()))
+                    .with_field(TaggedField::Metadata(vec![0x01, 0xfa, 0xfa, 0xf0]))
+                    .with_field(features()),
+            ),
+        ]
+    }
+
+    #[test]
+    fn decode() {
+        for (encoded, unsigned) in vectors() {
+            let invoice = encoded.parse::<Invoice>().unwrap();
+            assert_eq!(invoice.unsigned, unsigned);
+            assert_eq!(invoice.payee(), &payee());
+            assert_eq!(invoice.to_string(), encoded);
+            assert!(invoice.payment_secret().is_some());
+        }
+    }
+
+    #[test]
+    fn encode() {
+        for (encoded, unsigned) in vectors() {
+            let invoice = unsigned.sign(&secret_key()).unwrap();
+            assert_eq!(invoice.to_string(), encoded);
+        }
+    }
+
+    #[test]
+    fn accessors() {
+        let vectors = vectors();
+
+        let invoice = vectors[1].0.parse::<Invoice>().unwrap();
+        assert_eq!(invoice.currency(), Currency::Bitcoin);
+        assert_eq!(invoice.amount(), Some(MilliSatoshi::from(250_000_000)));
+        assert_eq!(invoice.timestamp(), TIMESTAMP);
+        assert_eq!(invoice.description(), Some("1 cup coffee"));
+        assert_eq!(invoice.expiry(), 60);
+        assert_eq!(invoice.min_final_cltv_expiry(), 18);
+
+        let invoice = vectors[0].0.parse::<Invoice>().unwrap();
+        assert_eq!(invoice.amount(), None);
+        assert_eq!(invoice.expiry(), 3600);
+
+        let invoice = vectors[5].0.parse::<Invoice>().unwrap();
+        assert_eq!(invoice.route_hints().len(), 1);
+        assert_eq!(invoice.route_hints()[0].len(), 2);
+        assert_eq!(invoice.fallbacks().len(), 1);
+        assert!(invoice.description().is_none());
+        assert!(invoice.description_hash().is_some());
+
+        let invoice = vectors[9].0.parse::<Invoice>().unwrap();
+        assert_eq!(invoice.currency(), Currency::Regtest);
+        assert_eq!(invoice.amount(), Some(MilliSatoshi::from(967878534)));
+        assert_eq!(invoice.min_final_cltv_expiry(), 144);
+    }
+
+    #[test]
+    fn amount() {
+        let amount = |hrp: &str| parse_hrp(hrp).map(|(_, amount)| amount.map(u64::from));
+
+        assert_eq!(amount("lnbc").unwrap(), None);
+        assert_eq!(amount("lnbc1").unwrap(), Some(100_000_000_000));
+        assert_eq!(amount("lnbc10p").unwrap(), Some(1));
+        assert_eq!(amount("lnbcrt10n").unwrap(), Some(1_000));
+        assert_eq!(amount("lntbs25m").unwrap(), Some(2_500_000_000));
+        assert!(amount("lnbc1p").is_err());
+        assert!(amount("lnbc01m").is_err());
+        assert!(amount("lnbc1x").is_err());
+        assert!(amount("lnbcm").is_err());
+        assert!(amount("lnbc99999999999999999").is_err());
+        assert!(amount("lnxy1m").is_err());
+        assert!(amount("bc1m").is_err());
+
+        let hrp = |amount: u64| UnsignedInvoice::new(Currency::Bitcoin, 0).with_amount(MilliSatoshi::from(amount)).hrp();
+        assert_eq!(hrp(100_000_000_000), "lnbc1");
+        assert_eq!(hrp(2_000_000_000), "lnbc20m");
+        assert_eq!(hrp(1_000), "lnbc10n");
+        assert_eq!(hrp(1), "lnbc10p");
+    }
+
+    #[test]
+    fn invalid() {
+        let encoded = vectors()[0].0;
+        let (hrp, words) = bech32::decode(encoded).unwrap();
+
+        // bad checksum
+        let mut tampered = encoded.to_owned();
+        tampered.pop();
+        tampered.push('p');
+        match tampered.parse::<Invoice>() {
+            Err(InvoiceError::Bech32(Bech32Error::InvalidChecksum)) => (),
+            r => panic!("unexpected: {:?}", r),
+        }
+
+        // unknown currency
+        match bech32::encode("lnxy", words.as_slice()).parse::<Invoice>() {
+            Err(InvoiceError::UnknownCurrency(_)) => (),
+            r => panic!("unexpected: {:?}", r),
+        }
+
+        // the field length overflows the data
+        let mut truncated = words[..(TIMESTAMP_WORDS + 2)].to_vec();
+        truncated.extend_from_slice(&words[(words.len() - SIGNATURE_WORDS)..]);
+        match bech32::encode(hrp.as_str(), truncated.as_slice()).parse::<Invoice>() {
+            Err(InvoiceError::MalformedField(_)) => (),
+            r => panic!("unexpected: {:?}", r),
+        }
+
+        // the signature of another key does not match the payee node id
+        let other = Secp256k1SecretKey::from_slice(&Secp256k1::new(), &[1; 32]).unwrap().into();
+        let unsigned = vectors()[9].1.clone();
+        match unsigned.sign(&other) {
+            Err(InvoiceError::InvalidSignature) => (),
+            r => panic!("unexpected: {:?}", r),
+        }
+
+        // the payment hash is mandatory
+        let unsigned = UnsignedInvoice::new(Currency::Bitcoin, TIMESTAMP)
+            .with_field(TaggedField::Description("no hash".to_owned()));
+        match unsigned.sign(&secret_key()) {
+            Err(InvoiceError::MissingPaymentHash) => (),
+            r => panic!("unexpected: {:?}", r),
+        }
+
+        // either the description or its hash, not both
+        let unsigned = UnsignedInvoice::new(Currency::Bitcoin, TIMESTAMP)
+            .with_field(payment_hash())
+            .with_field(TaggedField::Description("both".to_owned()))
+            .with_field(description_hash());
+        match unsigned.sign(&secret_key()) {
+            Err(InvoiceError::DescriptionAndHash) => (),
+            r => panic!("unexpected: {:?}", r),
+        }
+
+        // the repeated payment hash
+        let unsigned = vectors()[0].1.clone().with_field(payment_hash());
+        match unsigned.sign(&secret_key()) {
+            Err(InvoiceError::DuplicateField(TaggedField::PAYMENT_HASH)) => (),
+            r => panic!("unexpected: {:?}", r),
+        }
+
+        // the repeated description hash
+        let unsigned = vectors()[3].1.clone().with_field(description_hash());
+        match unsigned.sign(&secret_key()) {
+            Err(InvoiceError::DuplicateField(TaggedField::DESCRIPTION_HASH)) => (),
+            r => panic!("unexpected: {:?}", r),
+        }
+    }
+}
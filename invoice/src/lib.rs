@@ -0,0 +1,30 @@
+#![forbid(unsafe_code)]
+#![allow(non_shorthand_field_patterns)]
+
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+extern crate secp256k1;
+extern crate rand;
+extern crate sha2;
+extern crate digest;
+extern crate wire;
+
+#[cfg(test)]
+extern crate hex;
+
+mod bech32;
+mod field;
+mod invoice;
+mod schnorr;
+mod bolt12;
+
+pub use self::bech32::Bech32Error;
+pub use self::field::TaggedField;
+pub use self::field::Fallback;
+pub use self::field::RouteHop;
+pub use self::invoice::Currency;
+pub use self::invoice::UnsignedInvoice;
+pub use self::invoice::Invoice;
+pub use self::invoice::InvoiceError;
+pub use self::bolt12::*;
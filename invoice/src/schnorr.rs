@@ -0,0 +1,346 @@
+//! BIP 340 signatures on top of the tweak operations of `secp256k1`,
+//! the public key is x-only, the point with even `y` is implied.
+//!
+//! The `secp256k1` version in use has no schnorr module, so the scheme is built here
+//! and checked against the whole BIP 340 vector file. The arithmetic is not constant time,
+//! the signing must not be used with the long-term keys, such as the node key,
+//! only with the keys made for a single offer or request. The verification is safe to use.
+
+use secp256k1::Secp256k1;
+use secp256k1::PublicKey;
+use secp256k1::SecretKey;
+use secp256k1::Error;
+
+// ORDER is the order of the curve, the scalar should be less
+const ORDER: [u8; 32] = [
+    0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xfe,
+    0xba, 0xae, 0xdc, 0xe6, 0xaf, 0x48, 0xa0, 0x3b, 0xbf, 0xd2, 0x5e, 0x8c, 0xd0, 0x36, 0x41, 0x41,
+];
+
+/// The tagged hash `sha256(sha256(tag) || sha256(tag) || data)`.
+pub fn tagged_hash(tag: &[u8], data: &[&[u8]]) -> [u8; 32] {
+    use sha2::Sha256;
+    use digest::FixedOutput;
+    use digest::Input;
+
+    let mut tag_hasher = Sha256::default();
+    tag_hasher.process(tag);
+    let tag_hash = tag_hasher.fixed_result();
+
+    let mut hasher = Sha256::default();
+    hasher.process(tag_hash.as_slice());
+    hasher.process(tag_hash.as_slice());
+    for part in data {
+        hasher.process(part);
+    }
+    let mut hash = [0; 32];
+    hash.copy_from_slice(hasher.fixed_result().as_slice());
+    hash
+}
+
+// the hash interpreted as a scalar modulo the order, it is less than twice the order
+fn reduce(hash: [u8; 32]) -> [u8; 32] {
+    if hash < ORDER {
+        return hash;
+    }
+
+    let mut result = [0; 32];
+    let mut borrow = 0i16;
+    for i in (0..32).rev() {
+        let difference = (hash[i] as i16) - (ORDER[i] as i16) - borrow;
+        borrow = if difference < 0 { 1 } else { 0 };
+        result[i] = (difference + (borrow << 8)) as u8;
+    }
+    result
+}
+
+fn minus_one(context: &Secp256k1) -> SecretKey {
+    let mut data = ORDER;
+    data[31] -= 1;
+    SecretKey::from_slice(context, &data[..]).unwrap()
+}
+
+// the secret key and the x-only public key of the point with even `y`
+fn even(context: &Secp256k1, secret_key: &SecretKey) -> Result<(SecretKey, [u8; 32]), Error> {
+    let public_key = PublicKey::from_secret_key(context, secret_key)?.serialize();
+    let mut secret_key = secret_key.clone();
+    if public_key[0] == 0x03 {
+        secret_key.mul_assign(context, &minus_one(context))?;
+    }
+    let mut x = [0; 32];
+    x.copy_from_slice(&public_key[1..]);
+    Ok((secret_key, x))
+}
+
+/// Signs the 32 bytes message, `aux` is the auxiliary random data.
+pub fn sign(secret_key: &SecretKey, message: &[u8; 32], aux: &[u8; 32]) -> Result<[u8; 64], Error> {
+    let context = Secp256k1::new();
+    let (secret_key, public_x) = even(&context, secret_key)?;
+
+    let aux_hash = tagged_hash(b"BIP0340/aux", &[&aux[..]]);
+    let mut masked = [0; 32];
+    for i in 0..32 {
+        masked[i] = secret_key[i] ^ aux_hash[i];
+    }
+    let nonce = reduce(tagged_hash(b"BIP0340/nonce", &[&masked[..], &public_x[..], &message[..]]));
+    let nonce = SecretKey::from_slice(&context, &nonce[..])?;
+    let (nonce, nonce_x) = even(&context, &nonce)?;
+
+    let challenge = reduce(tagged_hash(b"BIP0340/challenge", &[&nonce_x[..], &public_x[..], &message[..]]));
+    let mut s = SecretKey::from_slice(&context, &challenge[..])?;
+    s.mul_assign(&context, &secret_key)?;
+    s.add_assign(&context, &nonce)?;
+
+    let mut signature = [0; 64];
+    signature[..32].copy_from_slice(&nonce_x[..]);
+    signature[32..].copy_from_slice(&s[..]);
+    Ok(signature)
+}
+
+/// Verifies the signature against the x-only key of the `public_key`.
+pub fn verify(public_key: &PublicKey, message: &[u8; 32], signature: &[u8; 64]) -> bool {
+    let context = Secp256k1::new();
+
+    let mut even_key = public_key.serialize();
+    even_key[0] = 0x02;
+    let public_key = match PublicKey::from_slice(&context, &even_key[..]) {
+        Ok(public_key) => public_key,
+        Err(_) => return false,
+    };
+    let s = match SecretKey::from_slice(&context, &signature[32..]) {
+        Ok(s) => s,
+        Err(_) => return false,
+    };
+
+    // R = s * G - e * P
+    let challenge = reduce(tagged_hash(b"BIP0340/challenge", &[&signature[..32], &even_key[1..], &message[..]]));
+    let r = SecretKey::from_slice(&context, &challenge[..])
+        .and_then(|mut e| e.mul_assign(&context, &minus_one(&context)).map(|()| e))
+        .and_then(|minus_e| {
+            let mut public_key = public_key;
+            public_key.mul_assign(&context, &minus_e)?;
+            PublicKey::from_secret_key(&context, &s)?.combine(&context, &public_key)
+        });
+
+    match r {
+        Ok(r) => {
+            let r = r.serialize();
+            r[0] == 0x02 && &r[1..] == &signature[..32]
+        },
+        Err(_) => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hex;
+
+    fn array<T>(s: &str) -> T where T: Default + AsMut<[u8]> {
+        let mut array = T::default();
+        array.as_mut().copy_from_slice(hex::decode(s).unwrap().as_slice());
+        array
+    }
+
+    // the BIP 340 `test-vectors.csv`: secret key, public key, aux, message, signature, verification result;
+    // the secret key and the aux are empty for the vectors which are only verified
+    const VECTORS: [(&str, &str, &str, &str, &str, bool); 15] = [
+        (
+            "0000000000000000000000000000000000000000000000000000000000000003",
+            "f9308a019258c31049344f85f89d5229b531c845836f99b08601f113bce036f9",
+            "0000000000000000000000000000000000000000000000000000000000000000",
+            "0000000000000000000000000000000000000000000000000000000000000000",
+            "e907831f80848d1069a5371b402410364bdf1c5f8307b0084c55f1ce2dca8215\
+             25f66a4a85ea8b71e482a74f382d2ce5ebeee8fdb2172f477df4900d310536c0",
+            true,
+        ),
+        (
+            "b7e151628aed2a6abf7158809cf4f3c762e7160f38b4da56a784d9045190cfef",
+            "dff1d77f2a671c5f36183726db2341be58feae1da2deced843240f7b502ba659",
+            "0000000000000000000000000000000000000000000000000000000000000001",
+            "243f6a8885a308d313198a2e03707344a4093822299f31d0082efa98ec4e6c89",
+            "6896bd60eeae296db48a229ff71dfe071bde413e6d43f917dc8dcf8c78de3341\
+             8906d11ac976abccb20b091292bff4ea897efcb639ea871cfa95f6de339e4b0a",
+            true,
+        ),
+        (
+            "c90fdaa22168c234c4c6628b80dc1cd129024e088a67cc74020bbea63b14e5c9",
+            "dd308afec5777e13121fa72b9cc1b7cc0139715309b086c960e18fd969774eb8",
+            "c87aa53824b4d7ae2eb035a2b5bbbccc080e76cdc6d1692c4b0b62d798e6d906",
+            "7e2d58d8b3bcdf1abadec7829054f90dda9805aab56c77333024b9d0a508b75c",
+            "5831aaeed7b44bb74e5eab94ba9d4294c49bcf2a60728d8b4c200f50dd313c1b\
+             ab745879a5ad954a72c45a91c3a51d3c7adea98d82f8481e0e1e03674a6f3fb7",
+            true,
+        ),
+        // the message is not reduced modulo p or n
+        (
+            "0b432b2677937381aef05bb02a66ecd012773062cf3fa2549e44f58ed2401710",
+            "25d1dff95105f5253c4022f628a996ad3a0d95fbf21d468a1b33f8c160d8f517",
+            "ffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff",
+            "ffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff",
+            "7eb0509757e246f19449885651611cb965ecc1a187dd51b64fda1edc9637d5ec\
+             97582b9cb13db3933705b32ba982af5af25fd78881ebb32771fc5922efc66ea3",
+            true,
+        ),
+        (
+            "",
+            "d69c3509bb99e412e68b0fe8544e72837dfa30746d8be2aa65975f29d22dc7b9",
+            "",
+            "4df3c3f68fcc83b27e9d42c90431a72499f17875c81a599b566c9889b9696703",
+            "00000000000000000000003b78ce563f89a0ed9414f5aa28ad0d96d6795f9c63\
+             76afb1548af603b3eb45c9f8207dee1060cb71c04e80f593060b07d28308d7f4",
+            true,
+        ),
+        // the public key is not on the curve
+        (
+            "",
+            "eefdea4cdb677750a420fee807eacf21eb9898ae79b9768766e4faa04a2d4a34",
+            "",
+            "243f6a8885a308d313198a2e03707344a4093822299f31d0082efa98ec4e6c89",
+            "6cff5c3ba86c69ea4b7376f31a9bcb4f74c1976089b2d9963da2e5543e177769\
+             69e89b4c5564d00349106b8497785dd7d1d713a8ae82b32fa79d5f7fc407d39b",
+            false,
+        ),
+        // has_even_y(R) is false
+        (
+            "",
+            "dff1d77f2a671c5f36183726db2341be58feae1da2deced843240f7b502ba659",
+            "",
+            "243f6a8885a308d313198a2e03707344a4093822299f31d0082efa98ec4e6c89",
+            "fff97bd5755eeea420453a14355235d382f6472f8568a18b2f057a1460297556\
+             3cc27944640ac607cd107ae10923d9ef7a73c643e166be5ebeafa34b1ac553e2",
+            false,
+        ),
+        // the message is negated
+        (
+            "",
+            "dff1d77f2a671c5f36183726db2341be58feae1da2deced843240f7b502ba659",
+            "",
+            "243f6a8885a308d313198a2e03707344a4093822299f31d0082efa98ec4e6c89",
+            "1fa62e331edbc21c394792d2ab1100a7b432b013df3f6ff4f99fcb33e0e1515f\
+             28890b3edb6e7189b630448b515ce4f8622a954cfe545735aaea5134fccdb2bd",
+            false,
+        ),
+        // s is negated
+        (
+            "",
+            "dff1d77f2a671c5f36183726db2341be58feae1da2deced843240f7b502ba659",
+            "",
+            "243f6a8885a308d313198a2e03707344a4093822299f31d0082efa98ec4e6c89",
+            "6cff5c3ba86c69ea4b7376f31a9bcb4f74c1976089b2d9963da2e5543e177769\
+             961764b3aa9b2ffcb6ef947b6887a226e8d7c93e00c5ed0c1834ff0d0c2e6da6",
+            false,
+        ),
+        // sG - eP is infinite, x(inf) must not be taken as 0
+        (
+            "",
+            "dff1d77f2a671c5f36183726db2341be58feae1da2deced843240f7b502ba659",
+            "",
+            "243f6a8885a308d313198a2e03707344a4093822299f31d0082efa98ec4e6c89",
+            "0000000000000000000000000000000000000000000000000000000000000000\
+             123dda8328af9c23a94c1feecfd123ba4fb73476f0d594dcb65c6425bd186051",
+            false,
+        ),
+        // sG - eP is infinite, x(inf) must not be taken as 1
+        (
+            "",
+            "dff1d77f2a671c5f36183726db2341be58feae1da2deced843240f7b502ba659",
+            "",
+            "243f6a8885a308d313198a2e03707344a4093822299f31d0082efa98ec4e6c89",
+            "0000000000000000000000000000000000000000000000000000000000000001\
+             7615fbaf5ae28864013c099742deadb4dba87f11ac6754f93780d5a1837cf197",
+            false,
+        ),
+        // sig[0:32] is not an x coordinate on the curve
+        (
+            "",
+            "dff1d77f2a671c5f36183726db2341be58feae1da2deced843240f7b502ba659",
+            "",
+            "243f6a8885a308d313198a2e03707344a4093822299f31d0082efa98ec4e6c89",
+            "4a298dacae57395a15d0795ddbfd1dcb564da82b0f269bc70a74f8220429ba1d\
+             69e89b4c5564d00349106b8497785dd7d1d713a8ae82b32fa79d5f7fc407d39b",
+            false,
+        ),
+        // sig[0:32] is equal to the field size
+        (
+            "",
+            "dff1d77f2a671c5f36183726db2341be58feae1da2deced843240f7b502ba659",
+            "",
+            "243f6a8885a308d313198a2e03707344a4093822299f31d0082efa98ec4e6c89",
+            "fffffffffffffffffffffffffffffffffffffffffffffffffffffffefffffc2f\
+             69e89b4c5564d00349106b8497785dd7d1d713a8ae82b32fa79d5f7fc407d39b",
+            false,
+        ),
+        // sig[32:64] is equal to the curve order
+        (
+            "",
+            "dff1d77f2a671c5f36183726db2341be58feae1da2deced843240f7b502ba659",
+            "",
+            "243f6a8885a308d313198a2e03707344a4093822299f31d0082efa98ec4e6c89",
+            "6cff5c3ba86c69ea4b7376f31a9bcb4f74c1976089b2d9963da2e5543e177769\
+             fffffffffffffffffffffffffffffffebaaedce6af48a03bbfd25e8cd0364141",
+            false,
+        ),
+        // the public key exceeds the field size
+        (
+            "",
+            "fffffffffffffffffffffffffffffffffffffffffffffffffffffffefffffc30",
+            "",
+            "243f6a8885a308d313198a2e03707344a4093822299f31d0082efa98ec4e6c89",
+            "6cff5c3ba86c69ea4b7376f31a9bcb4f74c1976089b2d9963da2e5543e177769\
+             69e89b4c5564d00349106b8497785dd7d1d713a8ae82b32fa79d5f7fc407d39b",
+            false,
+        ),
+    ];
+
+    #[test]
+    fn bip340() {
+        let context = Secp256k1::new();
+
+        for &(secret_key, public_x, aux, message, signature, valid) in VECTORS.iter() {
+            let message: [u8; 32] = array(message);
+            let signature: [u8; 64] = {
+                let mut array = [0; 64];
+                array.copy_from_slice(hex::decode(signature).unwrap().as_slice());
+                array
+            };
+
+            if !secret_key.is_empty() {
+                let secret_key = SecretKey::from_slice(&context, hex::decode(secret_key).unwrap().as_slice()).unwrap();
+                let aux: [u8; 32] = array(aux);
+                assert_eq!(&sign(&secret_key, &message, &aux).unwrap()[..], &signature[..]);
+
+                let public_key = PublicKey::from_secret_key(&context, &secret_key).unwrap();
+                assert_eq!(hex::encode(&public_key.serialize()[1..]), public_x);
+            }
+
+            // the x coordinate which does not lift to a point fails the verification
+            let mut even_key = vec![0x02];
+            even_key.extend_from_slice(hex::decode(public_x).unwrap().as_slice());
+            let public_key = match PublicKey::from_slice(&context, even_key.as_slice()) {
+                Ok(public_key) => public_key,
+                Err(_) => {
+                    assert!(!valid, "the key of the valid vector {} does not lift", public_x);
+                    continue;
+                },
+            };
+            assert_eq!(verify(&public_key, &message, &signature), valid);
+
+            if valid {
+                let mut tampered = signature;
+                tampered[63] ^= 1;
+                assert!(!verify(&public_key, &message, &tampered));
+            }
+        }
+    }
+
+    #[test]
+    fn reduce_order() {
+        let mut above = ORDER;
+        above[31] += 5;
+        let mut expected = [0; 32];
+        expected[31] = 5;
+        assert_eq!(reduce(above), expected);
+        assert_eq!(reduce(expected), expected);
+    }
+}
@@ -14,10 +14,17 @@ use specs::NullStorage;
 use specs::System;
 use specs::Entities;
 use specs::Read;
+use specs::Write;
 use specs::ReadStorage;
 use specs::WriteStorage;
+use specs::ReadExpect;
 use specs::LazyUpdate;
 
+use std::sync::Arc;
+
+use super::tools::Misbehavior;
+use super::index::GossipIndex;
+
 #[derive(Component, Eq, PartialEq)]
 pub struct Peer {
     id: PublicKey,
@@ -91,6 +98,8 @@ impl<'a> System<'a> for AnnouncementChannelSystem {
         WriteStorage<'a, Blacklisted>,
         WriteStorage<'a, ChannelId>,
         WriteStorage<'a, ChannelParties>,
+        Write<'a, Misbehavior>,
+        ReadExpect<'a, Arc<GossipIndex>>,
     );
 
     fn run(&mut self, mut data: Self::SystemData) {
@@ -105,7 +114,9 @@ impl<'a> System<'a> for AnnouncementChannelSystem {
                 blacklist_mark,
                 channel_id,
                 mut channel_parties,
-            ) = (&*data.0, &*data.1, &*data.2, data.3, &mut data.4, &mut data.5, data.6, );
+                mut misbehavior,
+                index,
+            ) = (&*data.0, &*data.1, &*data.2, data.3, &mut data.4, &mut data.5, data.6, data.7, &*data.8, );
 
             if let Err(()) = announcement_channel.check_features(features) {
                 return;
@@ -113,7 +124,7 @@ impl<'a> System<'a> for AnnouncementChannelSystem {
 
             let announcement_channel = match announcement_channel.check_signatures() {
                 Err(()) => {
-                    // TODO: fail the connection
+                    misbehavior.report("channel announcement has incorrect signatures");
                     return;
                 },
                 Ok(s) => s,
@@ -142,7 +153,7 @@ impl<'a> System<'a> for AnnouncementChannelSystem {
 
             // check if nodes should be blacklisted
             let mut shell_blacklist_channel_parties = None;
-            for (channel_parties, channel_id) in (&mut channel_parties, channel_id).join() {
+            for (channel_parties, channel_id) in (&mut channel_parties, &*channel_id).join() {
                 if (&*channel_id).eq(&id) && !channel_parties.lightning.eq(&this_parties.lightning) {
                     shell_blacklist_channel_parties = Some(channel_parties.clone());
                     break;
@@ -170,8 +181,9 @@ impl<'a> System<'a> for AnnouncementChannelSystem {
                     }
                 }
 
-                for (entity, channel_parties) in (entities, &mut channel_parties).join() {
+                for (entity, channel_parties, channel_id) in (entities, &mut channel_parties, &*channel_id).join() {
                     if shell_blacklist_channel(channel_parties) {
+                        index.remove_channel(channel_id.hash, channel_id.short_channel_id.clone());
                         update.remove::<ChannelParties>(entity);
                         update.remove::<ChannelId>(entity);
                         update.remove::<ChannelHistory>(entity);
@@ -182,6 +194,7 @@ impl<'a> System<'a> for AnnouncementChannelSystem {
                 return;
             }
 
+            index.add_channel(id.hash, id.short_channel_id.clone());
             let channel_ref = entities.create();
             update.insert(channel_ref, id);
             update.insert(channel_ref, this_parties);
@@ -221,6 +234,7 @@ impl<'a> System<'a> for UpdateChannelSystem {
         ReadStorage<'a, ChannelId>,
         ReadStorage<'a, ChannelParties>,
         WriteStorage<'a, ChannelHistory>,
+        ReadExpect<'a, Arc<GossipIndex>>,
     );
 
     fn run(&mut self, data: Self::SystemData) {
@@ -231,6 +245,7 @@ impl<'a> System<'a> for UpdateChannelSystem {
                 channel_id,
                 channel_parties,
                 mut channel_history,
+                index,
             ) = data;
 
 
@@ -250,6 +265,7 @@ impl<'a> System<'a> for UpdateChannelSystem {
                         base_fee: update_channel.base_fee,
                         fee_rate: update_channel.fee_rate,
                     });
+                    index.update_channel(id.hash, id.short_channel_id.clone(), update_channel.flags, update_channel.timestamp);
 
                     break;
                 }
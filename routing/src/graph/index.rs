@@ -0,0 +1,127 @@
+use wire::Hash256;
+use wire::ShortChannelId;
+use wire::ChannelUpdateFlags;
+use wire::GossipView;
+use wire::GossipFilter;
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+#[derive(Default)]
+struct Known {
+    // the timestamps of the latest updates of both directions of the channel
+    channels: HashMap<(Hash256, ShortChannelId), [Option<u32>; 2]>,
+    // the timestamp of the latest announcement, the keys are the serialized node ids
+    nodes: HashMap<Vec<u8>, u32>,
+}
+
+/// What the graph has accepted, the systems record it, so the decoder
+/// can tell if the gossip message is new without looking into the world.
+#[derive(Default)]
+pub struct GossipIndex {
+    known: Mutex<Known>,
+}
+
+fn direction(flags: ChannelUpdateFlags) -> usize {
+    if flags.contains(ChannelUpdateFlags::DIRECTION) { 1 } else { 0 }
+}
+
+impl GossipIndex {
+    pub fn add_channel(&self, hash: Hash256, short_channel_id: ShortChannelId) {
+        self.known.lock().unwrap().channels.entry((hash, short_channel_id)).or_insert([None; 2]);
+    }
+
+    pub fn remove_channel(&self, hash: Hash256, short_channel_id: ShortChannelId) {
+        self.known.lock().unwrap().channels.remove(&(hash, short_channel_id));
+    }
+
+    pub fn update_channel(&self, hash: Hash256, short_channel_id: ShortChannelId, flags: ChannelUpdateFlags, timestamp: u32) {
+        let mut known = self.known.lock().unwrap();
+        if let Some(timestamps) = known.channels.get_mut(&(hash, short_channel_id)) {
+            let latest = &mut timestamps[direction(flags)];
+            *latest = Some(latest.map_or(timestamp, |t| t.max(timestamp)));
+        }
+    }
+
+    pub fn add_node(&self, node_id: Vec<u8>, timestamp: u32) {
+        let mut known = self.known.lock().unwrap();
+        let latest = known.nodes.entry(node_id).or_insert(timestamp);
+        *latest = (*latest).max(timestamp);
+    }
+
+    /// The channel is new if it is not in the graph yet.
+    pub fn is_new_announcement_channel(&self, hash: Hash256, short_channel_id: ShortChannelId) -> bool {
+        !self.known.lock().unwrap().channels.contains_key(&(hash, short_channel_id))
+    }
+
+    /// The update is new if the channel is known and the update is more recent
+    /// than any other update of the same direction.
+    pub fn is_new_update_channel(&self, hash: Hash256, short_channel_id: ShortChannelId, flags: ChannelUpdateFlags, timestamp: u32) -> bool {
+        match self.known.lock().unwrap().channels.get(&(hash, short_channel_id)) {
+            Some(timestamps) => timestamps[direction(flags)].map_or(true, |t| t < timestamp),
+            None => false,
+        }
+    }
+
+    /// The announcement is new if the node is unknown or the announcement is more recent.
+    pub fn is_new_announcement_node(&self, node_id: &[u8], timestamp: u32) -> bool {
+        match self.known.lock().unwrap().nodes.get(node_id) {
+            Some(&latest) => latest < timestamp,
+            None => true,
+        }
+    }
+}
+
+impl GossipFilter for GossipIndex {
+    fn is_new(&self, view: &GossipView) -> bool {
+        match view {
+            &GossipView::AnnouncementChannel(ref v) => {
+                self.is_new_announcement_channel(v.chain_hash(), v.short_channel_id())
+            },
+            &GossipView::UpdateChannel(ref v) => {
+                self.is_new_update_channel(v.chain_hash(), v.short_channel_id(), v.flags(), v.timestamp())
+            },
+            &GossipView::AnnouncementNode(ref v) => {
+                self.is_new_announcement_node(v.node_id(), v.timestamp())
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn known_gossip() {
+        let index = GossipIndex::default();
+        let (hash, id) = (Hash256::BITCOIN_CHAIN_HASH, ShortChannelId::from(0x083a8400034d0001));
+        let forward = ChannelUpdateFlags::empty();
+        let backward = ChannelUpdateFlags::DIRECTION;
+
+        // the update of the unknown channel is useless
+        assert!(index.is_new_announcement_channel(hash, id.clone()));
+        assert!(!index.is_new_update_channel(hash, id.clone(), forward, 100));
+
+        index.add_channel(hash, id.clone());
+        assert!(!index.is_new_announcement_channel(hash, id.clone()));
+        assert!(!index.is_new_announcement_channel(Hash256::TEST_HASH, id.clone()));
+        assert!(index.is_new_update_channel(hash, id.clone(), forward, 100));
+
+        // the directions are independent
+        index.update_channel(hash, id.clone(), forward, 100);
+        assert!(!index.is_new_update_channel(hash, id.clone(), forward, 100));
+        assert!(index.is_new_update_channel(hash, id.clone(), forward, 101));
+        assert!(index.is_new_update_channel(hash, id.clone(), backward, 50));
+
+        index.remove_channel(hash, id.clone());
+        assert!(index.is_new_announcement_channel(hash, id.clone()));
+
+        // the older announcement of the node does not replace the newer one
+        index.add_node(vec![2; 33], 200);
+        index.add_node(vec![2; 33], 150);
+        assert!(!index.is_new_announcement_node(&[2; 33], 200));
+        assert!(index.is_new_announcement_node(&[2; 33], 201));
+        assert!(index.is_new_announcement_node(&[3; 33], 1));
+    }
+}
@@ -2,13 +2,22 @@ use specs::prelude::*;
 
 use super::TopologyMessage;
 use wire::MessageFiltered;
+use wire::Warning;
+use wire::GossipView;
+use wire::GossipFilter;
+
+use std::sync::Arc;
 
 mod channel;
 mod node;
 mod tools;
+mod index;
+
+use self::index::GossipIndex;
 
 pub struct Graph {
     world: World,
+    index: Arc<GossipIndex>,
 }
 
 impl Graph {
@@ -18,7 +27,9 @@ impl Graph {
         use self::tools::UseOnce;
         use wire::AnnouncementNode;
 
+        let index = Arc::new(GossipIndex::default());
         let mut world = World::new();
+        world.add_resource(index.clone());
         world.setup::<<AnnouncementChannelSystem as System>::SystemData>();
         world.setup::<<UpdateChannelSystem as System>::SystemData>();
         world.setup::<<UseOnce<AnnouncementNode> as System>::SystemData>();
@@ -26,13 +37,15 @@ impl Graph {
 
         Graph {
             world: world,
+            index: index,
         }
     }
 
-    pub fn message(&mut self, message: TopologyMessage) {
+    /// Returns the warning if the peer has misbehaved, the connection should be failed.
+    pub fn message(&mut self, message: TopologyMessage) -> Result<(), Warning> {
         use self::TopologyMessage::*;
         use self::channel::{AnnouncementChannelSystem, UpdateChannelSystem};
-        use self::tools::UseOnce;
+        use self::tools::{UseOnce, Misbehavior};
 
         match message {
             Init(v) => (),  // TODO:
@@ -41,6 +54,22 @@ impl Graph {
             AnnouncementNode(v) => UseOnce::from(v).run_now(&mut self.world.res),
         }
         self.world.maintain();
+
+        match self.world.write_resource::<Misbehavior>().take() {
+            Some(reason) => Err(Warning::all_channels_text(reason.as_str())),
+            None => Ok(()),
+        }
+    }
+
+    /// Tells if the gossip message carries something new for the graph, the message
+    /// that is not new should be dropped without parsing and checking the signatures.
+    pub fn is_new(&self, view: &GossipView) -> bool {
+        self.index.is_new(view)
+    }
+
+    /// The filter to skip the known gossip while decoding, see `wire::GossipFilter`.
+    pub fn gossip_filter(&self) -> Arc<dyn GossipFilter> {
+        self.index.clone()
     }
 
     pub fn enumerate_nodes(&mut self) {
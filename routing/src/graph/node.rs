@@ -9,10 +9,16 @@ use specs::DenseVecStorage;
 use specs::System;
 use specs::Entities;
 use specs::Read;
+use specs::Write;
 use specs::ReadStorage;
+use specs::ReadExpect;
 use specs::LazyUpdate;
 
+use std::sync::Arc;
+
 use super::tools::UseOnce;
+use super::tools::Misbehavior;
+use super::index::GossipIndex;
 
 #[derive(Component, Eq, PartialEq, Debug)]
 pub struct Node {
@@ -29,6 +35,8 @@ impl<'a> System<'a> for UseOnce<AnnouncementNode> {
         Entities<'a>,
         Read<'a, LazyUpdate>,
         ReadStorage<'a, Node>,
+        Write<'a, Misbehavior>,
+        ReadExpect<'a, Arc<GossipIndex>>,
     );
 
     fn run(&mut self, data: Self::SystemData) {
@@ -36,15 +44,20 @@ impl<'a> System<'a> for UseOnce<AnnouncementNode> {
         use std::iter::Iterator;
 
         self.consume().map(|announcement_node| {
-            let (entities, update, node_storage) = (&*data.0, &*data.1, data.2);
+            let (entities, update, node_storage, mut misbehavior, index) = (&*data.0, &*data.1, data.2, data.3, &*data.4);
 
             // TODO: check features
 
             let announcement_node = match announcement_node.verify_owned(|s| &s.node_id) {
                 Ok(s) => s.0,
-                // TODO: fail the connection
-                Err(SignError::IncorrectSignature) => return,
-                Err(e) => panic!("error {:?}", e),
+                Err(SignError::IncorrectSignature) => {
+                    misbehavior.report("node announcement has incorrect signature");
+                    return;
+                },
+                Err(e) => {
+                    misbehavior.report(&format!("node announcement is malformed: {:?}", e));
+                    return;
+                },
             };
 
             let node = Node {
@@ -55,6 +68,7 @@ impl<'a> System<'a> for UseOnce<AnnouncementNode> {
                 address: announcement_node.address.0,
             };
 
+            index.add_node(node.node_id.as_ref().serialize().to_vec(), node.timestamp);
             if (&node_storage).join().find(|&n| n == &node).is_none() {
                 let node_ref = entities.create();
                 update.insert(node_ref, node);
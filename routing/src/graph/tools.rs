@@ -25,3 +25,22 @@ impl<T> UseOnce<T> where T: Debug, {
         temp
     }
 }
+
+/// The peer has sent the message that violates the protocol,
+/// the connection should be failed.
+#[derive(Default, Debug)]
+pub struct Misbehavior {
+    reason: Option<String>,
+}
+
+impl Misbehavior {
+    pub fn report(&mut self, reason: &str) {
+        if self.reason.is_none() {
+            self.reason = Some(reason.to_owned());
+        }
+    }
+
+    pub fn take(&mut self) -> Option<String> {
+        self.reason.take()
+    }
+}
@@ -0,0 +1,419 @@
+use tokio::prelude::{Stream, Sink, Future, Async, AsyncSink, Poll};
+use tokio::timer::Delay;
+
+use wire::{Message, MessageSize, Ping, Pong, WireError};
+
+use rand::{thread_rng, Rng};
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use std::{io, fmt, error};
+
+// PONG_LENGTH_LIMIT is the least `num_pong_bytes` of the ping
+// that must be ignored, the pong would not fit in the message.
+const PONG_LENGTH_LIMIT: MessageSize = 65532;
+
+// PING_WINDOW is the time in which the peer may send `max_pings` pings.
+const PING_WINDOW: Duration = Duration::from_secs(30);
+
+#[derive(Clone, Debug)]
+pub struct KeepaliveConfig {
+    // the time between the pong and the next ping
+    pub interval: Duration,
+    // the time to wait for the pong
+    pub timeout: Duration,
+    // the lengths of the ping padding and the requested pong are random up to it
+    pub max_padding: MessageSize,
+    // the peer sending more pings during 30 seconds is disconnected
+    pub max_pings: u32,
+}
+
+impl Default for KeepaliveConfig {
+    fn default() -> Self {
+        KeepaliveConfig {
+            interval: Duration::new(60, 0),
+            timeout: Duration::new(30, 0),
+            max_padding: 256,
+            max_pings: 10,
+        }
+    }
+}
+
+/// The round trip time of the pings, the quality metric of the peer.
+#[derive(Clone, Default, Debug)]
+pub struct Liveness {
+    pub latest: Option<Duration>,
+    // the exponentially weighted moving average, as the smoothed rtt of tcp
+    pub average: Option<Duration>,
+    pub pongs: u64,
+}
+
+impl Liveness {
+    fn record(&mut self, latency: Duration) {
+        self.latest = Some(latency);
+        self.average = Some(match self.average {
+            Some(average) => (average * 7 + latency) / 8,
+            None => latency,
+        });
+        self.pongs += 1;
+    }
+}
+
+/// Wraps the framed connection, sends `Ping` on schedule and fails the read
+/// if `Pong` of the requested length does not arrive in time. Answers the pings
+/// of the peer, and ignores the ones requesting too long pong.
+/// The pings and pongs are not passed further.
+pub struct Keepalive<S> {
+    inner: S,
+    config: KeepaliveConfig,
+    // fires the next ping, or the timeout if the ping is outstanding
+    delay: Delay,
+    // the pong length requested by the outstanding ping, and when it was sent
+    outstanding: Option<(MessageSize, Instant)>,
+    // the pings and the pongs to send
+    queue: VecDeque<Message>,
+    // the start of the current window and the pings received during it
+    window: (Instant, u32),
+    liveness: Arc<Mutex<Liveness>>,
+}
+
+/// Why the keepalive has failed the connection, it is wrapped
+/// in the io error, see `keepalive_error`.
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum KeepaliveError {
+    // the pong has not arrived in time
+    Unresponsive,
+    // the peer has sent more than `max_pings` pings during 30 seconds
+    TooManyPings,
+    PongLength,
+    // `max_padding` does not fit in the ping
+    PaddingTooLong,
+}
+
+impl fmt::Display for KeepaliveError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::KeepaliveError::*;
+
+        match self {
+            &Unresponsive => write!(f, "the peer is unresponsive"),
+            &TooManyPings => write!(f, "too many pings"),
+            &PongLength => write!(f, "the pong length does not match the ping"),
+            &PaddingTooLong => write!(f, "the padding is too long"),
+        }
+    }
+}
+
+impl error::Error for KeepaliveError {
+}
+
+impl KeepaliveError {
+    fn kind(&self) -> io::ErrorKind {
+        use self::KeepaliveError::*;
+
+        match self {
+            &Unresponsive => io::ErrorKind::TimedOut,
+            &TooManyPings | &PongLength => io::ErrorKind::InvalidData,
+            &PaddingTooLong => io::ErrorKind::InvalidInput,
+        }
+    }
+}
+
+impl From<KeepaliveError> for WireError {
+    fn from(e: KeepaliveError) -> Self {
+        WireError::from(io::Error::new(e.kind(), e))
+    }
+}
+
+/// The reason if the connection is failed by the keepalive.
+pub fn keepalive_error(e: &WireError) -> Option<KeepaliveError> {
+    use bincode::ErrorKind;
+
+    match &**e {
+        &ErrorKind::Io(ref e) => e.get_ref().and_then(|e| e.downcast_ref::<KeepaliveError>()).cloned(),
+        _ => None,
+    }
+}
+
+impl<S> Keepalive<S>
+where
+    S: Stream<Item=Message, Error=WireError> + Sink<SinkItem=Message, SinkError=WireError>,
+{
+    pub fn new(inner: S, config: KeepaliveConfig) -> Self {
+        let now = Instant::now();
+        Keepalive {
+            inner: inner,
+            delay: Delay::new(now + config.interval),
+            config: config,
+            outstanding: None,
+            queue: VecDeque::new(),
+            window: (now, 0),
+            liveness: Arc::new(Mutex::new(Liveness::default())),
+        }
+    }
+
+    /// The handle to watch the latency of the peer.
+    pub fn liveness(&self) -> Arc<Mutex<Liveness>> {
+        self.liveness.clone()
+    }
+
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+
+    fn ping(&self) -> Result<Ping, WireError> {
+        let mut rng = thread_rng();
+        let limit = self.config.max_padding as u32 + 1;
+        let length = rng.gen_range(0, limit) as MessageSize;
+        let pong_length = rng.gen_range(0, limit) as MessageSize;
+        Ping::new(length, pong_length)
+            .map_err(|()| KeepaliveError::PaddingTooLong.into())
+    }
+
+    fn poll_delay(&mut self) -> Result<(), WireError> {
+        loop {
+            match self.delay.poll() {
+                Ok(Async::NotReady) => return Ok(()),
+                Ok(Async::Ready(())) => (),
+                Err(e) => return Err(WireError::from(io::Error::new(io::ErrorKind::Other, e))),
+            }
+
+            if self.outstanding.is_some() {
+                return Err(KeepaliveError::Unresponsive.into());
+            }
+            let ping = self.ping()?;
+            let now = Instant::now();
+            self.outstanding = Some((ping.pong_length(), now));
+            self.queue.push_back(Message::Ping(ping));
+            self.delay.reset(now + self.config.timeout);
+        }
+    }
+
+    fn received_ping(&mut self, ping: Ping) -> Result<(), WireError> {
+        let now = Instant::now();
+        if now.duration_since(self.window.0) >= PING_WINDOW {
+            self.window = (now, 0);
+        }
+        self.window.1 += 1;
+        if self.window.1 > self.config.max_pings {
+            return Err(KeepaliveError::TooManyPings.into());
+        }
+
+        if ping.pong_length() < PONG_LENGTH_LIMIT {
+            self.queue.push_back(Message::Pong(Pong::new(&ping)));
+        }
+        Ok(())
+    }
+
+    fn received_pong(&mut self, pong: Pong) -> Result<(), WireError> {
+        // the unsolicited pong is ignored
+        if let Some((length, sent)) = self.outstanding.take() {
+            if pong.length() != length {
+                return Err(KeepaliveError::PongLength.into());
+            }
+            let now = Instant::now();
+            self.liveness.lock().unwrap().record(now.duration_since(sent));
+            self.delay.reset(now + self.config.interval);
+        }
+        Ok(())
+    }
+
+    // the task is notified when the sink is ready for the rest
+    fn flush_queue(&mut self) -> Result<(), WireError> {
+        while let Some(message) = self.queue.pop_front() {
+            match self.inner.start_send(message)? {
+                AsyncSink::Ready => (),
+                AsyncSink::NotReady(message) => {
+                    self.queue.push_front(message);
+                    break;
+                },
+            }
+        }
+        self.inner.poll_complete().map(|_| ())
+    }
+}
+
+impl<S> Stream for Keepalive<S>
+where
+    S: Stream<Item=Message, Error=WireError> + Sink<SinkItem=Message, SinkError=WireError>,
+{
+    type Item = Message;
+    type Error = WireError;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        self.poll_delay()?;
+        loop {
+            self.flush_queue()?;
+            match self.inner.poll()? {
+                Async::NotReady => return Ok(Async::NotReady),
+                Async::Ready(Some(Message::Ping(ping))) => self.received_ping(ping)?,
+                Async::Ready(Some(Message::Pong(pong))) => {
+                    self.received_pong(pong)?;
+                    // the delay is reset, register the task
+                    self.poll_delay()?;
+                },
+                r @ _ => return Ok(r),
+            }
+        }
+    }
+}
+
+impl<S> Sink for Keepalive<S>
+where
+    S: Stream<Item=Message, Error=WireError> + Sink<SinkItem=Message, SinkError=WireError>,
+{
+    type SinkItem = Message;
+    type SinkError = WireError;
+
+    fn start_send(&mut self, item: Self::SinkItem) -> Result<AsyncSink<Self::SinkItem>, Self::SinkError> {
+        self.inner.start_send(item)
+    }
+
+    fn poll_complete(&mut self) -> Poll<(), Self::SinkError> {
+        self.flush_queue()?;
+        self.inner.poll_complete()
+    }
+
+    fn close(&mut self) -> Poll<(), Self::SinkError> {
+        self.inner.close()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use futures::sync::mpsc;
+    use tokio::runtime::current_thread;
+    use tokio::prelude::future;
+    use wire::{Warning, BinarySD};
+
+    // the connection to the stand-in peer
+    struct Pipe {
+        receiver: mpsc::UnboundedReceiver<Message>,
+        sender: mpsc::UnboundedSender<Message>,
+    }
+
+    fn pipe() -> (Pipe, mpsc::UnboundedSender<Message>, mpsc::UnboundedReceiver<Message>) {
+        let (to_local, from_peer) = mpsc::unbounded();
+        let (to_peer, from_local) = mpsc::unbounded();
+        let pipe = Pipe {
+            receiver: from_peer,
+            sender: to_peer,
+        };
+        (pipe, to_local, from_local)
+    }
+
+    impl Stream for Pipe {
+        type Item = Message;
+        type Error = WireError;
+
+        fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+            Ok(self.receiver.poll().unwrap())
+        }
+    }
+
+    impl Sink for Pipe {
+        type SinkItem = Message;
+        type SinkError = WireError;
+
+        fn start_send(&mut self, item: Self::SinkItem) -> Result<AsyncSink<Self::SinkItem>, Self::SinkError> {
+            self.sender.unbounded_send(item).unwrap();
+            Ok(AsyncSink::Ready)
+        }
+
+        fn poll_complete(&mut self) -> Poll<(), Self::SinkError> {
+            Ok(Async::Ready(()))
+        }
+    }
+
+    fn config(interval: u64, timeout: u64) -> KeepaliveConfig {
+        KeepaliveConfig {
+            interval: Duration::from_millis(interval),
+            timeout: Duration::from_millis(timeout),
+            max_padding: 64,
+            max_pings: 2,
+        }
+    }
+
+    fn warning() -> Message {
+        Message::Warning(Warning::all_channels_text("next"))
+    }
+
+    #[test]
+    fn answer_ping() {
+        let (pipe, to_local, from_local) = pipe();
+        let keepalive = Keepalive::new(pipe, config(10000, 10000));
+
+        to_local.unbounded_send(Message::Ping(Ping::new(0, 10).unwrap())).unwrap();
+        // too long pong requested, ignore it
+        let ping = BinarySD::deserialize(&[0xff, 0xfc, 0, 0][..]).unwrap();
+        to_local.unbounded_send(Message::Ping(ping)).unwrap();
+        to_local.unbounded_send(warning()).unwrap();
+        let (message, keepalive) = current_thread::block_on_all(keepalive.into_future()).ok().unwrap();
+        assert_eq!(message, Some(warning()));
+
+        // the third ping during the window
+        to_local.unbounded_send(Message::Ping(Ping::new(0, 10).unwrap())).unwrap();
+        assert!(current_thread::block_on_all(keepalive.into_future()).is_err());
+
+        let sent = current_thread::block_on_all(from_local.collect()).unwrap();
+        match sent.as_slice() {
+            &[Message::Pong(ref pong)] => assert_eq!(pong.length(), 10),
+            s => panic!("unexpected messages: {:?}", s),
+        }
+    }
+
+    #[test]
+    fn latency() {
+        let (pipe, to_local, from_local) = pipe();
+        let keepalive = Keepalive::new(pipe, config(20, 10000));
+        let liveness = keepalive.liveness();
+
+        // the peer answers the first ping, and sends the wrong pong for the second
+        let peer = from_local
+            .take(2)
+            .fold((to_local, 0), |(to_local, count), message| {
+                let pong = match message {
+                    Message::Ping(ref ping) if count == 0 => Pong::new(ping),
+                    Message::Ping(ref ping) => Pong::new(&Ping::new(0, ping.pong_length() + 1).unwrap()),
+                    m => panic!("unexpected message: {:?}", m),
+                };
+                to_local.unbounded_send(Message::Pong(pong)).unwrap();
+                Ok::<_, ()>((to_local, count + 1))
+            });
+
+        let task = future::lazy(move || {
+            current_thread::spawn(peer.map(|_| ()));
+            keepalive.into_future().map_err(|(e, _)| e)
+        });
+        match current_thread::block_on_all(task) {
+            Err(e) => assert_eq!(keepalive_error(&e), Some(KeepaliveError::PongLength)),
+            Ok(_) => panic!("the wrong pong is accepted"),
+        }
+
+        let liveness = liveness.lock().unwrap().clone();
+        assert_eq!(liveness.pongs, 1);
+        assert!(liveness.latest.is_some());
+        assert_eq!(liveness.latest, liveness.average);
+    }
+
+    #[test]
+    fn unresponsive() {
+        let (pipe, _to_local, from_local) = pipe();
+        let keepalive = Keepalive::new(pipe, config(10, 20));
+
+        let (e, _) = current_thread::block_on_all(keepalive.into_future()).err().unwrap();
+        assert_eq!(keepalive_error(&e), Some(KeepaliveError::Unresponsive));
+
+        let sent = current_thread::block_on_all(from_local.take(1).collect()).unwrap();
+        match &sent[0] {
+            &Message::Ping(ref ping) => assert!(ping.length() <= 64 && ping.pong_length() <= 64),
+            m => panic!("unexpected message: {:?}", m),
+        }
+    }
+}
@@ -8,6 +8,7 @@ extern crate secp256k1;
 extern crate chrono;
 #[macro_use]
 extern crate wire;
+extern crate bincode;
 extern crate brontide;
 extern crate bitcoin_types;
 extern crate common_types;
@@ -24,21 +25,25 @@ extern crate rayon;
 #[macro_use]
 extern crate hex_literal;
 
-#[cfg(test)]
 extern crate hex;
-
 extern crate tokio;
+extern crate futures;
+#[macro_use]
+extern crate log;
 
-pub mod tcp_connection;
+pub mod peer_manager;
+pub mod keepalive;
 mod graph;
 
 use wire::{
     Message, Init, AnnouncementNode, AnnouncementChannel, UpdateChannel,
-    MessageFiltered, MessageConsumer, WireError
+    MessageFiltered, MessageConsumer, WireError, GossipFilter
 };
 
 use tokio::prelude::{Future, Sink};
 
+use std::sync::Arc;
+
 pub use self::graph::Graph;
 
 pub enum TopologyMessage {
@@ -68,8 +73,22 @@ impl MessageConsumer for Graph {
         S: Sink<SinkItem=Message, SinkError=WireError> + Send + 'static,
     {
         use tokio::prelude::IntoFuture;
+        use std::io;
+
+        match self.message(message) {
+            Ok(()) => Box::new(Ok((self, sink)).into_future()),
+            Err(warning) => {
+                // tell the peer why, and then fail the connection
+                let error = io::Error::new(io::ErrorKind::ConnectionAborted, warning.to_string());
+                Box::new(
+                    sink.send(Message::Warning(warning))
+                        .and_then(move |_| Err::<(Self, S), _>(WireError::from(error)))
+                )
+            },
+        }
+    }
 
-        self.message(message);
-        Box::new(Ok((self, sink)).into_future())
+    fn gossip_filter(&self) -> Option<Arc<dyn GossipFilter>> {
+        Some(Graph::gossip_filter(self))
     }
 }
@@ -0,0 +1,792 @@
+use tokio::net::{TcpStream, TcpListener};
+use tokio::prelude::{Future, Stream, Sink, IntoFuture, FutureExt, stream};
+use tokio::prelude::future::Either;
+use tokio::runtime::current_thread;
+use tokio::timer::{self, Delay, timeout};
+use futures::sync::{mpsc, oneshot};
+
+use secp256k1::PublicKey;
+use brontide::{BrontideStream, HandshakeError, HandshakeTimeouts, SessionDirection};
+use wire::{Message, Custom, Init, RawFeatureVector, FeatureError, MessageConsumerChain, NodeSigner, WireError};
+use wire::Error as LpdError;
+use wire::Warning as LpdWarning;
+
+use super::keepalive::{Keepalive, KeepaliveConfig, Liveness};
+
+use std::rc::Rc;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use std::{io, fmt, error, cmp};
+
+/// The reason the connection is closed.
+#[derive(Debug)]
+pub enum PeerError {
+    Io(io::Error),
+    Handshake(HandshakeError),
+    Wire(WireError),
+    Feature(FeatureError),
+    // the first message of the peer is not `Init`
+    NoInit,
+    InitTimeout,
+    Timer(timer::Error),
+}
+
+impl PeerError {
+    fn from_timeout(e: timeout::Error<WireError>) -> Self {
+        if e.is_elapsed() {
+            PeerError::InitTimeout
+        } else if e.is_timer() {
+            PeerError::Timer(e.into_timer().unwrap())
+        } else {
+            PeerError::Wire(e.into_inner().unwrap())
+        }
+    }
+}
+
+impl error::Error for PeerError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        use self::PeerError::*;
+
+        match self {
+            &Io(ref e) => Some(e),
+            &Handshake(ref e) => Some(e),
+            &Wire(ref e) => Some(e),
+            &Feature(ref e) => Some(e),
+            &Timer(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for PeerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::PeerError::*;
+
+        match self {
+            &Io(ref e) => write!(f, "io error: {}", e),
+            &Handshake(ref e) => write!(f, "handshake error: {}", e),
+            &Wire(ref e) => write!(f, "wire error: {}", e),
+            &Feature(ref e) => write!(f, "feature negotiation failed: {}", e),
+            &NoInit => write!(f, "the first message is not init"),
+            &InitTimeout => write!(f, "init timeout"),
+            &Timer(ref e) => write!(f, "timer error: {}", e),
+        }
+    }
+}
+
+/// What the other subsystems learn about the peers, see `PeerManager::subscribe`.
+#[derive(Clone, Debug)]
+pub enum PeerEvent {
+    Connected {
+        node_id: PublicKey,
+        direction: SessionDirection,
+        // the features negotiated by `Init`
+        features: RawFeatureVector,
+    },
+    Disconnected {
+        node_id: PublicKey,
+    },
+}
+
+#[derive(Clone, Debug)]
+pub struct PeerManagerConfig {
+    pub global_features: RawFeatureVector,
+    pub local_features: RawFeatureVector,
+    pub timeouts: HandshakeTimeouts,
+    pub keepalive: KeepaliveConfig,
+    // the delay before the first reconnect, it is doubled after each failure
+    pub min_backoff: Duration,
+    pub max_backoff: Duration,
+    // the directory to write the captures of the sessions, see `brontide::Recorder`,
+    // the captures contain the secrets of the channels, the files are readable by the owner only
+    pub capture: Option<PathBuf>,
+}
+
+impl Default for PeerManagerConfig {
+    fn default() -> Self {
+        PeerManagerConfig {
+            global_features: RawFeatureVector::new(),
+            local_features: RawFeatureVector::new(),
+            timeouts: HandshakeTimeouts::default(),
+            keepalive: KeepaliveConfig::default(),
+            min_backoff: Duration::new(1, 0),
+            max_backoff: Duration::new(3600, 0),
+            capture: None,
+        }
+    }
+}
+
+impl PeerManagerConfig {
+    fn init(&self) -> Init {
+        Init::new(self.global_features.clone(), self.local_features.clone())
+    }
+}
+
+// the next delay before reconnect
+fn next_backoff(current: Duration, min: Duration, max: Duration) -> Duration {
+    if current < min {
+        min
+    } else {
+        cmp::min(current * 2, max)
+    }
+}
+
+// when both nodes connect to each other at the same time, the connection
+// initiated by the node with the lesser key survives, so both keep the same one
+fn preferred_direction(local: &PublicKey, remote: &PublicKey) -> SessionDirection {
+    if local.serialize()[..] < remote.serialize()[..] {
+        SessionDirection::Outgoing
+    } else {
+        SessionDirection::Incoming
+    }
+}
+
+struct Peer {
+    id: u64,
+    direction: SessionDirection,
+    liveness: Arc<Mutex<Liveness>>,
+    // the custom messages to send, see `PeerManager::send_custom`
+    outbox: mpsc::UnboundedSender<Custom>,
+    // dropping it closes the connection
+    _close: oneshot::Sender<()>,
+}
+
+struct PersistentPeer {
+    address: SocketAddr,
+    // the delay before the next attempt
+    backoff: Duration,
+    // the attempt is scheduled
+    pending: bool,
+}
+
+#[derive(Default)]
+struct State {
+    next_id: u64,
+    // the keys are the serialized node ids
+    peers: HashMap<Vec<u8>, Peer>,
+    persistent: HashMap<Vec<u8>, PersistentPeer>,
+    subscribers: Vec<mpsc::UnboundedSender<PeerEvent>>,
+}
+
+impl State {
+    fn notify(&mut self, event: PeerEvent) {
+        self.subscribers.retain(|s| s.unbounded_send(event.clone()).is_ok());
+    }
+}
+
+struct Inner<F> {
+    signer: Arc<dyn NodeSigner>,
+    config: PeerManagerConfig,
+    chain: F,
+    state: RefCell<State>,
+}
+
+/// Keeps the connections to the peers, at most one connection per peer.
+/// Each connection runs the `MessageConsumerChain` created by `chain`
+/// in its own task. The persistent peers are reconnected with exponential backoff.
+/// The tasks are spawned on the current thread executor, the manager
+/// should be used inside of it.
+pub struct PeerManager<F> {
+    inner: Rc<Inner<F>>,
+}
+
+impl<F> Clone for PeerManager<F> {
+    fn clone(&self) -> Self {
+        PeerManager {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<F, C> PeerManager<F>
+where
+    F: Fn(&PublicKey) -> C + 'static,
+    C: MessageConsumerChain + 'static,
+{
+    pub fn new(signer: Arc<dyn NodeSigner>, config: PeerManagerConfig, chain: F) -> Self {
+        PeerManager {
+            inner: Rc::new(Inner {
+                signer: signer,
+                config: config,
+                chain: chain,
+                state: RefCell::new(State::default()),
+            }),
+        }
+    }
+
+    /// The stream of the connect and disconnect events.
+    pub fn subscribe(&self) -> mpsc::UnboundedReceiver<PeerEvent> {
+        let (sender, receiver) = mpsc::unbounded();
+        self.inner.state.borrow_mut().subscribers.push(sender);
+        receiver
+    }
+
+    /// The peers that have completed `Init`.
+    pub fn connected(&self) -> Vec<PublicKey> {
+        use secp256k1::Secp256k1;
+
+        let context = Secp256k1::new();
+        self.inner.state.borrow().peers.keys()
+            .filter_map(|key| PublicKey::from_slice(&context, key.as_slice()).ok())
+            .collect()
+    }
+
+    /// The round trip time of the pings, `None` if the peer is not connected.
+    pub fn liveness(&self, node_id: &PublicKey) -> Option<Liveness> {
+        self.inner.state.borrow().peers.get(&node_id.serialize().to_vec())
+            .map(|peer| peer.liveness.lock().unwrap().clone())
+    }
+
+    /// Sends the custom message of the application protocol to the peer,
+    /// see `wire::CustomMessageHandler`. Returns the message back
+    /// if the peer is not connected.
+    pub fn send_custom(&self, node_id: &PublicKey, message: Custom) -> Result<(), Custom> {
+        match self.inner.state.borrow().peers.get(&node_id.serialize().to_vec()) {
+            Some(peer) => peer.outbox.unbounded_send(message).map_err(|e| e.into_inner()),
+            None => Err(message),
+        }
+    }
+
+    /// Keeps the connection to the peer, reconnects it after any failure.
+    pub fn add_persistent(&self, node_id: PublicKey, address: SocketAddr) {
+        {
+            let mut state = self.inner.state.borrow_mut();
+            let persistent = state.persistent.entry(node_id.serialize().to_vec())
+                .or_insert(PersistentPeer {
+                    address: address,
+                    backoff: Duration::new(0, 0),
+                    pending: false,
+                });
+            persistent.address = address;
+        }
+        self.reconnect(&node_id);
+    }
+
+    /// Closes the connection to the peer, it is not persistent anymore.
+    pub fn disconnect(&self, node_id: &PublicKey) {
+        let mut state = self.inner.state.borrow_mut();
+        let key = node_id.serialize().to_vec();
+        state.persistent.remove(&key);
+        if state.peers.remove(&key).is_some() {
+            state.notify(PeerEvent::Disconnected { node_id: node_id.clone() });
+        }
+    }
+
+    /// Connects to the peer once.
+    pub fn connect(&self, node_id: PublicKey, address: SocketAddr) {
+        current_thread::spawn(self.dial(node_id, address));
+    }
+
+    /// Runs the connection initiated by the peer.
+    pub fn accept(&self, stream: TcpStream) {
+        let manager = self.clone();
+        let task = BrontideStream::incoming_with_timeouts(
+            stream,
+            self.inner.signer.clone(),
+            self.inner.config.timeouts.clone(),
+        )
+            .map_err(|e| warn!("incoming handshake failed: {}", e))
+            .and_then(move |stream| {
+                let node_id = stream.remote_key().clone();
+                manager.run(stream, SessionDirection::Incoming)
+                    .then(move |r| {
+                        if let Err(e) = r {
+                            warn!("peer {:?} failed: {}", node_id, e);
+                        }
+                        manager.reconnect(&node_id);
+                        Ok(())
+                    })
+            });
+        current_thread::spawn(task);
+    }
+
+    /// Accepts the incoming connections.
+    pub fn listen(&self, listener: TcpListener) -> impl Future<Item = (), Error = io::Error> {
+        let manager = self.clone();
+        listener.incoming()
+            .for_each(move |stream| {
+                manager.accept(stream);
+                Ok(())
+            })
+    }
+
+    fn dial(&self, node_id: PublicKey, address: SocketAddr) -> impl Future<Item = (), Error = ()> {
+        let manager = self.clone();
+        let signer = self.inner.signer.clone();
+        let timeouts = self.inner.config.timeouts.clone();
+        let remote = node_id.clone();
+        TcpStream::connect(&address)
+            .map_err(PeerError::Io)
+            .and_then(move |stream| {
+                BrontideStream::outgoing_with_timeouts(stream, signer, remote, timeouts)
+                    .map_err(PeerError::Handshake)
+            })
+            .and_then({
+                let manager = manager.clone();
+                move |stream| manager.run(stream, SessionDirection::Outgoing)
+            })
+            .then(move |r| {
+                if let Err(e) = r {
+                    warn!("peer {:?} at {} failed: {}", node_id, address, e);
+                }
+                manager.reconnect(&node_id);
+                Ok(())
+            })
+    }
+
+    // schedules the next attempt if the peer is persistent and not connected
+    fn reconnect(&self, node_id: &PublicKey) {
+        let key = node_id.serialize().to_vec();
+        let delay = {
+            let state = &mut *self.inner.state.borrow_mut();
+            if state.peers.contains_key(&key) {
+                return;
+            }
+            match state.persistent.get_mut(&key) {
+                Some(ref mut persistent) if !persistent.pending => {
+                    let config = &self.inner.config;
+                    let delay = persistent.backoff;
+                    persistent.pending = true;
+                    persistent.backoff = next_backoff(delay, config.min_backoff, config.max_backoff);
+                    delay
+                },
+                _ => return,
+            }
+        };
+
+        let manager = self.clone();
+        let node_id = node_id.clone();
+        let task = Delay::new(Instant::now() + delay)
+            .then(move |_| {
+                let address = {
+                    let state = &mut *manager.inner.state.borrow_mut();
+                    let connected = state.peers.contains_key(&key);
+                    state.persistent.get_mut(&key)
+                        .and_then(|persistent| {
+                            persistent.pending = false;
+                            // the peer has connected to us meanwhile
+                            if connected { None } else { Some(persistent.address) }
+                        })
+                };
+                match address {
+                    Some(address) => Either::A(manager.dial(node_id, address)),
+                    None => Either::B(Ok::<(), ()>(()).into_future()),
+                }
+            });
+        current_thread::spawn(task);
+    }
+
+    // exchanges `Init`, and then runs the chain until the connection is closed
+    fn run(&self, stream: BrontideStream<TcpStream>, direction: SessionDirection) -> Box<dyn Future<Item = (), Error = PeerError>> {
+        let mut stream = stream;
+        let remote = stream.remote_key().clone();
+        if let Some(ref directory) = self.inner.config.capture {
+            if let Err(e) = capture(&mut stream, directory.as_path()) {
+                return Box::new(Err(PeerError::Wire(e)).into_future());
+            }
+        }
+
+        let manager = self.clone();
+        let task = exchange_init(stream.framed(), self.inner.config.init(), self.inner.config.timeouts.handshake)
+            .and_then(move |(framed, features)| {
+                let framed = Keepalive::new(framed, manager.inner.config.keepalive.clone());
+                let (outbox, outgoing) = mpsc::unbounded();
+                let close = match manager.register(&remote, direction, features, framed.liveness(), outbox) {
+                    Some(close) => close,
+                    // the other connection to the peer is kept
+                    None => return Either::A(Ok::<(), PeerError>(()).into_future()),
+                };
+                let (id, close) = close;
+                let chain = (manager.inner.chain)(&remote);
+                let mut framed = framed;
+                // the decoder skips the gossip the chain already knows
+                if let Some(filter) = chain.gossip_filter() {
+                    framed.get_mut().codec_mut().filter_gossip(filter);
+                }
+                let (sink, stream) = framed.split();
+                let task = process(chain, stream, sink, outgoing)
+                    .map_err(PeerError::Wire)
+                    .select2(close)
+                    .then(move |r| {
+                        manager.unregister(&remote, id);
+                        match r {
+                            Err(Either::A((e, _))) => Err(e),
+                            _ => Ok(()),
+                        }
+                    });
+                Either::B(task)
+            });
+        Box::new(task)
+    }
+
+    // the result is `None` if the connection loses to the existing one and should be closed,
+    // otherwise it is the id of the connection and the signal to close it
+    fn register(
+        &self,
+        remote: &PublicKey,
+        direction: SessionDirection,
+        features: RawFeatureVector,
+        liveness: Arc<Mutex<Liveness>>,
+        outbox: mpsc::UnboundedSender<Custom>,
+    ) -> Option<(u64, oneshot::Receiver<()>)> {
+        let local = self.inner.signer.node_id();
+        let key = remote.serialize().to_vec();
+        let state = &mut *self.inner.state.borrow_mut();
+
+        let replaced = match state.peers.get(&key) {
+            None => false,
+            Some(existing) => {
+                // the new connection in the same direction means the peer has reconnected,
+                // the old one is likely dead; if both have dialed at the same time,
+                // keep the connection in the direction both sides prefer
+                if existing.direction != direction && direction != preferred_direction(&local, remote) {
+                    return None;
+                }
+                true
+            },
+        };
+
+        let (close, closed) = oneshot::channel();
+        state.next_id += 1;
+        let id = state.next_id;
+        // the replaced connection is closed when its peer is dropped
+        state.peers.insert(key.clone(), Peer {
+            id: id,
+            direction: direction,
+            liveness: liveness,
+            outbox: outbox,
+            _close: close,
+        });
+        if let Some(persistent) = state.persistent.get_mut(&key) {
+            persistent.backoff = self.inner.config.min_backoff;
+        }
+        if replaced {
+            state.notify(PeerEvent::Disconnected { node_id: remote.clone() });
+        }
+        state.notify(PeerEvent::Connected {
+            node_id: remote.clone(),
+            direction: direction,
+            features: features,
+        });
+        Some((id, closed))
+    }
+
+    fn unregister(&self, remote: &PublicKey, id: u64) {
+        let key = remote.serialize().to_vec();
+        let state = &mut *self.inner.state.borrow_mut();
+        let current = state.peers.get(&key).map(|peer| peer.id == id).unwrap_or(false);
+        if current {
+            state.peers.remove(&key);
+            state.notify(PeerEvent::Disconnected { node_id: remote.clone() });
+        }
+    }
+}
+
+// writes the capture of the session into the `directory`,
+// replay it by the `replay-capture` example
+fn capture(stream: &mut BrontideStream<TcpStream>, directory: &Path) -> Result<(), WireError> {
+    use std::fs;
+    use std::time::{SystemTime, UNIX_EPOCH};
+    use hex;
+
+    let started = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    let name = format!("{}-{}.capture", hex::encode(&stream.remote_key().serialize()[..]), started);
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let file = options.open(directory.join(name))?;
+    stream.record(file)
+}
+
+// sends the local `Init`, the first message of the peer should be its `Init`,
+// the result is the negotiated features
+fn exchange_init<T>(framed: T, local: Init, time: Duration) -> impl Future<Item = (T, RawFeatureVector), Error = PeerError>
+where
+    T: Stream<Item = Message, Error = WireError> + Sink<SinkItem = Message, SinkError = WireError>,
+{
+    let local_features = local.features();
+    framed.send(Message::Init(local))
+        .and_then(|framed| framed.into_future().map_err(|(e, _)| e))
+        .timeout(time)
+        .map_err(PeerError::from_timeout)
+        .and_then(move |(message, framed)| {
+            let remote = match message {
+                Some(Message::Init(remote)) => remote,
+                _ => return Either::A(Err(PeerError::NoInit).into_future()),
+            };
+            match local_features.negotiate(&remote.features()) {
+                Ok(features) => Either::A(Ok((framed, features)).into_future()),
+                Err(e) => {
+                    // tell the peer why
+                    let error = LpdError::all_channels_text(&e.to_string());
+                    Either::B(framed.send(Message::Error(error)).then(move |_| Err(PeerError::Feature(e))))
+                },
+            }
+        })
+}
+
+// what the connection task handles next
+enum Input {
+    Received(Result<Message, WireError>),
+    Send(Custom),
+}
+
+// feeds the messages of the peer into the chain, and sends the custom messages of the outbox
+fn process<C, I, O>(chain: C, stream: I, sink: O, outbox: mpsc::UnboundedReceiver<Custom>) -> impl Future<Item = (), Error = WireError>
+where
+    C: MessageConsumerChain + 'static,
+    I: Stream<Item = Message, Error = WireError>,
+    O: Sink<SinkItem = Message, SinkError = WireError> + Send + 'static,
+{
+    let received = stream
+        // the malformed message should not terminate the stream before the peer is told
+        .then(|message| Ok::<_, WireError>(Some(Input::Received(message))))
+        .chain(stream::once(Ok(None)));
+    let sent = outbox
+        .map(|message| Some(Input::Send(message)))
+        .map_err(|()| WireError::from(io::Error::new(io::ErrorKind::Other, "the outbox is broken")));
+
+    received.select(sent)
+        // the outbox is open while the peer is registered, stop when the connection is over
+        .take_while(|input| Ok::<_, WireError>(input.is_some()))
+        .filter_map(|input| input)
+        .fold((chain, sink), |(chain, sink), input| -> Box<dyn Future<Item = _, Error = WireError>> {
+            let message = match input {
+                Input::Received(Ok(message)) => message,
+                Input::Send(message) => {
+                    return Box::new(
+                        sink.send(Message::Custom(message))
+                            .map(move |sink| (chain, sink))
+                    );
+                },
+                Input::Received(Err(e)) => {
+                    let notice = if Message::unknown_even_type(&e).is_some() {
+                        // the peer requires something we do not understand,
+                        // no need to fail the channels, just close the connection
+                        Message::Warning(LpdWarning::all_channels_text("unknown even message type"))
+                    } else {
+                        Message::Error(LpdError::all_channels_text("cannot decode the message"))
+                    };
+                    return Box::new(
+                        sink.send(notice)
+                            .and_then(move |_| Err(e))
+                    );
+                },
+            };
+
+            match chain.process(sink, message) {
+                Ok(f) => f,
+                // if any previous MessageConsumer did not consumed the message
+                Err((chain, sink, Message::Custom(custom))) => {
+                    if custom.is_odd() {
+                        debug!("ignoring custom message {:?}", custom);
+                        Box::new(Ok((chain, sink)).into_future())
+                    } else {
+                        // nobody claims the even type, cannot ignore it
+                        let warning = LpdWarning::all_channels_text("unknown even message type");
+                        let error = io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!("unclaimed custom message type: {}", custom.message_type()),
+                        );
+                        let error = WireError::from(error);
+                        Box::new(
+                            sink.send(Message::Warning(warning))
+                                .and_then(move |_| Err(error))
+                        )
+                    }
+                },
+                Err((chain, sink, Message::Unknown(unknown))) => {
+                    debug!("ignoring unknown message {:?}", unknown);
+                    Box::new(Ok((chain, sink)).into_future())
+                },
+                Err((chain, sink, message)) => {
+                    warn!("skipped message {:?}", message);
+                    Box::new(Ok((chain, sink)).into_future())
+                },
+            }
+        })
+        .map(|(_, _)| ())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use secp256k1::{Secp256k1, SecretKey};
+    use wire::{InMemorySigner, CustomMessageHandler, CustomMessageConsumer};
+    use wire;
+
+    fn signer(byte: u8) -> Arc<dyn NodeSigner> {
+        let secret = SecretKey::from_slice(&Secp256k1::new(), &[byte; 32]).unwrap();
+        Arc::new(InMemorySigner::new(secret).unwrap())
+    }
+
+    fn config() -> PeerManagerConfig {
+        let mut config = PeerManagerConfig::default();
+        config.min_backoff = Duration::from_millis(20);
+        config.max_backoff = Duration::from_millis(100);
+        config.keepalive.interval = Duration::from_millis(20);
+        config
+    }
+
+    fn listen<F, C>(manager: &PeerManager<F>) -> SocketAddr
+    where
+        F: Fn(&PublicKey) -> C + 'static,
+        C: MessageConsumerChain + 'static,
+    {
+        let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap()).unwrap();
+        let address = listener.local_addr().unwrap();
+        current_thread::spawn(manager.listen(listener).map_err(|e| panic!("listener failed: {}", e)));
+        address
+    }
+
+    fn manager(byte: u8) -> (PeerManager<impl Fn(&PublicKey) -> ()>, SocketAddr) {
+        let manager = PeerManager::new(signer(byte), config(), |_: &PublicKey| ());
+        let address = listen(&manager);
+        (manager, address)
+    }
+
+    #[test]
+    fn backoff() {
+        let (min, max) = (Duration::from_millis(100), Duration::from_millis(350));
+        let mut backoff = Duration::new(0, 0);
+        let sequence = (0..5)
+            .map(|_| {
+                backoff = next_backoff(backoff, min, max);
+                backoff.subsec_millis()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(sequence, vec![100, 200, 350, 350, 350]);
+    }
+
+    #[test]
+    fn preferred() {
+        let (a, b) = (signer(0x11).node_id(), signer(0x22).node_id());
+        assert_ne!(preferred_direction(&a, &b), preferred_direction(&b, &a));
+    }
+
+    #[test]
+    fn reconnect() {
+        let mut runtime = current_thread::Runtime::new().unwrap();
+        let events = runtime.block_on(::tokio::prelude::future::lazy(|| {
+            let (a, _) = manager(0x11);
+            let (b, b_address) = manager(0x22);
+            let (a_id, b_id) = (a.inner.signer.node_id(), b.inner.signer.node_id());
+
+            let events = a.subscribe();
+            a.add_persistent(b_id.clone(), b_address);
+            events.into_future()
+                .map_err(|_| ())
+                .and_then(move |(first, events)| {
+                    // b closes the connection, a connects again
+                    b.disconnect(&a_id);
+                    events.take(2).collect().map(move |rest| (first, rest, a))
+                })
+                .timeout(Duration::new(5, 0))
+                .map_err(|e| -> () { panic!("no events: {:?}", e) })
+        })).unwrap();
+
+        let (first, rest, a) = events;
+        match first {
+            Some(PeerEvent::Connected { direction: SessionDirection::Outgoing, .. }) => (),
+            e => panic!("unexpected event: {:?}", e),
+        }
+        match (&rest[0], &rest[1]) {
+            (&PeerEvent::Disconnected { .. }, &PeerEvent::Connected { .. }) => (),
+            e => panic!("unexpected events: {:?}", e),
+        }
+        assert_eq!(a.connected().len(), 1);
+    }
+
+    #[test]
+    fn deduplicate() {
+        let (local, remote) = (signer(0x11).node_id(), signer(0x22).node_id());
+        let manager = PeerManager::new(signer(0x11), config(), |_: &PublicKey| ());
+        let events = manager.subscribe();
+        let register = |direction| {
+            let (outbox, _) = mpsc::unbounded();
+            let liveness = Arc::new(Mutex::new(Liveness::default()));
+            manager.register(&remote, direction, RawFeatureVector::new(), liveness, outbox)
+                .map(|(id, _)| id)
+        };
+        let preferred = preferred_direction(&local, &remote);
+        let other = match preferred {
+            SessionDirection::Outgoing => SessionDirection::Incoming,
+            SessionDirection::Incoming => SessionDirection::Outgoing,
+        };
+
+        // the peer has reconnected, the new connection replaces the old one
+        let first = register(other).unwrap();
+        let second = register(other).unwrap();
+        assert_ne!(first, second);
+
+        // both have dialed at the same time, the preferred direction wins
+        let third = register(preferred).unwrap();
+        assert!(register(other).is_none());
+        assert_eq!(manager.inner.state.borrow().peers[&remote.serialize().to_vec()].id, third);
+        assert_eq!(manager.connected(), vec![remote.clone()]);
+
+        let events = events.take(5).collect().wait().unwrap();
+        let directions = events.iter()
+            .map(|event| match event {
+                &PeerEvent::Connected { direction, .. } => Some(direction),
+                &PeerEvent::Disconnected { .. } => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(directions, vec![Some(other), None, Some(other), None, Some(preferred)]);
+    }
+
+    // passes the received custom messages to the test
+    struct Forward(mpsc::UnboundedSender<Custom>);
+
+    impl CustomMessageHandler for Forward {
+        const FIRST_TYPE: u16 = 40000;
+        const LAST_TYPE: u16 = 40009;
+
+        fn handle(&mut self, peer: &wire::PublicKey, message: Custom) -> Result<Vec<Custom>, WireError> {
+            let _ = peer;
+            self.0.unbounded_send(message).unwrap();
+            Ok(vec![])
+        }
+    }
+
+    #[test]
+    fn send_custom() {
+        let mut runtime = current_thread::Runtime::new().unwrap();
+        let received = runtime.block_on(::tokio::prelude::future::lazy(|| {
+            let (forward, received) = mpsc::unbounded();
+            let (a, _) = manager(0x11);
+            let b = PeerManager::new(signer(0x22), config(), move |node_id: &PublicKey| {
+                let node_id = wire::PublicKey::from(node_id.clone());
+                (CustomMessageConsumer::new(node_id, Forward(forward.clone())).unwrap(), ())
+            });
+            let b_address = listen(&b);
+            let b_id = b.inner.signer.node_id();
+
+            let message = Custom::new(40001, vec![1, 2, 3]).unwrap();
+            assert_eq!(a.send_custom(&b_id, message.clone()), Err(message.clone()));
+
+            let events = a.subscribe();
+            a.connect(b_id.clone(), b_address);
+            events.into_future()
+                .map_err(|_| ())
+                .and_then(move |_| {
+                    a.send_custom(&b_id, message).unwrap();
+                    received.into_future().map_err(|_| ())
+                })
+                .map(|(received, _)| received)
+                .timeout(Duration::new(5, 0))
+                .map_err(|e| -> () { panic!("nothing received: {:?}", e) })
+        })).unwrap();
+
+        assert_eq!(received, Custom::new(40001, vec![1, 2, 3]));
+    }
+}
@@ -5,4 +5,6 @@ extern crate hex;
 pub mod producer_tree;
 pub mod store_tree;
 mod util;
-mod error;
\ No newline at end of file
+mod error;
+
+pub use util::{Sha256Hash, LeafIndex};
\ No newline at end of file
@@ -0,0 +1,313 @@
+use secp256k1::{Secp256k1, PublicKey, SecretKey};
+
+use wire::{TlvStream, TlvKnownTypes, BinarySD, BlindedPath, BlindedHop};
+use wire::PublicKey as WirePublicKey;
+
+use super::crypto_tools::{SharedSecret, KeyType};
+use super::crypto_tools::{generate_key, ecdh, blinding_factor, blind};
+use super::packet::OnionError;
+
+// TAG_SIZE is the size of the poly1305 tag appended to the encrypted data
+const TAG_SIZE: usize = 16;
+
+/// The types of the records of the data encrypted for the hop of the blinded path.
+#[derive(Default, Clone, Eq, PartialEq, Debug)]
+pub struct EncryptedDataTlv;
+
+impl EncryptedDataTlv {
+    pub const PADDING: u64 = 1;
+    pub const SHORT_CHANNEL_ID: u64 = 2;
+    pub const NEXT_NODE_ID: u64 = 4;
+    pub const PATH_ID: u64 = 6;
+    pub const NEXT_BLINDING_OVERRIDE: u64 = 8;
+    pub const PAYMENT_RELAY: u64 = 10;
+    pub const PAYMENT_CONSTRAINTS: u64 = 12;
+    pub const ALLOWED_FEATURES: u64 = 14;
+}
+
+impl TlvKnownTypes for EncryptedDataTlv {
+    const KNOWN: &'static [u64] = &[
+        Self::PADDING,
+        Self::SHORT_CHANNEL_ID,
+        Self::NEXT_NODE_ID,
+        Self::PATH_ID,
+        Self::NEXT_BLINDING_OVERRIDE,
+        Self::PAYMENT_RELAY,
+        Self::PAYMENT_CONSTRAINTS,
+        Self::ALLOWED_FEATURES,
+    ];
+}
+
+// chacha20-poly1305 keyed by `rho` of the shared secret, the nonce is zero,
+// the key is used only once
+fn encrypt(shared_secret: &SharedSecret, plain_text: &[u8]) -> Vec<u8> {
+    use chacha20_poly1305_aead::encrypt;
+
+    let rho_key = generate_key(KeyType::Rho, shared_secret);
+    let mut cipher_text = Vec::with_capacity(plain_text.len() + TAG_SIZE);
+    let tag = encrypt(&rho_key[..], &[0; 12], &[], plain_text, &mut cipher_text).unwrap();
+    cipher_text.extend_from_slice(&tag[..]);
+    cipher_text
+}
+
+fn decrypt(shared_secret: &SharedSecret, cipher_text: &[u8]) -> Option<Vec<u8>> {
+    use chacha20_poly1305_aead::decrypt;
+
+    if cipher_text.len() < TAG_SIZE {
+        return None;
+    }
+    let (cipher_text, tag) = cipher_text.split_at(cipher_text.len() - TAG_SIZE);
+    let rho_key = generate_key(KeyType::Rho, shared_secret);
+    let mut plain_text = Vec::with_capacity(cipher_text.len());
+    decrypt(&rho_key[..], &[0; 12], &[], cipher_text, tag, &mut plain_text).ok()?;
+    Some(plain_text)
+}
+
+fn blinded_node_factor(shared_secret: &SharedSecret) -> Result<SecretKey, OnionError> {
+    let factor = generate_key(KeyType::BlindedNodeId, shared_secret);
+    SecretKey::from_slice(&Secp256k1::new(), &factor[..]).map_err(OnionError::Crypto)
+}
+
+/// Creates the blinded path through the `route`, the last node is the recipient.
+/// The `encrypted_data` of each node is readable only by the node.
+/// The `session_key` should be random, its public key is the first blinding point.
+pub fn create_blinded_path(
+    route: &[PublicKey],
+    encrypted_data: &[TlvStream<EncryptedDataTlv>],
+    session_key: &SecretKey,
+) -> Result<BlindedPath, OnionError> {
+    if route.is_empty() {
+        return Err(OnionError::EmptyPath);
+    }
+    if route.len() != encrypted_data.len() {
+        return Err(OnionError::RouteLength(route.len()));
+    }
+
+    let context = Secp256k1::new();
+    let mut ephemeral_secret = session_key.clone();
+    let mut hops = Vec::with_capacity(route.len());
+    for (node_id, data) in route.iter().zip(encrypted_data.iter()) {
+        let ephemeral_key = PublicKey::from_secret_key(&context, &ephemeral_secret).map_err(OnionError::Crypto)?;
+        let shared_secret = ecdh(node_id, &ephemeral_secret).map_err(OnionError::Crypto)?;
+
+        let mut plain_text = Vec::new();
+        BinarySD::serialize(&mut plain_text, data).map_err(OnionError::Wire)?;
+        let blinded_node_id = blind(node_id, &blinded_node_factor(&shared_secret)?).map_err(OnionError::Crypto)?;
+        hops.push(BlindedHop {
+            blinded_node_id: blinded_node_id.into(),
+            encrypted_recipient_data: encrypt(&shared_secret, plain_text.as_slice()),
+        });
+
+        let factor = blinding_factor(&ephemeral_key, &shared_secret).map_err(OnionError::Crypto)?;
+        ephemeral_secret.mul_assign(&context, &factor).map_err(OnionError::Crypto)?;
+    }
+
+    Ok(BlindedPath {
+        introduction_node_id: route[0].clone().into(),
+        blinding_point: PublicKey::from_secret_key(&context, session_key).map_err(OnionError::Crypto)?.into(),
+        hops: hops,
+    })
+}
+
+/// The path of the onion messages to us, the `route` ends with our node id.
+/// Each node forwards the message to the next one, the `path_id`
+/// comes back to us, so we know the message came through the path.
+pub fn create_message_path(route: &[PublicKey], path_id: Vec<u8>, session_key: &SecretKey) -> Result<BlindedPath, OnionError> {
+    let encrypted_data = (0..route.len())
+        .map(|index| {
+            let mut stream = TlvStream::new();
+            match route.get(index + 1) {
+                Some(next_node_id) => stream.insert(EncryptedDataTlv::NEXT_NODE_ID, next_node_id.serialize().to_vec()),
+                None => stream.insert(EncryptedDataTlv::PATH_ID, path_id.clone()),
+            };
+            stream
+        })
+        .collect::<Vec<_>>();
+    create_blinded_path(route, encrypted_data.as_slice(), session_key)
+}
+
+/// The hop of the blinded path as the node sees it, all its keys
+/// are derived from the blinding point and the secret key of the node.
+pub struct BlindedHopKeys {
+    blinding_point: PublicKey,
+    shared_secret: SharedSecret,
+    node_secret: SecretKey,
+}
+
+impl BlindedHopKeys {
+    pub fn new(blinding_point: &PublicKey, node_secret: &SecretKey) -> Result<Self, OnionError> {
+        let shared_secret = ecdh(blinding_point, node_secret).map_err(OnionError::Crypto)?;
+        let mut blinded_secret = node_secret.clone();
+        blinded_secret.mul_assign(&Secp256k1::new(), &blinded_node_factor(&shared_secret)?)
+            .map_err(OnionError::Crypto)?;
+        Ok(BlindedHopKeys {
+            blinding_point: blinding_point.clone(),
+            shared_secret: shared_secret,
+            node_secret: blinded_secret,
+        })
+    }
+
+    /// The secret key of the blinded node id, the onion is peeled with it.
+    pub fn node_secret(&self) -> &SecretKey {
+        &self.node_secret
+    }
+
+    /// Decrypts and parses the data of the hop, the tag is checked.
+    pub fn decrypt(&self, encrypted_data: &[u8]) -> Result<TlvStream<EncryptedDataTlv>, OnionError> {
+        let plain_text = decrypt(&self.shared_secret, encrypted_data).ok_or(OnionError::InvalidEncryptedData)?;
+        BinarySD::deserialize(plain_text.as_slice()).map_err(|_| OnionError::InvalidEncryptedData)
+    }
+
+    /// The blinding point of the next hop, unless the `data` of the hop overrides it.
+    pub fn next_blinding_point(&self, data: &TlvStream<EncryptedDataTlv>) -> Result<PublicKey, OnionError> {
+        match data.get_value::<WirePublicKey>(EncryptedDataTlv::NEXT_BLINDING_OVERRIDE) {
+            Ok(Some(blinding_point)) => Ok(blinding_point.into()),
+            Ok(None) => {
+                let factor = blinding_factor(&self.blinding_point, &self.shared_secret).map_err(OnionError::Crypto)?;
+                blind(&self.blinding_point, &factor).map_err(OnionError::Crypto)
+            },
+            Err(_) => Err(OnionError::InvalidEncryptedData),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use hex;
+
+    fn node_secret(index: u8) -> SecretKey {
+        SecretKey::from_slice(&Secp256k1::new(), &[0x41 + index; 32]).unwrap()
+    }
+
+    fn node_id(index: u8) -> PublicKey {
+        PublicKey::from_secret_key(&Secp256k1::new(), &node_secret(index)).unwrap()
+    }
+
+    #[test]
+    fn message_path() {
+        let context = Secp256k1::new();
+        let route = (0..3).map(node_id).collect::<Vec<_>>();
+        let session_key = SecretKey::from_slice(&context, &[0x51; 32]).unwrap();
+        let path = create_message_path(&route, vec![1, 2, 3], &session_key).unwrap();
+        assert_eq!(PublicKey::from(path.introduction_node_id.clone()), route[0]);
+        assert_eq!(path.hops.len(), 3);
+
+        let mut blinding_point = PublicKey::from(path.blinding_point.clone());
+        for (index, hop) in path.hops.iter().enumerate() {
+            let keys = BlindedHopKeys::new(&blinding_point, &node_secret(index as u8)).unwrap();
+
+            // the blinded node id is the public key of the blinded secret
+            let blinded_node_id = PublicKey::from_secret_key(&context, keys.node_secret()).unwrap();
+            assert_eq!(blinded_node_id, PublicKey::from(hop.blinded_node_id.clone()));
+            assert_ne!(blinded_node_id, route[index]);
+
+            let data = keys.decrypt(hop.encrypted_recipient_data.as_slice()).unwrap();
+            if index < 2 {
+                let next_node_id: WirePublicKey = data.get_value(EncryptedDataTlv::NEXT_NODE_ID).unwrap().unwrap();
+                assert_eq!(PublicKey::from(next_node_id), route[index + 1]);
+                assert_eq!(data.get(EncryptedDataTlv::PATH_ID), None);
+            } else {
+                assert_eq!(data.get(EncryptedDataTlv::PATH_ID), Some(&[1, 2, 3][..]));
+            }
+
+            // other node cannot read the data
+            let other = BlindedHopKeys::new(&blinding_point, &node_secret(7)).unwrap();
+            match other.decrypt(hop.encrypted_recipient_data.as_slice()) {
+                Err(OnionError::InvalidEncryptedData) => (),
+                r => panic!("unexpected result: {:?}", r),
+            }
+
+            blinding_point = keys.next_blinding_point(&data).unwrap();
+        }
+    }
+
+    #[test]
+    fn next_blinding_override() {
+        let context = Secp256k1::new();
+        let session_key = SecretKey::from_slice(&context, &[0x51; 32]).unwrap();
+        let override_point = node_id(9);
+        let mut data = TlvStream::new();
+        data.insert(EncryptedDataTlv::NEXT_NODE_ID, node_id(1).serialize().to_vec());
+        data.insert(EncryptedDataTlv::NEXT_BLINDING_OVERRIDE, override_point.serialize().to_vec());
+        let path = create_blinded_path(&[node_id(0)], &[data], &session_key).unwrap();
+
+        let keys = BlindedHopKeys::new(&path.blinding_point.clone().into(), &node_secret(0)).unwrap();
+        let data = keys.decrypt(path.hops[0].encrypted_recipient_data.as_slice()).unwrap();
+        assert_eq!(keys.next_blinding_point(&data).unwrap(), override_point);
+    }}
+
+    // Bob's data of `route-blinding-test.json`, it keeps the unknown odd record
+    fn bob_data() -> TlvStream<EncryptedDataTlv> {
+        let mut data = TlvStream::new();
+        data.insert(EncryptedDataTlv::PADDING, vec![0; 26]);
+        data.insert(EncryptedDataTlv::SHORT_CHANNEL_ID, hex::decode("00000000000006c1").unwrap());
+        data.insert(EncryptedDataTlv::PAYMENT_RELAY, hex::decode("0024000000962710").unwrap());
+        data.insert(EncryptedDataTlv::PAYMENT_CONSTRAINTS, hex::decode("000b69e505dc").unwrap());
+        data.insert(EncryptedDataTlv::ALLOWED_FEATURES, vec![]);
+        data.insert(561, hex::decode("123456").unwrap());
+        data
+    }
+
+    // `route-blinding-test.json` of BOLT 4: the path Bob -> Carol made from the `0x02` bytes
+    // and the path Dave -> Eve made from the `0x01` bytes, the path keys and the blinded node ids
+    // do not depend on the data, so only Bob's data is reproduced
+    #[test]
+    fn bolt04_vectors() {
+        let context = Secp256k1::new();
+
+        let cases = vec![
+            (
+                [0x02; 32],
+                [1, 2],
+                vec![bob_data(), TlvStream::new()],
+                [
+                    "024d4b6cd1361032ca9bd2aeb9d900aa4d45d9ead80ac9423374c451a7254d0766",
+                    "034e09f450a80c3d252b258aba0a61215bf60dda3b0dc78ffb0736ea1259dfd8a0",
+                ],
+                [
+                    "03da173ad2aee2f701f17e59fbd16cb708906d69838a5f088e8123fb36e89a2c25",
+                    "02e466727716f044290abf91a14a6d90e87487da160c2a3cbd0d465d7a78eb83a7",
+                ],
+            ),
+            (
+                [0x01; 32],
+                [3, 4],
+                vec![TlvStream::new(), TlvStream::new()],
+                [
+                    "031b84c5567b126440995d3ed5aaba0565d71e1834604819ff9c17f5e9d5dd078f",
+                    "03e09038ee76e50f444b19abf0a555e8697e035f62937168b80adf0931b31ce52a",
+                ],
+                [
+                    "036861b366f284f0a11738ffbf7eda46241a8977592878fe3175ae1d1e4754eccf",
+                    "021982a48086cb8984427d3727fe35a03d396b234f0701f5249daa12e8105c8dae",
+                ],
+            ),
+        ];
+
+        for (session_key, nodes, encrypted_data, path_keys, blinded_node_ids) in cases {
+            let session_key = SecretKey::from_slice(&context, &session_key).unwrap();
+            let route = nodes.iter().cloned().map(node_id).collect::<Vec<_>>();
+            let path = create_blinded_path(&route, encrypted_data.as_slice(), &session_key).unwrap();
+            assert_eq!(hex::encode(&PublicKey::from(path.blinding_point.clone()).serialize()[..]), path_keys[0]);
+
+            let mut path_key = PublicKey::from(path.blinding_point.clone());
+            for (index, hop) in path.hops.iter().enumerate() {
+                assert_eq!(hex::encode(&path_key.serialize()[..]), path_keys[index]);
+                assert_eq!(hex::encode(&PublicKey::from(hop.blinded_node_id.clone()).serialize()[..]), blinded_node_ids[index]);
+
+                let keys = BlindedHopKeys::new(&path_key, &node_secret(nodes[index])).unwrap();
+                let data = keys.decrypt(hop.encrypted_recipient_data.as_slice()).unwrap();
+                path_key = keys.next_blinding_point(&data).unwrap();
+            }
+        }
+
+        let session_key = SecretKey::from_slice(&context, &[0x02; 32]).unwrap();
+        let path = create_blinded_path(&[node_id(1)], &[bob_data()], &session_key).unwrap();
+        assert_eq!(
+            hex::encode(path.hops[0].encrypted_recipient_data.as_slice()),
+            "cd4100ff9c09ed28102b210ac73aa12d63e90852cebc496c49f57c49982088b49f2e70b99287fdee0aa58aa39913ab40\
+             5813b999f66783aa2fe637b3cda91ffc0913c30324e2c6ce327e045183e4bffecb",
+        );
+    }
+}
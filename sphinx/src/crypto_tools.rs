@@ -0,0 +1,133 @@
+use secp256k1::{Secp256k1, PublicKey, SecretKey, Error as EcdsaError};
+
+use crypto::chacha20::ChaCha20;
+use crypto::symmetriccipher::SynchronousStreamCipher;
+use crypto::hmac::Hmac;
+use crypto::mac::Mac;
+use crypto::sha2::Sha256;
+use crypto::digest::Digest;
+
+pub const HMAC_SIZE: usize = 32;
+
+/// The secret shared between the sender of the onion and a single hop
+/// of the route. All keys of the hop are derived from it.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct SharedSecret([u8; 32]);
+
+impl From<[u8; 32]> for SharedSecret {
+    fn from(data: [u8; 32]) -> Self {
+        SharedSecret(data)
+    }
+}
+
+impl AsRef<[u8]> for SharedSecret {
+    fn as_ref(&self) -> &[u8] {
+        &self.0[..]
+    }
+}
+
+/// The purpose of the key generated from the shared secret,
+/// the name of the variant is used as a key of the hmac.
+pub enum KeyType {
+    Rho,
+    Mu,
+    Um,
+    Ammag,
+    BlindedNodeId,
+}
+
+impl KeyType {
+    fn as_bytes(&self) -> &'static [u8] {
+        use self::KeyType::*;
+
+        match self {
+            &Rho => b"rho",
+            &Mu => b"mu",
+            &Um => b"um",
+            &Ammag => b"ammag",
+            &BlindedNodeId => b"blinded_node_id",
+        }
+    }
+}
+
+pub fn sha256(data: &[&[u8]]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    for chunk in data {
+        hasher.input(chunk);
+    }
+    let mut hash = [0; 32];
+    hasher.result(&mut hash);
+    hash
+}
+
+pub fn hmac(key: &[u8], data: &[&[u8]]) -> [u8; HMAC_SIZE] {
+    let mut mac = Hmac::new(Sha256::new(), key);
+    for chunk in data {
+        mac.input(chunk);
+    }
+    let mut result = [0; HMAC_SIZE];
+    mac.raw_result(&mut result);
+    result
+}
+
+// generate_key derives the key of the given type, it is the hmac
+// of the shared secret where the name of the type is used as a key
+pub fn generate_key(key_type: KeyType, secret: &SharedSecret) -> [u8; 32] {
+    hmac(key_type.as_bytes(), &[secret.as_ref()])
+}
+
+// generate_cipher_stream generates the pseudo random stream of bytes
+// by encrypting zeros with chacha20 keyed by the key, the nonce is zero
+pub fn generate_cipher_stream(key: &[u8; 32], length: usize) -> Vec<u8> {
+    let mut cipher = ChaCha20::new(&key[..], &[0; 8]);
+    let zeros = vec![0; length];
+    let mut stream = vec![0; length];
+    cipher.process(zeros.as_slice(), stream.as_mut_slice());
+    stream
+}
+
+pub fn xor(data: &mut [u8], stream: &[u8]) {
+    for (d, s) in data.iter_mut().zip(stream.iter()) {
+        *d ^= *s;
+    }
+}
+
+// ecdh performs an ECDH operation between public and private. The returned value is
+// the sha256 of the compressed shared point.
+pub fn ecdh(pk: &PublicKey, sk: &SecretKey) -> Result<SharedSecret, EcdsaError> {
+    let mut pk_cloned = pk.clone();
+    pk_cloned.mul_assign(&Secp256k1::new(), sk)?;
+
+    Ok(SharedSecret(sha256(&[&pk_cloned.serialize()[..]])))
+}
+
+// blinding_factor is the sha256 of the ephemeral key concatenated with the shared secret,
+// it is used to derive the ephemeral key for the next hop
+pub fn blinding_factor(ephemeral_key: &PublicKey, secret: &SharedSecret) -> Result<SecretKey, EcdsaError> {
+    let hash = sha256(&[&ephemeral_key.serialize()[..], secret.as_ref()]);
+    SecretKey::from_slice(&Secp256k1::new(), &hash[..])
+}
+
+pub fn blind(pk: &PublicKey, factor: &SecretKey) -> Result<PublicKey, EcdsaError> {
+    let mut pk_cloned = pk.clone();
+    pk_cloned.mul_assign(&Secp256k1::new(), factor)?;
+    Ok(pk_cloned)
+}
+
+/// Computes the secret shared with each hop of the route.
+/// The sender needs them again in order to decrypt a failure message.
+pub fn generate_shared_secrets(path: &[PublicKey], session_key: &SecretKey) -> Result<Vec<SharedSecret>, EcdsaError> {
+    let context = Secp256k1::new();
+
+    let mut ephemeral_secret = session_key.clone();
+    let mut shared_secrets = Vec::with_capacity(path.len());
+    for hop_public in path {
+        let ephemeral_key = PublicKey::from_secret_key(&context, &ephemeral_secret)?;
+        let shared_secret = ecdh(hop_public, &ephemeral_secret)?;
+        let factor = blinding_factor(&ephemeral_key, &shared_secret)?;
+        ephemeral_secret.mul_assign(&context, &factor)?;
+        shared_secrets.push(shared_secret);
+    }
+
+    Ok(shared_secrets)
+}
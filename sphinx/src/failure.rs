@@ -0,0 +1,154 @@
+use crypto::util::fixed_time_eq;
+
+use wire::{FailureMessage, OpaqueReason, BinarySD};
+
+use super::crypto_tools::{SharedSecret, KeyType, HMAC_SIZE};
+use super::crypto_tools::{generate_key, generate_cipher_stream, hmac, xor};
+use super::packet::OnionError;
+
+// FAILURE_MESSAGE_PADDED_SIZE is the size of the failure message together
+// with the padding, the padding hides the type of the failure from the hops.
+pub const FAILURE_MESSAGE_PADDED_SIZE: usize = 256;
+
+/// The failure decrypted by the origin node.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct DecryptedFailure {
+    // the index of the erring node in the route
+    pub index: usize,
+    pub message: FailureMessage,
+}
+
+/// Creates the failure reason, called by the erring node,
+/// the shared secret is the one obtained while processing the onion packet.
+pub fn create_failure(shared_secret: &SharedSecret, message: &FailureMessage) -> Result<OpaqueReason, OnionError> {
+    let message = message.to_bytes().map_err(OnionError::Wire)?;
+    let pad_length = FAILURE_MESSAGE_PADDED_SIZE.saturating_sub(message.len());
+
+    let mut payload = Vec::with_capacity(4 + message.len() + pad_length);
+    BinarySD::serialize(&mut payload, &message).map_err(OnionError::Wire)?;
+    BinarySD::serialize(&mut payload, &vec![0u8; pad_length]).map_err(OnionError::Wire)?;
+
+    let um_key = generate_key(KeyType::Um, shared_secret);
+    let mut data = hmac(&um_key[..], &[payload.as_slice()]).to_vec();
+    data.append(&mut payload);
+
+    Ok(forward_failure(shared_secret, data.into()))
+}
+
+/// Obfuscates the failure reason, called by each hop on the way back to the origin node.
+pub fn forward_failure(shared_secret: &SharedSecret, reason: OpaqueReason) -> OpaqueReason {
+    let ammag_key = generate_key(KeyType::Ammag, shared_secret);
+    let stream = generate_cipher_stream(&ammag_key, reason.as_ref().len());
+
+    let mut reason = reason;
+    xor(reason.as_mut(), stream.as_slice());
+    reason
+}
+
+/// Decrypts the failure reason, called by the origin node, `shared_secrets` are
+/// the secrets generated while creating the onion packet in the order of the route.
+/// Finds out which node of the route has created the failure.
+pub fn decrypt_failure(shared_secrets: &[SharedSecret], reason: OpaqueReason) -> Result<DecryptedFailure, OnionError> {
+    let mut reason = reason;
+    for (index, shared_secret) in shared_secrets.iter().enumerate() {
+        reason = forward_failure(shared_secret, reason);
+        if reason.as_ref().len() < HMAC_SIZE {
+            break;
+        }
+
+        let (expected_hmac, payload) = reason.as_ref().split_at(HMAC_SIZE);
+        let um_key = generate_key(KeyType::Um, shared_secret);
+        if fixed_time_eq(&hmac(&um_key[..], &[payload])[..], expected_hmac) {
+            let message: Vec<u8> = BinarySD::deserialize(payload).map_err(OnionError::Wire)?;
+            let message = FailureMessage::from_bytes(message.as_slice()).map_err(OnionError::Wire)?;
+            return Ok(DecryptedFailure {
+                index: index,
+                message: message,
+            });
+        }
+    }
+
+    Err(OnionError::UnreadableFailure)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use super::super::crypto_tools::generate_shared_secrets;
+
+    use secp256k1::{Secp256k1, PublicKey, SecretKey};
+    use wire::MilliSatoshi;
+    use hex;
+
+    // BOLT 04 test vector, the failure returned by the last hop
+    const FAILURE_HEX: &str = "\
+9c5add3963fc7f6ed7f148623c84134b5647e1306419dbe2174e523fa9e2fbed3a06a19f899145610741c83ad40b7712\
+aefaddec8c6baf7325d92ea4ca4d1df8bce517f7e54554608bf2bd8071a4f52a7a2f7ffbb1413edad81eeea5785aa9d9\
+90f2865dc23b4bc3c301a94eec4eabebca66be5cf638f693ec256aec514620cc28ee4a94bd9565bc4d4962b9d3641d42\
+78fb319ed2b84de5b665f307a2db0f7fbb757366067d88c50f7e829138fde4f78d39b5b5802f1b92a8a820865af5cc79\
+f9f30bc3f461c66af95d13e5e1f0381c184572a91dee1c849048a647a1158cf884064deddbf1b0b88dfe2f791428d0ba\
+0f6fb2f04e14081f69165ae66d9297c118f0907705c9c4954a199bae0bb96fad763d690e7daa6cfda59ba7f2c8d11448\
+b604d12d";
+
+    fn shared_secrets() -> Vec<SharedSecret> {
+        let context = Secp256k1::new();
+        let route = (0..5u8)
+            .map(|i| {
+                let secret = SecretKey::from_slice(&context, &[0x41 + i; 32]).unwrap();
+                PublicKey::from_secret_key(&context, &secret).unwrap()
+            })
+            .collect::<Vec<_>>();
+        let session_key = SecretKey::from_slice(&context, &[0x41; 32]).unwrap();
+        generate_shared_secrets(&route, &session_key).unwrap()
+    }
+
+    #[test]
+    fn failure_vector() {
+        let shared_secrets = shared_secrets();
+
+        let mut reason = create_failure(&shared_secrets[4], &FailureMessage::TemporaryNodeFailure).unwrap();
+        for shared_secret in shared_secrets[..4].iter().rev() {
+            reason = forward_failure(shared_secret, reason);
+        }
+        assert_eq!(hex::encode(reason.as_ref()), FAILURE_HEX);
+
+        let decrypted = decrypt_failure(&shared_secrets, reason).unwrap();
+        assert_eq!(decrypted.index, 4);
+        assert_eq!(decrypted.message, FailureMessage::TemporaryNodeFailure);
+    }
+
+    #[test]
+    fn attribution() {
+        let shared_secrets = shared_secrets();
+        let message = FailureMessage::IncorrectOrUnknownPaymentDetails {
+            htlc_msat: MilliSatoshi::from(1000),
+            height: Some(100),
+        };
+
+        for erring in 0..5 {
+            let mut reason = create_failure(&shared_secrets[erring], &message).unwrap();
+            assert_eq!(reason.as_ref().len(), HMAC_SIZE + 4 + FAILURE_MESSAGE_PADDED_SIZE);
+            for shared_secret in shared_secrets[..erring].iter().rev() {
+                reason = forward_failure(shared_secret, reason);
+            }
+
+            let decrypted = decrypt_failure(&shared_secrets, reason).unwrap();
+            assert_eq!(decrypted.index, erring);
+            assert_eq!(decrypted.message, message);
+        }
+    }
+
+    #[test]
+    fn unreadable() {
+        let shared_secrets = shared_secrets();
+        let mut reason = create_failure(&shared_secrets[2], &FailureMessage::UnknownNextPeer).unwrap();
+        reason.as_mut()[40] ^= 1;
+        reason = forward_failure(&shared_secrets[1], reason);
+        reason = forward_failure(&shared_secrets[0], reason);
+
+        match decrypt_failure(&shared_secrets, reason) {
+            Err(OnionError::UnreadableFailure) => (),
+            r => panic!("unexpected result: {:?}", r),
+        }
+    }
+}
@@ -0,0 +1,212 @@
+use wire::ShortChannelId;
+use wire::MilliSatoshi;
+use wire::BigSize;
+use wire::TlvStream;
+use wire::TlvKnownTypes;
+use wire::PackSized;
+use wire::BinarySD;
+use wire::WireError;
+
+/// The size of the hop payload without the hmac.
+pub const HOP_PAYLOAD_SIZE: usize = 33;
+
+/// The per hop payload of the legacy (realm 0) format,
+/// tells the hop where and how to forward the htlc.
+#[derive(Serialize, Deserialize, Eq, PartialEq, Debug, Clone)]
+pub struct HopData {
+    realm: u8,
+    next_address: ShortChannelId,
+    forward_amount: MilliSatoshi,
+    outgoing_cltv: u32,
+    padding: [u8; 12],
+}
+
+impl HopData {
+    pub fn new(next_address: ShortChannelId, forward_amount: MilliSatoshi, outgoing_cltv: u32) -> Self {
+        HopData {
+            realm: 0,
+            next_address: next_address,
+            forward_amount: forward_amount,
+            outgoing_cltv: outgoing_cltv,
+            padding: [0; 12],
+        }
+    }
+
+    pub fn realm(&self) -> u8 {
+        self.realm
+    }
+
+    pub fn next_address(&self) -> &ShortChannelId {
+        &self.next_address
+    }
+
+    pub fn forward_amount(&self) -> MilliSatoshi {
+        self.forward_amount
+    }
+
+    pub fn outgoing_cltv(&self) -> u32 {
+        self.outgoing_cltv
+    }
+}
+
+/// The types of the records of the TLV hop payload.
+#[derive(Default, Clone, Eq, PartialEq, Debug)]
+pub struct HopPayloadTlv;
+
+impl HopPayloadTlv {
+    pub const AMOUNT_TO_FORWARD: u64 = 2;
+    pub const OUTGOING_CLTV_VALUE: u64 = 4;
+    pub const SHORT_CHANNEL_ID: u64 = 6;
+    pub const PAYMENT_DATA: u64 = 8;
+}
+
+impl TlvKnownTypes for HopPayloadTlv {
+    const KNOWN: &'static [u64] = &[
+        Self::AMOUNT_TO_FORWARD,
+        Self::OUTGOING_CLTV_VALUE,
+        Self::SHORT_CHANNEL_ID,
+        Self::PAYMENT_DATA,
+    ];
+}
+
+/// The payload of the hop, either legacy fixed size `HopData`,
+/// or variable size TLV stream prefixed by its length.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub enum HopPayload {
+    Legacy(HopData),
+    Tlv(TlvStream<HopPayloadTlv>),
+}
+
+impl From<HopData> for HopPayload {
+    fn from(hop_data: HopData) -> Self {
+        HopPayload::Legacy(hop_data)
+    }
+}
+
+impl HopPayload {
+    /// The TLV payload, the exit node has no `next_address`.
+    pub fn tlv(next_address: Option<ShortChannelId>, forward_amount: MilliSatoshi, outgoing_cltv: u32) -> Self {
+        let mut stream = TlvStream::new();
+        stream.insert_tu64(HopPayloadTlv::AMOUNT_TO_FORWARD, forward_amount.into());
+        stream.insert_tu64(HopPayloadTlv::OUTGOING_CLTV_VALUE, outgoing_cltv as u64);
+        if let Some(next_address) = next_address {
+            stream.insert(HopPayloadTlv::SHORT_CHANNEL_ID, {
+                let mut data = Vec::with_capacity(ShortChannelId::SIZE);
+                BinarySD::serialize(&mut data, &next_address).unwrap();
+                data
+            });
+        }
+        HopPayload::Tlv(stream)
+    }
+
+    pub fn next_address(&self) -> Option<ShortChannelId> {
+        match self {
+            &HopPayload::Legacy(ref hop_data) => Some(hop_data.next_address().clone()),
+            &HopPayload::Tlv(ref stream) => stream.get_value(HopPayloadTlv::SHORT_CHANNEL_ID).ok().and_then(|x| x),
+        }
+    }
+
+    pub fn forward_amount(&self) -> Option<MilliSatoshi> {
+        match self {
+            &HopPayload::Legacy(ref hop_data) => Some(hop_data.forward_amount()),
+            &HopPayload::Tlv(ref stream) => stream.get_tu64(HopPayloadTlv::AMOUNT_TO_FORWARD).ok()
+                .and_then(|x| x)
+                .map(MilliSatoshi::from),
+        }
+    }
+
+    pub fn outgoing_cltv(&self) -> Option<u32> {
+        match self {
+            &HopPayload::Legacy(ref hop_data) => Some(hop_data.outgoing_cltv()),
+            &HopPayload::Tlv(ref stream) => stream.get_tu64(HopPayloadTlv::OUTGOING_CLTV_VALUE).ok()
+                .and_then(|x| x)
+                .filter(|&x| x <= (u32::max_value() as u64))
+                .map(|x| x as u32),
+        }
+    }
+
+    // serialize the payload as it is placed in the routing info, without hmac
+    pub(crate) fn to_bytes(&self) -> Result<Vec<u8>, WireError> {
+        match self {
+            &HopPayload::Legacy(ref hop_data) => {
+                let mut data = Vec::with_capacity(HOP_PAYLOAD_SIZE);
+                BinarySD::serialize(&mut data, hop_data)?;
+                Ok(data)
+            },
+            &HopPayload::Tlv(ref stream) => write_tlv_payload(stream),
+        }
+    }
+
+    // parse the payload from the beginning of the routing info,
+    // returns the payload and its size without hmac
+    pub(crate) fn from_bytes(data: &[u8]) -> Result<(Self, usize), WireError> {
+        if data.first() == Some(&0) {
+            let hop_data = BinarySD::deserialize(data)?;
+            Ok((HopPayload::Legacy(hop_data), HOP_PAYLOAD_SIZE))
+        } else {
+            read_tlv_payload(data).map(|(stream, size)| (HopPayload::Tlv(stream), size))
+        }
+    }
+}
+
+// the TLV stream prefixed by its length, the onion message uses it as well
+pub(crate) fn write_tlv_payload<K>(stream: &TlvStream<K>) -> Result<Vec<u8>, WireError> where K: TlvKnownTypes {
+    let mut stream_data = Vec::new();
+    BinarySD::serialize(&mut stream_data, stream)?;
+    let mut data = Vec::with_capacity(9 + stream_data.len());
+    BinarySD::serialize(&mut data, &BigSize::from(stream_data.len() as u64))?;
+    data.append(&mut stream_data);
+    Ok(data)
+}
+
+pub(crate) fn read_tlv_payload<K>(data: &[u8]) -> Result<(TlvStream<K>, usize), WireError> where K: TlvKnownTypes {
+    use serde::de::Error;
+
+    let length: BigSize = BinarySD::deserialize(data)?;
+    let start = length.pack_size();
+    if u64::from(length) > (data.len() - start) as u64 {
+        return Err(WireError::custom("hop payload is too long"));
+    }
+    let end = start + (u64::from(length) as usize);
+    let stream = BinarySD::deserialize(&data[start..end])?;
+    Ok((stream, end))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use wire::BinarySD;
+
+    #[test]
+    fn size() {
+        let hop_data = HopData::new(0x0101010101010101u64.into(), 1u64.into(), 1);
+        let mut data = Vec::new();
+        BinarySD::serialize(&mut data, &hop_data).unwrap();
+        assert_eq!(data.len(), HOP_PAYLOAD_SIZE);
+
+        let restored: HopData = BinarySD::deserialize(&data[..]).unwrap();
+        assert_eq!(restored, hop_data);
+    }
+
+    #[test]
+    fn tlv_payload() {
+        let payload = HopPayload::tlv(Some(0x0102030405060708u64.into()), 1000u64.into(), 500000);
+        let data = payload.to_bytes().unwrap();
+        assert_eq!(data, vec![
+            0x13,
+            0x02, 0x02, 0x03, 0xe8,
+            0x04, 0x03, 0x07, 0xa1, 0x20,
+            0x06, 0x08, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08,
+        ]);
+
+        let (restored, size) = HopPayload::from_bytes(data.as_slice()).unwrap();
+        assert_eq!(size, data.len());
+        assert_eq!(restored, payload);
+        assert_eq!(restored.next_address(), Some(0x0102030405060708u64.into()));
+        assert_eq!(restored.forward_amount(), Some(1000u64.into()));
+        assert_eq!(restored.outgoing_cltv(), Some(500000));
+
+        let exit = HopPayload::tlv(None, 1000u64.into(), 500000);
+        assert_eq!(exit.next_address(), None);
+    }
+}
@@ -0,0 +1,30 @@
+#![forbid(unsafe_code)]
+#![allow(non_shorthand_field_patterns)]
+
+extern crate secp256k1;
+extern crate crypto;
+extern crate chacha20_poly1305_aead;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+extern crate wire;
+extern crate tokio;
+
+#[cfg(test)]
+extern crate hex;
+
+mod crypto_tools;
+mod hop;
+mod packet;
+mod failure;
+mod blinding;
+mod onion_message;
+
+pub use self::crypto_tools::{SharedSecret, generate_shared_secrets};
+pub use self::hop::{HopData, HopPayload, HopPayloadTlv};
+pub use self::packet::{OnionPacket, ProcessedPacket, OnionError};
+pub use self::packet::{NUM_MAX_HOPS, HOP_DATA_SIZE, ROUTING_INFO_SIZE};
+pub use self::failure::{DecryptedFailure, create_failure, forward_failure, decrypt_failure};
+pub use self::blinding::{EncryptedDataTlv, BlindedHopKeys, create_blinded_path, create_message_path};
+pub use self::onion_message::{OnionMessageTlv, ProcessedOnionMessage, create_onion_message, process_onion_message};
+pub use self::onion_message::{OnionMessageHandler, IncomingOnionMessage, OnionMessageConsumer, LARGE_PAYLOADS_SIZE};
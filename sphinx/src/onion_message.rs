@@ -0,0 +1,413 @@
+use secp256k1::{PublicKey, SecretKey};
+
+use wire::{TlvStream, TlvKnownTypes, BlindedPath, OnionMessage, Message, WireError};
+use wire::{MessageFiltered, MessageConsumer};
+use wire::PublicKey as WirePublicKey;
+
+use tokio::prelude::Future;
+use tokio::prelude::Sink;
+
+use super::blinding::{EncryptedDataTlv, BlindedHopKeys, create_blinded_path};
+use super::hop::{read_tlv_payload, write_tlv_payload};
+use super::packet::{OnionPacket, OnionError, ROUTING_INFO_SIZE};
+
+// LARGE_PAYLOADS_SIZE is the size of the routing info of the onion message
+// if the payloads do not fit the usual size
+pub const LARGE_PAYLOADS_SIZE: usize = 32768;
+
+/// The types of the records of the payload of the onion message.
+/// The types starting from 64 are the content for the recipient.
+#[derive(Default, Clone, Eq, PartialEq, Debug)]
+pub struct OnionMessageTlv;
+
+impl OnionMessageTlv {
+    pub const REPLY_PATH: u64 = 2;
+    pub const ENCRYPTED_RECIPIENT_DATA: u64 = 4;
+    pub const INVOICE_REQUEST: u64 = 64;
+    pub const INVOICE: u64 = 66;
+    pub const INVOICE_ERROR: u64 = 68;
+}
+
+impl TlvKnownTypes for OnionMessageTlv {
+    const KNOWN: &'static [u64] = &[
+        Self::REPLY_PATH,
+        Self::ENCRYPTED_RECIPIENT_DATA,
+    ];
+
+    // the application decides what to do with the content
+    fn is_known(t: u64) -> bool {
+        Self::KNOWN.contains(&t) || t >= Self::INVOICE_REQUEST
+    }
+}
+
+/// Creates the onion message to the `destination`, the `content` is for
+/// the recipient, it may contain the reply path. The `route` are the nodes
+/// before the introduction node, the path to the introduction node is blinded
+/// by the `blinding_key`. Returns the node to send the message to.
+pub fn create_onion_message(
+    route: &[PublicKey],
+    destination: &BlindedPath,
+    content: TlvStream<OnionMessageTlv>,
+    session_key: SecretKey,
+    blinding_key: &SecretKey,
+) -> Result<(PublicKey, OnionMessage), OnionError> {
+    // the destination is the last hop of its path, the path cannot be empty
+    if destination.hops.is_empty() {
+        return Err(OnionError::EmptyPath);
+    }
+
+    let mut hops = Vec::with_capacity(route.len() + destination.hops.len());
+    let (first_node_id, blinding_point) = if route.is_empty() {
+        (destination.introduction_node_id.clone(), destination.blinding_point.clone())
+    } else {
+        // the last node of the route switches to the blinding point of the destination
+        let encrypted_data = (0..route.len())
+            .map(|index| {
+                let mut stream = TlvStream::new();
+                match route.get(index + 1) {
+                    Some(next_node_id) => {
+                        stream.insert(EncryptedDataTlv::NEXT_NODE_ID, next_node_id.serialize().to_vec());
+                    },
+                    None => {
+                        stream.insert_value(EncryptedDataTlv::NEXT_NODE_ID, &destination.introduction_node_id).unwrap();
+                        stream.insert_value(EncryptedDataTlv::NEXT_BLINDING_OVERRIDE, &destination.blinding_point).unwrap();
+                    },
+                };
+                stream
+            })
+            .collect::<Vec<_>>();
+        let path = create_blinded_path(route, encrypted_data.as_slice(), blinding_key)?;
+        hops.extend(path.hops);
+        (path.introduction_node_id, path.blinding_point)
+    };
+    hops.extend(destination.hops.iter().cloned());
+
+    let blinded_route = hops.iter()
+        .map(|hop| hop.blinded_node_id.clone().into())
+        .collect::<Vec<PublicKey>>();
+    let last = hops.len() - 1;
+    let payloads = hops.into_iter()
+        .enumerate()
+        .map(|(index, hop)| {
+            let mut payload = if index == last { content.clone() } else { TlvStream::new() };
+            payload.insert(OnionMessageTlv::ENCRYPTED_RECIPIENT_DATA, hop.encrypted_recipient_data);
+            write_tlv_payload(&payload)
+        })
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(OnionError::Wire)?;
+
+    let packet = match OnionPacket::with_payloads(&blinded_route, &payloads, session_key.clone(), &[], ROUTING_INFO_SIZE) {
+        Err(OnionError::PayloadSize(_)) => {
+            OnionPacket::with_payloads(&blinded_route, &payloads, session_key, &[], LARGE_PAYLOADS_SIZE)?
+        },
+        r => r?,
+    };
+
+    let message = OnionMessage {
+        blinding_point: blinding_point,
+        onion_message_packet: packet.to_message_packet(),
+    };
+    Ok((first_node_id.into(), message))
+}
+
+/// The result of processing the onion message by the node.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub enum ProcessedOnionMessage {
+    /// The message reached us, the `path_id` is the one we put in our blinded path.
+    Receive {
+        path_id: Option<Vec<u8>>,
+        content: TlvStream<OnionMessageTlv>,
+    },
+    /// The message should be sent to the next node.
+    Forward {
+        next_node_id: PublicKey,
+        message: OnionMessage,
+    },
+}
+
+/// Peels one layer of the onion message using the secret key of the node.
+pub fn process_onion_message(message: OnionMessage, node_secret: &SecretKey) -> Result<ProcessedOnionMessage, OnionError> {
+    let keys = BlindedHopKeys::new(&message.blinding_point.into(), node_secret)?;
+    let packet = OnionPacket::from_message_packet(&message.onion_message_packet)?;
+    let (payload, next_packet, _) = packet.peel(&[], keys.node_secret(), read_tlv_payload::<OnionMessageTlv>)?;
+
+    let mut payload = payload;
+    let data = payload.remove(OnionMessageTlv::ENCRYPTED_RECIPIENT_DATA)
+        .ok_or(OnionError::InvalidEncryptedData)?;
+    let data = keys.decrypt(data.as_slice())?;
+
+    match next_packet {
+        None => Ok(ProcessedOnionMessage::Receive {
+            path_id: data.get(EncryptedDataTlv::PATH_ID).map(<[u8]>::to_vec),
+            content: payload,
+        }),
+        Some(next_packet) => {
+            let next_node_id = match data.get_value::<WirePublicKey>(EncryptedDataTlv::NEXT_NODE_ID) {
+                Ok(Some(next_node_id)) => next_node_id.into(),
+                _ => return Err(OnionError::InvalidEncryptedData),
+            };
+            Ok(ProcessedOnionMessage::Forward {
+                next_node_id: next_node_id,
+                message: OnionMessage {
+                    blinding_point: keys.next_blinding_point(&data)?.into(),
+                    onion_message_packet: next_packet.to_message_packet(),
+                },
+            })
+        },
+    }
+}
+
+/// The application using the onion messages, e.g. offers.
+pub trait OnionMessageHandler {
+    /// The message reached us, the `path_id` should be checked,
+    /// otherwise anybody could probe our blinded paths.
+    /// The error fails the connection.
+    fn receive(&mut self, path_id: Option<Vec<u8>>, content: TlvStream<OnionMessageTlv>) -> Result<(), WireError>;
+
+    /// The message should be sent to the peer `next_node_id`,
+    /// if there is no such peer, the message should be dropped.
+    fn forward(&mut self, next_node_id: PublicKey, message: OnionMessage) -> Result<(), WireError>;
+}
+
+pub struct IncomingOnionMessage {
+    inner: OnionMessage,
+}
+
+impl MessageFiltered for IncomingOnionMessage {
+    fn filter(v: Message) -> Result<Self, Message> {
+        match v {
+            Message::OnionMessage(m) => Ok(IncomingOnionMessage { inner: m }),
+            v @ _ => Err(v),
+        }
+    }
+}
+
+/// Peels the onion messages and passes them to the handler,
+/// should be placed in the `MessageConsumerChain` of the peer connection.
+/// The invalid message is dropped, it does not fail the connection.
+pub struct OnionMessageConsumer<H> where H: OnionMessageHandler {
+    node_secret: SecretKey,
+    handler: H,
+}
+
+impl<H> OnionMessageConsumer<H> where H: OnionMessageHandler {
+    pub fn new(node_secret: SecretKey, handler: H) -> Self {
+        OnionMessageConsumer {
+            node_secret: node_secret,
+            handler: handler,
+        }
+    }
+
+    pub fn handler(&self) -> &H {
+        &self.handler
+    }
+
+    pub fn handler_mut(&mut self) -> &mut H {
+        &mut self.handler
+    }
+}
+
+impl<H> MessageConsumer for OnionMessageConsumer<H> where H: OnionMessageHandler + 'static {
+    type Message = IncomingOnionMessage;
+
+    fn consume<S>(self, sink: S, message: Self::Message) -> Box<dyn Future<Item=(Self, S), Error=WireError>>
+    where
+        S: Sink<SinkItem=Message, SinkError=WireError> + Send + 'static,
+    {
+        use tokio::prelude::IntoFuture;
+
+        let mut this = self;
+        let result = match process_onion_message(message.inner, &this.node_secret) {
+            Ok(ProcessedOnionMessage::Receive { path_id, content }) => this.handler.receive(path_id, content),
+            Ok(ProcessedOnionMessage::Forward { next_node_id, message }) => this.handler.forward(next_node_id, message),
+            Err(_) => Ok(()),
+        };
+        Box::new(result.map(move |()| (this, sink)).into_future())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use super::super::blinding::create_message_path;
+    use secp256k1::Secp256k1;
+    use wire::BinarySD;
+    use hex;
+
+    fn node_secret(index: u8) -> SecretKey {
+        SecretKey::from_slice(&Secp256k1::new(), &[0x41 + index; 32]).unwrap()
+    }
+
+    fn node_id(index: u8) -> PublicKey {
+        PublicKey::from_secret_key(&Secp256k1::new(), &node_secret(index)).unwrap()
+    }
+
+    // sends the message from the first node of the route through the rest,
+    // the last node is the recipient
+    fn deliver(first: u8, message: OnionMessage, size: usize) -> (Option<Vec<u8>>, TlvStream<OnionMessageTlv>) {
+        let mut index = first;
+        let mut message = message;
+        loop {
+            assert_eq!(message.onion_message_packet.len(), size);
+
+            // the message survives the wire
+            let mut data = Vec::new();
+            BinarySD::serialize(&mut data, &Message::OnionMessage(message)).unwrap();
+            let restored: Message = BinarySD::deserialize(data.as_slice()).unwrap();
+
+            match process_onion_message(restored.as_onion_message().unwrap(), &node_secret(index)).unwrap() {
+                ProcessedOnionMessage::Forward { next_node_id, message: next } => {
+                    index = (0..10).find(|&i| node_id(i) == next_node_id).unwrap();
+                    message = next;
+                },
+                ProcessedOnionMessage::Receive { path_id, content } => {
+                    assert_eq!(index, 4);
+                    return (path_id, content);
+                },
+            }
+        }
+    }
+
+    #[test]
+    fn through_blinded_path() {
+        let context = Secp256k1::new();
+        let path_key = SecretKey::from_slice(&context, &[0x51; 32]).unwrap();
+        let path = create_message_path(&[node_id(2), node_id(3), node_id(4)], vec![7; 32], &path_key).unwrap();
+        let reply_path = create_message_path(&[node_id(1), node_id(0)], vec![8; 32], &path_key).unwrap();
+
+        let mut content = TlvStream::new();
+        content.insert_value(OnionMessageTlv::REPLY_PATH, &reply_path).unwrap();
+        content.insert(OnionMessageTlv::INVOICE_REQUEST, vec![1, 2, 3]);
+
+        // the sender is connected to the node 1, the introduction node is 2
+        let session_key = SecretKey::from_slice(&context, &[0x52; 32]).unwrap();
+        let blinding_key = SecretKey::from_slice(&context, &[0x53; 32]).unwrap();
+        let (first, message) = create_onion_message(&[node_id(1)], &path, content.clone(), session_key, &blinding_key).unwrap();
+        assert_eq!(first, node_id(1));
+
+        let (path_id, received) = deliver(1, message, 1366);
+        assert_eq!(path_id, Some(vec![7; 32]));
+        assert_eq!(received, content);
+        assert_eq!(received.get_value(OnionMessageTlv::REPLY_PATH).unwrap(), Some(reply_path));
+    }
+
+    #[test]
+    fn large_content() {
+        let context = Secp256k1::new();
+        let path_key = SecretKey::from_slice(&context, &[0x51; 32]).unwrap();
+        let path = create_message_path(&[node_id(3), node_id(4)], vec![], &path_key).unwrap();
+
+        let mut content = TlvStream::new();
+        content.insert(OnionMessageTlv::INVOICE, vec![0x42; 2000]);
+        let session_key = SecretKey::from_slice(&context, &[0x52; 32]).unwrap();
+        let (first, message) = create_onion_message(&[], &path, content.clone(), session_key, &path_key).unwrap();
+        assert_eq!(first, node_id(3));
+
+        let (path_id, received) = deliver(3, message, 66 + LARGE_PAYLOADS_SIZE);
+        assert_eq!(path_id, Some(vec![]));
+        assert_eq!(received, content);
+    }
+
+    #[test]
+    fn wrong_node() {
+        let context = Secp256k1::new();
+        let path_key = SecretKey::from_slice(&context, &[0x51; 32]).unwrap();
+        let path = create_message_path(&[node_id(3), node_id(4)], vec![], &path_key).unwrap();
+        let session_key = SecretKey::from_slice(&context, &[0x52; 32]).unwrap();
+        let (_, message) = create_onion_message(&[], &path, TlvStream::new(), session_key, &path_key).unwrap();
+        match process_onion_message(message, &node_secret(4)) {
+            Err(OnionError::InvalidHmac) => (),
+            r => panic!("unexpected result: {:?}", r),
+        }
+    }
+
+    #[test]
+    fn empty_path() {
+        let context = Secp256k1::new();
+        let path_key = SecretKey::from_slice(&context, &[0x51; 32]).unwrap();
+        let mut path = create_message_path(&[node_id(3), node_id(4)], vec![], &path_key).unwrap();
+        path.hops.clear();
+
+        for route in [vec![], vec![node_id(1)]].iter() {
+            let session_key = SecretKey::from_slice(&context, &[0x52; 32]).unwrap();
+            match create_onion_message(route.as_slice(), &path, TlvStream::new(), session_key, &path_key) {
+                Err(OnionError::EmptyPath) => (),
+                r => panic!("unexpected result: {:?}", r),
+            }
+        }
+    }}
+
+    // `blinded-onion-message-onion-test.json` of BOLT 4: Dave's path Bob -> Carol -> Dave
+    // is made from the `0x01` bytes, the sender reaches Bob through Alice, Alice's hop
+    // is blinded by the `0x63` bytes and overrides the path key to the one of Dave's path
+    #[test]
+    fn bolt04_vectors() {
+        let context = Secp256k1::new();
+        let secret = |byte: u8| SecretKey::from_slice(&context, &[byte; 32]).unwrap();
+        let hex_key = |key: &PublicKey| hex::encode(&key.serialize()[..]);
+
+        let mut bob = TlvStream::new();
+        bob.insert(EncryptedDataTlv::NEXT_NODE_ID, node_id(2).serialize().to_vec());
+        bob.insert(561, hex::decode("123456").unwrap());
+        let mut carol = TlvStream::new();
+        carol.insert(EncryptedDataTlv::PADDING, vec![0; 5]);
+        carol.insert(EncryptedDataTlv::NEXT_NODE_ID, node_id(3).serialize().to_vec());
+        let mut dave = TlvStream::new();
+        dave.insert(EncryptedDataTlv::PATH_ID, hex::decode("deadbeefbadc0ffeedeadbeefbadc0ffeedeadbeefbadc0ffeedeadbeefbadc0ffee").unwrap());
+        let path = create_blinded_path(&[node_id(1), node_id(2), node_id(3)], &[bob, carol, dave], &secret(0x01)).unwrap();
+
+        let (first, message) = create_onion_message(&[node_id(0)], &path, TlvStream::new(), secret(0x03), &secret(0x63)).unwrap();
+        assert_eq!(first, node_id(0));
+        let packet = message.onion_message_packet.as_ref();
+        assert_eq!(packet.len(), 1366);
+        assert_eq!(hex::encode(&packet[..34]), "0002531fe6068134503d2723133227c867ac8fa6c83c537e9a44c3c5bdbdcb1fe337");
+
+        // the path key and the encrypted data each hop receives,
+        // Dave's data is not reproduced, its path id is checked instead
+        let hops = vec![
+            (
+                "031195a8046dcbb8e17034bca630065e7a0982e4e36f6f7e5a8d4554e4846fcd99",
+                Some("49531cf38d3280b7f4af6d6461a2b32e3df50acfd35176fc61422a1096eed4dfc3806f29bf74320f712a61c766e7f7caac0c42f86040125fbaeec0c7613202b206dbdd31fda56394367b66a711bfd7d5bedbe20bed1b"),
+            ),
+            (
+                "031b84c5567b126440995d3ed5aaba0565d71e1834604819ff9c17f5e9d5dd078f",
+                Some("adf6771d3983b7f543d1b3d7a12b440b2bd3e1b3b8d6ec1023f6dec4f0e7548a6f57f6dbe9573b0a0f24f7c5773a7dd7a7bdb6bd0ee686d759f5"),
+            ),
+            (
+                "02b684babfd400c8dd48b367e9754b8021a3594a34dc94d7101776c7f6a86d0582",
+                Some("d8903df7a79ac799a0b59f4ba22f6a599fa32e7ff1a8325fc22b88d278ce3e4840af02adfb82d6145a189ba50c2219c9e4351e634d198e0849ac"),
+            ),
+            (
+                "025aaca62db7ce6b46386206ef9930daa32e979a35cb185a41cb951aa7d254b03c",
+                None,
+            ),
+        ];
+
+        let mut message = message;
+        for (index, &(path_key, encrypted_data)) in hops.iter().enumerate() {
+            assert_eq!(hex_key(&message.blinding_point.clone().into()), path_key);
+
+            // peel the layer as `process_onion_message` does to see the payload
+            let keys = BlindedHopKeys::new(&message.blinding_point.clone().into(), &node_secret(index as u8)).unwrap();
+            let packet = OnionPacket::from_message_packet(&message.onion_message_packet).unwrap();
+            let (payload, _, _) = packet.peel(&[], keys.node_secret(), read_tlv_payload::<OnionMessageTlv>).unwrap();
+            if let Some(encrypted_data) = encrypted_data {
+                assert_eq!(hex::encode(payload.get(OnionMessageTlv::ENCRYPTED_RECIPIENT_DATA).unwrap()), encrypted_data);
+            }
+
+            match process_onion_message(message.clone(), &node_secret(index as u8)).unwrap() {
+                ProcessedOnionMessage::Forward { next_node_id, message: next } => {
+                    assert_eq!(next_node_id, node_id(index as u8 + 1));
+                    message = next;
+                },
+                ProcessedOnionMessage::Receive { path_id, content } => {
+                    assert_eq!(index, 3);
+                    assert_eq!(path_id.map(hex::encode), Some("deadbeefbadc0ffeedeadbeefbadc0ffeedeadbeefbadc0ffeedeadbeefbadc0ffee".to_owned()));
+                    assert!(content.is_empty());
+                    return;
+                },
+            }
+        }
+        panic!("Dave is not reached")
+    }
+}
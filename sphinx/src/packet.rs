@@ -0,0 +1,539 @@
+use secp256k1::{Secp256k1, PublicKey, SecretKey, Error as EcdsaError};
+use crypto::util::fixed_time_eq;
+
+use wire::{OnionBlob, OnionMessagePacket, WireError};
+
+use super::crypto_tools::{SharedSecret, KeyType, HMAC_SIZE};
+use super::crypto_tools::{generate_key, generate_cipher_stream, hmac, xor, ecdh, blinding_factor, blind};
+use super::hop::{HopPayload, HOP_PAYLOAD_SIZE};
+
+use std::{error, fmt};
+
+// ONION_VERSION is the version of the packet format this implementation
+// is able to create and process.
+pub const ONION_VERSION: u8 = 0;
+
+// NUM_MAX_HOPS is the maximum path length. This should be set to an
+// estimate of the upper limit of the diameter of the node graph.
+pub const NUM_MAX_HOPS: usize = 20;
+
+// HOP_DATA_SIZE is the fixed size of hop_data. BOLT 04 currently
+// specifies this to be 1 byte realm, 8 byte channel_id, 8 byte amount
+// to forward, 4 byte outgoing CLTV value, 12 bytes padding and 32 bytes
+// HMAC for a total of 65 bytes per hop.
+pub const HOP_DATA_SIZE: usize = HOP_PAYLOAD_SIZE + HMAC_SIZE;
+
+// ROUTING_INFO_SIZE is the fixed size of the the routing info. This
+// consists of a address_size byte address and a HMAC_SIZE byte HMAC for
+// each hop of the route, the first pair in clear text and the rest
+// encrypted.
+pub const ROUTING_INFO_SIZE: usize = NUM_MAX_HOPS * HOP_DATA_SIZE;
+
+// num_stream_bytes is the number of bytes produced by our CSPRG for the
+// key stream implementing our stream cipher to encrypt/decrypt the mix
+// header. The extra bytes are used to shift the routing info when a hop
+// peels its layer, the payload of the hop is never larger than the routing info.
+fn num_stream_bytes(routing_info_size: usize) -> usize {
+    routing_info_size * 2
+}
+
+#[derive(Debug)]
+pub enum OnionError {
+    InvalidVersion(u8),
+    InvalidHmac,
+    RouteLength(usize),
+    EmptyPath,
+    PayloadSize(usize),
+    PacketSize(usize),
+    UnreadableFailure,
+    InvalidEncryptedData,
+    Crypto(EcdsaError),
+    Wire(WireError),
+}
+
+impl error::Error for OnionError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        use self::OnionError::*;
+
+        match self {
+            &Crypto(ref e) => Some(e),
+            &Wire(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for OnionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::OnionError::*;
+
+        match self {
+            &InvalidVersion(ref version) => write!(f, "invalid onion version: {}", version),
+            &InvalidHmac => write!(f, "invalid onion hmac"),
+            &RouteLength(ref length) => write!(f, "invalid route length: {}", length),
+            &EmptyPath => write!(f, "the blinded path has no hops"),
+            &PayloadSize(ref size) => write!(f, "the payloads do not fit the routing info: {}", size),
+            &PacketSize(ref size) => write!(f, "invalid onion packet size: {}", size),
+            &UnreadableFailure => write!(f, "the failure cannot be decrypted by any hop"),
+            &InvalidEncryptedData => write!(f, "the data of the blinded hop cannot be decrypted or read"),
+            &Crypto(ref e) => write!(f, "crypto error: {}", e),
+            &Wire(ref e) => write!(f, "wire error: {}", e),
+        }
+    }
+}
+
+/// The onion packet as it is described in BOLT 04, it is carried
+/// by `UpdateAddHtlc` in the serialized form of `OnionBlob`.
+/// The routing info of the onion message may be of other size.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct OnionPacket {
+    version: u8,
+    ephemeral_key: PublicKey,
+    routing_info: Vec<u8>,
+    hmac: [u8; HMAC_SIZE],
+}
+
+/// The result of processing the onion packet by the hop.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub enum ProcessedPacket {
+    // the hop is the final destination of the payment
+    ExitNode(HopPayload),
+    // the hop should forward the packet to the next hop
+    MoreHops(HopPayload, OnionPacket),
+}
+
+impl OnionPacket {
+    /// Creates the onion packet for the given route, `hops_data` should contain
+    /// the payload for each hop of the route in the same order.
+    pub fn new(route: &[PublicKey], hops_data: &[HopPayload], session_key: SecretKey, associated_data: &[u8]) -> Result<Self, OnionError> {
+        if route.len() > NUM_MAX_HOPS {
+            return Err(OnionError::RouteLength(route.len()));
+        }
+
+        let payloads = hops_data.iter()
+            .map(HopPayload::to_bytes)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(OnionError::Wire)?;
+        OnionPacket::with_payloads(route, payloads.as_slice(), session_key, associated_data, ROUTING_INFO_SIZE)
+    }
+
+    // creates the packet from the serialized payloads, the routing info is of the given size
+    pub(crate) fn with_payloads(
+        route: &[PublicKey],
+        payloads: &[Vec<u8>],
+        session_key: SecretKey,
+        associated_data: &[u8],
+        routing_info_size: usize,
+    ) -> Result<Self, OnionError> {
+        use super::crypto_tools::generate_shared_secrets;
+
+        let num_hops = route.len();
+        if num_hops == 0 || num_hops != payloads.len() {
+            return Err(OnionError::RouteLength(num_hops));
+        }
+
+        let frame_sizes = payloads.iter()
+            .map(|payload| payload.len() + HMAC_SIZE)
+            .collect::<Vec<_>>();
+        let total_size = frame_sizes.iter().sum();
+        if total_size > routing_info_size {
+            return Err(OnionError::PayloadSize(total_size));
+        }
+
+        let shared_secrets = generate_shared_secrets(route, &session_key)
+            .map_err(OnionError::Crypto)?;
+
+        // Generate the padding, called "filler strings" in the paper.
+        let filler = generate_header_padding(&shared_secrets[..num_hops - 1], &frame_sizes[..num_hops - 1], routing_info_size);
+
+        // Allocate zero'd out byte slices to store the final mix header
+        // packet and the hmac for each hop.
+        let mut mix_header = vec![0; routing_info_size];
+        let mut next_hmac = [0; HMAC_SIZE];
+
+        // Now we compute the routing information for each hop, along with
+        // a MAC of the routing info using the shared key for that hop.
+        for i in (0..num_hops).rev() {
+            let rho_key = generate_key(KeyType::Rho, &shared_secrets[i]);
+            let mu_key = generate_key(KeyType::Mu, &shared_secrets[i]);
+
+            // Shift and obfuscate routing info.
+            let stream = generate_cipher_stream(&rho_key, routing_info_size);
+            mix_header.rotate_right(frame_sizes[i]);
+            {
+                let (payload, hmac_slot) = mix_header[..frame_sizes[i]].split_at_mut(payloads[i].len());
+                payload.copy_from_slice(payloads[i].as_slice());
+                hmac_slot.copy_from_slice(&next_hmac[..]);
+            }
+            xor(mix_header.as_mut_slice(), stream.as_slice());
+
+            // These need to be overwritten, so every node generates a
+            // correct padding.
+            if i == num_hops - 1 {
+                let offset = routing_info_size - filler.len();
+                mix_header[offset..].copy_from_slice(filler.as_slice());
+            }
+
+            next_hmac = hmac(&mu_key[..], &[mix_header.as_slice(), associated_data]);
+        }
+
+        let context = Secp256k1::new();
+        Ok(OnionPacket {
+            version: ONION_VERSION,
+            ephemeral_key: PublicKey::from_secret_key(&context, &session_key).map_err(OnionError::Crypto)?,
+            routing_info: mix_header,
+            hmac: next_hmac,
+        })
+    }
+
+    /// Peels one layer of the onion using the secret key of the node.
+    /// Returns the result of processing and the secret shared with the sender,
+    /// the latter is needed to report a failure back to the sender.
+    pub fn process(self, associated_data: &[u8], node_secret: &SecretKey) -> Result<(ProcessedPacket, SharedSecret), OnionError> {
+        let (hop_data, next_packet, shared_secret) = self.peel(associated_data, node_secret, HopPayload::from_bytes)?;
+        let processed = match next_packet {
+            None => ProcessedPacket::ExitNode(hop_data),
+            Some(next_packet) => ProcessedPacket::MoreHops(hop_data, next_packet),
+        };
+        Ok((processed, shared_secret))
+    }
+
+    // peels one layer, `read_payload` parses the payload of the hop and returns its size,
+    // there is no next packet if the hop is the final destination
+    pub(crate) fn peel<P, F>(
+        self,
+        associated_data: &[u8],
+        node_secret: &SecretKey,
+        read_payload: F,
+    ) -> Result<(P, Option<OnionPacket>, SharedSecret), OnionError>
+    where
+        F: FnOnce(&[u8]) -> Result<(P, usize), WireError>,
+    {
+        if self.version != ONION_VERSION {
+            return Err(OnionError::InvalidVersion(self.version));
+        }
+
+        let shared_secret = ecdh(&self.ephemeral_key, node_secret).map_err(OnionError::Crypto)?;
+
+        // Using the derived shared secret, ensure the integrity of the routing
+        // information by checking the attached MAC without leaking timing
+        // information.
+        let mu_key = generate_key(KeyType::Mu, &shared_secret);
+        let calculated_hmac = hmac(&mu_key[..], &[self.routing_info.as_slice(), associated_data]);
+        if !fixed_time_eq(&calculated_hmac[..], &self.hmac[..]) {
+            return Err(OnionError::InvalidHmac);
+        }
+
+        // Attach the padding zeroes in order to properly strip an
+        // encryption layer off the routing info revealing the routing
+        // information for the next hop.
+        let routing_info_size = self.routing_info.len();
+        let rho_key = generate_key(KeyType::Rho, &shared_secret);
+        let stream = generate_cipher_stream(&rho_key, num_stream_bytes(routing_info_size));
+        let mut header_with_padding = self.routing_info;
+        header_with_padding.resize(num_stream_bytes(routing_info_size), 0);
+        xor(header_with_padding.as_mut_slice(), stream.as_slice());
+
+        let (hop_data, payload_size) = read_payload(&header_with_padding[..routing_info_size])
+            .map_err(OnionError::Wire)?;
+        let frame_size = payload_size + HMAC_SIZE;
+        if frame_size > routing_info_size {
+            return Err(OnionError::PayloadSize(frame_size));
+        }
+        let mut next_hmac = [0; HMAC_SIZE];
+        next_hmac.copy_from_slice(&header_with_padding[payload_size..frame_size]);
+
+        // The zero hmac signals that the hop is the final destination.
+        if next_hmac == [0; HMAC_SIZE] {
+            return Ok((hop_data, None, shared_secret));
+        }
+
+        // Randomize the DH group element for the next hop using the
+        // deterministic blinding factor.
+        let factor = blinding_factor(&self.ephemeral_key, &shared_secret).map_err(OnionError::Crypto)?;
+        let next_ephemeral_key = blind(&self.ephemeral_key, &factor).map_err(OnionError::Crypto)?;
+
+        let mut routing_info = header_with_padding.split_off(frame_size);
+        routing_info.truncate(routing_info_size);
+        let next_packet = OnionPacket {
+            version: ONION_VERSION,
+            ephemeral_key: next_ephemeral_key,
+            routing_info: routing_info,
+            hmac: next_hmac,
+        };
+
+        Ok((hop_data, Some(next_packet), shared_secret))
+    }
+
+    pub fn ephemeral_key(&self) -> &PublicKey {
+        &self.ephemeral_key
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(34 + self.routing_info.len() + HMAC_SIZE);
+        data.push(self.version);
+        data.extend_from_slice(&self.ephemeral_key.serialize()[..]);
+        data.extend_from_slice(self.routing_info.as_slice());
+        data.extend_from_slice(&self.hmac[..]);
+        data
+    }
+
+    // the size of the routing info is the rest of the data
+    fn from_bytes(data: &[u8]) -> Result<Self, OnionError> {
+        if data.len() <= 34 + HMAC_SIZE {
+            return Err(OnionError::PacketSize(data.len()));
+        }
+        let hmac_offset = data.len() - HMAC_SIZE;
+        let ephemeral_key = PublicKey::from_slice(&Secp256k1::new(), &data[1..34])
+            .map_err(OnionError::Crypto)?;
+        let mut packet_hmac = [0; HMAC_SIZE];
+        packet_hmac.copy_from_slice(&data[hmac_offset..]);
+
+        Ok(OnionPacket {
+            version: data[0],
+            ephemeral_key: ephemeral_key,
+            routing_info: data[34..hmac_offset].to_vec(),
+            hmac: packet_hmac,
+        })
+    }
+
+    /// Fails if the routing info is not of the size `UpdateAddHtlc` carries,
+    /// the packet of the onion message goes to `to_message_packet`.
+    pub fn to_blob(&self) -> Result<OnionBlob, OnionError> {
+        OnionBlob::from_vec(self.to_bytes())
+            .map_err(|data| OnionError::PacketSize(data.len()))
+    }
+
+    pub fn from_blob(blob: &OnionBlob) -> Result<Self, OnionError> {
+        OnionPacket::from_bytes(blob.as_ref())
+    }
+
+    pub fn to_message_packet(&self) -> OnionMessagePacket {
+        OnionMessagePacket::new(self.to_bytes())
+    }
+
+    /// The size of the routing info is the rest of the packet.
+    pub fn from_message_packet(packet: &OnionMessagePacket) -> Result<Self, OnionError> {
+        OnionPacket::from_bytes(packet.as_ref())
+    }
+}
+
+// generate_header_padding derives the bytes that are appended to the end
+// of the routing info by each hop while it is peeling its layer, the last hop
+// should see the same bytes in order to check the hmac.
+fn generate_header_padding(shared_secrets: &[SharedSecret], frame_sizes: &[usize], routing_info_size: usize) -> Vec<u8> {
+    let mut filler = Vec::with_capacity(frame_sizes.iter().sum());
+
+    for (shared_secret, &frame_size) in shared_secrets.iter().zip(frame_sizes.iter()) {
+        filler.resize(filler.len() + frame_size, 0);
+
+        let rho_key = generate_key(KeyType::Rho, shared_secret);
+        let stream = generate_cipher_stream(&rho_key, num_stream_bytes(routing_info_size));
+        let end = routing_info_size + frame_size;
+        let start = end - filler.len();
+        xor(filler.as_mut_slice(), &stream[start..end]);
+    }
+
+    filler
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use wire::ONION_PACKET_SIZE;
+    use hex;
+
+    // BOLT 04 test vector
+    const PACKET_HEX: &str = "\
+0002eec7245d6b7d2ccb30380bfbe2a3648cd7a942653f5aa340edcea1f283686619e5f14350c2a76fc232b5e46d421e\
+9615471ab9e0bc887beff8c95fdb878f7b3a71da571226458c510bbadd1276f045c21c520a07d35da256ef75b4367962\
+437b0dd10f7d61ab590531cf08000178a333a347f8b4072e216400406bdf3bf038659793a86cae5f52d32f3438527b47\
+a1cfc54285a8afec3a4c9f3323db0c946f5d4cb2ce721caad69320c3a469a202f3e468c67eaf7a7cda226d0fd32f7b48\
+084dca885d15222e60826d5d971f64172d98e0760154400958f00e86697aa1aa9d41bee8119a1ec866abe044a9ad6357\
+78ba61fc0776dc832b39451bd5d35072d2269cf9b040d6ba38b54ec35f81d7fc67678c3be47274f3c4cc472aff005c34\
+69eb3bc140769ed4c7f0218ff8c6c7dd7221d189c65b3b9aaa71a01484b122846c7c7b57e02e679ea8469b70e14fe4f7\
+0fee4d87b910cf144be6fe48eef24da475c0b0bcc6565ae82cd3f4e3b24c76eaa5616c6111343306ab35c1fe5ca4a77c\
+0e314ed7dba39d6f1e0de791719c241a939cc493bea2bae1c1e932679ea94d29084278513c77b899cc98059d06a27d17\
+1b0dbdf6bee13ddc4fc17a0c4d2827d488436b57baa167544138ca2e64a11b43ac8a06cd0c2fba2d4d900ed2d9205305\
+e2d7383cc98dacb078133de5f6fb6bed2ef26ba92cea28aafc3b9948dd9ae5559e8bd6920b8cea462aa445ca6a95e0e7\
+ba52961b181c79e73bd581821df2b10173727a810c92b83b5ba4a0403eb710d2ca10689a35bec6c3a708e9e92f7d78ff\
+3c5d9989574b00c6736f84c199256e76e19e78f0c98a9d580b4a658c84fc8f2096c2fbea8f5f8c59d0fdacb3be2802ef\
+802abbecb3aba4acaac69a0e965abd8981e9896b1f6ef9d60f7a164b371af869fd0e48073742825e9434fc54da837e12\
+0266d53302954843538ea7c6c3dbfb4ff3b2fdbe244437f2a153ccf7bdb4c92aa08102d4f3cff2ae5ef86fab4653595e\
+6a5837fa2f3e29f27a9cde5966843fb847a4a61f1e76c281fe8bb2b0a181d096100db5a1a5ce7a910238251a43ca5567\
+12eaadea167fb4d7d75825e440f3ecd782036d7574df8bceacb397abefc5f5254d2722215c53ff54af8299aaaad642c6\
+d72a14d27882d9bbd539e1cc7a527526ba89b8c037ad09120e98ab042d3e8652b31ae0e478516bfaf88efca9f3676ffe\
+99d2819dcaeb7610a626695f53117665d267d3f7abebd6bbd6733f645c72c389f03855bdf1e4b8075b516569b118233a\
+0f0971d24b83113c0b096f5216a207ca99a7cddc81c130923fe3d91e7508c9ac5f2e914ff5dccab9e558566fa14efb34\
+ac98d878580814b94b73acbfde9072f30b881f7f0fff42d4045d1ace6322d86a97d164aa84d93a60498065cc7c20e636\
+f5862dc81531a88c60305a2e59a985be327a6902e4bed986dbf4a0b50c217af0ea7fdf9ab37f9ea1a1aaa72f54cf4015\
+4ea9b269f1a7c09f9f43245109431a175d50e2db0132337baa0ef97eed0fcf20489da36b79a1172faccc2f7ded7c60e0\
+0694282d93359c4682135642bc81f433574aa8ef0c97b4ade7ca372c5ffc23c7eddd839bab4e0f14d6df15c9dbeab176\
+bec8b5701cf054eb3072f6dadc98f88819042bf10c407516ee58bce33fbe3b3d86a54255e577db4598e30a135361528c\
+101683a5fcde7e8ba53f3456254be8f45fe3a56120ae96ea3773631fcb3873aa3abd91bcff00bd38bd43697a2e789e00\
+da6077482e7b1b1a677b5afae4c54e6cbdf7377b694eb7d7a5b913476a5be923322d3de06060fd5e819635232a2cf4f0\
+731da13b8546d1d6d4f8d75b9fce6c2341a71b0ea6f780df54bfdb0dd5cd9855179f602f917265f21f9190c70217774a\
+6fbaaa7d63ad64199f4664813b955cff954949076dcf";
+
+    fn node_secret(index: u8) -> SecretKey {
+        SecretKey::from_slice(&Secp256k1::new(), &[0x41 + index; 32]).unwrap()
+    }
+
+    fn route() -> Vec<PublicKey> {
+        let context = Secp256k1::new();
+        (0..5)
+            .map(|i| PublicKey::from_secret_key(&context, &node_secret(i)).unwrap())
+            .collect()
+    }
+
+    fn hops_data() -> Vec<HopPayload> {
+        use super::super::hop::HopData;
+
+        (0..5u8)
+            .map(|i| HopData::new((0x0101010101010101u64 * i as u64).into(), (i as u64).into(), i as u32).into())
+            .collect()
+    }
+
+    fn packet() -> OnionPacket {
+        let session_key = SecretKey::from_slice(&Secp256k1::new(), &[0x41; 32]).unwrap();
+        OnionPacket::new(&route(), &hops_data(), session_key, &[0x42; 32]).unwrap()
+    }
+
+    #[test]
+    fn route_pubkeys() {
+        let expected = [
+            "02eec7245d6b7d2ccb30380bfbe2a3648cd7a942653f5aa340edcea1f283686619",
+            "0324653eac434488002cc06bbfb7f10fe18991e35f9fe4302dbea6d2353dc0ab1c",
+            "027f31ebc5462c1fdce1b737ecff52d37d75dea43ce11c74d25aa297165faa2007",
+            "032c0b7cf95324a07d05398b240174dc0c2be444d96b159aa6c7f7b1e668680991",
+            "02edabbd16b41c8371b92ef2f04c1185b4f03b6dcd52ba9b78d9d7c89c8f221145",
+        ];
+        for (pk, expected) in route().iter().zip(expected.iter()) {
+            assert_eq!(hex::encode(&pk.serialize()[..]), *expected);
+        }
+    }
+
+    #[test]
+    fn shared_secrets() {
+        use super::super::crypto_tools::generate_shared_secrets;
+
+        let expected = [
+            "53eb63ea8a3fec3b3cd433b85cd62a4b145e1dda09391b348c4e1cd36a03ea66",
+            "a6519e98832a0b179f62123b3567c106db99ee37bef036e783263602f3488fae",
+            "3a6b412548762f0dbccce5c7ae7bb8147d1caf9b5471c34120b30bc9c04891cc",
+            "21e13c2d7cfe7e18836df50872466117a295783ab8aab0e7ecc8c725503ad02d",
+            "b5756b9b542727dbafc6765a49488b023a725d631af688fc031217e90770c328",
+        ];
+        let session_key = SecretKey::from_slice(&Secp256k1::new(), &[0x41; 32]).unwrap();
+        let shared_secrets = generate_shared_secrets(&route(), &session_key).unwrap();
+        for (secret, expected) in shared_secrets.iter().zip(expected.iter()) {
+            assert_eq!(hex::encode(secret.as_ref()), *expected);
+        }
+    }
+
+    #[test]
+    fn new_packet() {
+        let blob = packet().to_blob().unwrap();
+        assert_eq!(hex::encode(blob.as_ref()), PACKET_HEX);
+    }
+
+    #[test]
+    fn blob_round_trip() {
+        let packet = packet();
+        let restored = OnionPacket::from_blob(&packet.to_blob().unwrap()).unwrap();
+        assert_eq!(restored, packet);
+    }
+
+    #[test]
+    fn process() {
+        let hops_data = hops_data();
+        let mut packet = packet();
+        for i in 0..5 {
+            let (processed, _) = packet.process(&[0x42; 32], &node_secret(i as u8)).unwrap();
+            match processed {
+                ProcessedPacket::MoreHops(hop_data, next) => {
+                    assert!(i < 4);
+                    assert_eq!(hop_data, hops_data[i]);
+                    packet = next;
+                },
+                ProcessedPacket::ExitNode(hop_data) => {
+                    assert_eq!(i, 4);
+                    assert_eq!(hop_data, hops_data[i]);
+                    return;
+                },
+            }
+        }
+        panic!("the exit node is not reached")
+    }
+
+    #[test]
+    fn process_mixed_payloads() {
+        let mut hops_data = hops_data();
+        hops_data[1] = HopPayload::tlv(Some(0x0202020202020202u64.into()), 1u64.into(), 1);
+        hops_data[3] = HopPayload::tlv(Some(0x0404040404040404u64.into()), 3u64.into(), 3);
+        hops_data[4] = HopPayload::tlv(None, 4u64.into(), 4);
+
+        let session_key = SecretKey::from_slice(&Secp256k1::new(), &[0x41; 32]).unwrap();
+        let mut packet = OnionPacket::new(&route(), &hops_data, session_key, &[0x42; 32]).unwrap();
+        for i in 0..5 {
+            let restored = OnionPacket::from_blob(&packet.to_blob().unwrap()).unwrap();
+            let (processed, _) = restored.process(&[0x42; 32], &node_secret(i as u8)).unwrap();
+            match processed {
+                ProcessedPacket::MoreHops(hop_data, next) => {
+                    assert!(i < 4);
+                    assert_eq!(hop_data, hops_data[i]);
+                    packet = next;
+                },
+                ProcessedPacket::ExitNode(hop_data) => {
+                    assert_eq!(i, 4);
+                    assert_eq!(hop_data, hops_data[i]);
+                    assert_eq!(hop_data.forward_amount(), Some(4u64.into()));
+                    return;
+                },
+            }
+        }
+        panic!("the exit node is not reached")
+    }
+
+    #[test]
+    fn payload_too_large() {
+        use wire::TlvStream;
+
+        let mut stream = TlvStream::new();
+        stream.insert(1, vec![0; ROUTING_INFO_SIZE]);
+        let session_key = SecretKey::from_slice(&Secp256k1::new(), &[0x41; 32]).unwrap();
+        match OnionPacket::new(&route()[..1], &[HopPayload::Tlv(stream)], session_key, &[]) {
+            Err(OnionError::PayloadSize(_)) => (),
+            r => panic!("unexpected result: {:?}", r),
+        }
+    }
+
+    #[test]
+    fn invalid_hmac() {
+        let blob = packet().to_blob().unwrap();
+        let mut data = [0; ONION_PACKET_SIZE];
+        data.copy_from_slice(blob.as_ref());
+        data[100] ^= 1;
+        let packet = OnionPacket::from_blob(&OnionBlob::new(data)).unwrap();
+        match packet.process(&[0x42; 32], &node_secret(0)) {
+            Err(OnionError::InvalidHmac) => (),
+            r => panic!("unexpected result: {:?}", r),
+        }
+
+        // the wrong associated data
+        match self::packet().process(&[0x43; 32], &node_secret(0)) {
+            Err(OnionError::InvalidHmac) => (),
+            r => panic!("unexpected result: {:?}", r),
+        }
+    }
+
+    #[test]
+    fn invalid_version() {
+        let mut packet = packet();
+        packet.version = 1;
+        match packet.process(&[0x42; 32], &node_secret(0)) {
+            Err(OnionError::InvalidVersion(1)) => (),
+            r => panic!("unexpected result: {:?}", r),
+        }
+    }
+}
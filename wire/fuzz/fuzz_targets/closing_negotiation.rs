@@ -0,0 +1,10 @@
+#![no_main]
+
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate wire;
+extern crate wire_fuzz;
+
+fuzz_target!(|data: &[u8]| {
+    wire_fuzz::round_trip::<wire::ClosingNegotiation>(data);
+});
@@ -0,0 +1,118 @@
+#![forbid(unsafe_code)]
+
+//! The checks shared by the fuzz targets, each target feeds
+//! arbitrary bytes to the decoder of a single message type,
+//! the `message` target feeds them to the decoder of `Message`.
+
+extern crate wire;
+extern crate serde;
+
+use wire::*;
+
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+
+use std::fmt::Debug;
+
+/// Decodes the data, the decoder may reject it, but should never panic.
+/// If the data is accepted, the value is encoded and decoded again,
+/// the result should be the same value and the same encoding.
+/// Returns whether the data is accepted.
+pub fn round_trip<T>(data: &[u8]) -> bool where T: Serialize + DeserializeOwned + Eq + Debug {
+    let value: T = match BinarySD::deserialize(data) {
+        Ok(value) => value,
+        Err(_) => return false,
+    };
+
+    let mut encoded = Vec::new();
+    BinarySD::serialize(&mut encoded, &value)
+        .expect("the decoded value should be encoded");
+    let restored: T = BinarySD::deserialize(encoded.as_slice())
+        .expect("the encoded value should be decoded");
+    assert_eq!(restored, value);
+
+    let mut encoded_again = Vec::new();
+    BinarySD::serialize(&mut encoded_again, &restored).unwrap();
+    assert_eq!(encoded_again, encoded);
+
+    true
+}
+
+macro_rules! targets {
+    ($($name:ident: $t:ty),*) => {
+        /// The name of each fuzz target and its check.
+        pub const TARGETS: &'static [(&'static str, fn(&[u8]) -> bool)] = &[
+            $((stringify!($name), round_trip::<$t> as fn(&[u8]) -> bool),)*
+        ];
+    }
+}
+
+targets! {
+    warning: Warning,
+    init: Init,
+    error: Error,
+    ping: Ping,
+    pong: Pong,
+    open_channel: OpenChannel,
+    accept_channel: AcceptChannel,
+    funding_created: FundingCreated,
+    funding_signed: FundingSigned,
+    funding_locked: FundingLocked,
+    shutdown_channel: ShutdownChannel,
+    closing_negotiation: ClosingNegotiation,
+    update_add_htlc: UpdateAddHtlc,
+    update_fulfill_htlc: UpdateFulfillHtlc,
+    update_fail_htlc: UpdateFailHtlc,
+    update_fail_malformed_htlc: UpdateFailMalformedHtlc,
+    commitment_signed: CommitmentSigned,
+    revoke_and_ack: RevokeAndAck,
+    update_fee: UpdateFee,
+    reestablish_channel: ReestablishChannel,
+    announcement_channel: AnnouncementChannel,
+    announcement_node: AnnouncementNode,
+    update_channel: UpdateChannel,
+    announce_signatures: AnnounceSignatures,
+    query_short_channel_ids: QueryShortChannelIds,
+    reply_short_channel_ids_end: ReplyShortChannelIdsEnd,
+    query_channel_range: QueryChannelRange,
+    reply_channel_range: ReplyChannelRange,
+    gossip_timestamp_range: GossipTimestampRange,
+    onion_message: OnionMessage,
+    message: Message
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TARGETS;
+
+    use std::fs;
+    use std::path::Path;
+
+    // replays the seed corpus without libfuzzer
+    #[test]
+    fn seeds() {
+        let seeds = Path::new(env!("CARGO_MANIFEST_DIR")).join("seeds");
+        for &(name, check) in TARGETS {
+            let directory = seeds.join(name);
+            if !directory.exists() {
+                continue;
+            }
+            for entry in fs::read_dir(directory).unwrap() {
+                let path = entry.unwrap().path();
+                let data = fs::read(&path).unwrap();
+                assert!(check(data.as_slice()), "seed {:?} is rejected", path);
+            }
+        }
+    }
+
+    #[test]
+    fn garbage() {
+        for &(_, check) in TARGETS {
+            check(&[]);
+            check(&[0xff; 64]);
+            for length in 0..64 {
+                check(&[0xfd; 512][..length]);
+            }
+        }
+    }
+}
@@ -30,6 +30,11 @@ impl<T> Serialize for SerdeVec<T> where T: PackSized + Serialize {
         use self::ser::SerializeTuple;
 
         let &SerdeVec(ref data) = self;
+        // the size is meaningless in human readable form
+        if serializer.is_human_readable() {
+            return data.serialize(serializer);
+        }
+
         let mut tuple = serializer.serialize_tuple(1 + data.len())?;
         let size_in_bytes = if T::SIZE == 0 {
             data.iter()
@@ -52,6 +57,10 @@ impl<'de, T> Deserialize<'de> for SerdeVec<T> where T: PackSized + de::Deseriali
         use std::marker::PhantomData;
         use self::de::SeqAccess;
 
+        if deserializer.is_human_readable() {
+            return Vec::<T>::deserialize(deserializer).map(SerdeVec);
+        }
+
         struct Visitor<T> where T: PackSized + de::DeserializeOwned {
             phantom_data: PhantomData<T>,
         }
@@ -73,12 +82,8 @@ impl<'de, T> Deserialize<'de> for SerdeVec<T> where T: PackSized + de::Deseriali
                     if size == 0 { break; }
                     let element: T = seq.next_element()?
                         .ok_or(<A::Error as de::Error>::custom(format!("cannot read T")))?;
-                    if size == element.pack_size() {
-                        break;
-                    } else {
-                        if size < element.pack_size() {
-                            return Err(<A::Error as de::Error>::custom(format!("cannot assemble integer amount of T")))
-                        }
+                    if size < element.pack_size() {
+                        return Err(<A::Error as de::Error>::custom(format!("cannot assemble integer amount of T")))
                     }
                     size -= element.pack_size();
                     data.push(element);
@@ -97,15 +102,29 @@ impl<'de, T> Deserialize<'de> for SerdeVec<T> where T: PackSized + de::Deseriali
 /// the underlying data is uncompressed and deserialized into rust type,
 /// but serialization / deserialization will read / write zlib compressed data
 /// this is exactly desired by lnd specification
+/// the compressed data is the items one by one, without the size
 #[derive(Eq, PartialEq, Debug)]
 pub struct UncompressedData<T>(pub SerdeVec<T>) where T: PackSized;
 
+impl<T> UncompressedData<T> where T: PackSized {
+    // MAX_UNCOMPRESSED_SIZE protects against zip bombs, the peer has no reason
+    // to compress more than 1 MiB into the single message
+    pub const MAX_UNCOMPRESSED_SIZE: usize = 1024 * 1024;
+}
+
 impl<T> Serialize for UncompressedData<T> where T: PackSized + Serialize {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error> where S: Serializer {
-        let mut bytes = Vec::<u8>::new();
         let &UncompressedData(ref data) = self;
-        BinarySD::serialize(&mut bytes, data)
-            .map_err(|e| <S::Error as ser::Error>::custom(format!("serialize error: {:?}", e)))?;
+        if serializer.is_human_readable() {
+            return data.serialize(serializer);
+        }
+
+        let &SerdeVec(ref data) = data;
+        let mut bytes = Vec::<u8>::new();
+        for item in data {
+            BinarySD::serialize(&mut bytes, item)
+                .map_err(|e| <S::Error as ser::Error>::custom(format!("serialize error: {:?}", e)))?;
+        }
         let mut encoder = read::ZlibEncoder::new(bytes.as_slice(), Compression::fast());
         let mut compressed_bytes = Vec::<u8>::new();
         let _ = Read::read_to_end(&mut encoder, &mut compressed_bytes)
@@ -119,6 +138,10 @@ impl<'de, T> Deserialize<'de> for UncompressedData<T> where T: PackSized + de::D
         use std::fmt;
         use std::marker::PhantomData;
 
+        if deserializer.is_human_readable() {
+            return SerdeVec::<T>::deserialize(deserializer).map(UncompressedData);
+        }
+
         struct Visitor<T> where T: de::DeserializeOwned {
             phantom_data: PhantomData<T>,
         }
@@ -131,12 +154,28 @@ impl<'de, T> Deserialize<'de> for UncompressedData<T> where T: PackSized + de::D
             }
 
             fn visit_bytes<E>(self, v: &[u8]) -> Result<Self::Value, E> where E: de::Error, {
-                let mut decoder = read::ZlibDecoder::new(v);
+                let limit = UncompressedData::<T>::MAX_UNCOMPRESSED_SIZE;
+                let decoder = read::ZlibDecoder::new(v);
                 let mut decompressed_bytes = Vec::<u8>::new();
-                let _ = Read::read_to_end(&mut decoder, &mut decompressed_bytes)
+                // read one byte more than the limit to detect the excess
+                let _ = Read::read_to_end(&mut decoder.take((limit + 1) as u64), &mut decompressed_bytes)
                     .map_err(|e| E::custom(format!("decompression error: {:?}", e)))?;
-                BinarySD::deserialize(decompressed_bytes.as_slice())
-                    .map_err(|e| E::custom(format!("deserialize error: {:?}", e)))
+                if decompressed_bytes.len() > limit {
+                    return Err(E::custom(format!("decompressed data exceeds the limit: {}", limit)));
+                }
+
+                let mut cursor = decompressed_bytes.as_slice();
+                let mut data = Vec::new();
+                while !cursor.is_empty() {
+                    let item = BinarySD::deserialize(&mut cursor)
+                        .map_err(|e| E::custom(format!("deserialize error: {:?}", e)))?;
+                    data.push(item);
+                }
+                Ok(UncompressedData(SerdeVec(data)))
+            }
+
+            fn visit_byte_buf<E>(self, v: Vec<u8>) -> Result<Self::Value, E> where E: de::Error, {
+                self.visit_bytes(v.as_slice())
             }
         }
 
@@ -148,9 +187,49 @@ impl<'de, T> Deserialize<'de> for UncompressedData<T> where T: PackSized + de::D
 
 #[cfg(test)]
 mod test {
+    use super::*;
+
+    #[derive(Serialize, Deserialize, Eq, PartialEq, Debug)]
+    struct Item(u32);
+
+    impl PackSized for Item {
+        const SIZE: usize = 4;
+    }
+
+    #[test]
+    fn serde_vec() {
+        let vec = SerdeVec(vec![Item(1), Item(2), Item(3)]);
+        let mut data = Vec::new();
+        BinarySD::serialize(&mut data, &vec).unwrap();
+        assert_eq!(data, vec![0, 12, 0, 0, 0, 1, 0, 0, 0, 2, 0, 0, 0, 3]);
+
+        let restored: SerdeVec<Item> = BinarySD::deserialize(&data[..]).unwrap();
+        assert_eq!(restored, vec);
+    }
+
     #[test]
     fn compression_test() {
-        // TODO:
-        assert!(true)
+        let uncompressed = UncompressedData(SerdeVec((0..1000).map(Item).collect()));
+        let mut data = Vec::new();
+        BinarySD::serialize(&mut data, &uncompressed).unwrap();
+        assert!(data.len() < 4000);
+
+        let restored: UncompressedData<Item> = BinarySD::deserialize(&data[..]).unwrap();
+        assert_eq!(restored, uncompressed);
+    }
+
+    #[test]
+    fn zip_bomb() {
+        use flate2::write::ZlibEncoder;
+        use std::io::Write;
+
+        let size = UncompressedData::<Item>::MAX_UNCOMPRESSED_SIZE + 4;
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::best());
+        encoder.write_all(vec![0; size].as_slice()).unwrap();
+        let compressed = encoder.finish().unwrap();
+
+        let mut data = Vec::new();
+        BinarySD::serialize(&mut data, &compressed).unwrap();
+        assert!(BinarySD::deserialize::<UncompressedData<Item>, _>(&data[..]).is_err());
     }
 }
@@ -0,0 +1,154 @@
+//! Helpers for the human readable (JSON) representation of the messages.
+//! Each implementation checks `is_human_readable` and falls back
+//! to the binary representation, so `BinarySD` is not affected.
+
+/// The bytes rendered as a hex string.
+pub trait HexData: Sized {
+    fn as_bytes(&self) -> &[u8];
+
+    fn from_bytes(data: &[u8]) -> Option<Self>;
+}
+
+impl HexData for Vec<u8> {
+    fn as_bytes(&self) -> &[u8] {
+        self.as_slice()
+    }
+
+    fn from_bytes(data: &[u8]) -> Option<Self> {
+        Some(data.to_vec())
+    }
+}
+
+macro_rules! hex_data_array {
+    ($($size:expr),*) => {
+        $(
+            impl HexData for [u8; $size] {
+                fn as_bytes(&self) -> &[u8] {
+                    &self[..]
+                }
+
+                fn from_bytes(data: &[u8]) -> Option<Self> {
+                    if data.len() == $size {
+                        let mut array = [0; $size];
+                        array.copy_from_slice(data);
+                        Some(array)
+                    } else {
+                        None
+                    }
+                }
+            }
+        )*
+    }
+}
+
+hex_data_array!(3, 4, 10, 16, 32);
+
+/// Use it as `#[serde(with = "::human_readable::hex")]`.
+pub mod hex {
+    use super::HexData;
+
+    use serde::Serialize;
+    use serde::Serializer;
+    use serde::Deserialize;
+    use serde::Deserializer;
+    use serde::de::Error;
+
+    use hex::encode;
+    use hex::decode;
+
+    pub fn serialize<T, S>(value: &T, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        T: HexData + Serialize,
+        S: Serializer,
+    {
+        if serializer.is_human_readable() {
+            serializer.serialize_str(encode(value.as_bytes()).as_str())
+        } else {
+            value.serialize(serializer)
+        }
+    }
+
+    pub fn deserialize<'de, T, D>(deserializer: D) -> Result<T, D::Error>
+    where
+        T: HexData + Deserialize<'de>,
+        D: Deserializer<'de>,
+    {
+        if deserializer.is_human_readable() {
+            let string = String::deserialize(deserializer)?;
+            let data = decode(string.as_str()).map_err(D::Error::custom)?;
+            T::from_bytes(data.as_slice())
+                .ok_or(D::Error::custom(format!("wrong length: {}", data.len())))
+        } else {
+            T::deserialize(deserializer)
+        }
+    }
+}
+
+// BASE32_ALPHABET is RFC 4648 alphabet in lower case, as it used in onion addresses
+const BASE32_ALPHABET: &'static [u8; 32] = b"abcdefghijklmnopqrstuvwxyz234567";
+
+/// Encodes the data in base32 without padding.
+pub fn base32_encode(data: &[u8]) -> String {
+    let mut result = String::with_capacity((data.len() * 8 + 4) / 5);
+    let (mut buffer, mut bits) = (0u16, 0);
+    for &byte in data {
+        buffer = (buffer << 8) | (byte as u16);
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            result.push(BASE32_ALPHABET[((buffer >> bits) & 0x1f) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        result.push(BASE32_ALPHABET[((buffer << (5 - bits)) & 0x1f) as usize] as char);
+    }
+    result
+}
+
+/// Decodes base32 without padding, case insensitive.
+pub fn base32_decode(data: &str) -> Option<Vec<u8>> {
+    let mut result = Vec::with_capacity(data.len() * 5 / 8);
+    let (mut buffer, mut bits) = (0u16, 0);
+    for c in data.bytes() {
+        let c = c.to_ascii_lowercase();
+        let value = BASE32_ALPHABET.iter().position(|&a| a == c)? as u16;
+        buffer = (buffer << 5) | value;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            result.push((buffer >> bits) as u8);
+        }
+    }
+    // the remaining bits should be zero padding
+    if bits >= 5 || buffer & ((1 << bits) - 1) != 0 {
+        return None;
+    }
+    Some(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn base32() {
+        let cases = vec![
+            ("", ""),
+            ("f", "my"),
+            ("fo", "mzxq"),
+            ("foo", "mzxw6"),
+            ("foob", "mzxw6yq"),
+            ("fooba", "mzxw6ytb"),
+            ("foobar", "mzxw6ytboi"),
+        ];
+
+        for (data, encoded) in cases {
+            assert_eq!(base32_encode(data.as_bytes()), encoded);
+            assert_eq!(base32_decode(encoded), Some(data.as_bytes().to_vec()));
+            assert_eq!(base32_decode(encoded.to_uppercase().as_str()), Some(data.as_bytes().to_vec()));
+        }
+
+        assert_eq!(base32_decode("mzxw6yr"), None);
+        assert_eq!(base32_decode("mzxw1"), None);
+    }
+}
@@ -2,6 +2,7 @@
 #![allow(non_shorthand_field_patterns)]
 
 extern crate serde;
+extern crate serde_json;
 #[macro_use]
 extern crate serde_derive;
 extern crate bincode;
@@ -21,15 +22,20 @@ extern crate tokio;
 mod message;
 
 mod serde_facade;
+mod human_readable;
 mod compression_facade;
 mod message_processor;
+mod node_signer;
 
 pub use self::message::*;
 pub use self::message::types::*;
 
 pub use self::serde_facade::BinarySD;
 pub use self::serde_facade::WireError;
+pub use self::serde_facade::JsonSD;
+pub use self::serde_facade::JsonError;
 pub use self::compression_facade::UncompressedData;
 pub use self::compression_facade::SerdeVec;
 pub use self::compression_facade::PackSized;
 pub use self::message_processor::*;
+pub use self::node_signer::*;
@@ -1,16 +1,94 @@
 use super::ChannelId;
 use super::Satoshi;
 use super::Signature;
+use super::TlvStream;
+use super::TlvKnownTypes;
+
+use ::WireError;
 
 #[derive(Serialize, Deserialize, Eq, PartialEq, Debug)]
 pub struct ShutdownChannel {
-    channel_id: ChannelId,
-    script: Vec<u8>,
+    pub channel_id: ChannelId,
+    #[serde(with = "::human_readable::hex")]
+    pub script: Vec<u8>,
+}
+
+/// The types of the trailing TLV records of `ClosingNegotiation` message.
+#[derive(Default, Clone, Eq, PartialEq, Debug)]
+pub struct ClosingTlv;
+
+impl ClosingTlv {
+    pub const FEE_RANGE: u64 = 1;
+}
+
+impl TlvKnownTypes for ClosingTlv {
+    const KNOWN: &'static [u64] = &[Self::FEE_RANGE];
+}
+
+/// The fees the sender of `closing_signed` is willing to pay or to accept.
+#[derive(Serialize, Deserialize, Copy, Clone, Eq, PartialEq, Debug)]
+pub struct FeeRange {
+    pub min: Satoshi,
+    pub max: Satoshi,
 }
 
 #[derive(Serialize, Deserialize, Eq, PartialEq, Debug)]
 pub struct ClosingNegotiation {
-    channel_id: ChannelId,
-    fee: Satoshi,
-    signature: Signature,
+    pub channel_id: ChannelId,
+    pub fee: Satoshi,
+    pub signature: Signature,
+    extension: TlvStream<ClosingTlv>,
+}
+
+impl ClosingNegotiation {
+    pub fn new(channel_id: ChannelId, fee: Satoshi, signature: Signature, fee_range: Option<FeeRange>) -> Self {
+        let mut extension = TlvStream::new();
+        if let Some(fee_range) = fee_range {
+            extension.insert_value(ClosingTlv::FEE_RANGE, &fee_range).unwrap();
+        }
+        ClosingNegotiation {
+            channel_id: channel_id,
+            fee: fee,
+            signature: signature,
+            extension: extension,
+        }
+    }
+
+    /// The peer unaware of the fee range negotiates by halving the difference.
+    pub fn fee_range(&self) -> Result<Option<FeeRange>, WireError> {
+        self.extension.get_value(ClosingTlv::FEE_RANGE)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::BinarySD;
+    use rand;
+
+    #[test]
+    fn closing_negotiation() {
+        let fee_range = FeeRange {
+            min: Satoshi::from(1000),
+            max: Satoshi::from(0x0102),
+        };
+        let closing = ClosingNegotiation::new(rand::random(), Satoshi::from(2000), rand::random(), Some(fee_range));
+
+        let mut data = Vec::new();
+        BinarySD::serialize(&mut data, &closing).unwrap();
+        assert_eq!(data.len(), 32 + 8 + 64 + 2 + 16);
+        assert_eq!(::hex::encode(&data[104..]), "011000000000000003e80000000000000102");
+
+        let restored: ClosingNegotiation = BinarySD::deserialize(&data[..]).unwrap();
+        assert_eq!(restored.fee_range().unwrap(), Some(fee_range));
+        assert_eq!(restored, closing);
+
+        // the legacy message has no records
+        let legacy = ClosingNegotiation::new(rand::random(), Satoshi::from(2000), rand::random(), None);
+        let mut data = Vec::new();
+        BinarySD::serialize(&mut data, &legacy).unwrap();
+        assert_eq!(data.len(), 32 + 8 + 64);
+        let restored: ClosingNegotiation = BinarySD::deserialize(&data[..]).unwrap();
+        assert_eq!(restored.fee_range().unwrap(), None);
+    }
 }
@@ -0,0 +1,484 @@
+use super::Hash256;
+use super::MilliSatoshi;
+use super::UpdateChannel;
+
+use ::BinarySD;
+use ::WireError;
+
+use std::io;
+
+bitflags! {
+    #[derive(Serialize, Deserialize)]
+    pub struct FailureFlags: u16 {
+        // unparsable onion encrypted by sending peer
+        const BADONION = 0x8000;
+        // permanent failure (otherwise transient)
+        const PERM = 0x4000;
+        // node failure (otherwise channel)
+        const NODE = 0x2000;
+        // new channel update enclosed
+        const UPDATE = 0x1000;
+    }
+}
+
+/// The 16-bit failure code, the upper bits are `FailureFlags`.
+#[derive(Serialize, Deserialize, Eq, PartialEq, Debug, Copy, Clone)]
+pub struct FailureCode {
+    code: u16,
+}
+
+impl FailureCode {
+    const BADONION: u16 = FailureFlags::BADONION.bits;
+    const PERM: u16 = FailureFlags::PERM.bits;
+    const NODE: u16 = FailureFlags::NODE.bits;
+    const UPDATE: u16 = FailureFlags::UPDATE.bits;
+
+    pub const INVALID_REALM: Self = FailureCode { code: Self::PERM | 1 };
+    pub const TEMPORARY_NODE_FAILURE: Self = FailureCode { code: Self::NODE | 2 };
+    pub const PERMANENT_NODE_FAILURE: Self = FailureCode { code: Self::PERM | Self::NODE | 2 };
+    pub const REQUIRED_NODE_FEATURE_MISSING: Self = FailureCode { code: Self::PERM | Self::NODE | 3 };
+    pub const INVALID_ONION_VERSION: Self = FailureCode { code: Self::BADONION | Self::PERM | 4 };
+    pub const INVALID_ONION_HMAC: Self = FailureCode { code: Self::BADONION | Self::PERM | 5 };
+    pub const INVALID_ONION_KEY: Self = FailureCode { code: Self::BADONION | Self::PERM | 6 };
+    pub const TEMPORARY_CHANNEL_FAILURE: Self = FailureCode { code: Self::UPDATE | 7 };
+    pub const PERMANENT_CHANNEL_FAILURE: Self = FailureCode { code: Self::PERM | 8 };
+    pub const REQUIRED_CHANNEL_FEATURE_MISSING: Self = FailureCode { code: Self::PERM | 9 };
+    pub const UNKNOWN_NEXT_PEER: Self = FailureCode { code: Self::PERM | 10 };
+    pub const AMOUNT_BELOW_MINIMUM: Self = FailureCode { code: Self::UPDATE | 11 };
+    pub const FEE_INSUFFICIENT: Self = FailureCode { code: Self::UPDATE | 12 };
+    pub const INCORRECT_CLTV_EXPIRY: Self = FailureCode { code: Self::UPDATE | 13 };
+    pub const EXPIRY_TOO_SOON: Self = FailureCode { code: Self::UPDATE | 14 };
+    pub const INCORRECT_OR_UNKNOWN_PAYMENT_DETAILS: Self = FailureCode { code: Self::PERM | 15 };
+    pub const FINAL_EXPIRY_TOO_SOON: Self = FailureCode { code: 17 };
+    pub const FINAL_INCORRECT_CLTV_EXPIRY: Self = FailureCode { code: 18 };
+    pub const FINAL_INCORRECT_HTLC_AMOUNT: Self = FailureCode { code: 19 };
+    pub const CHANNEL_DISABLED: Self = FailureCode { code: Self::UPDATE | 20 };
+    pub const EXPIRY_TOO_FAR: Self = FailureCode { code: 21 };
+    pub const MPP_TIMEOUT: Self = FailureCode { code: 23 };
+
+    pub fn flags(&self) -> FailureFlags {
+        FailureFlags::from_bits_truncate(self.code)
+    }
+
+    pub fn is_bad_onion(&self) -> bool {
+        self.flags().contains(FailureFlags::BADONION)
+    }
+
+    pub fn is_permanent(&self) -> bool {
+        self.flags().contains(FailureFlags::PERM)
+    }
+
+    pub fn is_node(&self) -> bool {
+        self.flags().contains(FailureFlags::NODE)
+    }
+
+    pub fn has_update(&self) -> bool {
+        self.flags().contains(FailureFlags::UPDATE)
+    }
+}
+
+impl From<u16> for FailureCode {
+    fn from(code: u16) -> Self {
+        FailureCode {
+            code: code,
+        }
+    }
+}
+
+impl From<FailureCode> for u16 {
+    fn from(c: FailureCode) -> Self {
+        return c.code;
+    }
+}
+
+/// The failure message as it is described in BOLT 04,
+/// the erring node puts it into the failure onion returned to the origin node.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub enum FailureMessage {
+    InvalidRealm,
+    TemporaryNodeFailure,
+    PermanentNodeFailure,
+    RequiredNodeFeatureMissing,
+    InvalidOnionVersion {
+        sha256_of_onion: Hash256,
+    },
+    InvalidOnionHmac {
+        sha256_of_onion: Hash256,
+    },
+    InvalidOnionKey {
+        sha256_of_onion: Hash256,
+    },
+    TemporaryChannelFailure {
+        channel_update: UpdateChannel,
+    },
+    PermanentChannelFailure,
+    RequiredChannelFeatureMissing,
+    UnknownNextPeer,
+    AmountBelowMinimum {
+        htlc_msat: MilliSatoshi,
+        channel_update: UpdateChannel,
+    },
+    FeeInsufficient {
+        htlc_msat: MilliSatoshi,
+        channel_update: UpdateChannel,
+    },
+    IncorrectCltvExpiry {
+        cltv_expiry: u32,
+        channel_update: UpdateChannel,
+    },
+    ExpiryTooSoon {
+        channel_update: UpdateChannel,
+    },
+    IncorrectOrUnknownPaymentDetails {
+        htlc_msat: MilliSatoshi,
+        // the older nodes do not send the height
+        height: Option<u32>,
+    },
+    FinalExpiryTooSoon,
+    FinalIncorrectCltvExpiry {
+        cltv_expiry: u32,
+    },
+    FinalIncorrectHtlcAmount {
+        incoming_htlc_amt: MilliSatoshi,
+    },
+    ChannelDisabled {
+        flags: u16,
+        channel_update: UpdateChannel,
+    },
+    ExpiryTooFar,
+    MppTimeout,
+    // the code is not known by this implementation, keep the data as is
+    Unknown {
+        code: FailureCode,
+        data: Vec<u8>,
+    },
+}
+
+// the type of `UpdateChannel` message, it might prefix the embedded channel update
+const UPDATE_CHANNEL_TYPE: [u8; 2] = [0x01, 0x02];
+
+fn write_channel_update<W>(w: &mut W, channel_update: &UpdateChannel) -> Result<(), WireError> where W: io::Write {
+    let mut data = UPDATE_CHANNEL_TYPE.to_vec();
+    BinarySD::serialize(&mut data, channel_update)?;
+    BinarySD::serialize(w, &data)
+}
+
+fn read_channel_update<R>(r: &mut R) -> Result<UpdateChannel, WireError> where R: io::Read {
+    let data: Vec<u8> = BinarySD::deserialize(r)?;
+    // the type prefix is optional, but most implementations put it
+    if data.len() >= 2 && data[0..2] == UPDATE_CHANNEL_TYPE {
+        BinarySD::deserialize(&data[2..])
+    } else {
+        BinarySD::deserialize(data.as_slice())
+    }
+}
+
+impl FailureMessage {
+    pub fn code(&self) -> FailureCode {
+        use self::FailureMessage::*;
+
+        match self {
+            &InvalidRealm => FailureCode::INVALID_REALM,
+            &TemporaryNodeFailure => FailureCode::TEMPORARY_NODE_FAILURE,
+            &PermanentNodeFailure => FailureCode::PERMANENT_NODE_FAILURE,
+            &RequiredNodeFeatureMissing => FailureCode::REQUIRED_NODE_FEATURE_MISSING,
+            &InvalidOnionVersion { .. } => FailureCode::INVALID_ONION_VERSION,
+            &InvalidOnionHmac { .. } => FailureCode::INVALID_ONION_HMAC,
+            &InvalidOnionKey { .. } => FailureCode::INVALID_ONION_KEY,
+            &TemporaryChannelFailure { .. } => FailureCode::TEMPORARY_CHANNEL_FAILURE,
+            &PermanentChannelFailure => FailureCode::PERMANENT_CHANNEL_FAILURE,
+            &RequiredChannelFeatureMissing => FailureCode::REQUIRED_CHANNEL_FEATURE_MISSING,
+            &UnknownNextPeer => FailureCode::UNKNOWN_NEXT_PEER,
+            &AmountBelowMinimum { .. } => FailureCode::AMOUNT_BELOW_MINIMUM,
+            &FeeInsufficient { .. } => FailureCode::FEE_INSUFFICIENT,
+            &IncorrectCltvExpiry { .. } => FailureCode::INCORRECT_CLTV_EXPIRY,
+            &ExpiryTooSoon { .. } => FailureCode::EXPIRY_TOO_SOON,
+            &IncorrectOrUnknownPaymentDetails { .. } => FailureCode::INCORRECT_OR_UNKNOWN_PAYMENT_DETAILS,
+            &FinalExpiryTooSoon => FailureCode::FINAL_EXPIRY_TOO_SOON,
+            &FinalIncorrectCltvExpiry { .. } => FailureCode::FINAL_INCORRECT_CLTV_EXPIRY,
+            &FinalIncorrectHtlcAmount { .. } => FailureCode::FINAL_INCORRECT_HTLC_AMOUNT,
+            &ChannelDisabled { .. } => FailureCode::CHANNEL_DISABLED,
+            &ExpiryTooFar => FailureCode::EXPIRY_TOO_FAR,
+            &MppTimeout => FailureCode::MPP_TIMEOUT,
+            &Unknown { code: code, .. } => code,
+        }
+    }
+
+    /// The channel update enclosed in the failure, if any.
+    pub fn channel_update(&self) -> Option<&UpdateChannel> {
+        use self::FailureMessage::*;
+
+        match self {
+            &TemporaryChannelFailure { channel_update: ref u } => Some(u),
+            &AmountBelowMinimum { channel_update: ref u, .. } => Some(u),
+            &FeeInsufficient { channel_update: ref u, .. } => Some(u),
+            &IncorrectCltvExpiry { channel_update: ref u, .. } => Some(u),
+            &ExpiryTooSoon { channel_update: ref u } => Some(u),
+            &ChannelDisabled { channel_update: ref u, .. } => Some(u),
+            _ => None,
+        }
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>, WireError> {
+        use self::FailureMessage::*;
+
+        let mut w = Vec::new();
+        BinarySD::serialize(&mut w, &self.code())?;
+        match self {
+            &InvalidOnionVersion { ref sha256_of_onion } |
+            &InvalidOnionHmac { ref sha256_of_onion } |
+            &InvalidOnionKey { ref sha256_of_onion } => {
+                BinarySD::serialize(&mut w, sha256_of_onion)?;
+            },
+            &TemporaryChannelFailure { ref channel_update } |
+            &ExpiryTooSoon { ref channel_update } => {
+                write_channel_update(&mut w, channel_update)?;
+            },
+            &AmountBelowMinimum { ref htlc_msat, ref channel_update } |
+            &FeeInsufficient { ref htlc_msat, ref channel_update } => {
+                BinarySD::serialize(&mut w, htlc_msat)?;
+                write_channel_update(&mut w, channel_update)?;
+            },
+            &IncorrectCltvExpiry { ref cltv_expiry, ref channel_update } => {
+                BinarySD::serialize(&mut w, cltv_expiry)?;
+                write_channel_update(&mut w, channel_update)?;
+            },
+            &IncorrectOrUnknownPaymentDetails { ref htlc_msat, ref height } => {
+                BinarySD::serialize(&mut w, htlc_msat)?;
+                if let &Some(ref height) = height {
+                    BinarySD::serialize(&mut w, height)?;
+                }
+            },
+            &FinalIncorrectCltvExpiry { ref cltv_expiry } => {
+                BinarySD::serialize(&mut w, cltv_expiry)?;
+            },
+            &FinalIncorrectHtlcAmount { ref incoming_htlc_amt } => {
+                BinarySD::serialize(&mut w, incoming_htlc_amt)?;
+            },
+            &ChannelDisabled { ref flags, ref channel_update } => {
+                BinarySD::serialize(&mut w, flags)?;
+                write_channel_update(&mut w, channel_update)?;
+            },
+            &Unknown { ref data, .. } => {
+                w.extend_from_slice(data.as_slice());
+            },
+            _ => (),
+        };
+
+        Ok(w)
+    }
+
+    /// Parses the failure message, trailing bytes are ignored
+    /// as the specification allows to extend the message.
+    pub fn from_bytes(data: &[u8]) -> Result<Self, WireError> {
+        use self::FailureMessage::*;
+
+        let mut r = io::Cursor::new(data);
+        let code: FailureCode = BinarySD::deserialize(&mut r)?;
+        let message = match code {
+            FailureCode::INVALID_REALM => InvalidRealm,
+            FailureCode::TEMPORARY_NODE_FAILURE => TemporaryNodeFailure,
+            FailureCode::PERMANENT_NODE_FAILURE => PermanentNodeFailure,
+            FailureCode::REQUIRED_NODE_FEATURE_MISSING => RequiredNodeFeatureMissing,
+            FailureCode::INVALID_ONION_VERSION => InvalidOnionVersion {
+                sha256_of_onion: BinarySD::deserialize(&mut r)?,
+            },
+            FailureCode::INVALID_ONION_HMAC => InvalidOnionHmac {
+                sha256_of_onion: BinarySD::deserialize(&mut r)?,
+            },
+            FailureCode::INVALID_ONION_KEY => InvalidOnionKey {
+                sha256_of_onion: BinarySD::deserialize(&mut r)?,
+            },
+            FailureCode::TEMPORARY_CHANNEL_FAILURE => TemporaryChannelFailure {
+                channel_update: read_channel_update(&mut r)?,
+            },
+            FailureCode::PERMANENT_CHANNEL_FAILURE => PermanentChannelFailure,
+            FailureCode::REQUIRED_CHANNEL_FEATURE_MISSING => RequiredChannelFeatureMissing,
+            FailureCode::UNKNOWN_NEXT_PEER => UnknownNextPeer,
+            FailureCode::AMOUNT_BELOW_MINIMUM => AmountBelowMinimum {
+                htlc_msat: BinarySD::deserialize(&mut r)?,
+                channel_update: read_channel_update(&mut r)?,
+            },
+            FailureCode::FEE_INSUFFICIENT => FeeInsufficient {
+                htlc_msat: BinarySD::deserialize(&mut r)?,
+                channel_update: read_channel_update(&mut r)?,
+            },
+            FailureCode::INCORRECT_CLTV_EXPIRY => IncorrectCltvExpiry {
+                cltv_expiry: BinarySD::deserialize(&mut r)?,
+                channel_update: read_channel_update(&mut r)?,
+            },
+            FailureCode::EXPIRY_TOO_SOON => ExpiryTooSoon {
+                channel_update: read_channel_update(&mut r)?,
+            },
+            FailureCode::INCORRECT_OR_UNKNOWN_PAYMENT_DETAILS => IncorrectOrUnknownPaymentDetails {
+                htlc_msat: BinarySD::deserialize(&mut r)?,
+                height: if r.position() == data.len() as u64 {
+                    None
+                } else {
+                    Some(BinarySD::deserialize(&mut r)?)
+                },
+            },
+            FailureCode::FINAL_EXPIRY_TOO_SOON => FinalExpiryTooSoon,
+            FailureCode::FINAL_INCORRECT_CLTV_EXPIRY => FinalIncorrectCltvExpiry {
+                cltv_expiry: BinarySD::deserialize(&mut r)?,
+            },
+            FailureCode::FINAL_INCORRECT_HTLC_AMOUNT => FinalIncorrectHtlcAmount {
+                incoming_htlc_amt: BinarySD::deserialize(&mut r)?,
+            },
+            FailureCode::CHANNEL_DISABLED => ChannelDisabled {
+                flags: BinarySD::deserialize(&mut r)?,
+                channel_update: read_channel_update(&mut r)?,
+            },
+            FailureCode::EXPIRY_TOO_FAR => ExpiryTooFar,
+            FailureCode::MPP_TIMEOUT => MppTimeout,
+            code @ _ => Unknown {
+                code: code,
+                data: data[2..].to_vec(),
+            },
+        };
+
+        Ok(message)
+    }
+}
+
+/// The failure encrypted by the erring node and obfuscated by each hop
+/// on the way back, only the origin node is able to read it.
+#[derive(Serialize, Deserialize, Clone, Eq, PartialEq, Debug)]
+#[serde(transparent)]
+pub struct OpaqueReason {
+    #[serde(with = "::human_readable::hex")]
+    data: Vec<u8>,
+}
+
+impl From<Vec<u8>> for OpaqueReason {
+    fn from(data: Vec<u8>) -> Self {
+        OpaqueReason {
+            data: data,
+        }
+    }
+}
+
+impl From<OpaqueReason> for Vec<u8> {
+    fn from(r: OpaqueReason) -> Self {
+        return r.data;
+    }
+}
+
+impl AsRef<[u8]> for OpaqueReason {
+    fn as_ref(&self) -> &[u8] {
+        self.data.as_slice()
+    }
+}
+
+impl AsMut<[u8]> for OpaqueReason {
+    fn as_mut(&mut self) -> &mut [u8] {
+        self.data.as_mut_slice()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn channel_update() -> UpdateChannel {
+        let mut data = vec![1; 64];
+        data.extend_from_slice(&[0x6f; 32]);
+        data.extend_from_slice(&[0, 0, 1, 0, 0, 2, 0, 3]);
+        data.extend_from_slice(&[0x5b, 0x92, 0x99, 0x11]);
+        data.extend_from_slice(&[0, 1]);
+        data.extend_from_slice(&[0, 144]);
+        data.extend_from_slice(&[0, 0, 0, 0, 0, 0, 3, 232]);
+        data.extend_from_slice(&[0, 0, 3, 232]);
+        data.extend_from_slice(&[0, 0, 0, 1]);
+        BinarySD::deserialize(data.as_slice()).unwrap()
+    }
+
+    #[test]
+    fn flags() {
+        let code = FailureMessage::InvalidOnionHmac {
+            sha256_of_onion: Hash256::TEST_HASH,
+        }.code();
+        assert_eq!(u16::from(code), 0xc005);
+        assert!(code.is_bad_onion());
+        assert!(code.is_permanent());
+        assert!(!code.is_node());
+
+        let code = FailureMessage::TemporaryNodeFailure.code();
+        assert_eq!(u16::from(code), 0x2002);
+        assert_eq!(code.flags(), FailureFlags::NODE);
+    }
+
+    #[test]
+    fn temporary_channel_failure() {
+        let message = FailureMessage::TemporaryChannelFailure {
+            channel_update: channel_update(),
+        };
+        let data = message.to_bytes().unwrap();
+        // code, length of update, type of update
+        assert_eq!(&data[0..6], &[0x10, 0x07, 0, 130, 0x01, 0x02]);
+        assert_eq!(data.len(), 2 + 2 + 2 + 128);
+
+        let restored = FailureMessage::from_bytes(data.as_slice()).unwrap();
+        assert_eq!(restored, message);
+        assert_eq!(restored.channel_update(), Some(&channel_update()));
+    }
+
+    #[test]
+    fn channel_update_without_type() {
+        let mut data = vec![0x10, 0x0e, 0, 128];
+        BinarySD::serialize(&mut data, &channel_update()).unwrap();
+        let restored = FailureMessage::from_bytes(data.as_slice()).unwrap();
+        assert_eq!(restored, FailureMessage::ExpiryTooSoon { channel_update: channel_update() });
+    }
+
+    #[test]
+    fn round_trip() {
+        let messages = vec![
+            FailureMessage::FeeInsufficient {
+                htlc_msat: MilliSatoshi::from(1000),
+                channel_update: channel_update(),
+            },
+            FailureMessage::IncorrectOrUnknownPaymentDetails {
+                htlc_msat: MilliSatoshi::from(1000),
+                height: Some(600000),
+            },
+            FailureMessage::IncorrectOrUnknownPaymentDetails {
+                htlc_msat: MilliSatoshi::from(1000),
+                height: None,
+            },
+            FailureMessage::InvalidOnionKey {
+                sha256_of_onion: Hash256::TEST_HASH,
+            },
+            FailureMessage::ChannelDisabled {
+                flags: 0,
+                channel_update: channel_update(),
+            },
+            FailureMessage::Unknown {
+                code: FailureCode::from(0x4063),
+                data: vec![1, 2, 3],
+            },
+        ];
+
+        for message in messages {
+            let data = message.to_bytes().unwrap();
+            assert_eq!(FailureMessage::from_bytes(data.as_slice()).unwrap(), message);
+        }
+    }
+
+    #[test]
+    fn payment_details_without_height() {
+        let data = [0x40, 0x0f, 0, 0, 0, 0, 0, 0, 0x03, 0xe8];
+        let restored = FailureMessage::from_bytes(&data[..]).unwrap();
+        assert_eq!(restored, FailureMessage::IncorrectOrUnknownPaymentDetails {
+            htlc_msat: MilliSatoshi::from(1000),
+            height: None,
+        });
+        assert_eq!(restored.to_bytes().unwrap(), data.to_vec());
+    }
+
+    #[test]
+    fn truncated() {
+        let data = [0x40, 0x0f, 0, 0, 0, 0];
+        assert!(FailureMessage::from_bytes(&data[..]).is_err());
+    }
+}
@@ -4,7 +4,9 @@ use super::PublicKey;
 use super::OutputIndex;
 
 #[derive(Serialize, Deserialize, Eq, PartialEq, Debug, Copy, Clone)]
+#[serde(transparent)]
 pub struct FundingTxid {
+    #[serde(with = "::human_readable::hex")]
     data: [u8; 32],
 }
 
@@ -32,4 +34,10 @@ impl From<FundingTxid> for [u8; 32] {
     fn from(tx_id: FundingTxid) -> Self {
         return tx_id.data;
     }
+}
+
+impl From<[u8; 32]> for FundingTxid {
+    fn from(data: [u8; 32]) -> Self {
+        return FundingTxid { data: data };
+    }
 }
\ No newline at end of file
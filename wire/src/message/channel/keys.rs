@@ -83,6 +83,15 @@ impl ChannelPrivateKeys {
     pub fn first_per_commitment_sk(&self) -> &SecretKey {
         &self.first_per_commitment
     }
+
+    /// The per commitment secrets are usually derived from the shachain seed,
+    /// so the first one is replaced by the secret of the commitment number zero.
+    pub fn with_first_per_commitment(self, secret: SecretKey) -> Self {
+        ChannelPrivateKeys {
+            first_per_commitment: secret,
+            ..self
+        }
+    }
 }
 
 #[cfg(any(test, feature = "testing"))]
@@ -13,6 +13,9 @@ pub use self::close::*;
 mod operation;
 pub use self::operation::*;
 
+mod failure;
+pub use self::failure::*;
+
 mod open;
 pub use self::open::*;
 
@@ -41,7 +44,9 @@ bitflags! {
 }
 
 #[derive(Serialize, Deserialize, Eq, PartialEq, Debug, Copy, Clone)]
+#[serde(transparent)]
 pub struct ChannelId {
+    #[serde(with = "::human_readable::hex")]
     data: [u8; 32],
 }
 
@@ -61,7 +66,8 @@ impl From<[u8; 32]> for ChannelId {
     }
 }
 
-#[derive(Eq, PartialEq, Debug, Clone)]
+// the order of the fields matters, ordering is the same as for `u64` representation
+#[derive(Eq, PartialEq, Ord, PartialOrd, Hash, Debug, Clone)]
 pub struct ShortChannelId {
     block_height: u32,
     tx_index: u32,
@@ -112,14 +118,42 @@ mod serde {
     use serde::de::Error;
     use std::fmt;
 
+    // the block height and the transaction index are 24 bits
+    const MAX_24_BITS: u32 = 0xffffff;
+
     impl Serialize for ShortChannelId {
         fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error> where S: Serializer {
-            serializer.serialize_u64(self.clone().into())
+            if serializer.is_human_readable() {
+                let s = format!("{}x{}x{}", self.block_height, self.tx_index, self.tx_position);
+                serializer.serialize_str(s.as_str())
+            } else {
+                serializer.serialize_u64(self.clone().into())
+            }
         }
     }
 
     impl<'de> Deserialize<'de> for ShortChannelId {
         fn deserialize<D>(deserializer: D) -> Result<Self, D::Error> where D: Deserializer<'de> {
+            if deserializer.is_human_readable() {
+                let string = String::deserialize(deserializer)?;
+                let error = || D::Error::custom(format!("expected `BLOCKxTXxOUTPUT`, got `{}`", string));
+                let parts = string.split('x').collect::<Vec<_>>();
+                if parts.len() != 3 {
+                    return Err(error());
+                }
+                let block_height = parts[0].parse::<u32>().map_err(|_| error())?;
+                let tx_index = parts[1].parse::<u32>().map_err(|_| error())?;
+                let tx_position = parts[2].parse::<u16>().map_err(|_| error())?;
+                if block_height > MAX_24_BITS || tx_index > MAX_24_BITS {
+                    return Err(error());
+                }
+                return Ok(ShortChannelId {
+                    block_height: block_height,
+                    tx_index: tx_index,
+                    tx_position: tx_position,
+                });
+            }
+
             struct V;
 
             impl<'de> Visitor<'de> for V {
@@ -180,6 +214,18 @@ mod tests {
         assert_eq!(value, restored);
     }
 
+    #[test]
+    fn short_channel_id_human_readable() {
+        use ::JsonSD;
+
+        let short_channel_id = ShortChannelId::from(0x083a840000340001);
+        let json = JsonSD::to_string(&short_channel_id).unwrap();
+        assert_eq!(json, "\"539268x52x1\"");
+        assert_eq!(JsonSD::from_str::<ShortChannelId>(json.as_str()).unwrap(), short_channel_id);
+        assert!(JsonSD::from_str::<ShortChannelId>("\"16777216x0x0\"").is_err());
+        assert!(JsonSD::from_str::<ShortChannelId>("\"1x2\"").is_err());
+    }
+
     #[test]
     fn some_test() {
         let v = vec![0u8, 1, 145, 0, 0, 1, 0, 0, ];
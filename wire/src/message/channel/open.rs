@@ -7,6 +7,9 @@ use super::CsvDelay;
 use super::PublicKey;
 use super::ChannelFlags;
 use super::ChannelKeys;
+use super::TlvStream;
+use super::TlvKnownTypes;
+use super::NoKnownTypes;
 
 #[cfg(test)]
 use super::ChannelPrivateKeys;
@@ -26,6 +29,27 @@ pub struct OpenChannel {
     pub max_accepted_htlc_number: u16,
     pub keys: ChannelKeys,
     pub flags: ChannelFlags,
+    pub extension: TlvStream<OpenChannelTlv>,
+}
+
+/// The types of the trailing TLV records
+/// of `OpenChannel` and `AcceptChannel` messages.
+#[derive(Default, Clone, Eq, PartialEq, Debug)]
+pub struct OpenChannelTlv;
+
+impl OpenChannelTlv {
+    pub const UPFRONT_SHUTDOWN_SCRIPT: u64 = 0;
+    pub const CHANNEL_TYPE: u64 = 1;
+}
+
+impl TlvKnownTypes for OpenChannelTlv {
+    const KNOWN: &'static [u64] = &[Self::UPFRONT_SHUTDOWN_SCRIPT, Self::CHANNEL_TYPE];
+}
+
+impl OpenChannel {
+    pub fn upfront_shutdown_script(&self) -> Option<&[u8]> {
+        self.extension.get(OpenChannelTlv::UPFRONT_SHUTDOWN_SCRIPT)
+    }
 }
 
 #[derive(Serialize, Deserialize, Eq, PartialEq, Debug)]
@@ -44,6 +68,7 @@ pub struct AcceptChannel {
     pub csv_delay: CsvDelay,
     pub max_accepted_htlc_number: u16,
     pub keys: ChannelKeys,
+    pub extension: TlvStream<OpenChannelTlv>,
 }
 
 impl AcceptChannel {
@@ -58,17 +83,47 @@ impl AcceptChannel {
             csv_delay: open_channel.csv_delay.clone(),
             max_accepted_htlc_number: open_channel.max_accepted_htlc_number.clone(),
             keys: keys.clone(),
+            extension: TlvStream::new(),
         }
     }
+
+    pub fn upfront_shutdown_script(&self) -> Option<&[u8]> {
+        self.extension.get(OpenChannelTlv::UPFRONT_SHUTDOWN_SCRIPT)
+    }
 }
 
 #[derive(Serialize, Deserialize, Eq, PartialEq, Debug)]
 pub struct ReestablishChannel {
-    channel_id: ChannelId,
-    next_local_commitment_number: u64,
-    next_remote_revocation_number: u64,
-    last_remote_commit_secret: [u8; 32],
-    local_unrevoked_commit_point: PublicKey,
+    pub channel_id: ChannelId,
+    // the number of the next `commitment_signed` the sender expects
+    pub next_local_commitment_number: u64,
+    // the number of the commitment the next `revoke_and_ack` the sender expects revokes
+    pub next_remote_revocation_number: u64,
+    // option_data_loss_protect, the last secret the sender has received, or zeros
+    #[serde(with = "::human_readable::hex")]
+    pub last_remote_commit_secret: [u8; 32],
+    // the point of the latest commitment of the sender
+    pub local_unrevoked_commit_point: PublicKey,
+    extension: TlvStream<NoKnownTypes>,
+}
+
+impl ReestablishChannel {
+    pub fn new(
+        channel_id: ChannelId,
+        next_local_commitment_number: u64,
+        next_remote_revocation_number: u64,
+        last_remote_commit_secret: [u8; 32],
+        local_unrevoked_commit_point: PublicKey,
+    ) -> Self {
+        ReestablishChannel {
+            channel_id: channel_id,
+            next_local_commitment_number: next_local_commitment_number,
+            next_remote_revocation_number: next_remote_revocation_number,
+            last_remote_commit_secret: last_remote_commit_secret,
+            local_unrevoked_commit_point: local_unrevoked_commit_point,
+            extension: TlvStream::new(),
+        }
+    }
 }
 
 #[cfg(test)]
@@ -99,6 +154,7 @@ mod test {
             max_accepted_htlc_number: Default::default(),
             keys: ChannelKeys::new(&private).unwrap(),
             flags: ChannelFlags::FF_ANNOUNCE_CHANNEL,
+            extension: TlvStream::new(),
         };
 
         // try to estimate size without aligning
@@ -113,5 +169,16 @@ mod test {
 
         let restored: OpenChannel = BinarySD::deserialize(vec.as_slice()).unwrap();
         assert_eq!(restored, msg);
+
+        let mut msg = msg;
+        msg.extension.insert(OpenChannelTlv::UPFRONT_SHUTDOWN_SCRIPT, vec![0x00, 0x14]);
+        vec.clear();
+        let _ = BinarySD::serialize(&mut vec, &msg).unwrap();
+        assert_eq!(vec.len(), estimated_size + 4);
+        assert_eq!(&vec[estimated_size..], &[0, 2, 0x00, 0x14]);
+
+        let restored: OpenChannel = BinarySD::deserialize(vec.as_slice()).unwrap();
+        assert_eq!(restored.upfront_shutdown_script(), Some(&[0x00, 0x14][..]));
+        assert_eq!(restored, msg);
     }
 }
@@ -5,10 +5,12 @@ use super::OnionBlob;
 use super::Signature;
 use super::PublicKey;
 use super::SatoshiPerKiloWeight;
-
-use ::SerdeVec;
+use super::OpaqueReason;
+use super::FailureCode;
+use super::FailureMessage;
 
 #[derive(Serialize, Deserialize, Eq, PartialEq, Debug, Copy, Clone)]
+#[serde(transparent)]
 pub struct HtlcId {
     id: u64,
 }
@@ -27,7 +29,7 @@ impl HtlcId {
     }
 }
 
-#[derive(Serialize, Deserialize, Eq, PartialEq, Debug)]
+#[derive(Serialize, Deserialize, Eq, PartialEq, Debug, Clone)]
 pub struct UpdateAddHtlc {
     pub channel_id: ChannelId,
     pub id: HtlcId,
@@ -37,44 +39,164 @@ pub struct UpdateAddHtlc {
     pub onion_blob: OnionBlob,
 }
 
-#[derive(Serialize, Deserialize, Eq, PartialEq, Debug)]
+#[derive(Serialize, Deserialize, Eq, PartialEq, Debug, Clone)]
 pub struct UpdateFulfillHtlc {
     pub channel_id: ChannelId,
     pub id: HtlcId,
+    #[serde(with = "::human_readable::hex")]
     pub payment_preimage: [u8; 32],
 }
 
-#[derive(Serialize, Deserialize, Eq, PartialEq, Debug)]
+#[derive(Serialize, Deserialize, Eq, PartialEq, Debug, Clone)]
 pub struct UpdateFailHtlc {
     channel_id: ChannelId,
     id: HtlcId,
-    reason: Vec<u8>,
+    reason: OpaqueReason,
 }
 
-#[derive(Serialize, Deserialize, Eq, PartialEq, Debug)]
+impl UpdateFailHtlc {
+    pub fn new(channel_id: ChannelId, id: HtlcId, reason: OpaqueReason) -> Self {
+        UpdateFailHtlc {
+            channel_id: channel_id,
+            id: id,
+            reason: reason,
+        }
+    }
+
+    pub fn channel_id(&self) -> &ChannelId {
+        &self.channel_id
+    }
+
+    pub fn id(&self) -> HtlcId {
+        self.id
+    }
+
+    pub fn reason(&self) -> &OpaqueReason {
+        &self.reason
+    }
+}
+
+#[derive(Serialize, Deserialize, Eq, PartialEq, Debug, Clone)]
 pub struct UpdateFailMalformedHtlc {
     channel_id: ChannelId,
     id: HtlcId,
     sha256_of_onion: Hash256,
-    failure_code: u16,
+    failure_code: FailureCode,
+}
+
+impl UpdateFailMalformedHtlc {
+    /// The failure code must have `BADONION` flag, returns `None` otherwise.
+    pub fn new(channel_id: ChannelId, id: HtlcId, sha256_of_onion: Hash256, failure_code: FailureCode) -> Option<Self> {
+        if !failure_code.is_bad_onion() {
+            return None;
+        }
+
+        Some(UpdateFailMalformedHtlc {
+            channel_id: channel_id,
+            id: id,
+            sha256_of_onion: sha256_of_onion,
+            failure_code: failure_code,
+        })
+    }
+
+    pub fn channel_id(&self) -> &ChannelId {
+        &self.channel_id
+    }
+
+    pub fn id(&self) -> HtlcId {
+        self.id
+    }
+
+    pub fn sha256_of_onion(&self) -> &Hash256 {
+        &self.sha256_of_onion
+    }
+
+    pub fn failure_code(&self) -> FailureCode {
+        self.failure_code
+    }
+
+    /// The failure message the node should wrap and send back
+    /// instead of the malformed htlc, `None` if the code is not the bad onion.
+    pub fn failure(&self) -> Option<FailureMessage> {
+        let sha256_of_onion = self.sha256_of_onion;
+        match self.failure_code {
+            FailureCode::INVALID_ONION_VERSION => Some(FailureMessage::InvalidOnionVersion {
+                sha256_of_onion: sha256_of_onion,
+            }),
+            FailureCode::INVALID_ONION_HMAC => Some(FailureMessage::InvalidOnionHmac {
+                sha256_of_onion: sha256_of_onion,
+            }),
+            FailureCode::INVALID_ONION_KEY => Some(FailureMessage::InvalidOnionKey {
+                sha256_of_onion: sha256_of_onion,
+            }),
+            code @ _ if code.is_bad_onion() => Some(FailureMessage::Unknown {
+                code: code,
+                data: <[u8; 32]>::from(sha256_of_onion).to_vec(),
+            }),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Eq, PartialEq, Debug)]
 pub struct CommitmentSigned {
     pub channel_id: ChannelId,
     pub signature: Signature,
-    pub htlc_signatures: SerdeVec<Signature>,
+    // prefixed by the number of signatures, one for each htlc output in the order of the outputs
+    pub htlc_signatures: Vec<Signature>,
 }
 
-#[derive(Serialize, Deserialize, Eq, PartialEq, Debug)]
+#[derive(Serialize, Deserialize, Eq, PartialEq, Debug, Clone)]
 pub struct RevokeAndAck {
     pub channel_id: ChannelId,
+    #[serde(with = "::human_readable::hex")]
     pub revocation_preimage: [u8; 32],
     pub next_per_commitment_point: PublicKey,
 }
 
-#[derive(Serialize, Deserialize, Eq, PartialEq, Debug)]
+#[derive(Serialize, Deserialize, Eq, PartialEq, Debug, Clone)]
 pub struct UpdateFee {
     channel_id: ChannelId,
     fee: SatoshiPerKiloWeight,
 }
+
+impl UpdateFee {
+    pub fn new(channel_id: ChannelId, fee: SatoshiPerKiloWeight) -> Self {
+        UpdateFee {
+            channel_id: channel_id,
+            fee: fee,
+        }
+    }
+
+    pub fn channel_id(&self) -> &ChannelId {
+        &self.channel_id
+    }
+
+    pub fn fee(&self) -> SatoshiPerKiloWeight {
+        self.fee
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::BinarySD;
+    use rand;
+
+    #[test]
+    fn commitment_signed() {
+        let commitment_signed = CommitmentSigned {
+            channel_id: rand::random(),
+            signature: rand::random(),
+            htlc_signatures: vec![rand::random(), rand::random()],
+        };
+
+        let mut data = Vec::new();
+        BinarySD::serialize(&mut data, &commitment_signed).unwrap();
+        assert_eq!(data.len(), 32 + 64 + 2 + 2 * 64);
+        assert_eq!(&data[96..98], &[0, 2]);
+
+        let restored: CommitmentSigned = BinarySD::deserialize(&data[..]).unwrap();
+        assert_eq!(restored, commitment_signed);
+    }
+}
@@ -1,5 +1,7 @@
 use super::Hash256;
+use super::ShortChannelId;
 use super::ShortChannelIdEncoding;
+use super::ShortChannelIdEncodingType;
 
 #[derive(Serialize, Deserialize, Eq, PartialEq, Debug)]
 pub struct QueryChannelRange {
@@ -27,11 +29,56 @@ pub struct ReplyChannelRange {
     encoded_short_ids: ShortChannelIdEncoding,
 }
 
+impl ReplyChannelRange {
+    pub fn new(
+        query: &QueryChannelRange,
+        complete: bool,
+        ids: Vec<ShortChannelId>,
+        encoding_type: ShortChannelIdEncodingType,
+    ) -> Self {
+        ReplyChannelRange {
+            chain_hash: query.chain_hash.clone(),
+            first_block_height: query.first_block_height,
+            number_of_blocks: query.number_of_blocks,
+            complete: complete,
+            encoded_short_ids: ShortChannelIdEncoding::new(ids, encoding_type),
+        }
+    }
+
+    pub fn complete(&self) -> bool {
+        self.complete
+    }
+
+    pub fn ids(&self) -> &ShortChannelIdEncoding {
+        &self.encoded_short_ids
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use ::BinarySD;
     use super::*;
 
+    #[test]
+    fn query_channel_range_bolt07() {
+        use ::message::Message;
+        use hex;
+
+        // the `query_channel_range` test vector from BOLT 7,
+        // the chain hash `0100...00`, the first block 100000 and 1500 blocks
+        let data = hex::decode("01070100000000000000000000000000000000000000000000000000000000000000000186a0000005dc").unwrap();
+        let mut chain_hash = [0; 32];
+        chain_hash[0] = 1;
+        let query = QueryChannelRange::new(Hash256::from(chain_hash), 100000, 1500);
+
+        let restored: Message = BinarySD::deserialize(&data[..]).unwrap();
+        assert_eq!(restored, Message::QueryChannelRange(query));
+
+        let mut new = Vec::new();
+        BinarySD::serialize(&mut new, &restored).unwrap();
+        assert_eq!(new, data);
+    }
+
     #[test]
     fn reply_channel_range() {
         let v = vec![
@@ -45,5 +92,21 @@ mod tests {
         ];
         let t: ReplyChannelRange = BinarySD::deserialize(&v[..]).unwrap();
         println!("{:?}", t);
+        assert!(t.ids().ids().is_empty());
+    }
+
+    #[test]
+    fn reply_channel_range_zlib() {
+        let query = QueryChannelRange::new(Hash256::BITCOIN_CHAIN_HASH, 500000, 1000);
+        let ids = (0..100u64).map(|i| ShortChannelId::from((500000 + i) << 40)).collect::<Vec<_>>();
+        let reply = ReplyChannelRange::new(&query, true, ids.clone(), ShortChannelIdEncodingType::Zlib);
+
+        let mut data = Vec::new();
+        BinarySD::serialize(&mut data, &reply).unwrap();
+        assert!(data.len() < 32 + 4 + 4 + 1 + 2 + 1 + ids.len() * 8);
+
+        let restored: ReplyChannelRange = BinarySD::deserialize(&data[..]).unwrap();
+        assert_eq!(restored.ids().ids(), ids.as_slice());
+        assert_eq!(restored, reply);
     }
 }
@@ -3,18 +3,85 @@ use super::Hash256;
 use ::UncompressedData;
 use ::SerdeVec;
 
+/// How the short channel ids are encoded, the value is the encoding type byte.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum ShortChannelIdEncodingType {
+    Uncompressed = 0,
+    Zlib = 1,
+}
+
+/// The short channel ids, sorted ascending as the specification requires.
 #[derive(Eq, PartialEq, Debug)]
 pub enum ShortChannelIdEncoding {
     StoredPlain(SerdeVec<ShortChannelId>),
     StoredZlib(UncompressedData<ShortChannelId>),
 }
 
+impl ShortChannelIdEncoding {
+    /// Sorts the ids, drops the duplicates and chooses the encoding.
+    pub fn new(ids: Vec<ShortChannelId>, encoding_type: ShortChannelIdEncodingType) -> Self {
+        use self::ShortChannelIdEncoding::*;
+
+        let mut ids = ids;
+        ids.sort();
+        ids.dedup();
+        match encoding_type {
+            ShortChannelIdEncodingType::Uncompressed => StoredPlain(SerdeVec(ids)),
+            ShortChannelIdEncodingType::Zlib => StoredZlib(UncompressedData(SerdeVec(ids))),
+        }
+    }
+
+    pub fn encoding_type(&self) -> ShortChannelIdEncodingType {
+        use self::ShortChannelIdEncoding::*;
+
+        match self {
+            &StoredPlain(_) => ShortChannelIdEncodingType::Uncompressed,
+            &StoredZlib(_) => ShortChannelIdEncodingType::Zlib,
+        }
+    }
+
+    pub fn ids(&self) -> &[ShortChannelId] {
+        use self::ShortChannelIdEncoding::*;
+
+        match self {
+            &StoredPlain(SerdeVec(ref ids)) => ids.as_slice(),
+            &StoredZlib(UncompressedData(SerdeVec(ref ids))) => ids.as_slice(),
+        }
+    }
+
+    pub fn into_ids(self) -> Vec<ShortChannelId> {
+        use self::ShortChannelIdEncoding::*;
+
+        match self {
+            StoredPlain(SerdeVec(ids)) => ids,
+            StoredZlib(UncompressedData(SerdeVec(ids))) => ids,
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Eq, PartialEq, Debug)]
 pub struct QueryShortChannelIds {
     chain_hash: Hash256,
     ids: ShortChannelIdEncoding,
 }
 
+impl QueryShortChannelIds {
+    pub fn new(chain_hash: Hash256, ids: Vec<ShortChannelId>, encoding_type: ShortChannelIdEncodingType) -> Self {
+        QueryShortChannelIds {
+            chain_hash: chain_hash,
+            ids: ShortChannelIdEncoding::new(ids, encoding_type),
+        }
+    }
+
+    pub fn chain_hash(&self) -> &Hash256 {
+        &self.chain_hash
+    }
+
+    pub fn ids(&self) -> &ShortChannelIdEncoding {
+        &self.ids
+    }
+}
+
 #[derive(Serialize, Deserialize, Eq, PartialEq, Debug)]
 pub struct ReplyShortChannelIdsEnd {
     chain_hash: Hash256,
@@ -24,8 +91,10 @@ pub struct ReplyShortChannelIdsEnd {
 mod serde {
     use ::BinarySD;
     use super::ShortChannelIdEncoding;
+    use super::ShortChannelIdEncodingType;
     use super::ShortChannelId;
     use ::SerdeVec;
+    use ::UncompressedData;
     use ::PackSized;
 
     use serde::Serialize;
@@ -35,11 +104,29 @@ mod serde {
     use serde::ser;
     use serde::de;
 
+    // the human readable form, the ids are not compressed
+    #[derive(Serialize, Deserialize)]
+    struct HumanReadable {
+        encoding: String,
+        ids: Vec<ShortChannelId>,
+    }
+
     impl Serialize for ShortChannelIdEncoding {
         fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error> where S: Serializer {
             use self::ser::SerializeTuple;
             use self::ShortChannelIdEncoding::*;
 
+            if serializer.is_human_readable() {
+                let encoding = match self.encoding_type() {
+                    ShortChannelIdEncodingType::Uncompressed => "uncompressed",
+                    ShortChannelIdEncodingType::Zlib => "zlib",
+                };
+                return HumanReadable {
+                    encoding: encoding.to_owned(),
+                    ids: self.ids().to_vec(),
+                }.serialize(serializer);
+            }
+
             match self {
                 &StoredPlain(ref plain) => {
                     let &SerdeVec(ref data) = plain;
@@ -71,6 +158,18 @@ mod serde {
         fn deserialize<D>(deserializer: D) -> Result<Self, D::Error> where D: Deserializer<'de> {
             use std::fmt;
 
+            if deserializer.is_human_readable() {
+                use self::de::Error;
+
+                let HumanReadable { encoding, ids } = HumanReadable::deserialize(deserializer)?;
+                let encoding_type = match encoding.as_str() {
+                    "uncompressed" => ShortChannelIdEncodingType::Uncompressed,
+                    "zlib" => ShortChannelIdEncodingType::Zlib,
+                    _ => return Err(D::Error::custom(format!("unknown encoding: {}", encoding))),
+                };
+                return Ok(ShortChannelIdEncoding::new(ids, encoding_type));
+            }
+
             struct Visitor;
 
             impl<'de> de::Visitor<'de> for Visitor {
@@ -84,6 +183,13 @@ mod serde {
                 fn visit_byte_buf<E>(self, v: Vec<u8>) -> Result<Self::Value, E> where E: de::Error, {
                     use self::ShortChannelIdEncoding::*;
 
+                    if v.is_empty() {
+                        return Err(E::custom(format!("expected encoding type")));
+                    }
+                    if v[0] == 0 && (v.len() - 1) % ShortChannelId::SIZE != 0 {
+                        return Err(E::custom(format!("cannot assemble integer amount of short channel ids")));
+                    }
+
                     // TODO: optimize me, get rid of copying
                     let mut v = v;
                     let header = v[0];
@@ -93,13 +199,22 @@ mod serde {
                     let _ = BinarySD::serialize(&mut other, &(size as u16)).unwrap();
                     other.append(&mut v);
 
-                    match header {
-                        0 => Ok(StoredPlain(BinarySD::deserialize(&other[..])
-                            .map_err(|e| E::custom(format!("deserialize error: {:?}", e)))?)),
-                        1 => Ok(StoredZlib(BinarySD::deserialize(&other[..])
-                            .map_err(|e| E::custom(format!("deserialize error: {:?}", e)))?)),
-                        _ => Err(E::custom(format!("unknown compression type")))
+                    let encoding = match header {
+                        0 => StoredPlain(BinarySD::deserialize(&other[..])
+                            .map_err(|e| E::custom(format!("deserialize error: {:?}", e)))?),
+                        // the empty zlib data means no ids
+                        1 if size == 0 => StoredZlib(UncompressedData(SerdeVec(Vec::new()))),
+                        1 => StoredZlib(BinarySD::deserialize(&other[..])
+                            .map_err(|e| E::custom(format!("deserialize error: {:?}", e)))?),
+                        _ => return Err(E::custom(format!("unknown compression type"))),
+                    };
+
+                    // the ids must be strictly increasing, so the duplicates are rejected as well
+                    let sorted = encoding.ids().windows(2).all(|pair| pair[0] < pair[1]);
+                    if !sorted {
+                        return Err(E::custom(format!("short channel ids are not strictly increasing")));
                     }
+                    Ok(encoding)
                 }
             }
 
@@ -107,3 +222,116 @@ mod serde {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use ::BinarySD;
+    use super::*;
+
+    use hex;
+
+    // BOLT 7 writes the short channel id as `block x tx x output`, `539268x845x1` is its example,
+    // the other two ids follow it, the compressed form is produced by the reference zlib
+    // with the default compression level, `zlib.compress` gives exactly these bytes
+    const IDS_HEX: &str = "083a8400034d0001083a840004bb0000083aa400000c0001";
+    const IDS_ZLIB_HEX: &str = "789ce3b06a6160f66560e400d22cbb191838ac963030f03030020030940390";
+
+    fn id(block: u64, tx: u64, output: u64) -> ShortChannelId {
+        ShortChannelId::from((block << 40) | (tx << 16) | output)
+    }
+
+    fn ids() -> Vec<ShortChannelId> {
+        vec![id(539268, 845, 1), id(539268, 1211, 0), id(539300, 12, 1)]
+    }
+
+    fn query(encoding_type: u8, encoded: &str) -> Vec<u8> {
+        let encoded = hex::decode(encoded).unwrap();
+        let mut data = vec![0x11; 32];
+        BinarySD::serialize(&mut data, &((encoded.len() + 1) as u16)).unwrap();
+        data.push(encoding_type);
+        data.extend_from_slice(encoded.as_slice());
+        data
+    }
+
+    #[test]
+    fn uncompressed() {
+        let data = query(0, IDS_HEX);
+        let restored: QueryShortChannelIds = BinarySD::deserialize(&data[..]).unwrap();
+        assert_eq!(restored.ids().encoding_type(), ShortChannelIdEncodingType::Uncompressed);
+        assert_eq!(restored.ids().ids(), ids().as_slice());
+
+        let mut new = Vec::new();
+        BinarySD::serialize(&mut new, &restored).unwrap();
+        assert_eq!(new, data);
+    }
+
+    #[test]
+    fn zlib() {
+        let data = query(1, IDS_ZLIB_HEX);
+        let restored: QueryShortChannelIds = BinarySD::deserialize(&data[..]).unwrap();
+        assert_eq!(restored.ids().encoding_type(), ShortChannelIdEncodingType::Zlib);
+        assert_eq!(restored.ids().ids(), ids().as_slice());
+
+        // the compressed bytes might differ, but should decode the same
+        let mut new = Vec::new();
+        BinarySD::serialize(&mut new, &restored).unwrap();
+        let new: QueryShortChannelIds = BinarySD::deserialize(&new[..]).unwrap();
+        assert_eq!(new, restored);
+    }
+
+    #[test]
+    fn choose_encoding() {
+        let mut reversed = ids();
+        reversed.reverse();
+
+        for &encoding_type in [ShortChannelIdEncodingType::Uncompressed, ShortChannelIdEncodingType::Zlib].iter() {
+            let query = QueryShortChannelIds::new(Hash256::from([0x11; 32]), reversed.clone(), encoding_type);
+            assert_eq!(query.ids().ids(), ids().as_slice());
+
+            let mut data = Vec::new();
+            BinarySD::serialize(&mut data, &query).unwrap();
+            assert_eq!(data[34], encoding_type as u8);
+            let restored: QueryShortChannelIds = BinarySD::deserialize(&data[..]).unwrap();
+            assert_eq!(restored, query);
+        }
+    }
+
+    #[test]
+    fn not_sorted() {
+        let encoded = "083aa400000c0001083a8400034d0001";
+        let data = query(0, encoded);
+        assert!(BinarySD::deserialize::<QueryShortChannelIds, _>(&data[..]).is_err());
+    }
+
+    #[test]
+    fn duplicate() {
+        for &encoding_type in [ShortChannelIdEncodingType::Uncompressed, ShortChannelIdEncodingType::Zlib].iter() {
+            // the duplicates are dropped, the query is still valid
+            let mut duplicated = ids();
+            duplicated.push(id(539300, 12, 1));
+            duplicated.insert(0, id(539268, 845, 1));
+            let query = QueryShortChannelIds::new(Hash256::from([0x11; 32]), duplicated, encoding_type);
+            assert_eq!(query.ids().ids(), ids().as_slice());
+
+            let mut data = Vec::new();
+            BinarySD::serialize(&mut data, &query).unwrap();
+            let restored: QueryShortChannelIds = BinarySD::deserialize(&data[..]).unwrap();
+            assert_eq!(restored, query);
+        }
+
+        // the duplicates received from the peer are rejected
+        let data = query(0, "083a8400034d0001083a8400034d0001");
+        assert!(BinarySD::deserialize::<QueryShortChannelIds, _>(&data[..]).is_err());
+    }
+
+    #[test]
+    fn malformed() {
+        // not integer amount of ids
+        let data = query(0, "083aa400000c00");
+        assert!(BinarySD::deserialize::<QueryShortChannelIds, _>(&data[..]).is_err());
+
+        // unknown encoding type
+        let data = query(2, IDS_HEX);
+        assert!(BinarySD::deserialize::<QueryShortChannelIds, _>(&data[..]).is_err());
+    }
+}
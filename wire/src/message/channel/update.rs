@@ -14,7 +14,7 @@ bitflags! {
     }
 }
 
-#[derive(Serialize, Deserialize, Eq, PartialEq, Debug)]
+#[derive(Clone, Serialize, Deserialize, Eq, PartialEq, Debug)]
 pub struct UpdateChannelData {
     hash: Hash256,
     short_channel_id: ShortChannelId,
@@ -14,6 +14,7 @@ use super::MessageSize;
 #[derive(Serialize, Deserialize, Eq, PartialEq, Debug)]
 pub struct Ping {
     pong_length: MessageSize,
+    #[serde(with = "::human_readable::hex")]
     data: Vec<u8>,
 }
 
@@ -61,6 +62,7 @@ impl Ping {
 /// Should fail the channel if
 #[derive(Serialize, Deserialize, Eq, PartialEq, Debug)]
 pub struct Pong {
+    #[serde(with = "::human_readable::hex")]
     data: Vec<u8>,
 }
 
@@ -0,0 +1,402 @@
+use super::Message;
+use super::AnnouncementChannel;
+use super::AnnouncementNode;
+use super::UpdateChannel;
+use super::ChannelUpdateFlags;
+use super::ShortChannelId;
+use super::types::Hash256;
+use super::types::PUBLIC_KEY_SIZE;
+
+use ::BinarySD;
+use ::WireError;
+
+use serde::de::Error;
+
+// SIGNATURE_SIZE is the size of the compact signature
+const SIGNATURE_SIZE: usize = 64;
+
+// HASH_SIZE is the size of the chain hash
+const HASH_SIZE: usize = 32;
+
+fn read_u16(data: &[u8], offset: usize) -> u16 {
+    ((data[offset] as u16) << 8) | (data[offset + 1] as u16)
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    (0..4).fold(0, |a, i| (a << 8) | (data[offset + i] as u32))
+}
+
+fn read_u64(data: &[u8], offset: usize) -> u64 {
+    (0..8).fold(0, |a, i| (a << 8) | (data[offset + i] as u64))
+}
+
+fn read_hash(data: &[u8], offset: usize) -> Hash256 {
+    let mut hash = [0; HASH_SIZE];
+    hash.copy_from_slice(&data[offset..(offset + HASH_SIZE)]);
+    Hash256::from(hash)
+}
+
+fn check_length(data: &[u8], length: usize, name: &str) -> Result<(), WireError> {
+    if data.len() < length {
+        Err(WireError::custom(format!("`{}` is too short: {} < {}", name, data.len(), length)))
+    } else {
+        Ok(())
+    }
+}
+
+/// Double sha256 of the region covered by the signatures of the gossip message,
+/// the same as `DataToSign::hash` of the owned message.
+fn double_sha256(data: &[u8]) -> [u8; 32] {
+    use sha2::Sha256;
+    use digest::FixedOutput;
+    use digest::Input;
+
+    let mut first = Sha256::default();
+    first.process(data);
+    let mut second = Sha256::default();
+    second.process(first.fixed_result().as_slice());
+
+    let mut hash = [0; 32];
+    hash.copy_from_slice(second.fixed_result().as_slice());
+    hash
+}
+
+/// The `AnnouncementChannel` borrowed from the received buffer,
+/// nothing is copied and no signature is parsed until `parse` is called.
+#[derive(Clone, Copy, Debug)]
+pub struct AnnouncementChannelView<'a> {
+    data: &'a [u8],
+    // the offset of the chain hash, the features are of variable length
+    offset: usize,
+}
+
+impl<'a> AnnouncementChannelView<'a> {
+    const SIGNATURES_SIZE: usize = SIGNATURE_SIZE * 4;
+
+    /// The `data` is the payload of the message, without the type.
+    pub fn new(data: &'a [u8]) -> Result<Self, WireError> {
+        let name = "AnnouncementChannel";
+        check_length(data, Self::SIGNATURES_SIZE + 2, name)?;
+        let offset = Self::SIGNATURES_SIZE + 2 + (read_u16(data, Self::SIGNATURES_SIZE) as usize);
+        check_length(data, offset + HASH_SIZE + 8 + PUBLIC_KEY_SIZE * 4, name)?;
+        Ok(AnnouncementChannelView {
+            data: data,
+            offset: offset,
+        })
+    }
+
+    /// The region covered by the signatures, it starts with the features.
+    pub fn signed_data(&self) -> &'a [u8] {
+        &self.data[Self::SIGNATURES_SIZE..]
+    }
+
+    pub fn signed_hash(&self) -> [u8; 32] {
+        double_sha256(self.signed_data())
+    }
+
+    pub fn chain_hash(&self) -> Hash256 {
+        read_hash(self.data, self.offset)
+    }
+
+    pub fn short_channel_id(&self) -> ShortChannelId {
+        ShortChannelId::from(read_u64(self.data, self.offset + HASH_SIZE))
+    }
+
+    /// The serialized node ids of the channel parties, they are not validated.
+    pub fn node_id(&self) -> (&'a [u8], &'a [u8]) {
+        let offset = self.offset + HASH_SIZE + 8;
+        (
+            &self.data[offset..(offset + PUBLIC_KEY_SIZE)],
+            &self.data[(offset + PUBLIC_KEY_SIZE)..(offset + PUBLIC_KEY_SIZE * 2)],
+        )
+    }
+
+    /// Full parsing, the signatures still should be checked.
+    pub fn parse(&self) -> Result<AnnouncementChannel, WireError> {
+        BinarySD::deserialize(self.data)
+    }
+}
+
+/// The `UpdateChannel` borrowed from the received buffer.
+#[derive(Clone, Copy, Debug)]
+pub struct UpdateChannelView<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> UpdateChannelView<'a> {
+    // chain hash, short channel id, timestamp, flags, time lock delta,
+    // htlc minimum, base fee and fee rate
+    const SIGNED_SIZE: usize = HASH_SIZE + 8 + 4 + 2 + 2 + 8 + 4 + 4;
+
+    pub fn new(data: &'a [u8]) -> Result<Self, WireError> {
+        check_length(data, SIGNATURE_SIZE + Self::SIGNED_SIZE, "UpdateChannel")?;
+        Ok(UpdateChannelView {
+            data: data,
+        })
+    }
+
+    pub fn signed_data(&self) -> &'a [u8] {
+        &self.data[SIGNATURE_SIZE..]
+    }
+
+    pub fn signed_hash(&self) -> [u8; 32] {
+        double_sha256(self.signed_data())
+    }
+
+    pub fn chain_hash(&self) -> Hash256 {
+        read_hash(self.data, SIGNATURE_SIZE)
+    }
+
+    pub fn short_channel_id(&self) -> ShortChannelId {
+        ShortChannelId::from(read_u64(self.data, SIGNATURE_SIZE + HASH_SIZE))
+    }
+
+    pub fn timestamp(&self) -> u32 {
+        read_u32(self.data, SIGNATURE_SIZE + HASH_SIZE + 8)
+    }
+
+    pub fn flags(&self) -> ChannelUpdateFlags {
+        ChannelUpdateFlags::from_bits_truncate(read_u16(self.data, SIGNATURE_SIZE + HASH_SIZE + 12))
+    }
+
+    pub fn parse(&self) -> Result<UpdateChannel, WireError> {
+        BinarySD::deserialize(self.data)
+    }
+}
+
+/// The `AnnouncementNode` borrowed from the received buffer.
+#[derive(Clone, Copy, Debug)]
+pub struct AnnouncementNodeView<'a> {
+    data: &'a [u8],
+    // the offset of the timestamp, the features are of variable length
+    offset: usize,
+}
+
+impl<'a> AnnouncementNodeView<'a> {
+    pub fn new(data: &'a [u8]) -> Result<Self, WireError> {
+        let name = "AnnouncementNode";
+        check_length(data, SIGNATURE_SIZE + 2, name)?;
+        let offset = SIGNATURE_SIZE + 2 + (read_u16(data, SIGNATURE_SIZE) as usize);
+        // timestamp, node id, color, alias and the length of the addresses
+        check_length(data, offset + 4 + PUBLIC_KEY_SIZE + 3 + 32 + 2, name)?;
+        Ok(AnnouncementNodeView {
+            data: data,
+            offset: offset,
+        })
+    }
+
+    pub fn signed_data(&self) -> &'a [u8] {
+        &self.data[SIGNATURE_SIZE..]
+    }
+
+    pub fn signed_hash(&self) -> [u8; 32] {
+        double_sha256(self.signed_data())
+    }
+
+    pub fn timestamp(&self) -> u32 {
+        read_u32(self.data, self.offset)
+    }
+
+    /// The serialized node id, it is not validated.
+    pub fn node_id(&self) -> &'a [u8] {
+        &self.data[(self.offset + 4)..(self.offset + 4 + PUBLIC_KEY_SIZE)]
+    }
+
+    pub fn parse(&self) -> Result<AnnouncementNode, WireError> {
+        BinarySD::deserialize(self.data)
+    }
+}
+
+/// The gossip message borrowed from the received buffer. The graph sync receives
+/// a lot of messages it already knows, the cheap fields of the view tell
+/// if the message is new, so only the new message is parsed and its signatures checked.
+#[derive(Clone, Copy, Debug)]
+pub enum GossipView<'a> {
+    AnnouncementChannel(AnnouncementChannelView<'a>),
+    AnnouncementNode(AnnouncementNodeView<'a>),
+    UpdateChannel(UpdateChannelView<'a>),
+}
+
+impl<'a> GossipView<'a> {
+    /// The `data` is the whole message, including the type.
+    /// Returns `None` if it is not a gossip message, it should be decoded as usual.
+    pub fn new(data: &'a [u8]) -> Result<Option<Self>, WireError> {
+        check_length(data, 2, "message type")?;
+        let payload = &data[2..];
+        match read_u16(data, 0) {
+            256 => AnnouncementChannelView::new(payload).map(GossipView::AnnouncementChannel).map(Some),
+            257 => AnnouncementNodeView::new(payload).map(GossipView::AnnouncementNode).map(Some),
+            258 => UpdateChannelView::new(payload).map(GossipView::UpdateChannel).map(Some),
+            _ => Ok(None),
+        }
+    }
+
+    pub fn signed_hash(&self) -> [u8; 32] {
+        match self {
+            &GossipView::AnnouncementChannel(ref v) => v.signed_hash(),
+            &GossipView::AnnouncementNode(ref v) => v.signed_hash(),
+            &GossipView::UpdateChannel(ref v) => v.signed_hash(),
+        }
+    }
+
+    /// Full parsing of the message.
+    pub fn parse(&self) -> Result<Message, WireError> {
+        match self {
+            &GossipView::AnnouncementChannel(ref v) => v.parse().map(Message::AnnouncementChannel),
+            &GossipView::AnnouncementNode(ref v) => v.parse().map(Message::AnnouncementNode),
+            &GossipView::UpdateChannel(ref v) => v.parse().map(Message::UpdateChannel),
+        }
+    }
+}
+
+/// Tells if the gossip message carries something new, the decoder skips
+/// the message that is not new, see `MessageConsumer::gossip_filter`.
+pub trait GossipFilter: Send + Sync {
+    fn is_new(&self, view: &GossipView) -> bool;
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use super::super::DataToSign;
+    use super::super::Signed;
+    use super::super::SignedData;
+    use super::super::UpdateChannelData;
+    use super::super::types::{SecretKey, PublicKey};
+    use secp256k1::Secp256k1;
+    use rand;
+
+    #[test]
+    fn update_channel() {
+        let secret: SecretKey = rand::random();
+        let context = Secp256k1::new();
+        let public = PublicKey::from(::secp256k1::PublicKey::from_secret_key(&context, secret.as_ref()).unwrap());
+
+        let mut data = Vec::new();
+        data.extend_from_slice(&<[u8; 32]>::from(Hash256::BITCOIN_CHAIN_HASH)[..]);
+        data.extend_from_slice(&[0, 1, 145, 0, 0, 1, 0, 0]);
+        data.extend_from_slice(&[91, 146, 153, 17]);
+        data.extend_from_slice(&[0, 1]);
+        data.extend_from_slice(&[0; 2 + 8 + 4 + 4]);
+        let data: UpdateChannelData = BinarySD::deserialize(data.as_slice()).unwrap();
+        let update: UpdateChannel = Signed::sign(SignedData(data), &secret).unwrap();
+
+        let mut message = Vec::new();
+        BinarySD::serialize(&mut message, &Message::UpdateChannel(update.clone())).unwrap();
+        let view = match GossipView::new(message.as_slice()).unwrap() {
+            Some(GossipView::UpdateChannel(view)) => view,
+            v => panic!("unexpected view: {:?}", v),
+        };
+
+        assert_eq!(view.chain_hash(), Hash256::BITCOIN_CHAIN_HASH);
+        assert_eq!(view.short_channel_id(), ShortChannelId::from(0x0001_9100_0001_0000));
+        assert_eq!(view.timestamp(), 0x5b929911);
+        assert_eq!(view.flags(), ChannelUpdateFlags::DIRECTION);
+        assert_eq!(&view.signed_hash()[..], &update.hash().unwrap()[..]);
+        assert_eq!(view.parse().unwrap().verify(&public).unwrap(), update.value);
+
+        // the truncated message is rejected before reading the fields
+        assert!(GossipView::new(&message[..100]).is_err());
+    }
+
+    #[test]
+    fn announcement_channel() {
+        let v = vec![
+            1, 0,
+
+            169, 177, 196, 25, 57, 80, 208, 176, 113, 192, 129, 194, 129, 60, 75, 12,
+            21, 77, 188, 167, 162, 88, 249, 147, 231, 18, 208, 195, 174, 189, 240, 95,
+            66, 108, 150, 147, 28, 77, 128, 69, 220, 78, 55, 45, 9, 120, 107, 254,
+            154, 144, 165, 228, 138, 174, 67, 16, 90, 251, 148, 174, 188, 40, 216, 163,
+
+            67, 115, 33, 54, 65, 131, 154, 187, 92, 226, 78, 198, 212, 93, 223, 21,
+            144, 23, 40, 58, 253, 210, 118, 240, 234, 246, 211, 83, 4, 42, 57, 55,
+            44, 231, 165, 215, 225, 114, 189, 99, 152, 241, 28, 69, 98, 36, 77, 240,
+            114, 117, 137, 137, 43, 40, 197, 122, 204, 118, 250, 86, 53, 126, 9, 154,
+
+            227, 178, 2, 243, 149, 135, 164, 247, 119, 8, 47, 214, 101, 138, 142, 71,
+            238, 246, 115, 116, 111, 204, 23, 56, 137, 242, 32, 9, 193, 227, 7, 96,
+            87, 154, 148, 14, 10, 143, 6, 44, 60, 186, 158, 171, 49, 31, 67, 18,
+            69, 82, 223, 147, 47, 251, 152, 172, 55, 128, 80, 185, 36, 161, 114, 70,
+
+            22, 193, 28, 214, 13, 181, 133, 248, 78, 134, 16, 44, 150, 133, 241, 129,
+            82, 231, 247, 160, 106, 6, 231, 242, 125, 97, 79, 59, 94, 47, 201, 90,
+            105, 171, 176, 101, 155, 38, 181, 222, 239, 138, 217, 90, 194, 85, 36, 49,
+            125, 184, 112, 152, 123, 14, 232, 246, 241, 126, 176, 138, 200, 5, 243, 63,
+
+            0, 0,
+
+            246, 122, 215, 105, 93, 155, 102, 42, 114, 255, 61, 142, 219, 187, 45, 224,
+            191, 166, 123, 19, 151, 75, 185, 145, 13, 17, 109, 92, 189, 134, 62, 104,
+
+            0, 1, 145, 0, 0, 1, 0, 0,
+
+            2, 248, 43, 81, 169, 251, 145, 163, 38, 87, 140, 176, 226, 78, 83, 136, 4, 246, 201, 235, 41, 126, 214, 0, 138, 132, 211, 64, 135, 97, 227, 175, 200,
+            3, 138, 59, 70, 133, 145, 48, 34, 87, 182, 67, 158, 181, 248, 107, 90, 90, 147, 24, 111, 103, 186, 235, 35, 222, 132, 178, 111, 201, 198, 152, 199, 181,
+            2, 68, 105, 45, 3, 43, 50, 104, 202, 38, 212, 250, 56, 173, 171, 55, 92, 149, 152, 44, 32, 44, 81, 36, 216, 168, 154, 73, 142, 101, 247, 192, 48,
+            2, 44, 199, 59, 73, 153, 4, 138, 110, 45, 6, 200, 74, 184, 2, 205, 187, 124, 135, 83, 223, 253, 42, 27, 173, 32, 91, 76, 212, 219, 161, 117, 40,
+        ];
+
+        let view = match GossipView::new(v.as_slice()).unwrap() {
+            Some(GossipView::AnnouncementChannel(view)) => view,
+            v => panic!("unexpected view: {:?}", v),
+        };
+        let owned = view.parse().unwrap();
+        let data = owned.as_ref_data();
+        assert_eq!(view.chain_hash(), data.hash().clone());
+        assert_eq!(view.short_channel_id(), ShortChannelId::from(0x0001_9100_0001_0000));
+        assert_eq!(view.short_channel_id(), data.id().clone());
+        assert_eq!(view.node_id().0, &data.node_id.0.as_ref().serialize()[..]);
+        assert_eq!(view.node_id().1, &data.node_id.1.as_ref().serialize()[..]);
+        assert_eq!(&view.signed_hash()[..], &owned.hash().unwrap()[..]);
+        assert!(owned.check_signatures().is_ok());
+
+        // the features are of variable length, the length is checked before the fields are read
+        assert!(GossipView::new(&v[..(v.len() - 1)]).is_err());
+        let mut long_features = v.clone();
+        long_features[2 + 256 + 1] = 0xff;
+        assert!(GossipView::new(long_features.as_slice()).is_err());
+    }
+
+    #[test]
+    fn announcement_node() {
+        let v = vec![
+            1, 1,
+
+            79, 96, 163, 97, 39, 255, 19, 117, 81, 200, 19, 41, 245, 33, 183, 23,
+            28, 117, 14, 1, 141, 75, 90, 129, 255, 60, 26, 118, 68, 184, 134, 161,
+            126, 127, 211, 53, 155, 124, 197, 63, 10, 126, 221, 18, 251, 234, 168, 147,
+            99, 251, 20, 136, 113, 32, 227, 211, 223, 70, 153, 226, 149, 241, 132, 217,
+
+            0, 0,
+
+            91, 146, 153, 17,
+
+            3,
+            106, 249, 251, 226, 207, 167, 82, 189, 243, 143, 88, 203, 80, 85, 198, 149,
+            217, 117, 60, 137, 4, 146, 41, 195, 185, 21, 143, 141, 208, 29, 135, 204,
+
+            51, 153, 255,
+
+            48, 51, 54, 97, 102, 57, 102, 98, 101, 50, 99, 102, 97, 55, 53, 50,
+            98, 100, 102, 51, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+
+            0, 0
+        ];
+
+        let view = match GossipView::new(v.as_slice()).unwrap() {
+            Some(GossipView::AnnouncementNode(view)) => view,
+            v => panic!("unexpected view: {:?}", v),
+        };
+        let owned = view.parse().unwrap();
+        assert_eq!(view.timestamp(), owned.value.0.timestamp);
+        assert_eq!(view.node_id(), &owned.value.0.node_id.as_ref().serialize()[..]);
+        assert_eq!(&view.signed_hash()[..], &owned.hash().unwrap()[..]);
+    }
+
+    #[test]
+    fn not_gossip() {
+        assert!(GossipView::new(&[0, 18, 0, 0]).unwrap().is_none());
+        assert!(GossipView::new(&[1]).is_err());
+    }
+}
@@ -22,6 +22,18 @@ pub use self::gossip_timestamp_range::*;
 mod signed;
 pub use self::signed::*;
 
+mod onion_message;
+pub use self::onion_message::*;
+
+mod gossip_view;
+pub use self::gossip_view::*;
+
+mod unknown;
+pub use self::unknown::Unknown;
+pub use self::unknown::Custom;
+pub use self::unknown::UnknownEvenType;
+use self::unknown::RawPayload;
+
 use serde::Serialize;
 use serde::Serializer;
 use serde::Deserialize;
@@ -29,8 +41,17 @@ use serde::Deserializer;
 use serde::ser;
 use serde::de;
 
+use bincode::ErrorKind;
+
+use serde_json;
+use hex;
+
 use std::u16;
 use std::fmt;
+use std::io;
+
+use super::WireError;
+use super::BinarySD;
 
 pub type MessageSize = u16;
 
@@ -40,6 +61,8 @@ macro_rules! message {
         #[derive(Eq, PartialEq)]
         pub enum $name {
             $($variant($variant),)*
+            Unknown(Unknown),
+            Custom(Custom),
         }
 
         impl $name {
@@ -61,8 +84,7 @@ macro_rules! message {
                 };
 
                 let unknown_err = |t| {
-                    let temp = format!("runtime type: `{}` is unknown", t);
-                    <A::Error as de::Error>::custom(temp)
+                    <A::Error as de::Error>::custom(UnknownEvenType(t))
                 };
 
                 let runtime_type = payload.next_element()?.ok_or(notype_err)?;
@@ -78,6 +100,22 @@ macro_rules! message {
                                 })
                         },
                     )*
+                    // the application protocol decides what to do, even if the type is even
+                    t @ _ if self::Custom::is_custom_type(t) => {
+                        payload.next_element()
+                            .map(|i| {
+                                let RawPayload(data) = i.unwrap_or(RawPayload(Vec::new()));
+                                Custom(self::Custom::new(t, data).unwrap())
+                            })
+                    },
+                    // it is ok to be odd
+                    t @ _ if t % 2 == 1 => {
+                        payload.next_element()
+                            .map(|i| {
+                                let RawPayload(data) = i.unwrap_or(RawPayload(Vec::new()));
+                                Unknown(self::Unknown::new(t, data))
+                            })
+                    },
                     t @ _ => Err(unknown_err(t)),
                 }
             }
@@ -93,6 +131,80 @@ macro_rules! message {
                             consumer.serialize_field("payload", payload)
                         },
                     )*
+                    &Unknown(ref unknown) => {
+                        consumer.serialize_field("type", &unknown.message_type())?;
+                        consumer.serialize_field("payload", &RawPayload(unknown.payload().to_vec()))
+                    },
+                    &Custom(ref custom) => {
+                        consumer.serialize_field("type", &custom.message_type())?;
+                        consumer.serialize_field("payload", &RawPayload(custom.payload().to_vec()))
+                    },
+                }
+            }
+
+            /// Whether the implementation knows how to interpret the message of the type.
+            pub fn is_known_type(message_type: u16) -> bool {
+                match message_type {
+                    $($rtt => true,)*
+                    _ => false,
+                }
+            }
+
+            /// The name of the message in the human readable representation.
+            pub fn name(&self) -> &'static str {
+                use self::$name::*;
+                match self {
+                    $(&$variant(_) => stringify!($variant),)*
+                    &Unknown(_) => "Unknown",
+                    &Custom(_) => "Custom",
+                }
+            }
+
+            /// The type of the message as it is on the wire.
+            pub fn message_type(&self) -> u16 {
+                use self::$name::*;
+                match self {
+                    $(&$variant(_) => $rtt,)*
+                    &Unknown(ref unknown) => unknown.message_type(),
+                    &Custom(ref custom) => custom.message_type(),
+                }
+            }
+
+            fn write_human_readable<A>(&self, consumer: &mut A) -> Result<(), A::Error> where
+                A: ser::SerializeStruct
+            {
+                use self::$name::*;
+                consumer.serialize_field("type", &self.message_type())?;
+                consumer.serialize_field("name", self.name())?;
+                match self {
+                    $(
+                        &$variant(ref payload) => consumer.serialize_field("payload", payload),
+                    )*
+                    &Unknown(ref unknown) => consumer.serialize_field("payload", &hex::encode(unknown.payload())),
+                    &Custom(ref custom) => consumer.serialize_field("payload", &hex::encode(custom.payload())),
+                }
+            }
+
+            fn read_human_readable(runtime_type: u16, payload: serde_json::Value) -> Result<Self, serde_json::Error> {
+                use serde::de::Error;
+
+                let raw_payload = |payload: serde_json::Value| -> Result<Vec<u8>, serde_json::Error> {
+                    let string: String = serde_json::from_value(payload)?;
+                    hex::decode(string.as_str()).map_err(serde_json::Error::custom)
+                };
+
+                use self::$name::*;
+                match runtime_type {
+                    $(
+                        $rtt => serde_json::from_value(payload).map($variant),
+                    )*
+                    t @ _ if self::Custom::is_custom_type(t) => {
+                        raw_payload(payload).map(|data| Custom(self::Custom::new(t, data).unwrap()))
+                    },
+                    t @ _ if t % 2 == 1 => {
+                        raw_payload(payload).map(|data| Unknown(self::Unknown::new(t, data)))
+                    },
+                    t @ _ => Err(serde_json::Error::custom(UnknownEvenType(t))),
                 }
             }
 
@@ -105,6 +217,22 @@ macro_rules! message {
                     }
                 }
             )*
+
+            pub fn as_unknown(self) -> Option<Unknown> {
+                use self::$name::*;
+                match self {
+                    Unknown(t) => Some(t),
+                    _ => None,
+                }
+            }
+
+            pub fn as_custom(self) -> Option<Custom> {
+                use self::$name::*;
+                match self {
+                    Custom(t) => Some(t),
+                    _ => None,
+                }
+            }
         }
 
         impl fmt::Debug for $name {
@@ -116,6 +244,12 @@ macro_rules! message {
                             write!(f, "{:?}", payload)
                         },
                     )*
+                    &Unknown(ref unknown) => {
+                        write!(f, "{:?}", unknown)
+                    },
+                    &Custom(ref custom) => {
+                        write!(f, "{:?}", custom)
+                    },
                 }
 
             }
@@ -128,6 +262,7 @@ macro_rules! message {
 /// Implements `Eq`, `Debug`, `Serialize`, `Deserialize`
 message! {
     pub enum Message {
+        Warning(1u16, as_warning),
         Init(16u16, as_init),
         Error(17u16, as_error),
         Ping(18u16, as_ping),
@@ -155,7 +290,43 @@ message! {
         ReplyShortChannelIdsEnd(262u16, as_reply_short_channel_ids_end),
         QueryChannelRange(263u16, as_query_channel_range),
         ReplyChannelRange(264u16, as_reply_channel_range),
-        GossipTimestampRange(265u16, as_gossip_timestamp_range)
+        GossipTimestampRange(265u16, as_gossip_timestamp_range),
+        OnionMessage(513u16, as_onion_message)
+    }
+}
+
+impl Message {
+    /// Reads the message from the whole payload of the transport frame.
+    /// The message of unknown even type is the `UnknownEvenType` error,
+    /// use `unknown_even_type` to get it from the result.
+    pub fn from_bytes(data: &[u8]) -> Result<Self, WireError> {
+        let message_type: u16 = BinarySD::deserialize(data)?;
+        let is_unknown_even = !Self::is_known_type(message_type)
+            && !Custom::is_custom_type(message_type)
+            && message_type % 2 == 0;
+        if is_unknown_even {
+            let error = io::Error::new(io::ErrorKind::InvalidData, UnknownEvenType(message_type));
+            return Err(WireError::from(error));
+        }
+
+        BinarySD::deserialize(data)
+    }
+
+    /// Returns the type if the error is caused by the message of unknown even type,
+    /// such message cannot be ignored, the connection should be failed.
+    pub fn unknown_even_type(error: &WireError) -> Option<UnknownEvenType> {
+        match &**error {
+            &ErrorKind::Io(ref e) => e.get_ref()
+                .and_then(|e| e.downcast_ref::<UnknownEvenType>())
+                .cloned(),
+            _ => None,
+        }
+    }
+}
+
+impl From<Custom> for Message {
+    fn from(v: Custom) -> Self {
+        Message::Custom(v)
     }
 }
 
@@ -163,6 +334,13 @@ impl Serialize for Message {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error> where S: Serializer {
         use self::ser::SerializeStruct;
 
+        // `{ "type": 16, "name": "Init", "payload": { ... } }`
+        if serializer.is_human_readable() {
+            let mut s_struct = serializer.serialize_struct("Message", 3)?;
+            self.write_human_readable(&mut s_struct)?;
+            return s_struct.end();
+        }
+
         // The names provided only for documentation, serializer drops it
         let mut s_struct = serializer.serialize_struct("Message", 2)?;
         self.write_into(&mut s_struct)?;
@@ -174,6 +352,21 @@ impl<'de> Deserialize<'de> for Message {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error> where D: Deserializer<'de> {
         use std::fmt;
 
+        // the type defines how to interpret the payload, the name is only informative
+        if deserializer.is_human_readable() {
+            use self::de::Error;
+
+            #[derive(Deserialize)]
+            struct HumanReadable {
+                #[serde(rename = "type")]
+                runtime_type: u16,
+                payload: serde_json::Value,
+            }
+
+            let HumanReadable { runtime_type, payload } = HumanReadable::deserialize(deserializer)?;
+            return Message::read_human_readable(runtime_type, payload).map_err(D::Error::custom);
+        }
+
         struct Visitor;
 
         impl<'de> de::Visitor<'de> for Visitor {
@@ -204,6 +397,19 @@ mod tests {
 
     use hex;
 
+    const OPEN_CHANNEL_HEX: &'static str =
+        "002006226e46111a0b59caaf126043eb5bbf28c34f3a5e332a1fc7b2b73cf188\
+        910f3283054b8d351cfd58a790cb502069a64c40e226a0d228eae7e83e316dd2\
+        791700000000000186a00000000000000000000000000000023d0000000005e6\
+        9ec000000000000003e800000000000003e8000030d4009001e302d254a0bc14\
+        d7c990d4c40e806bcaffc022ead28ba88eaa5450ef90565119020102859c2c7c\
+        7c0495198371dc0cb1329fdeca223972aeb089af2895c33b180cc6a20265ae92\
+        1bd8cd25b7c62eda488b0f87955b3df36ccdb72cb0c75336cc8d5dc7030363b7\
+        cea6090e2f78a67a29a7cc5b351695a0dc6c0f2bbf14dc9098ed6074a3230213\
+        f314dcc6dbdaea4fac352277f55d53f873901477d80b8d2da794b411e5102202\
+        e19840efe9d300361f2624dfb5516f254bc6381be106c85ba0d3c429a54166c2\
+        01";
+
     #[test]
     fn deserialize_init() {
         let data = vec![0, 16, 0, 0, 0, 1, 138];
@@ -216,22 +422,103 @@ mod tests {
 
     #[test]
     fn open_channel_message(){
-        let msg_bytes = hex::decode(
-            "002006226e46111a0b59caaf126043eb5bbf28c34f3a5e332a1fc7b2b73cf188\
-            910f3283054b8d351cfd58a790cb502069a64c40e226a0d228eae7e83e316dd2\
-            791700000000000186a00000000000000000000000000000023d0000000005e6\
-            9ec000000000000003e800000000000003e8000030d4009001e302d254a0bc14\
-            d7c990d4c40e806bcaffc022ead28ba88eaa5450ef90565119020102859c2c7c\
-            7c0495198371dc0cb1329fdeca223972aeb089af2895c33b180cc6a20265ae92\
-            1bd8cd25b7c62eda488b0f87955b3df36ccdb72cb0c75336cc8d5dc7030363b7\
-            cea6090e2f78a67a29a7cc5b351695a0dc6c0f2bbf14dc9098ed6074a3230213\
-            f314dcc6dbdaea4fac352277f55d53f873901477d80b8d2da794b411e5102202\
-            e19840efe9d300361f2624dfb5516f254bc6381be106c85ba0d3c429a54166c2\
-            01"
-        ).unwrap();
+        let msg_bytes = hex::decode(OPEN_CHANNEL_HEX).unwrap();
         let restored: Result<Message, _> = BinarySD::deserialize(msg_bytes.as_slice());
         let message = restored.unwrap();
         let open_channel = message.as_open_channel();
         assert!(open_channel.is_some());
     }
+
+    #[test]
+    fn unknown_odd_type() {
+        let data = vec![0x7f, 0xff, 1, 2, 3];
+
+        let message: Message = BinarySD::deserialize(&data[..]).unwrap();
+        let unknown = message.as_unknown().unwrap();
+        assert_eq!(unknown.message_type(), 0x7fff);
+        assert_eq!(unknown.payload(), &[1, 2, 3]);
+
+        let mut new = Vec::new();
+        BinarySD::serialize(&mut new, &Message::Unknown(unknown)).unwrap();
+        assert_eq!(data, new);
+
+        let message: Message = BinarySD::deserialize(&[0, 3][..]).unwrap();
+        assert_eq!(message.as_unknown(), Some(Unknown::new(3, vec![])));
+    }
+
+    #[test]
+    fn unknown_even_type() {
+        let data = vec![0x7f, 0xfe, 1, 2, 3];
+
+        let error = Message::from_bytes(&data[..]).unwrap_err();
+        assert_eq!(Message::unknown_even_type(&error), Some(UnknownEvenType(0x7ffe)));
+        assert!(BinarySD::deserialize::<Message, _>(&data[..]).is_err());
+
+        // malformed message of known type is something different
+        let error = Message::from_bytes(&[0, 18, 0][..]).unwrap_err();
+        assert_eq!(Message::unknown_even_type(&error), None);
+
+        // known, odd and custom types are read as usual
+        assert!(Message::from_bytes(&[0, 18, 0, 0, 0, 0][..]).unwrap().as_ping().is_some());
+        assert!(Message::from_bytes(&[0x7f, 0xff, 1][..]).unwrap().as_unknown().is_some());
+        assert!(Message::from_bytes(&[0x80, 0x02, 1][..]).unwrap().as_custom().is_some());
+    }
+
+    #[test]
+    fn custom_type() {
+        let data = vec![0x80, 0x02, 1, 2, 3];
+
+        // even custom type is not an error, the application protocol decides
+        let message: Message = BinarySD::deserialize(&data[..]).unwrap();
+        let custom = message.as_custom().unwrap();
+        assert_eq!(custom.message_type(), 0x8002);
+        assert!(!custom.is_odd());
+        assert_eq!(custom.payload(), &[1, 2, 3]);
+
+        let mut new = Vec::new();
+        BinarySD::serialize(&mut new, &Message::Custom(custom)).unwrap();
+        assert_eq!(data, new);
+
+        assert!(Custom::new(0x7fff, vec![]).is_none());
+        assert!(Custom::new(0xffff, vec![]).is_some());
+    }
+
+    #[test]
+    fn human_readable() {
+        use ::JsonSD;
+
+        let vectors = vec![
+            // init
+            "0010000000018a",
+            // error with text
+            "0011000000000000000000000000000000000000000000000000000000000000\
+            0000000474657374",
+            // query short channel ids, zlib encoded
+            "0105000000000019d6689c085ae165831e934ff763ae46a2a6c172b3f1b60a8c\
+            e26f002001789ce3b06a6160f66560e400d22cbb191838ac963030f03030020030940390",
+            // unknown odd and custom
+            "7fff010203",
+            "8002010203",
+            OPEN_CHANNEL_HEX,
+        ];
+
+        for vector in vectors {
+            let data = hex::decode(vector).unwrap();
+            let message: Message = BinarySD::deserialize(&data[..]).unwrap();
+            let json = JsonSD::to_string(&message).unwrap();
+            let restored: Message = JsonSD::from_str(json.as_str()).unwrap();
+            assert_eq!(restored, message);
+
+            let mut new = Vec::new();
+            BinarySD::serialize(&mut new, &restored).unwrap();
+            assert_eq!(new, data);
+        }
+
+        let json = JsonSD::to_string(&BinarySD::deserialize::<Message, _>(&[0, 16, 0, 0, 0, 1, 138][..]).unwrap()).unwrap();
+        assert!(json.contains("\"name\": \"Init\""));
+        assert!(json.contains("\"initial_routing_sync_optional\""));
+
+        let error = JsonSD::from_str::<Message>("{ \"type\": 32766, \"payload\": \"\" }").unwrap_err();
+        assert!(error.to_string().starts_with(&UnknownEvenType(32766).to_string()));
+    }
 }
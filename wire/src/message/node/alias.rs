@@ -30,6 +30,14 @@ mod serde {
 
     impl<'de> Deserialize<'de> for NodeAlias {
         fn deserialize<D>(deserializer: D) -> Result<Self, D::Error> where D: Deserializer<'de> {
+            if deserializer.is_human_readable() {
+                let string = String::deserialize(deserializer)?;
+                if string.len() > SIZE || string.contains('\0') {
+                    return Err(<D::Error as de::Error>::custom(format!("the alias `{}` cannot be encoded", string)));
+                }
+                return Ok(NodeAlias(string));
+            }
+
             let buffer: [u8; SIZE] = Deserialize::deserialize(deserializer)?;
             let len = buffer.iter()
                 .try_fold(0, |state, &b| if b == 0 { Err(state) } else { Ok(state + 1) })
@@ -43,10 +51,15 @@ mod serde {
     impl Serialize for NodeAlias {
         fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error> where S: Serializer {
             let &NodeAlias(ref s) = self;
+            if serializer.is_human_readable() {
+                return serializer.serialize_str(s.as_str());
+            }
+
             let v = s.as_bytes();
 
-            if v.len() >= SIZE {
-                Err(<S::Error as ser::Error>::custom(format!("the size {} of alias overflows {} limit", v.len(), SIZE - 1)))
+            // the alias of exactly 32 bytes has no trailing zero
+            if v.len() > SIZE {
+                Err(<S::Error as ser::Error>::custom(format!("the size {} of alias overflows {} limit", v.len(), SIZE)))
             } else {
                 let mut buffer = [0u8; SIZE];
                 buffer[0..v.len()].copy_from_slice(v);
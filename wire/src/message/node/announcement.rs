@@ -25,6 +25,37 @@ pub struct AnnouncementNodeData {
     pub address: SerdeVec<Address>,
 }
 
+impl AnnouncementNodeData {
+    pub fn new(node_id: PublicKey, timestamp: u32, color: Color, alias: NodeAlias) -> Self {
+        AnnouncementNodeData {
+            features: RawFeatureVector::new(),
+            timestamp: timestamp,
+            node_id: node_id,
+            color: color,
+            alias: alias,
+            address: SerdeVec(Vec::new()),
+        }
+    }
+
+    pub fn with_features(self, features: RawFeatureVector) -> Self {
+        let mut s = self;
+        s.features = features;
+        s
+    }
+
+    /// Adds the address where the node accepts the connections,
+    /// the onion service address as well.
+    pub fn with_address(self, address: Address) -> Self {
+        let mut s = self;
+        s.address.0.push(address);
+        s
+    }
+
+    pub fn features(&self) -> &RawFeatureVector {
+        &self.features
+    }
+}
+
 #[derive(Clone, Serialize, Deserialize, Eq, PartialEq, Debug)]
 pub struct IpV4 {
     pub ip: [u8; 4],
@@ -99,19 +130,98 @@ impl Address {
 impl PackSized for Address {
     const SIZE: usize = 0;
 
+    // the size on the wire, `mem::size_of` counts the alignment padding
     fn pack_size(&self) -> usize {
-        use std::mem;
-
         match self {
-            &Address::IpV4(_) => 1 + mem::size_of::<IpV4>(),
-            &Address::IpV6(_) => 1 + mem::size_of::<IpV6>(),
-            &Address::TorV2(_) => 1 + mem::size_of::<TorV2>(),
-            &Address::TorV3(_) => 1 + mem::size_of::<TorV3>(),
+            &Address::IpV4(_) => 1 + 4 + 2,
+            &Address::IpV6(_) => 1 + 16 + 2,
+            &Address::TorV2(_) => 1 + 10 + 2,
+            &Address::TorV3(_) => 1 + 32 + 2 + 1 + 2,
             _ => 1,
         }
     }
 }
 
+mod display {
+    use super::Address;
+    use super::TorV2;
+    use super::TorV3;
+
+    use ::human_readable::base32_encode;
+    use ::human_readable::base32_decode;
+
+    use std::fmt;
+    use std::str::FromStr;
+    use std::net::SocketAddr;
+
+    // TOR_V2_SIZE is the size of the decoded tor v2 onion host
+    const TOR_V2_SIZE: usize = 10;
+
+    // TOR_V3_SIZE is the size of the decoded tor v3 onion host:
+    // public key, checksum and version
+    const TOR_V3_SIZE: usize = 35;
+
+    impl fmt::Display for Address {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            use self::Address::*;
+
+            match self {
+                &None => write!(f, "none"),
+                &IpV4(_) | &IpV6(_) => write!(f, "{}", self.clone().into_socket_address().unwrap()),
+                &TorV2(ref v) => write!(f, "{}.onion:{}", base32_encode(&v.onion[..]), v.port),
+                &TorV3(ref v) => {
+                    let mut host = v.public_key.to_vec();
+                    host.extend_from_slice(&[(v.checksum >> 8) as u8, v.checksum as u8, v.version]);
+                    write!(f, "{}.onion:{}", base32_encode(host.as_slice()), v.port)
+                },
+            }
+        }
+    }
+
+    impl FromStr for Address {
+        type Err = String;
+
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            let error = || format!("cannot parse the address: `{}`", s);
+
+            if s == "none" {
+                return Ok(Address::None);
+            }
+
+            let mut parts = s.rsplitn(2, ':');
+            let port = parts.next().ok_or_else(error)?;
+            let host = parts.next().ok_or_else(error)?;
+            if !host.ends_with(".onion") {
+                return SocketAddr::from_str(s).map(Address::from).map_err(|_| error());
+            }
+
+            let port = port.parse::<u16>().map_err(|_| error())?;
+            let data = base32_decode(&host[..(host.len() - ".onion".len())]).ok_or_else(error)?;
+            match data.len() {
+                TOR_V2_SIZE => {
+                    let mut onion = [0; TOR_V2_SIZE];
+                    onion.copy_from_slice(data.as_slice());
+                    Ok(Address::TorV2(TorV2 {
+                        onion: onion,
+                        port: port,
+                    }))
+                },
+                TOR_V3_SIZE => {
+                    let mut public_key = [0; 32];
+                    public_key.copy_from_slice(&data[0..32]);
+                    Ok(Address::TorV3(TorV3 {
+                        public_key: public_key,
+                        checksum: ((data[32] as u16) << 8) | (data[33] as u16),
+                        version: data[34],
+                        port: port,
+                    }))
+                },
+                _ => Err(error()),
+            }
+        }
+    }
+}
+
 mod serde {
     use super::Address;
 
@@ -127,6 +237,13 @@ mod serde {
         fn deserialize<D>(deserializer: D) -> Result<Self, D::Error> where D: Deserializer<'de> {
             use self::de::SeqAccess;
 
+            // `1.2.3.4:9735`, `[::1]:9735`, `<base32>.onion:9735` or `none`
+            if deserializer.is_human_readable() {
+                return String::deserialize(deserializer)?
+                    .parse()
+                    .map_err(<D::Error as de::Error>::custom);
+            }
+
             struct V;
 
             impl<'de> de::Visitor<'de> for V {
@@ -168,6 +285,10 @@ mod serde {
             use self::ser::SerializeTuple;
             use self::Address::*;
 
+            if serializer.is_human_readable() {
+                return serializer.serialize_str(self.to_string().as_str());
+            }
+
             let mut tuple = serializer.serialize_tuple(2)?;
 
             match self {
@@ -229,4 +350,59 @@ mod tests {
         let t: AnnouncementNode = BinarySD::deserialize(&v[..]).unwrap();
         println!("{:?}", t);
     }
+
+    #[test]
+    fn address_human_readable() {
+        use ::JsonSD;
+
+        let addresses = vec![
+            Address::None,
+            Address::localhost(9735),
+            Address::from("[::1]:9735".parse::<SocketAddr>().unwrap()),
+            Address::TorV2(TorV2 {
+                onion: [1, 2, 3, 4, 5, 6, 7, 8, 9, 10],
+                port: 9735,
+            }),
+            Address::TorV3(TorV3 {
+                public_key: [7; 32],
+                checksum: 0x1234,
+                version: 3,
+                port: 9735,
+            }),
+        ];
+
+        for address in addresses {
+            let json = JsonSD::to_string(&address).unwrap();
+            assert_eq!(JsonSD::from_str::<Address>(json.as_str()).unwrap(), address);
+        }
+
+        assert_eq!(Address::localhost(9735).to_string(), "127.0.0.1:9735");
+        assert!("abc.onion:9735".parse::<Address>().is_err());
+        assert!("127.0.0.1".parse::<Address>().is_err());
+    }
+
+    #[test]
+    fn address_pack_size() {
+        let addresses = vec![
+            Address::None,
+            Address::localhost(9735),
+            Address::from("[::1]:9735".parse::<SocketAddr>().unwrap()),
+            Address::TorV2(TorV2 {
+                onion: [1; 10],
+                port: 9735,
+            }),
+            Address::TorV3(TorV3 {
+                public_key: [7; 32],
+                checksum: 0x1234,
+                version: 3,
+                port: 9735,
+            }),
+        ];
+
+        for address in addresses {
+            let mut data = Vec::new();
+            BinarySD::serialize(&mut data, &address).unwrap();
+            assert_eq!(data.len(), address.pack_size());
+        }
+    }
 }
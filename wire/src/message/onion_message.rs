@@ -0,0 +1,40 @@
+use super::types::PublicKey;
+use super::types::OnionMessagePacket;
+
+/// The message routed through the onion of blinded hops, BOLT 4.
+/// Each hop peels its layer using the `blinding_point`,
+/// and sends the rest to the next hop with the next blinding point.
+#[derive(Serialize, Deserialize, Clone, Eq, PartialEq, Debug)]
+pub struct OnionMessage {
+    pub blinding_point: PublicKey,
+    pub onion_message_packet: OnionMessagePacket,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::BinarySD;
+    use ::JsonSD;
+    use ::Message;
+    use rand;
+
+    #[test]
+    fn variable_size() {
+        let message = OnionMessage {
+            blinding_point: rand::random(),
+            onion_message_packet: OnionMessagePacket::new(vec![0x42; 100]),
+        };
+
+        let mut data = Vec::new();
+        BinarySD::serialize(&mut data, &Message::OnionMessage(message.clone())).unwrap();
+        assert_eq!(&data[..2], &[0x02, 0x01]);
+        assert_eq!(&data[35..37], &[0x00, 100]);
+        assert_eq!(data.len(), 2 + 33 + 2 + 100);
+        let restored: Message = BinarySD::deserialize(data.as_slice()).unwrap();
+        assert_eq!(restored.as_onion_message(), Some(message.clone()));
+
+        let json = JsonSD::to_string(&Message::OnionMessage(message.clone())).unwrap();
+        let restored: Message = JsonSD::from_str(json.as_str()).unwrap();
+        assert_eq!(restored.as_onion_message(), Some(message));
+    }
+}
@@ -1,10 +1,29 @@
 use super::types::RawFeatureVector;
+use super::types::FeatureError;
+use super::types::TlvStream;
+use super::types::TlvKnownTypes;
+use super::types::Hash256;
 use super::channel::ChannelId;
 
+use std::fmt;
+
+#[derive(Default, Clone, Eq, PartialEq, Debug)]
+pub struct InitTlv;
+
+impl InitTlv {
+    pub const NETWORKS: u64 = 1;
+    pub const REMOTE_ADDRESS: u64 = 3;
+}
+
+impl TlvKnownTypes for InitTlv {
+    const KNOWN: &'static [u64] = &[Self::NETWORKS, Self::REMOTE_ADDRESS];
+}
+
 #[derive(Serialize, Deserialize, Eq, PartialEq, Debug)]
 pub struct Init {
     global_features: RawFeatureVector,
     local_features: RawFeatureVector,
+    extension: TlvStream<InitTlv>,
 }
 
 impl Init {
@@ -12,21 +31,164 @@ impl Init {
         Init {
             global_features: global_features as _,
             local_features: local_features as _,
+            extension: TlvStream::new(),
         }
     }
+
+    pub fn global_features(&self) -> &RawFeatureVector {
+        &self.global_features
+    }
+
+    pub fn local_features(&self) -> &RawFeatureVector {
+        &self.local_features
+    }
+
+    /// Both global and local features, the receiver should not distinguish them.
+    pub fn features(&self) -> RawFeatureVector {
+        self.global_features.union(&self.local_features)
+    }
+
+    /// Negotiates the features with the peer, `self` is the local init message.
+    /// The error is the reason to disconnect.
+    pub fn negotiate(&self, remote: &Init) -> Result<RawFeatureVector, FeatureError> {
+        self.features().negotiate(&remote.features())
+    }
+
+    pub fn extension(&self) -> &TlvStream<InitTlv> {
+        &self.extension
+    }
+
+    pub fn extension_mut(&mut self) -> &mut TlvStream<InitTlv> {
+        &mut self.extension
+    }
+
+    /// The chains the node is interested in, `None` means any chain.
+    pub fn networks(&self) -> Option<Vec<Hash256>> {
+        self.extension.get(InitTlv::NETWORKS).map(|data| {
+            data.chunks(32)
+                .filter(|chunk| chunk.len() == 32)
+                .map(|chunk| {
+                    let mut hash = [0; 32];
+                    hash.copy_from_slice(chunk);
+                    Hash256::from(hash)
+                })
+                .collect()
+        })
+    }
+
+    pub fn set_networks(&mut self, networks: &[Hash256]) {
+        let data = networks.iter()
+            .fold(Vec::with_capacity(networks.len() * 32), |mut data, &hash| {
+                data.extend_from_slice(&<[u8; 32]>::from(hash)[..]);
+                data
+            });
+        self.extension.insert(InitTlv::NETWORKS, data);
+    }
 }
 
-#[derive(Serialize, Deserialize, Eq, PartialEq, Debug)]
+/// Tells the peer that the channel is failed, the zero channel id
+/// means all channels, the data is usually the printable ascii text.
+#[derive(Serialize, Deserialize, Clone, Eq, PartialEq, Debug)]
 pub struct Error {
     channel_id: ChannelId,
+    #[serde(with = "::human_readable::hex")]
+    data: Vec<u8>,
+}
+
+/// Same as `Error`, but the channel should not be failed,
+/// the peer might close the connection.
+#[derive(Serialize, Deserialize, Clone, Eq, PartialEq, Debug)]
+pub struct Warning {
+    channel_id: ChannelId,
+    #[serde(with = "::human_readable::hex")]
     data: Vec<u8>,
 }
 
+/// Checks if each byte is in the printable range of ascii, space is allowed.
+pub fn is_printable_ascii(data: &[u8]) -> bool {
+    data.iter().all(|&b| b >= 0x20 && b <= 0x7e)
+}
+
+macro_rules! channel_notice {
+    ($name:ident) => {
+        impl $name {
+            pub fn new(channel_id: ChannelId, data: Vec<u8>) -> Self {
+                $name {
+                    channel_id: channel_id,
+                    data: data,
+                }
+            }
+
+            /// Concerns all channels with the peer.
+            pub fn all_channels(data: Vec<u8>) -> Self {
+                Self::new(ChannelId::all(), data)
+            }
+
+            pub fn text(channel_id: ChannelId, text: &str) -> Self {
+                Self::new(channel_id, text.as_bytes().to_vec())
+            }
+
+            pub fn all_channels_text(text: &str) -> Self {
+                Self::text(ChannelId::all(), text)
+            }
+
+            pub fn is_all_channels(&self) -> bool {
+                self.channel_id == ChannelId::all()
+            }
+
+            /// Returns `None` if the message concerns all channels.
+            pub fn channel_id(&self) -> Option<&ChannelId> {
+                if self.is_all_channels() {
+                    None
+                } else {
+                    Some(&self.channel_id)
+                }
+            }
+
+            pub fn data(&self) -> &[u8] {
+                self.data.as_slice()
+            }
+
+            /// Returns the data as a string if it is printable ascii.
+            pub fn printable(&self) -> Option<&str> {
+                use std::str;
+
+                if is_printable_ascii(self.data.as_slice()) {
+                    str::from_utf8(self.data.as_slice()).ok()
+                } else {
+                    None
+                }
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                if self.is_all_channels() {
+                    write!(f, "all channels: ")?;
+                } else {
+                    write!(f, "channel {:?}: ", self.channel_id)?;
+                }
+
+                match self.printable() {
+                    Some(text) => write!(f, "{}", text),
+                    None => write!(f, "{:?}", self.data),
+                }
+            }
+        }
+    }
+}
+
+channel_notice!(Error);
+channel_notice!(Warning);
+
 #[cfg(test)]
 mod test {
     use ::serde_facade::BinarySD;
 
     use super::Init;
+    use super::Error;
+    use super::Warning;
+    use super::super::channel::ChannelId;
     use super::super::types::RawFeatureVector;
     use super::super::types::FeatureBit;
 
@@ -50,4 +212,87 @@ mod test {
 
         assert_eq!(init, new);
     }
+
+    #[test]
+    fn test_init_extension() {
+        use super::super::types::Hash256;
+
+        let mut init = Init::new(RawFeatureVector::new(), RawFeatureVector::new());
+        init.set_networks(&[Hash256::BITCOIN_CHAIN_HASH]);
+
+        let mut data = Vec::<u8>::new();
+        BinarySD::serialize(&mut data, &init).unwrap();
+        // two empty feature vectors, type, length and the hash
+        assert_eq!(data.len(), 2 + 2 + 1 + 1 + 32);
+
+        let new: Init = BinarySD::deserialize(&data[..]).unwrap();
+        assert_eq!(new.networks(), Some(vec![Hash256::BITCOIN_CHAIN_HASH]));
+        assert_eq!(init, new);
+
+        // unknown odd type is ignored, unknown even type is rejected
+        let mut odd = data[..4].to_vec();
+        odd.extend_from_slice(&[5, 1, 0]);
+        let new: Init = BinarySD::deserialize(&odd[..]).unwrap();
+        assert!(new.extension().is_empty());
+
+        let mut even = data[..4].to_vec();
+        even.extend_from_slice(&[6, 1, 0]);
+        assert!(BinarySD::deserialize::<Init, _>(&even[..]).is_err());
+    }
+
+    #[test]
+    fn test_init_negotiate() {
+        use self::FeatureBit::*;
+
+        let local = Init::new(
+            RawFeatureVector::new(),
+            RawFeatureVector::new()
+                .set_bit(VarOnionOptinOptional)
+                .set_bit(PaymentSecretOptional)
+        );
+        let remote = Init::new(
+            RawFeatureVector::new().set_bit(VarOnionOptinRequired),
+            RawFeatureVector::new().set_bit(PaymentSecretOptional)
+        );
+        assert_eq!(local.negotiate(&remote), Ok(RawFeatureVector::new()
+            .set_bit(VarOnionOptinRequired)
+            .set_bit(PaymentSecretOptional)));
+
+        // the dependency is missing
+        let remote = Init::new(RawFeatureVector::new(), RawFeatureVector::new().set_bit(PaymentSecretOptional));
+        assert!(local.negotiate(&remote).is_err());
+    }
+
+    #[test]
+    fn test_error() {
+        let error = Error::all_channels_text("internal error");
+        assert!(error.is_all_channels());
+        assert_eq!(error.channel_id(), None);
+        assert_eq!(error.printable(), Some("internal error"));
+        assert_eq!(format!("{}", error), "all channels: internal error");
+
+        let mut data = Vec::<u8>::new();
+        BinarySD::serialize(&mut data, &error).unwrap();
+        assert_eq!(&data[32..34], &[0, 14]);
+        let new: Error = BinarySD::deserialize(&data[..]).unwrap();
+        assert_eq!(error, new);
+
+        let channel_id = ChannelId::from([1; 32]);
+        let error = Error::new(channel_id, vec![0, 1, 2]);
+        assert_eq!(error.channel_id(), Some(&channel_id));
+        assert_eq!(error.printable(), None);
+    }
+
+    #[test]
+    fn test_warning() {
+        use super::super::Message;
+
+        let warning = Warning::text(ChannelId::from([1; 32]), "fee is too low");
+        let mut data = Vec::<u8>::new();
+        BinarySD::serialize(&mut data, &Message::Warning(warning.clone())).unwrap();
+        assert_eq!(&data[0..2], &[0, 1]);
+
+        let new: Message = BinarySD::deserialize(&data[..]).unwrap();
+        assert_eq!(new.as_warning(), Some(warning));
+    }
 }
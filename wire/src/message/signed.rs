@@ -8,6 +8,9 @@ use super::types::SecretKey;
 use super::types::PublicKey;
 use super::types::Signature;
 
+use ::NodeSigner;
+use ::SignerError;
+
 #[derive(Clone, Serialize, Deserialize, Eq, PartialEq, Debug)]
 pub struct Signed<T> where T: DataToSign {
     pub signature: Signature,
@@ -64,6 +67,7 @@ impl<T> DataToSign for Signed<T> where T: DataToSign {
 pub enum SignError {
     WireError(WireError),
     Secp256k1Error(Secp256k1Error),
+    SignerError(SignerError),
     IncorrectSignature,
 }
 
@@ -83,6 +87,19 @@ impl<T> Signed<T> where T: DataToSign {
             })
     }
 
+    /// Signs by the node key, the `signer` may keep it out of the process.
+    pub fn sign_by<S>(value: T, signer: &S) -> Result<Self, SignError> where S: NodeSigner + ?Sized {
+        let msg = value.hash()?;
+        signer.sign(&msg)
+            .map_err(SignError::SignerError)
+            .map(|s| {
+                Signed {
+                    signature: Signature::from(s),
+                    value: value,
+                }
+            })
+    }
+
     fn check(&self, public_key: &PublicKey) -> Result<(), SignError> {
         use secp256k1::Secp256k1;
 
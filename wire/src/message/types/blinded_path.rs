@@ -0,0 +1,161 @@
+use super::PublicKey;
+use super::MilliSatoshi;
+use super::RawFeatureVector;
+
+/// The hop of the blinded path, the data is encrypted for the hop.
+#[derive(Serialize, Deserialize, Clone, Eq, PartialEq, Debug)]
+pub struct BlindedHop {
+    pub blinded_node_id: PublicKey,
+    #[serde(with = "::human_readable::hex")]
+    pub encrypted_recipient_data: Vec<u8>,
+}
+
+/// The path to the recipient, only the introduction node is known to the sender.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct BlindedPath {
+    pub introduction_node_id: PublicKey,
+    pub blinding_point: PublicKey,
+    pub hops: Vec<BlindedHop>,
+}
+
+/// The aggregated fees and the limits of the blinded path.
+#[derive(Serialize, Deserialize, Clone, Eq, PartialEq, Debug)]
+pub struct BlindedPayInfo {
+    pub base_fee: u32,
+    pub fee_rate: u32,
+    pub time_lock_delta: u16,
+    pub htlc_minimum: MilliSatoshi,
+    pub htlc_maximum: MilliSatoshi,
+    pub features: RawFeatureVector,
+}
+
+mod serde {
+    use super::BlindedPath;
+    use super::BlindedHop;
+    use super::PublicKey;
+
+    use serde::Serialize;
+    use serde::Serializer;
+    use serde::Deserialize;
+    use serde::Deserializer;
+    use serde::de;
+    use serde::ser;
+    use std::fmt;
+    use std::u8;
+
+    #[derive(Serialize, Deserialize)]
+    struct HumanReadable {
+        introduction_node_id: PublicKey,
+        blinding_point: PublicKey,
+        hops: Vec<BlindedHop>,
+    }
+
+    // the number of hops is one byte, unlike the length of other sequences
+    impl Serialize for BlindedPath {
+        fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error> where S: Serializer {
+            use self::ser::SerializeTuple;
+
+            if serializer.is_human_readable() {
+                return HumanReadable {
+                    introduction_node_id: self.introduction_node_id.clone(),
+                    blinding_point: self.blinding_point.clone(),
+                    hops: self.hops.clone(),
+                }.serialize(serializer);
+            }
+
+            if self.hops.len() > u8::MAX as usize {
+                return Err(<S::Error as ser::Error>::custom(format!("too many hops: {}", self.hops.len())));
+            }
+            let mut tuple = serializer.serialize_tuple(3 + self.hops.len())?;
+            tuple.serialize_element(&self.introduction_node_id)?;
+            tuple.serialize_element(&self.blinding_point)?;
+            tuple.serialize_element(&(self.hops.len() as u8))?;
+            for hop in &self.hops {
+                tuple.serialize_element(hop)?;
+            }
+            tuple.end()
+        }
+    }
+
+    impl<'de> Deserialize<'de> for BlindedPath {
+        fn deserialize<D>(deserializer: D) -> Result<Self, D::Error> where D: Deserializer<'de> {
+            if deserializer.is_human_readable() {
+                let HumanReadable { introduction_node_id, blinding_point, hops } = HumanReadable::deserialize(deserializer)?;
+                return Ok(BlindedPath {
+                    introduction_node_id: introduction_node_id,
+                    blinding_point: blinding_point,
+                    hops: hops,
+                });
+            }
+
+            struct V;
+
+            impl<'de> de::Visitor<'de> for V {
+                type Value = BlindedPath;
+
+                fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                    write!(formatter, "introduction node id, blinding point and hops")
+                }
+
+                fn visit_seq<A>(self, seq: A) -> Result<Self::Value, A::Error> where A: de::SeqAccess<'de> {
+                    use self::de::Error;
+
+                    let mut seq = seq;
+                    let end = || A::Error::custom("unexpected end");
+                    let introduction_node_id = seq.next_element()?.ok_or_else(end)?;
+                    let blinding_point = seq.next_element()?.ok_or_else(end)?;
+                    let length: u8 = seq.next_element()?.ok_or_else(end)?;
+                    if length == 0 {
+                        return Err(A::Error::custom("the blinded path has no hops"));
+                    }
+                    let hops = (0..length)
+                        .map(|_| seq.next_element()?.ok_or_else(end))
+                        .collect::<Result<Vec<_>, _>>()?;
+                    Ok(BlindedPath {
+                        introduction_node_id: introduction_node_id,
+                        blinding_point: blinding_point,
+                        hops: hops,
+                    })
+                }
+            }
+
+            deserializer.deserialize_tuple(3 + u8::MAX as usize, V)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::BinarySD;
+    use ::JsonSD;
+    use rand;
+
+    #[test]
+    fn blinded_path() {
+        let path = BlindedPath {
+            introduction_node_id: rand::random(),
+            blinding_point: rand::random(),
+            hops: vec![
+                BlindedHop {
+                    blinded_node_id: rand::random(),
+                    encrypted_recipient_data: vec![1, 2, 3],
+                },
+                BlindedHop {
+                    blinded_node_id: rand::random(),
+                    encrypted_recipient_data: vec![],
+                },
+            ],
+        };
+
+        let mut data = Vec::new();
+        BinarySD::serialize(&mut data, &path).unwrap();
+        assert_eq!(data.len(), 33 + 33 + 1 + (33 + 2 + 3) + (33 + 2));
+        assert_eq!(data[66], 2);
+        let restored: BlindedPath = BinarySD::deserialize(data.as_slice()).unwrap();
+        assert_eq!(restored, path);
+
+        let json = JsonSD::to_string(&path).unwrap();
+        assert_eq!(JsonSD::from_str::<BlindedPath>(json.as_str()).unwrap(), path);
+    }
+}
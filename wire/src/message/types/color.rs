@@ -1,7 +1,9 @@
 use super::common::Module;
 
 #[derive(Clone, Default, Serialize, Deserialize, Eq, PartialEq, Debug)]
+#[serde(transparent)]
 pub struct Color {
+    #[serde(with = "::human_readable::hex")]
     data: [u8; 3],
 }
 
@@ -67,13 +67,20 @@ mod serde {
     use serde::de::Error;
     use std::fmt;
 
+    use hex::encode;
+    use hex::decode;
+
     impl Serialize for Signature {
         fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error> where S: Serializer {
             use serde::ser::SerializeTuple;
             use secp256k1::Secp256k1;
 
-            let mut tuple = serializer.serialize_tuple(SIGNATURE_SIZE)?;
             let data = self.data.serialize_compact(&Secp256k1::new());
+            if serializer.is_human_readable() {
+                return serializer.serialize_str(encode(&data[..]).as_str());
+            }
+
+            let mut tuple = serializer.serialize_tuple(SIGNATURE_SIZE)?;
             for i in 0..SIGNATURE_SIZE {
                 tuple.serialize_element(&data[i])?;
             }
@@ -84,6 +91,15 @@ mod serde {
 
     impl<'de> Deserialize<'de> for Signature {
         fn deserialize<D>(deserializer: D) -> Result<Self, D::Error> where D: Deserializer<'de> {
+            if deserializer.is_human_readable() {
+                use secp256k1::Secp256k1;
+
+                let data = decode(String::deserialize(deserializer)?.as_str()).map_err(D::Error::custom)?;
+                return Secp256k1Signature::from_compact(&Secp256k1::new(), data.as_slice())
+                    .map(Into::into)
+                    .map_err(D::Error::custom);
+            }
+
             struct V;
 
             impl<'de> Visitor<'de> for V {
@@ -117,8 +133,12 @@ mod serde {
         fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error> where S: Serializer {
             use serde::ser::SerializeTuple;
 
-            let mut tuple = serializer.serialize_tuple(PUBLIC_KEY_SIZE)?;
             let array = self.raw.serialize();
+            if serializer.is_human_readable() {
+                return serializer.serialize_str(encode(&array[..]).as_str());
+            }
+
+            let mut tuple = serializer.serialize_tuple(PUBLIC_KEY_SIZE)?;
             for i in 0..PUBLIC_KEY_SIZE {
                 tuple.serialize_element(&array[i])?;
             }
@@ -129,6 +149,15 @@ mod serde {
 
     impl<'de> Deserialize<'de> for PublicKey {
         fn deserialize<D>(deserializer: D) -> Result<Self, D::Error> where D: Deserializer<'de> {
+            if deserializer.is_human_readable() {
+                use secp256k1::Secp256k1;
+
+                let data = decode(String::deserialize(deserializer)?.as_str()).map_err(D::Error::custom)?;
+                return Secp256k1PublicKey::from_slice(&Secp256k1::new(), data.as_slice())
+                    .map(|v| PublicKey { raw: v })
+                    .map_err(|e| D::Error::custom(format!("secp256k1::PublicKey cannot be created from such data: {:?}", e)));
+            }
+
             struct V;
 
             impl<'de> Visitor<'de> for V {
@@ -161,6 +190,15 @@ mod serde {
 
     impl<'de> Deserialize<'de> for SecretKey {
         fn deserialize<D>(deserializer: D) -> Result<Self, D::Error> where D: Deserializer<'de> {
+            if deserializer.is_human_readable() {
+                use secp256k1::Secp256k1;
+
+                let data = decode(String::deserialize(deserializer)?.as_str()).map_err(D::Error::custom)?;
+                return Secp256k1SecretKey::from_slice(&Secp256k1::new(), data.as_slice())
+                    .map(|v| SecretKey { raw: v })
+                    .map_err(|e| D::Error::custom(format!("secp256k1::SecretKey cannot be created from such data: {:?}", e)));
+            }
+
             struct V;
 
             impl<'de> Visitor<'de> for V {
@@ -247,6 +285,7 @@ mod secp256k1 {
 
     use secp256k1::Secp256k1;
     use secp256k1::PublicKey;
+    use secp256k1::SecretKey;
     use secp256k1::Signature;
 
     impl LpdPublicKey {
@@ -271,6 +310,14 @@ mod secp256k1 {
         }
     }
 
+    impl From<SecretKey> for LpdPrivateKey {
+        fn from(v: SecretKey) -> Self {
+            LpdPrivateKey {
+                raw: v,
+            }
+        }
+    }
+
     impl From<Signature> for LpdSignature {
         fn from(v: Signature) -> Self {
             LpdSignature {
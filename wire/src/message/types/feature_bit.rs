@@ -1,40 +1,124 @@
 use super::Wrapper;
 
-#[derive(Clone, Eq, PartialEq, Hash, Ord, PartialOrd, Debug)]
-pub enum FeatureBit {
-    DataLossProtectRequired,
-    DataLossProtectOptional,
-    InitialRoutingSync,
-    GossipQueriesRequired,
-    GossipQueriesOptional,
-    Custom(u16),
+/// Where the feature bit may be present.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub enum FeatureContext {
+    Init,
+    NodeAnnouncement,
+    ChannelAnnouncement,
+    Invoice,
 }
 
-impl From<u16> for FeatureBit {
-    fn from(raw: u16) -> Self {
-        use self::FeatureBit::*;
-        match raw {
-            0 => DataLossProtectRequired,
-            1 => DataLossProtectOptional,
-            3 => InitialRoutingSync,
-            6 => GossipQueriesRequired,
-            7 => GossipQueriesOptional,
-            c @ _ => Custom(c),
+macro_rules! feature_bits {
+    (pub enum $name:ident {
+        $($variant:ident($bit:expr, $feature_name:expr, [$($context:ident),*], [$($dependency:ident),*])),*
+    }) => {
+        #[derive(Clone, Eq, PartialEq, Hash, Ord, PartialOrd, Debug)]
+        pub enum $name {
+            $($variant,)*
+            Custom(u16),
+        }
+
+        impl From<u16> for $name {
+            fn from(raw: u16) -> Self {
+                use self::$name::*;
+                match raw {
+                    $($bit => $variant,)*
+                    c @ _ => Custom(c),
+                }
+            }
+        }
+
+        impl From<$name> for u16 {
+            fn from(feature_bit: $name) -> Self {
+                use self::$name::*;
+                match feature_bit {
+                    $($variant => $bit,)*
+                    Custom(c) => c,
+                }
+            }
+        }
+
+        impl $name {
+            /// All named feature bits.
+            pub fn known() -> Vec<Self> {
+                use self::$name::*;
+                vec![$($variant),*]
+            }
+
+            /// The name of the feature as it is written in BOLT 9,
+            /// the same for both required and optional bits.
+            pub fn name(&self) -> Option<&'static str> {
+                use self::$name::*;
+                match self {
+                    $(&$variant => Some($feature_name),)*
+                    &Custom(_) => None,
+                }
+            }
+
+            /// The contexts where the feature bit may be set, empty for unknown bit.
+            pub fn contexts(&self) -> &'static [FeatureContext] {
+                use self::$name::*;
+                match self {
+                    $(&$variant => &[$(FeatureContext::$context),*],)*
+                    &Custom(_) => &[],
+                }
+            }
+
+            /// The features that should be set, either required or optional,
+            /// if this feature is set.
+            pub fn dependencies(&self) -> Vec<Self> {
+                use self::$name::*;
+                match self {
+                    $(&$variant => vec![$($dependency),*],)*
+                    &Custom(_) => vec![],
+                }
+            }
         }
     }
 }
 
-impl From<FeatureBit> for u16 {
-    fn from(feature_bit: FeatureBit) -> Self {
-        use self::FeatureBit::*;
-        match feature_bit {
-            DataLossProtectRequired => 0,
-            DataLossProtectOptional => 1,
-            InitialRoutingSync => 3,
-            GossipQueriesRequired => 6,
-            GossipQueriesOptional => 7,
-            Custom(c) => c,
-        }
+feature_bits! {
+    pub enum FeatureBit {
+        DataLossProtectRequired(0, "option_data_loss_protect", [Init, NodeAnnouncement], []),
+        DataLossProtectOptional(1, "option_data_loss_protect", [Init, NodeAnnouncement], []),
+        InitialRoutingSync(3, "initial_routing_sync", [Init], []),
+        UpfrontShutdownScriptRequired(4, "option_upfront_shutdown_script", [Init, NodeAnnouncement], []),
+        UpfrontShutdownScriptOptional(5, "option_upfront_shutdown_script", [Init, NodeAnnouncement], []),
+        GossipQueriesRequired(6, "gossip_queries", [Init, NodeAnnouncement], []),
+        GossipQueriesOptional(7, "gossip_queries", [Init, NodeAnnouncement], []),
+        VarOnionOptinRequired(8, "var_onion_optin", [Init, NodeAnnouncement, Invoice], []),
+        VarOnionOptinOptional(9, "var_onion_optin", [Init, NodeAnnouncement, Invoice], []),
+        GossipQueriesExRequired(10, "gossip_queries_ex", [Init, NodeAnnouncement], [GossipQueriesOptional]),
+        GossipQueriesExOptional(11, "gossip_queries_ex", [Init, NodeAnnouncement], [GossipQueriesOptional]),
+        StaticRemoteKeyRequired(12, "option_static_remotekey", [Init, NodeAnnouncement], []),
+        StaticRemoteKeyOptional(13, "option_static_remotekey", [Init, NodeAnnouncement], []),
+        PaymentSecretRequired(14, "payment_secret", [Init, NodeAnnouncement, Invoice], [VarOnionOptinOptional]),
+        PaymentSecretOptional(15, "payment_secret", [Init, NodeAnnouncement, Invoice], [VarOnionOptinOptional]),
+        BasicMppRequired(16, "basic_mpp", [Init, NodeAnnouncement, Invoice], [PaymentSecretOptional]),
+        BasicMppOptional(17, "basic_mpp", [Init, NodeAnnouncement, Invoice], [PaymentSecretOptional]),
+        WumboRequired(18, "option_support_large_channel", [Init, NodeAnnouncement], []),
+        WumboOptional(19, "option_support_large_channel", [Init, NodeAnnouncement], []),
+        AnchorOutputsRequired(20, "option_anchor_outputs", [Init, NodeAnnouncement], [StaticRemoteKeyOptional]),
+        AnchorOutputsOptional(21, "option_anchor_outputs", [Init, NodeAnnouncement], [StaticRemoteKeyOptional]),
+        AnchorsZeroFeeHtlcTxRequired(22, "option_anchors_zero_fee_htlc_tx", [Init, NodeAnnouncement], [StaticRemoteKeyOptional]),
+        AnchorsZeroFeeHtlcTxOptional(23, "option_anchors_zero_fee_htlc_tx", [Init, NodeAnnouncement], [StaticRemoteKeyOptional]),
+        RouteBlindingRequired(24, "option_route_blinding", [Init, NodeAnnouncement, Invoice], []),
+        RouteBlindingOptional(25, "option_route_blinding", [Init, NodeAnnouncement, Invoice], []),
+        ShutdownAnySegwitRequired(26, "option_shutdown_anysegwit", [Init, NodeAnnouncement], []),
+        ShutdownAnySegwitOptional(27, "option_shutdown_anysegwit", [Init, NodeAnnouncement], []),
+        DualFundRequired(28, "option_dual_fund", [Init, NodeAnnouncement], []),
+        DualFundOptional(29, "option_dual_fund", [Init, NodeAnnouncement], []),
+        OnionMessagesRequired(38, "option_onion_messages", [Init, NodeAnnouncement], []),
+        OnionMessagesOptional(39, "option_onion_messages", [Init, NodeAnnouncement], []),
+        ChannelTypeRequired(44, "option_channel_type", [Init, NodeAnnouncement], []),
+        ChannelTypeOptional(45, "option_channel_type", [Init, NodeAnnouncement], []),
+        ScidAliasRequired(46, "option_scid_alias", [Init, NodeAnnouncement], []),
+        ScidAliasOptional(47, "option_scid_alias", [Init, NodeAnnouncement], []),
+        PaymentMetadataRequired(48, "option_payment_metadata", [Invoice], []),
+        PaymentMetadataOptional(49, "option_payment_metadata", [Invoice], []),
+        ZeroConfRequired(50, "option_zeroconf", [Init, NodeAnnouncement], [ScidAliasOptional]),
+        ZeroConfOptional(51, "option_zeroconf", [Init, NodeAnnouncement], [ScidAliasOptional])
     }
 }
 
@@ -54,11 +138,30 @@ impl FeatureBit {
     pub fn is_required(&self) -> bool {
         u16::from(self.clone()) & 1 == 0
     }
+
+    pub fn is_known(&self) -> bool {
+        self.name().is_some()
+    }
+
+    /// The required bit of the pair.
+    pub fn required(&self) -> Self {
+        self.clone().fmap(|x| x & !1)
+    }
+
+    /// The optional bit of the pair.
+    pub fn optional(&self) -> Self {
+        self.clone().fmap(|x| x | 1)
+    }
+
+    pub fn allowed_in(&self, context: FeatureContext) -> bool {
+        self.contexts().contains(&context)
+    }
 }
 
 #[cfg(test)]
 mod test {
     use super::FeatureBit;
+    use super::FeatureContext;
     use super::Wrapper;
 
     #[test]
@@ -68,4 +171,25 @@ mod test {
 
         assert_eq!(feature_bit.clone().fmap(|x| x), feature_bit);
     }
+
+    #[test]
+    fn catalogue() {
+        for feature_bit in FeatureBit::known() {
+            let raw = u16::from(feature_bit.clone());
+            assert_eq!(FeatureBit::from(raw), feature_bit);
+            assert!(feature_bit.name().is_some());
+            assert!(!feature_bit.contexts().is_empty());
+            for dependency in feature_bit.dependencies() {
+                assert!(dependency.is_known());
+            }
+        }
+
+        assert_eq!(FeatureBit::from(9), FeatureBit::VarOnionOptinOptional);
+        assert_eq!(FeatureBit::BasicMppRequired.optional(), FeatureBit::BasicMppOptional);
+        assert_eq!(FeatureBit::BasicMppOptional.required(), FeatureBit::BasicMppRequired);
+        assert_eq!(FeatureBit::WumboOptional.name(), Some("option_support_large_channel"));
+        assert!(FeatureBit::from(100).name().is_none());
+        assert!(FeatureBit::InitialRoutingSync.allowed_in(FeatureContext::Init));
+        assert!(!FeatureBit::InitialRoutingSync.allowed_in(FeatureContext::NodeAnnouncement));
+    }
 }
@@ -2,6 +2,8 @@ use std::collections::HashMap;
 
 use super::RawFeatureVector;
 use super::FeatureBit;
+use super::FeatureContext;
+use super::FeatureError;
 
 pub struct FeatureVector {
     raw: RawFeatureVector,
@@ -10,10 +12,15 @@ pub struct FeatureVector {
 
 impl FeatureVector {
     pub fn new() -> Self {
-        FeatureVector {
-            raw: RawFeatureVector::new(),
-            names: HashMap::new(),
-        }
+        FeatureVector::from(RawFeatureVector::new())
+    }
+
+    pub fn raw(&self) -> &RawFeatureVector {
+        &self.raw
+    }
+
+    pub fn validate(&self, context: FeatureContext) -> Result<(), FeatureError> {
+        self.raw.validate(context)
     }
 
     pub fn has(&self, feature_bit: &FeatureBit) -> bool {
@@ -37,3 +44,19 @@ impl FeatureVector {
         }
     }
 }
+
+impl From<RawFeatureVector> for FeatureVector {
+    fn from(raw: RawFeatureVector) -> Self {
+        let names = FeatureBit::known()
+            .into_iter()
+            .filter_map(|feature_bit| {
+                feature_bit.name().map(|name| (feature_bit.clone(), name.to_owned()))
+            })
+            .collect();
+
+        FeatureVector {
+            raw: raw,
+            names: names,
+        }
+    }
+}
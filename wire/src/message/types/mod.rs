@@ -7,6 +7,8 @@ mod crypto_types;
 mod units;
 mod onion_blob;
 mod color;
+mod tlv;
+mod blinded_path;
 
 pub use common_types::*;
 pub use bitcoin_types::*;
@@ -17,7 +19,9 @@ pub use self::common::Module;
 
 // let's export without wildcard
 pub use self::feature_bit::FeatureBit;
+pub use self::feature_bit::FeatureContext;
 pub use self::raw_feature_vector::RawFeatureVector;
+pub use self::raw_feature_vector::FeatureError;
 pub use self::feature_vector::FeatureVector;
 pub use self::crypto_types::PublicKey;
 pub use self::crypto_types::SecretKey;
@@ -29,4 +33,13 @@ pub use self::units::SatoshiPerKiloWeight;
 pub use self::units::CsvDelay;
 pub use self::units::SatoshiPerVByte;
 pub use self::onion_blob::OnionBlob;
+pub use self::onion_blob::OnionMessagePacket;
+pub use self::onion_blob::ONION_PACKET_SIZE;
 pub use self::color::Color;
+pub use self::tlv::BigSize;
+pub use self::tlv::TlvStream;
+pub use self::tlv::TlvKnownTypes;
+pub use self::tlv::NoKnownTypes;
+pub use self::blinded_path::BlindedHop;
+pub use self::blinded_path::BlindedPath;
+pub use self::blinded_path::BlindedPayInfo;
@@ -1,8 +1,59 @@
-
 pub const ONION_PACKET_SIZE: usize = 1366;
 
+/// The onion packet of `UpdateAddHtlc`, it is always `ONION_PACKET_SIZE` bytes,
+/// the onion message carries `OnionMessagePacket` instead.
+#[derive(Clone, Eq, PartialEq)]
 pub struct OnionBlob {
-    data: [u8; ONION_PACKET_SIZE],
+    data: Vec<u8>,
+}
+
+impl OnionBlob {
+    pub fn new(data: [u8; ONION_PACKET_SIZE]) -> Self {
+        OnionBlob {
+            data: data.to_vec(),
+        }
+    }
+
+    /// Returns the data back if it is not `ONION_PACKET_SIZE` bytes.
+    pub fn from_vec(data: Vec<u8>) -> Result<Self, Vec<u8>> {
+        if data.len() != ONION_PACKET_SIZE {
+            return Err(data);
+        }
+        Ok(OnionBlob {
+            data: data,
+        })
+    }
+}
+
+impl AsRef<[u8]> for OnionBlob {
+    fn as_ref(&self) -> &[u8] {
+        self.data.as_slice()
+    }
+}
+
+/// The onion packet of the onion message, it is of any size,
+/// on the wire it is prefixed by its 16 bit length.
+#[derive(Clone, Eq, PartialEq)]
+pub struct OnionMessagePacket {
+    data: Vec<u8>,
+}
+
+impl OnionMessagePacket {
+    pub fn new(data: Vec<u8>) -> Self {
+        OnionMessagePacket {
+            data: data,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+}
+
+impl AsRef<[u8]> for OnionMessagePacket {
+    fn as_ref(&self) -> &[u8] {
+        self.data.as_slice()
+    }
 }
 
 mod serde {
@@ -18,13 +69,20 @@ mod serde {
     use serde::de::Error;
     use std::fmt;
 
+    use hex::encode;
+    use hex::decode;
+
     impl Serialize for OnionBlob {
         fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error> where S: Serializer {
             use serde::ser::SerializeTuple;
 
+            if serializer.is_human_readable() {
+                return serializer.serialize_str(encode(self.data.as_slice()).as_str());
+            }
+
             let mut tuple = serializer.serialize_tuple(ONION_PACKET_SIZE)?;
-            for i in 0..ONION_PACKET_SIZE {
-                tuple.serialize_element(&self.data[i])?;
+            for byte in &self.data {
+                tuple.serialize_element(byte)?;
             }
 
             tuple.end()
@@ -33,6 +91,12 @@ mod serde {
 
     impl<'de> Deserialize<'de> for OnionBlob {
         fn deserialize<D>(deserializer: D) -> Result<Self, D::Error> where D: Deserializer<'de> {
+            if deserializer.is_human_readable() {
+                let data = decode(String::deserialize(deserializer)?.as_str()).map_err(D::Error::custom)?;
+                return OnionBlob::from_vec(data)
+                    .map_err(|data| D::Error::custom(format!("wrong length: {}", data.len())));
+            }
+
             struct V;
 
             impl<'de> Visitor<'de> for V {
@@ -44,18 +108,18 @@ mod serde {
 
                 fn visit_seq<A>(self, seq: A) -> Result<Self::Value, A::Error> where A: SeqAccess<'de> {
                     let mut seq = seq;
-                    let mut blob = OnionBlob {
-                        data: [0; ONION_PACKET_SIZE],
-                    };
-                    for i in 0..ONION_PACKET_SIZE {
+                    let mut data = Vec::with_capacity(ONION_PACKET_SIZE);
+                    for _ in 0..ONION_PACKET_SIZE {
                         if let Some(value) =  seq.next_element()? {
-                            blob.data[i] = value;
+                            data.push(value);
                         } else {
                             return Err(Error::custom("unexpected end"));
                         }
                     }
 
-                    Ok(blob)
+                    Ok(OnionBlob {
+                        data: data,
+                    })
                 }
             }
 
@@ -64,26 +128,43 @@ mod serde {
     }
 }
 
-mod eq {
-    use super::OnionBlob;
-    use super::ONION_PACKET_SIZE;
+mod variable_size {
+    use super::OnionMessagePacket;
+
+    use serde::Serialize;
+    use serde::Serializer;
+    use serde::Deserialize;
+    use serde::Deserializer;
+    use serde::de::Error;
 
-    use std::cmp::Eq;
-    use std::cmp::PartialEq;
+    use hex::encode;
+    use hex::decode;
 
-    impl PartialEq for OnionBlob {
-        fn eq(&self, other: &Self) -> bool {
-            (0..ONION_PACKET_SIZE)
-                .fold(true, |acc, index| acc && self.data[index] == other.data[index])
+    impl Serialize for OnionMessagePacket {
+        fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error> where S: Serializer {
+            if serializer.is_human_readable() {
+                serializer.serialize_str(encode(self.data.as_slice()).as_str())
+            } else {
+                self.data.serialize(serializer)
+            }
         }
     }
 
-    impl Eq for OnionBlob {
+    impl<'de> Deserialize<'de> for OnionMessagePacket {
+        fn deserialize<D>(deserializer: D) -> Result<Self, D::Error> where D: Deserializer<'de> {
+            if deserializer.is_human_readable() {
+                let string = String::deserialize(deserializer)?;
+                decode(string.as_str()).map(OnionMessagePacket::new).map_err(D::Error::custom)
+            } else {
+                Vec::<u8>::deserialize(deserializer).map(OnionMessagePacket::new)
+            }
+        }
     }
 }
 
 mod debug {
     use super::OnionBlob;
+    use super::OnionMessagePacket;
 
     use std::fmt::Debug;
     use std::fmt::Formatter;
@@ -94,4 +175,34 @@ mod debug {
             self.data.fmt(f)
         }
     }
+
+    impl Debug for OnionMessagePacket {
+        fn fmt(&self, f: &mut Formatter) -> Result {
+            self.data.fmt(f)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::BinarySD;
+
+    #[test]
+    fn fixed_size() {
+        assert_eq!(OnionBlob::from_vec(vec![0x42; 100]), Err(vec![0x42; 100]));
+
+        let blob = OnionBlob::from_vec(vec![0x42; ONION_PACKET_SIZE]).unwrap();
+        let mut data = Vec::new();
+        BinarySD::serialize(&mut data, &blob).unwrap();
+        assert_eq!(data.len(), ONION_PACKET_SIZE);
+        let restored: OnionBlob = BinarySD::deserialize(data.as_slice()).unwrap();
+        assert_eq!(restored, blob);
+
+        // the packet of the onion message is of any size
+        let packet = OnionMessagePacket::new(vec![0x42; 100]);
+        let mut data = Vec::new();
+        BinarySD::serialize(&mut data, &packet).unwrap();
+        assert_eq!(data.len(), 2 + 100);
+    }
 }
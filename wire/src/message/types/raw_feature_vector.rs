@@ -6,7 +6,53 @@ use serde::Deserialize;
 use serde::Deserializer;
 
 use super::FeatureBit;
+use super::FeatureContext;
 
+use std::fmt;
+use std::error;
+
+/// The feature vector is not acceptable.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub enum FeatureError {
+    WrongContext {
+        feature_bit: FeatureBit,
+        context: FeatureContext,
+    },
+    MissingDependency {
+        feature_bit: FeatureBit,
+        dependency: FeatureBit,
+    },
+    // the peer requires the feature we do not know
+    UnknownRequired(FeatureBit),
+    // the peer requires the feature we do not support, or vice versa
+    NotSupported(FeatureBit),
+}
+
+impl error::Error for FeatureError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        None
+    }
+}
+
+impl fmt::Display for FeatureError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::FeatureError::*;
+
+        match self {
+            &WrongContext { ref feature_bit, ref context } =>
+                write!(f, "feature: {:?} is not allowed in context: {:?}", feature_bit, context),
+            &MissingDependency { ref feature_bit, ref dependency } =>
+                write!(f, "feature: {:?} requires feature: {:?}", feature_bit, dependency),
+            &UnknownRequired(ref feature_bit) => write!(f, "unknown required feature: {:?}", feature_bit),
+            &NotSupported(ref feature_bit) => write!(f, "feature: {:?} is not supported", feature_bit),
+        }
+    }
+}
+
+/// The set of feature bits. The wire format is big endian as BOLT 9 requires,
+/// the bit 0 is the least significant bit of the last byte, so `init`,
+/// `node_announcement`, `channel_announcement` and the BOLT 12 features
+/// are read the same way by every implementation.
 #[derive(Default, Clone, Eq, PartialEq)]
 pub struct RawFeatureVector {
     set: HashSet<FeatureBit>,
@@ -44,6 +90,84 @@ impl RawFeatureVector {
     pub fn is_set_bit(&self, feature_bit: &FeatureBit) -> bool {
         self.set.contains(feature_bit)
     }
+
+    pub fn iter(&self) -> impl Iterator<Item=&FeatureBit> {
+        self.set.iter()
+    }
+
+    /// Either required or optional bit of the feature is set.
+    pub fn has(&self, feature_bit: &FeatureBit) -> bool {
+        self.is_set_bit(&feature_bit.required()) || self.is_set_bit(&feature_bit.optional())
+    }
+
+    /// The required bit of the feature is set.
+    pub fn requires(&self, feature_bit: &FeatureBit) -> bool {
+        self.is_set_bit(&feature_bit.required())
+    }
+
+    /// Contains bits of both vectors.
+    pub fn union(&self, other: &Self) -> Self {
+        RawFeatureVector {
+            set: self.set.union(&other.set).cloned().collect(),
+        }
+    }
+
+    /// Checks if each known bit is allowed in the context, and its dependencies are set,
+    /// and there is no unknown required bit.
+    pub fn validate(&self, context: FeatureContext) -> Result<(), FeatureError> {
+        let mut bits = self.set.iter().cloned().collect::<Vec<_>>();
+        bits.sort();
+        for feature_bit in bits {
+            if !feature_bit.is_known() {
+                if feature_bit.is_required() {
+                    return Err(FeatureError::UnknownRequired(feature_bit));
+                }
+                continue;
+            }
+
+            if !feature_bit.allowed_in(context) {
+                return Err(FeatureError::WrongContext {
+                    feature_bit: feature_bit,
+                    context: context,
+                });
+            }
+
+            for dependency in feature_bit.dependencies() {
+                if !self.has(&dependency) {
+                    return Err(FeatureError::MissingDependency {
+                        feature_bit: feature_bit,
+                        dependency: dependency,
+                    });
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Negotiates the features in the `init` context, `self` is the local vector.
+    /// The result contains the known features both sides support,
+    /// the feature is required if any side requires it.
+    pub fn negotiate(&self, remote: &Self) -> Result<Self, FeatureError> {
+        remote.validate(FeatureContext::Init)?;
+
+        let mut bits = self.set.union(&remote.set).cloned().collect::<Vec<_>>();
+        bits.sort();
+        bits.into_iter()
+            .filter(|feature_bit| feature_bit.is_known())
+            .fold(Ok(RawFeatureVector::new()), |negotiated, feature_bit| {
+                let negotiated = negotiated?;
+                let (local, remote) = (self.has(&feature_bit), remote.has(&feature_bit));
+                if local && remote {
+                    let required = self.requires(&feature_bit) || remote.requires(&feature_bit);
+                    Ok(negotiated.set_bit(if required { feature_bit.required() } else { feature_bit.optional() }))
+                } else if feature_bit.is_required() {
+                    Err(FeatureError::NotSupported(feature_bit))
+                } else {
+                    Ok(negotiated)
+                }
+            })
+    }
 }
 
 impl From<Vec<u8>> for RawFeatureVector {
@@ -54,7 +178,8 @@ impl From<Vec<u8>> for RawFeatureVector {
             .into_iter()
             .enumerate()
             .fold(feature_vector, |fv, (i, byte): (usize, u8)| {
-                let byte_index = i as u16;
+                // big endian, the last byte contains bits 0-7
+                let byte_index = (len - 1 - i) as u16;
                 (0..Self::BITS).fold(fv, |fv, bit_index| {
                     let global_bit_index = byte_index * RawFeatureVector::BITS + bit_index;
                     let feature_bit = global_bit_index.into();
@@ -75,7 +200,8 @@ impl From<RawFeatureVector> for Vec<u8> {
         match feature_vector.serialize_size() {
             Some(len) => {
                 // map each byte index into actual byte
-                (0..(len as u16)).map(|byte_index|
+                // big endian, the last byte contains bits 0-7
+                (0..(len as u16)).rev().map(|byte_index|
                     // for each bit in byte check
                     // if corresponding feature_bit is contained in the set
                     // and accumulate the presence in the byte
@@ -94,9 +220,40 @@ impl From<RawFeatureVector> for Vec<u8> {
     }
 }
 
+impl FeatureBit {
+    /// The name of the bit in human readable representation,
+    /// e.g. `basic_mpp_optional`, or `bit_100` if the bit is unknown.
+    pub fn bit_name(&self) -> String {
+        match self.name() {
+            Some(name) => {
+                let suffix = if self.is_required() { "required" } else { "optional" };
+                format!("{}_{}", name, suffix)
+            },
+            None => format!("bit_{}", u16::from(self.clone())),
+        }
+    }
+
+    pub fn from_bit_name(bit_name: &str) -> Option<Self> {
+        if bit_name.starts_with("bit_") {
+            return bit_name[4..].parse::<u16>().ok().map(FeatureBit::from);
+        }
+
+        // `initial_routing_sync` has only the optional bit, so the name ends with `_optional`
+        FeatureBit::known()
+            .into_iter()
+            .find(|feature_bit| feature_bit.bit_name() == bit_name)
+    }
+}
+
 impl Serialize for RawFeatureVector {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error> where S: Serializer {
-        serializer.serialize_bytes(Vec::<u8>::from(self.clone()).as_ref())
+        if serializer.is_human_readable() {
+            let mut bits = self.set.iter().cloned().collect::<Vec<_>>();
+            bits.sort();
+            bits.iter().map(FeatureBit::bit_name).collect::<Vec<_>>().serialize(serializer)
+        } else {
+            serializer.serialize_bytes(Vec::<u8>::from(self.clone()).as_ref())
+        }
     }
 }
 
@@ -105,6 +262,19 @@ impl<'de> Deserialize<'de> for RawFeatureVector {
         use serde::de;
         use std::fmt;
 
+        if deserializer.is_human_readable() {
+            use serde::de::Error;
+
+            return Vec::<String>::deserialize(deserializer)?
+                .into_iter()
+                .fold(Ok(RawFeatureVector::new()), |feature_vector, bit_name| {
+                    let feature_vector = feature_vector?;
+                    FeatureBit::from_bit_name(bit_name.as_str())
+                        .map(|feature_bit| feature_vector.set_bit(feature_bit))
+                        .ok_or(D::Error::custom(format!("unknown feature bit name: {}", bit_name)))
+                });
+        }
+
         #[derive(Default)]
         struct BytesVisitor;
 
@@ -175,10 +345,113 @@ mod test {
         assert_eq!(feature_vector, new_feature_vector);
     }
 
+    #[test]
+    fn bolt09_bit_numbering() {
+        // the bits 0, 1, 3 are in the last byte, the bit 8 in the previous,
+        // and the bit 18 is the bit 2 of the first byte
+        let feature_vector = [0, 1, 3, 8, 18].iter()
+            .fold(RawFeatureVector::new(), |fv, &bit: &u16| fv.set_bit(FeatureBit::from(bit)));
+
+        let mut data = Vec::new();
+        BinarySD::serialize(&mut data, &feature_vector).unwrap();
+        assert_eq!(data, vec![0x00, 0x03, 0x04, 0x01, 0x0b]);
+        assert_eq!(BinarySD::deserialize::<RawFeatureVector, _>(&data[..]).unwrap(), feature_vector);
+
+        // the leading zero bytes do not matter
+        let data = vec![0x00, 0x04, 0x00, 0x04, 0x01, 0x0b];
+        assert_eq!(BinarySD::deserialize::<RawFeatureVector, _>(&data[..]).unwrap(), feature_vector);
+    }
+
+    #[test]
+    fn byte_order() {
+        let feature_vector = RawFeatureVector::new()
+            .set_bit(FeatureBit::VarOnionOptinOptional)
+            .set_bit(FeatureBit::DataLossProtectRequired);
+
+        let data = Vec::<u8>::from(feature_vector.clone());
+        assert_eq!(data, vec![0x02, 0x01]);
+        assert_eq!(RawFeatureVector::from(data), feature_vector);
+    }
+
+    #[test]
+    fn validate() {
+        use super::FeatureContext::*;
+        use super::FeatureError;
+
+        let feature_vector = RawFeatureVector::new()
+            .set_bit(FeatureBit::VarOnionOptinOptional)
+            .set_bit(FeatureBit::PaymentSecretRequired)
+            .set_bit(FeatureBit::BasicMppOptional);
+        assert_eq!(feature_vector.validate(Init), Ok(()));
+        assert_eq!(feature_vector.validate(Invoice), Ok(()));
+
+        let feature_vector = RawFeatureVector::new().set_bit(FeatureBit::BasicMppOptional);
+        assert_eq!(feature_vector.validate(Init), Err(FeatureError::MissingDependency {
+            feature_bit: FeatureBit::BasicMppOptional,
+            dependency: FeatureBit::PaymentSecretOptional,
+        }));
+
+        let feature_vector = RawFeatureVector::new().set_bit(FeatureBit::InitialRoutingSync);
+        assert!(feature_vector.validate(Init).is_ok());
+        assert_eq!(feature_vector.validate(NodeAnnouncement), Err(FeatureError::WrongContext {
+            feature_bit: FeatureBit::InitialRoutingSync,
+            context: NodeAnnouncement,
+        }));
+
+        let feature_vector = RawFeatureVector::new().set_bit(FeatureBit::Custom(101));
+        assert!(feature_vector.validate(Init).is_ok());
+        let feature_vector = RawFeatureVector::new().set_bit(FeatureBit::Custom(100));
+        assert_eq!(feature_vector.validate(Init), Err(FeatureError::UnknownRequired(FeatureBit::Custom(100))));
+    }
+
+    #[test]
+    fn negotiate() {
+        use super::FeatureError;
+
+        let local = RawFeatureVector::new()
+            .set_bit(FeatureBit::DataLossProtectOptional)
+            .set_bit(FeatureBit::StaticRemoteKeyRequired)
+            .set_bit(FeatureBit::GossipQueriesOptional);
+        let remote = RawFeatureVector::new()
+            .set_bit(FeatureBit::DataLossProtectRequired)
+            .set_bit(FeatureBit::StaticRemoteKeyOptional)
+            .set_bit(FeatureBit::WumboOptional)
+            .set_bit(FeatureBit::Custom(101));
+
+        let negotiated = local.negotiate(&remote).unwrap();
+        assert_eq!(negotiated, RawFeatureVector::new()
+            .set_bit(FeatureBit::DataLossProtectRequired)
+            .set_bit(FeatureBit::StaticRemoteKeyRequired));
+
+        let remote = remote.set_bit(FeatureBit::WumboRequired);
+        assert_eq!(local.negotiate(&remote), Err(FeatureError::NotSupported(FeatureBit::WumboRequired)));
+
+        let remote = RawFeatureVector::new();
+        assert_eq!(local.negotiate(&remote), Err(FeatureError::NotSupported(FeatureBit::StaticRemoteKeyRequired)));
+    }
+
     #[test]
     fn empty() {
         let v = vec![0u8, 0u8];
         let t: RawFeatureVector = BinarySD::deserialize(&v[..]).unwrap();
         println!("{:?}", t);
     }
+
+    #[test]
+    fn human_readable() {
+        use ::JsonSD;
+
+        let fv = RawFeatureVector::new()
+            .set_bit(FeatureBit::InitialRoutingSync)
+            .set_bit(FeatureBit::BasicMppOptional)
+            .set_bit(FeatureBit::from(100));
+
+        let json = JsonSD::to_string(&fv).unwrap();
+        assert!(json.contains("\"initial_routing_sync_optional\""));
+        assert!(json.contains("\"basic_mpp_optional\""));
+        assert!(json.contains("\"bit_100\""));
+        assert_eq!(JsonSD::from_str::<RawFeatureVector>(json.as_str()).unwrap(), fv);
+
+        assert!(JsonSD::from_str::<RawFeatureVector>("[\"no_such_feature\"]").is_err());
+    }
 }
@@ -0,0 +1,510 @@
+use ::PackSized;
+use ::BinarySD;
+use ::WireError;
+
+use std::collections::BTreeMap;
+use std::marker::PhantomData;
+
+/// The variable length unsigned integer, big endian,
+/// the shortest encoding is the only valid.
+#[derive(Default, Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug)]
+pub struct BigSize {
+    raw: u64,
+}
+
+impl From<u64> for BigSize {
+    fn from(raw: u64) -> Self {
+        BigSize { raw: raw }
+    }
+}
+
+impl From<BigSize> for u64 {
+    fn from(b: BigSize) -> Self {
+        return b.raw;
+    }
+}
+
+impl PackSized for BigSize {
+    const SIZE: usize = 0;
+
+    fn pack_size(&self) -> usize {
+        match self.raw {
+            0..=0xfc => 1,
+            0xfd..=0xffff => 3,
+            0x10000..=0xffffffff => 5,
+            _ => 9,
+        }
+    }
+}
+
+/// Each TLV stream knows which types it can interpret,
+/// the stream with unknown even type is invalid.
+pub trait TlvKnownTypes {
+    const KNOWN: &'static [u64];
+
+    /// The record of the known type is kept in the stream, others are skipped.
+    /// The stream may keep unknown odd types as well, e.g. if it is signed.
+    fn is_known(t: u64) -> bool {
+        Self::KNOWN.contains(&t)
+    }
+}
+
+/// The stream without any known type.
+#[derive(Default, Copy, Clone, Eq, PartialEq, Debug)]
+pub struct NoKnownTypes;
+
+impl TlvKnownTypes for NoKnownTypes {
+    const KNOWN: &'static [u64] = &[];
+}
+
+/// The TLV stream, the records are ordered by type.
+/// It should be the last field of the message, because
+/// the deserializer reads records until the input ends.
+/// The empty stream takes no space, so the message
+/// remains compatible with peers unaware of the extension.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct TlvStream<K> where K: TlvKnownTypes {
+    records: BTreeMap<u64, Vec<u8>>,
+    phantom_data: PhantomData<K>,
+}
+
+impl<K> Default for TlvStream<K> where K: TlvKnownTypes {
+    fn default() -> Self {
+        TlvStream {
+            records: BTreeMap::new(),
+            phantom_data: PhantomData,
+        }
+    }
+}
+
+impl<K> TlvStream<K> where K: TlvKnownTypes {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_known(t: u64) -> bool {
+        K::is_known(t)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+
+    pub fn insert(&mut self, t: u64, value: Vec<u8>) -> Option<Vec<u8>> {
+        self.records.insert(t, value)
+    }
+
+    pub fn remove(&mut self, t: u64) -> Option<Vec<u8>> {
+        self.records.remove(&t)
+    }
+
+    pub fn get(&self, t: u64) -> Option<&[u8]> {
+        self.records.get(&t).map(Vec::as_slice)
+    }
+
+    pub fn iter<'a>(&'a self) -> impl Iterator<Item=(u64, &'a [u8])> + 'a {
+        self.records.iter().map(|(t, v)| (*t, v.as_slice()))
+    }
+
+    /// Insert the truncated integer, the leading zeros are omitted.
+    pub fn insert_tu64(&mut self, t: u64, value: u64) -> Option<Vec<u8>> {
+        let mut data = Vec::with_capacity(8);
+        BinarySD::serialize(&mut data, &value).unwrap();
+        let zeros = data.iter().take_while(|&&b| b == 0).count();
+        self.insert(t, data.split_off(zeros))
+    }
+
+    pub fn get_tu64(&self, t: u64) -> Result<Option<u64>, WireError> {
+        use serde::de::Error;
+
+        match self.get(t) {
+            None => Ok(None),
+            Some(data) => {
+                if data.len() > 8 {
+                    Err(WireError::custom("truncated integer is too long"))
+                } else if data.first() == Some(&0) {
+                    Err(WireError::custom("truncated integer is not minimal"))
+                } else {
+                    Ok(Some(data.iter().fold(0, |acc, &b| (acc << 8) | (b as u64))))
+                }
+            },
+        }
+    }
+
+    /// Insert the value serialized in wire format.
+    pub fn insert_value<T>(&mut self, t: u64, value: &T) -> Result<Option<Vec<u8>>, WireError> where T: ::serde::Serialize {
+        let mut data = Vec::new();
+        BinarySD::serialize(&mut data, value)?;
+        Ok(self.insert(t, data))
+    }
+
+    pub fn get_value<T>(&self, t: u64) -> Result<Option<T>, WireError> where T: ::serde::de::DeserializeOwned {
+        match self.get(t) {
+            None => Ok(None),
+            Some(data) => BinarySD::deserialize(data).map(Some),
+        }
+    }
+
+    // The records must take the whole input, the stream may end only
+    // between records, any truncated or malformed record is an error.
+    fn from_records(data: &[u8]) -> Result<Self, WireError> {
+        use serde::de::Error;
+        use std::io::Cursor;
+
+        let mut stream = Self::new();
+        let mut last_type = None;
+        let mut r = Cursor::new(data);
+        while (r.position() as usize) < data.len() {
+            let t: u64 = BinarySD::deserialize::<BigSize, _>(&mut r)?.into();
+            let length: u64 = BinarySD::deserialize::<BigSize, _>(&mut r)?.into();
+            let start = r.position() as usize;
+            if length > (data.len() - start) as u64 {
+                return Err(WireError::custom(format!("tlv type: {} value is truncated", t)));
+            }
+            let end = start + length as usize;
+            r.set_position(end as u64);
+
+            if last_type.map(|last| t <= last).unwrap_or(false) {
+                return Err(WireError::custom(format!("tlv type: {} is not strictly increasing", t)));
+            }
+            last_type = Some(t);
+
+            if Self::is_known(t) {
+                stream.insert(t, data[start..end].to_vec());
+            } else if t % 2 == 0 {
+                return Err(WireError::custom(format!("unknown even tlv type: {}", t)));
+            }
+        }
+
+        Ok(stream)
+    }
+}
+
+mod serde {
+    use super::BigSize;
+    use super::TlvStream;
+    use super::TlvKnownTypes;
+
+    use serde::Serialize;
+    use serde::Serializer;
+    use serde::Deserialize;
+    use serde::Deserializer;
+    use serde::de::Visitor;
+    use serde::de::SeqAccess;
+    use serde::de::Error;
+    use std::collections::BTreeMap;
+    use std::marker::PhantomData;
+    use std::fmt;
+    use std::usize;
+
+    use hex::encode;
+    use hex::decode;
+
+    impl Serialize for BigSize {
+        fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error> where S: Serializer {
+            use serde::ser::SerializeTuple;
+
+            if serializer.is_human_readable() {
+                return serializer.serialize_u64(self.raw);
+            }
+
+            let mut tuple = serializer.serialize_tuple(2)?;
+            match self.raw {
+                0..=0xfc => {
+                    tuple.serialize_element(&(self.raw as u8))?;
+                },
+                0xfd..=0xffff => {
+                    tuple.serialize_element(&0xfdu8)?;
+                    tuple.serialize_element(&(self.raw as u16))?;
+                },
+                0x10000..=0xffffffff => {
+                    tuple.serialize_element(&0xfeu8)?;
+                    tuple.serialize_element(&(self.raw as u32))?;
+                },
+                _ => {
+                    tuple.serialize_element(&0xffu8)?;
+                    tuple.serialize_element(&self.raw)?;
+                },
+            };
+            tuple.end()
+        }
+    }
+
+    // read the rest of the big size when the discriminant is already read
+    fn read_big_size<'de, A>(seq: &mut A, d: u8) -> Result<BigSize, A::Error> where A: SeqAccess<'de> {
+        let end = || A::Error::custom("unexpected end");
+        let not_canonical = || A::Error::custom("big size is not canonical");
+
+        let raw = match d {
+            0xfd => {
+                let v: u16 = seq.next_element()?.ok_or_else(end)?;
+                if v < 0xfd { return Err(not_canonical()); }
+                v as u64
+            },
+            0xfe => {
+                let v: u32 = seq.next_element()?.ok_or_else(end)?;
+                if v < 0x10000 { return Err(not_canonical()); }
+                v as u64
+            },
+            0xff => {
+                let v: u64 = seq.next_element()?.ok_or_else(end)?;
+                if v < 0x100000000 { return Err(not_canonical()); }
+                v
+            },
+            d @ _ => d as u64,
+        };
+
+        Ok(BigSize { raw: raw })
+    }
+
+    impl<'de> Deserialize<'de> for BigSize {
+        fn deserialize<D>(deserializer: D) -> Result<Self, D::Error> where D: Deserializer<'de> {
+            if deserializer.is_human_readable() {
+                return u64::deserialize(deserializer).map(BigSize::from);
+            }
+
+            struct V;
+
+            impl<'de> Visitor<'de> for V {
+                type Value = BigSize;
+
+                fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                    write!(formatter, "big size integer")
+                }
+
+                fn visit_seq<A>(self, seq: A) -> Result<Self::Value, A::Error> where A: SeqAccess<'de> {
+                    let mut seq = seq;
+                    let d: u8 = seq.next_element()?
+                        .ok_or(A::Error::custom("unexpected end"))?;
+                    read_big_size(&mut seq, d)
+                }
+            }
+
+            deserializer.deserialize_tuple(2, V)
+        }
+    }
+
+    impl<K> Serialize for TlvStream<K> where K: TlvKnownTypes {
+        fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error> where S: Serializer {
+            use serde::ser::SerializeTuple;
+
+            // the map from the type to the hex encoded value
+            if serializer.is_human_readable() {
+                return self.records.iter()
+                    .map(|(t, value)| (*t, encode(value)))
+                    .collect::<BTreeMap<_, _>>()
+                    .serialize(serializer);
+            }
+
+            let length = self.records.values().fold(0, |a, v| a + 2 + v.len());
+            let mut tuple = serializer.serialize_tuple(length)?;
+            for (t, value) in &self.records {
+                tuple.serialize_element(&BigSize::from(*t))?;
+                tuple.serialize_element(&BigSize::from(value.len() as u64))?;
+                for byte in value {
+                    tuple.serialize_element(byte)?;
+                }
+            }
+            tuple.end()
+        }
+    }
+
+    impl<'de, K> Deserialize<'de> for TlvStream<K> where K: TlvKnownTypes {
+        fn deserialize<D>(deserializer: D) -> Result<Self, D::Error> where D: Deserializer<'de> {
+            if deserializer.is_human_readable() {
+                let records = BTreeMap::<u64, String>::deserialize(deserializer)?;
+                let mut stream = TlvStream::<K>::new();
+                for (t, value) in records {
+                    let value = decode(value.as_str()).map_err(D::Error::custom)?;
+                    if TlvStream::<K>::is_known(t) {
+                        stream.insert(t, value);
+                    } else if t % 2 == 0 {
+                        return Err(D::Error::custom(format!("unknown even tlv type: {}", t)));
+                    }
+                }
+                return Ok(stream);
+            }
+
+            struct V<K> where K: TlvKnownTypes {
+                phantom_data: PhantomData<K>,
+            }
+
+            impl<'de, K> Visitor<'de> for V<K> where K: TlvKnownTypes {
+                type Value = TlvStream<K>;
+
+                fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                    write!(formatter, "tlv records until the end of the input")
+                }
+
+                fn visit_seq<A>(self, seq: A) -> Result<Self::Value, A::Error> where A: SeqAccess<'de> {
+                    let mut seq = seq;
+                    // the stream is the last field, take the rest of the input,
+                    // a single byte cannot be read only when the input is over
+                    let mut data = Vec::new();
+                    while let Ok(Some(byte)) = seq.next_element::<u8>() {
+                        data.push(byte);
+                    }
+
+                    TlvStream::<K>::from_records(data.as_slice()).map_err(A::Error::custom)
+                }
+            }
+
+            deserializer.deserialize_tuple(usize::MAX, V { phantom_data: PhantomData })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Clone, Eq, PartialEq, Debug)]
+    struct TestTypes;
+
+    impl TlvKnownTypes for TestTypes {
+        const KNOWN: &'static [u64] = &[1, 2, 254];
+    }
+
+    type TestStream = TlvStream<TestTypes>;
+
+    #[test]
+    fn big_size() {
+        let cases: Vec<(u64, &str)> = vec![
+            (0, "00"),
+            (252, "fc"),
+            (253, "fd00fd"),
+            (65535, "fdffff"),
+            (65536, "fe00010000"),
+            (4294967295, "feffffffff"),
+            (4294967296, "ff0000000100000000"),
+            (18446744073709551615, "ffffffffffffffffff"),
+        ];
+
+        for (value, encoded) in cases {
+            let mut data = Vec::new();
+            BinarySD::serialize(&mut data, &BigSize::from(value)).unwrap();
+            assert_eq!(::hex::encode(&data), encoded);
+            assert_eq!(data.len(), BigSize::from(value).pack_size());
+
+            let restored: BigSize = BinarySD::deserialize(data.as_slice()).unwrap();
+            assert_eq!(u64::from(restored), value);
+        }
+    }
+
+    #[test]
+    fn big_size_not_canonical() {
+        let cases = ["fd00fc", "fe0000ffff", "ff00000000ffffffff", "fd00", "feffff", ""];
+        for encoded in cases.iter() {
+            let data = ::hex::decode(encoded).unwrap();
+            assert!(BinarySD::deserialize::<BigSize, _>(data.as_slice()).is_err());
+        }
+    }
+
+    #[test]
+    fn stream() {
+        let mut stream = TestStream::new();
+        stream.insert_tu64(2, 0x0100);
+        stream.insert(1, vec![]);
+        stream.insert(254, vec![1, 2, 3]);
+
+        let mut data = Vec::new();
+        BinarySD::serialize(&mut data, &stream).unwrap();
+        assert_eq!(::hex::encode(&data), "010002020100fe03010203");
+
+        let restored: TestStream = BinarySD::deserialize(data.as_slice()).unwrap();
+        assert_eq!(restored, stream);
+        assert_eq!(restored.get_tu64(2).unwrap(), Some(0x0100));
+        assert_eq!(restored.get(1), Some(&[][..]));
+    }
+
+    #[test]
+    fn empty_stream() {
+        let stream: TestStream = BinarySD::deserialize(&[][..]).unwrap();
+        assert!(stream.is_empty());
+
+        let mut data = Vec::new();
+        BinarySD::serialize(&mut data, &stream).unwrap();
+        assert!(data.is_empty());
+    }
+
+    #[test]
+    fn unknown_odd_skipped() {
+        let data = ::hex::decode("0100030100fd0101020002").unwrap();
+        let stream: TestStream = BinarySD::deserialize(data.as_slice()).unwrap();
+        assert_eq!(stream.get(1), Some(&[][..]));
+        assert_eq!(stream.get(3), None);
+        assert_eq!(stream.get(254), None);
+        assert_eq!(stream.iter().count(), 1);
+    }
+
+    #[test]
+    fn invalid_streams() {
+        let cases = [
+            // unknown even type
+            "0400",
+            // not increasing
+            "02000100",
+            // duplicate
+            "01000100",
+            // value is too short
+            "0103aabb",
+            // length is not canonical
+            "01fd0001aa",
+        ];
+        for encoded in cases.iter() {
+            let data = ::hex::decode(encoded).unwrap();
+            assert!(BinarySD::deserialize::<TestStream, _>(data.as_slice()).is_err(), "{}", encoded);
+        }
+    }
+
+    #[test]
+    fn truncated_record() {
+        let cases = [
+            // the type without the length
+            "010002",
+            // the length without the value
+            "01000203",
+            // the value is shorter than the length
+            "01000203aabb",
+            // the length is truncated
+            "010002fd00",
+        ];
+        for encoded in cases.iter() {
+            let data = ::hex::decode(encoded).unwrap();
+            assert!(BinarySD::deserialize::<TestStream, _>(data.as_slice()).is_err(), "{}", encoded);
+        }
+
+        // the record is complete
+        let data = ::hex::decode("01000203aabbcc").unwrap();
+        let stream: TestStream = BinarySD::deserialize(data.as_slice()).unwrap();
+        assert_eq!(stream.get(2), Some(&[0xaa, 0xbb, 0xcc][..]));
+    }
+
+    #[test]
+    fn truncated_integer() {
+        let mut stream = TestStream::new();
+        stream.insert_tu64(2, 0);
+        assert_eq!(stream.get(2), Some(&[][..]));
+        assert_eq!(stream.get_tu64(2).unwrap(), Some(0));
+
+        stream.insert(2, vec![0, 1]);
+        assert!(stream.get_tu64(2).is_err());
+    }
+
+    #[test]
+    fn human_readable() {
+        use ::JsonSD;
+
+        let mut stream = TestStream::new();
+        stream.insert(1, vec![0xab, 0xcd]);
+        stream.insert_tu64(254, 0x0100);
+
+        let json = JsonSD::to_string(&stream).unwrap();
+        assert_eq!(JsonSD::from_str::<TestStream>(json.as_str()).unwrap(), stream);
+
+        let stream = JsonSD::from_str::<TestStream>("{\"1\": \"01\", \"3\": \"02\"}").unwrap();
+        assert_eq!(stream.get(1), Some(&[1u8][..]));
+        assert!(stream.get(3).is_none());
+        assert!(JsonSD::from_str::<TestStream>("{\"4\": \"02\"}").is_err());
+    }
+}
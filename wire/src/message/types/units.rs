@@ -1,26 +1,26 @@
 use super::Wrapper;
 
-#[derive(Default, Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Serialize, Deserialize, Debug)]
+#[derive(Default, Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug)]
 pub struct Satoshi {
     raw: u64,
 }
 
-#[derive(Default, Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Serialize, Deserialize, Debug)]
+#[derive(Default, Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug)]
 pub struct MilliSatoshi {
     raw: u64,
 }
 
-#[derive(Default, Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Serialize, Deserialize, Debug)]
+#[derive(Default, Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug)]
 pub struct SatoshiPerKiloWeight {
     raw: u32,
 }
 
-#[derive(Default, Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Serialize, Deserialize, Debug)]
+#[derive(Default, Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug)]
 pub struct CsvDelay {
     raw: u16,
 }
 
-#[derive(Default, Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Serialize, Deserialize, Debug)]
+#[derive(Default, Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug)]
 pub struct SatoshiPerVByte {
     raw: u64,
 }
@@ -28,6 +28,18 @@ pub struct SatoshiPerVByte {
 mod wrappers {
     use super::*;
 
+    impl From<u64> for Satoshi {
+        fn from(raw: u64) -> Self {
+            Satoshi { raw: raw }
+        }
+    }
+
+    impl From<u64> for MilliSatoshi {
+        fn from(raw: u64) -> Self {
+            MilliSatoshi { raw: raw }
+        }
+    }
+
     impl From<Satoshi> for u64 {
         fn from(s: Satoshi) -> Self {
             return s.raw;
@@ -99,6 +111,55 @@ mod wrappers {
     }
 }
 
+mod serde {
+    use super::*;
+
+    use serde::Serialize;
+    use serde::Serializer;
+    use serde::Deserialize;
+    use serde::Deserializer;
+    use serde::de::Error;
+
+    // In human readable form the value carries the unit, e.g. `"1000 msat"`,
+    // so satoshi and millisatoshi cannot be confused.
+    macro_rules! unit_serde {
+        ($name:ident, $wrapped:ty, $unit:expr) => {
+            impl Serialize for $name {
+                fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error> where S: Serializer {
+                    if serializer.is_human_readable() {
+                        serializer.serialize_str(format!("{} {}", self.raw, $unit).as_str())
+                    } else {
+                        self.raw.serialize(serializer)
+                    }
+                }
+            }
+
+            impl<'de> Deserialize<'de> for $name {
+                fn deserialize<D>(deserializer: D) -> Result<Self, D::Error> where D: Deserializer<'de> {
+                    if deserializer.is_human_readable() {
+                        let string = String::deserialize(deserializer)?;
+                        let suffix = concat!(" ", $unit);
+                        if !string.ends_with(suffix) {
+                            return Err(D::Error::custom(format!("expected `<number>{}`, got `{}`", suffix, string)));
+                        }
+                        let raw = string[..(string.len() - suffix.len())].parse::<$wrapped>()
+                            .map_err(D::Error::custom)?;
+                        Ok($name { raw: raw })
+                    } else {
+                        <$wrapped>::deserialize(deserializer).map(|raw| $name { raw: raw })
+                    }
+                }
+            }
+        }
+    }
+
+    unit_serde!(Satoshi, u64, "sat");
+    unit_serde!(MilliSatoshi, u64, "msat");
+    unit_serde!(SatoshiPerKiloWeight, u32, "sat/kw");
+    unit_serde!(CsvDelay, u16, "blocks");
+    unit_serde!(SatoshiPerVByte, u64, "sat/vbyte");
+}
+
 mod scaling {
     use super::*;
 
@@ -120,3 +181,23 @@ mod scaling {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::BinarySD;
+    use ::JsonSD;
+
+    #[test]
+    fn human_readable() {
+        let amount = MilliSatoshi::from(1000);
+        assert_eq!(JsonSD::to_string(&amount).unwrap(), "\"1000 msat\"");
+        assert_eq!(JsonSD::from_str::<MilliSatoshi>("\"1000 msat\"").unwrap(), amount);
+        assert!(JsonSD::from_str::<MilliSatoshi>("\"1000 sat\"").is_err());
+        assert!(JsonSD::from_str::<Satoshi>("\"1000\"").is_err());
+
+        let mut data = Vec::new();
+        BinarySD::serialize(&mut data, &amount).unwrap();
+        assert_eq!(data, vec![0, 0, 0, 0, 0, 0, 3, 232]);
+    }
+}
@@ -0,0 +1,144 @@
+use std::{fmt, error};
+
+/// The message of the type which is not known by this implementation.
+/// Only odd types are accepted from the peer, it is ok to ignore them.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct Unknown {
+    message_type: u16,
+    payload: Vec<u8>,
+}
+
+impl Unknown {
+    pub fn new(message_type: u16, payload: Vec<u8>) -> Self {
+        Unknown {
+            message_type: message_type,
+            payload: payload,
+        }
+    }
+
+    pub fn message_type(&self) -> u16 {
+        self.message_type
+    }
+
+    pub fn payload(&self) -> &[u8] {
+        self.payload.as_slice()
+    }
+
+    /// It is ok to be odd.
+    pub fn is_odd(&self) -> bool {
+        self.message_type % 2 == 1
+    }
+}
+
+/// The peer sent the message of the even type which is not known by this implementation,
+/// such message cannot be ignored, the connection should be failed.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct UnknownEvenType(pub u16);
+
+impl fmt::Display for UnknownEvenType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "unknown even runtime type: `{}`", self.0)
+    }
+}
+
+impl error::Error for UnknownEvenType {}
+
+/// The message of the custom type, the application protocol running
+/// over the connection is responsible for it, see `CustomMessageHandler`.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct Custom {
+    message_type: u16,
+    payload: Vec<u8>,
+}
+
+impl Custom {
+    pub const FIRST_TYPE: u16 = 32768;
+    pub const LAST_TYPE: u16 = 65535;
+
+    /// Returns `None` if the type is not in the custom range.
+    pub fn new(message_type: u16, payload: Vec<u8>) -> Option<Self> {
+        if Self::is_custom_type(message_type) {
+            Some(Custom {
+                message_type: message_type,
+                payload: payload,
+            })
+        } else {
+            None
+        }
+    }
+
+    pub fn is_custom_type(message_type: u16) -> bool {
+        (Self::FIRST_TYPE..=Self::LAST_TYPE).contains(&message_type)
+    }
+
+    pub fn message_type(&self) -> u16 {
+        self.message_type
+    }
+
+    pub fn payload(&self) -> &[u8] {
+        self.payload.as_slice()
+    }
+
+    pub fn into_payload(self) -> Vec<u8> {
+        self.payload
+    }
+
+    /// It is ok to be odd, nobody should fail the connection if nobody claims the message.
+    pub fn is_odd(&self) -> bool {
+        self.message_type % 2 == 1
+    }
+}
+
+/// The bytes until the end of the message, without length prefix.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub(crate) struct RawPayload(pub Vec<u8>);
+
+mod serde {
+    use super::RawPayload;
+
+    use serde::Serialize;
+    use serde::Serializer;
+    use serde::Deserialize;
+    use serde::Deserializer;
+    use serde::ser::SerializeTuple;
+    use serde::de::Visitor;
+    use serde::de::SeqAccess;
+    use std::fmt;
+    use std::usize;
+
+    impl Serialize for RawPayload {
+        fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error> where S: Serializer {
+            let mut tuple = serializer.serialize_tuple(self.0.len())?;
+            for byte in &self.0 {
+                tuple.serialize_element(byte)?;
+            }
+            tuple.end()
+        }
+    }
+
+    impl<'de> Deserialize<'de> for RawPayload {
+        fn deserialize<D>(deserializer: D) -> Result<Self, D::Error> where D: Deserializer<'de> {
+            struct V;
+
+            impl<'de> Visitor<'de> for V {
+                type Value = RawPayload;
+
+                fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                    write!(formatter, "bytes until the end of the input")
+                }
+
+                fn visit_seq<A>(self, seq: A) -> Result<Self::Value, A::Error> where A: SeqAccess<'de> {
+                    let mut seq = seq;
+                    let mut data = Vec::new();
+                    // cannot read even a single byte, the input is over
+                    while let Ok(Some(byte)) = seq.next_element::<u8>() {
+                        data.push(byte);
+                    }
+                    Ok(RawPayload(data))
+                }
+            }
+
+            deserializer.deserialize_tuple(usize::MAX, V)
+        }
+    }
+}
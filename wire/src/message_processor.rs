@@ -1,9 +1,15 @@
 use super::WireError;
 use super::Message;
+use super::Custom;
+use super::PublicKey;
+use super::GossipFilter;
 
 use tokio::prelude::Future;
 use tokio::prelude::Sink;
 
+use std::marker::PhantomData;
+use std::sync::Arc;
+
 pub trait MessageFiltered
     where
         Self: Sized,
@@ -32,6 +38,12 @@ pub trait MessageConsumer {
             Err(message) => Err((self, sink, message)),
         }
     }
+
+    /// The consumer that keeps the gossip tells which gossip messages
+    /// are not worth decoding, the filter is shared with the decoder.
+    fn gossip_filter(&self) -> Option<Arc<dyn GossipFilter>> {
+        None
+    }
 }
 
 pub trait MessageConsumerChain {
@@ -39,6 +51,11 @@ pub trait MessageConsumerChain {
     where
         Self: Sized,
         S: Sink<SinkItem=Message, SinkError=WireError> + Send + 'static;
+
+    /// The gossip filter of the first consumer having one.
+    fn gossip_filter(&self) -> Option<Arc<dyn GossipFilter>> {
+        None
+    }
 }
 
 impl MessageConsumerChain for () {
@@ -70,4 +87,178 @@ where
             },
         }
     }
+
+    fn gossip_filter(&self) -> Option<Arc<dyn GossipFilter>> {
+        let &(ref x, ref xs) = self;
+        x.gossip_filter().or_else(|| xs.gossip_filter())
+    }
+}
+
+/// The application protocol running over the peer connection,
+/// claims the range of custom message types. The messages which are not
+/// the responses are sent by `PeerManager::send_custom` of the routing crate.
+pub trait CustomMessageHandler {
+    // the inclusive range of the types claimed by the handler,
+    // must be inside of the custom range `Custom::FIRST_TYPE..=Custom::LAST_TYPE`
+    const FIRST_TYPE: u16;
+    const LAST_TYPE: u16;
+
+    /// Handles the message received from the peer, returns the messages to send back.
+    /// The error fails the connection.
+    fn handle(&mut self, peer: &PublicKey, message: Custom) -> Result<Vec<Custom>, WireError>;
+
+    fn claims(message_type: u16) -> bool {
+        (Self::FIRST_TYPE..=Self::LAST_TYPE).contains(&message_type)
+    }
+}
+
+/// The custom message of the range claimed by the handler `H`.
+pub struct CustomMessage<H> where H: CustomMessageHandler {
+    inner: Custom,
+    phantom_data: PhantomData<H>,
+}
+
+impl<H> MessageFiltered for CustomMessage<H> where H: CustomMessageHandler {
+    fn filter(v: Message) -> Result<Self, Message> {
+        match v {
+            Message::Custom(c) => {
+                if H::claims(c.message_type()) {
+                    Ok(CustomMessage {
+                        inner: c,
+                        phantom_data: PhantomData,
+                    })
+                } else {
+                    Err(Message::Custom(c))
+                }
+            },
+            v @ _ => Err(v),
+        }
+    }
+}
+
+impl<H> CustomMessage<H> where H: CustomMessageHandler {
+    pub fn into_inner(self) -> Custom {
+        self.inner
+    }
+}
+
+/// Passes the custom messages to the handler together with the identity of the peer,
+/// should be placed in the `MessageConsumerChain` of the peer connection.
+pub struct CustomMessageConsumer<H> where H: CustomMessageHandler {
+    peer: PublicKey,
+    handler: H,
+}
+
+impl<H> CustomMessageConsumer<H> where H: CustomMessageHandler {
+    /// Returns `None` if the range claimed by the handler is empty
+    /// or not inside of the custom range.
+    pub fn new(peer: PublicKey, handler: H) -> Option<Self> {
+        let valid = H::FIRST_TYPE <= H::LAST_TYPE
+            && Custom::is_custom_type(H::FIRST_TYPE)
+            && Custom::is_custom_type(H::LAST_TYPE);
+        if !valid {
+            return None;
+        }
+
+        Some(CustomMessageConsumer {
+            peer: peer,
+            handler: handler,
+        })
+    }
+
+    pub fn peer(&self) -> &PublicKey {
+        &self.peer
+    }
+
+    pub fn handler(&self) -> &H {
+        &self.handler
+    }
+
+    pub fn handler_mut(&mut self) -> &mut H {
+        &mut self.handler
+    }
+}
+
+impl<H> MessageConsumer for CustomMessageConsumer<H> where H: CustomMessageHandler + 'static {
+    type Message = CustomMessage<H>;
+
+    fn consume<S>(self, sink: S, message: Self::Message) -> Box<dyn Future<Item=(Self, S), Error=WireError>>
+    where
+        S: Sink<SinkItem=Message, SinkError=WireError> + Send + 'static,
+    {
+        use tokio::prelude::IntoFuture;
+        use tokio::prelude::stream;
+
+        let mut this = self;
+        match this.handler.handle(&this.peer, message.into_inner()) {
+            Ok(responses) => {
+                let responses = responses.into_iter().map(Message::Custom);
+                Box::new(
+                    sink.send_all(stream::iter_ok::<_, WireError>(responses))
+                        .map(move |(sink, _)| (this, sink))
+                )
+            },
+            Err(e) => Box::new(Err(e).into_future()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct TestHandler;
+
+    impl CustomMessageHandler for TestHandler {
+        const FIRST_TYPE: u16 = 40000;
+        const LAST_TYPE: u16 = 40009;
+
+        fn handle(&mut self, peer: &PublicKey, message: Custom) -> Result<Vec<Custom>, WireError> {
+            let _ = peer;
+            Ok(vec![message])
+        }
+    }
+
+    struct OutOfRangeHandler;
+
+    impl CustomMessageHandler for OutOfRangeHandler {
+        const FIRST_TYPE: u16 = 32760;
+        const LAST_TYPE: u16 = 32770;
+
+        fn handle(&mut self, peer: &PublicKey, message: Custom) -> Result<Vec<Custom>, WireError> {
+            let _ = (peer, message);
+            Ok(vec![])
+        }
+    }
+
+    struct EmptyRangeHandler;
+
+    impl CustomMessageHandler for EmptyRangeHandler {
+        const FIRST_TYPE: u16 = 40009;
+        const LAST_TYPE: u16 = 40000;
+
+        fn handle(&mut self, peer: &PublicKey, message: Custom) -> Result<Vec<Custom>, WireError> {
+            let _ = (peer, message);
+            Ok(vec![])
+        }
+    }
+
+    #[test]
+    fn custom_handler_range() {
+        let peer: PublicKey = ::rand::random();
+        assert!(CustomMessageConsumer::new(peer.clone(), TestHandler).is_some());
+        assert!(CustomMessageConsumer::new(peer.clone(), OutOfRangeHandler).is_none());
+        assert!(CustomMessageConsumer::new(peer, EmptyRangeHandler).is_none());
+    }
+
+    #[test]
+    fn custom_message_filter() {
+        let claimed = Message::Custom(Custom::new(40001, vec![1, 2]).unwrap());
+        let message = CustomMessage::<TestHandler>::filter(claimed).ok().unwrap();
+        assert_eq!(message.into_inner().payload(), &[1, 2]);
+
+        let foreign = Message::Custom(Custom::new(40010, vec![]).unwrap());
+        assert!(CustomMessage::<TestHandler>::filter(foreign).is_err());
+        assert!(CustomMessage::<TestHandler>::filter(Message::Custom(Custom::new(32768, vec![]).unwrap())).is_err());
+    }
 }
@@ -0,0 +1,336 @@
+use secp256k1::Secp256k1;
+use secp256k1::Error as Secp256k1Error;
+use secp256k1::Message as Secp256k1Message;
+use secp256k1::PublicKey as Secp256k1PublicKey;
+use secp256k1::SecretKey as Secp256k1SecretKey;
+use secp256k1::Signature as Secp256k1Signature;
+
+use super::PublicKey;
+use super::Signature;
+use super::WireError;
+
+use std::{io, fmt, error};
+#[cfg(unix)]
+use std::time::Duration;
+
+/// The holder of the node key. Brontide uses it for ECDH during the handshake,
+/// the gossip messages are signed by it. The key might live outside
+/// of the process, so every operation might fail.
+pub trait NodeSigner: Send + Sync {
+    /// The public key of the node, it is the node id.
+    fn node_id(&self) -> Secp256k1PublicKey;
+
+    /// The sha256 of the compressed point, the product of the node key and the `public_key`.
+    fn ecdh(&self, public_key: &Secp256k1PublicKey) -> Result<[u8; 32], SignerError>;
+
+    /// Signs the hash of the message by the node key.
+    fn sign(&self, hash: &Secp256k1Message) -> Result<Secp256k1Signature, SignerError>;
+
+    /// Whether the operations wait for something outside of the process,
+    /// the handshake calls such a signer from its worker thread, not from the reactor.
+    fn is_blocking(&self) -> bool {
+        false
+    }
+}
+
+#[derive(Debug)]
+pub enum SignerError {
+    Crypto(Secp256k1Error),
+    Io(io::Error),
+    Wire(WireError),
+    // the external signer has refused the request
+    Remote(String),
+}
+
+impl error::Error for SignerError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        use self::SignerError::*;
+
+        match self {
+            &Crypto(ref e) => Some(e),
+            &Io(ref e) => Some(e),
+            &Wire(ref e) => Some(e),
+            &Remote(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for SignerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::SignerError::*;
+
+        match self {
+            &Crypto(ref e) => write!(f, "crypto error: {}", e),
+            &Io(ref e) => write!(f, "io error: {}", e),
+            &Wire(ref e) => write!(f, "wire error: {}", e),
+            &Remote(ref msg) => write!(f, "the signer has refused: {}", msg),
+        }
+    }
+}
+
+impl From<io::Error> for SignerError {
+    fn from(e: io::Error) -> Self {
+        return SignerError::Io(e);
+    }
+}
+
+impl From<WireError> for SignerError {
+    fn from(e: WireError) -> Self {
+        return SignerError::Wire(e);
+    }
+}
+
+/// The node key is in the memory of the process.
+pub struct InMemorySigner {
+    secret: Secp256k1SecretKey,
+    node_id: Secp256k1PublicKey,
+}
+
+impl InMemorySigner {
+    pub fn new(secret: Secp256k1SecretKey) -> Result<Self, Secp256k1Error> {
+        let node_id = Secp256k1PublicKey::from_secret_key(&Secp256k1::new(), &secret)?;
+        Ok(InMemorySigner {
+            secret: secret,
+            node_id: node_id,
+        })
+    }
+}
+
+impl NodeSigner for InMemorySigner {
+    fn node_id(&self) -> Secp256k1PublicKey {
+        self.node_id.clone()
+    }
+
+    fn ecdh(&self, public_key: &Secp256k1PublicKey) -> Result<[u8; 32], SignerError> {
+        use sha2::Sha256;
+        use digest::FixedOutput;
+        use digest::Input;
+
+        let mut point = public_key.clone();
+        point.mul_assign(&Secp256k1::new(), &self.secret).map_err(SignerError::Crypto)?;
+
+        let mut hasher = Sha256::default();
+        hasher.process(&point.serialize()[..]);
+        let mut hash = [0; 32];
+        hash.copy_from_slice(hasher.fixed_result().as_slice());
+        Ok(hash)
+    }
+
+    fn sign(&self, hash: &Secp256k1Message) -> Result<Secp256k1Signature, SignerError> {
+        Secp256k1::new().sign(hash, &self.secret).map_err(SignerError::Crypto)
+    }
+}
+
+/// The request to the external signer, see `SocketSigner`.
+#[derive(Serialize, Deserialize, Debug)]
+pub enum SignerRequest {
+    NodeId,
+    Ecdh(PublicKey),
+    Sign([u8; 32]),
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub enum SignerResponse {
+    NodeId(PublicKey),
+    Ecdh([u8; 32]),
+    Signature(Signature),
+    Error(String),
+}
+
+/// Answers the single request coming from the `stream`, the external signer
+/// process should run it for each connection, `SocketSigner` connects per request.
+pub fn serve_signer_request<S, T>(signer: &S, stream: T) -> Result<(), SignerError>
+where
+    S: NodeSigner + ?Sized,
+    T: io::Read + io::Write,
+{
+    use ::BinarySD;
+
+    let mut stream = stream;
+    let request: SignerRequest = BinarySD::deserialize(&mut stream)?;
+    let response = match request {
+        SignerRequest::NodeId => Ok(SignerResponse::NodeId(signer.node_id().into())),
+        SignerRequest::Ecdh(public_key) => signer.ecdh(public_key.as_ref()).map(SignerResponse::Ecdh),
+        SignerRequest::Sign(hash) => Secp256k1Message::from_slice(&hash[..])
+            .map_err(SignerError::Crypto)
+            .and_then(|hash| signer.sign(&hash))
+            .map(|s| SignerResponse::Signature(s.into())),
+    };
+    let response = response.unwrap_or_else(|e| SignerResponse::Error(e.to_string()));
+    BinarySD::serialize(&mut stream, &response)?;
+    stream.flush().map_err(SignerError::Io)
+}
+
+// SIGNER_TIMEOUT is the default limit of the read and the write of the request
+// to the external signer, the hung signer fails the request instead of blocking forever.
+#[cfg(unix)]
+const SIGNER_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The node key is held by the external signer process listening on the unix socket.
+/// The requests are blocking, do not call it on the reactor, see `NodeSigner::is_blocking`.
+#[cfg(unix)]
+pub struct SocketSigner {
+    path: ::std::path::PathBuf,
+    timeout: Duration,
+    node_id: Secp256k1PublicKey,
+}
+
+#[cfg(unix)]
+impl SocketSigner {
+    /// Asks the signer listening on the `path` for the node id.
+    pub fn connect<P>(path: P) -> Result<Self, SignerError> where P: AsRef<::std::path::Path> {
+        Self::connect_with_timeout(path, SIGNER_TIMEOUT)
+    }
+
+    /// The same as `connect`, the read and the write of every request are limited by the `timeout`.
+    pub fn connect_with_timeout<P>(path: P, timeout: Duration) -> Result<Self, SignerError>
+    where
+        P: AsRef<::std::path::Path>,
+    {
+        let path = path.as_ref().to_path_buf();
+        match Self::request(&path, timeout, &SignerRequest::NodeId)? {
+            SignerResponse::NodeId(node_id) => Ok(SocketSigner {
+                path: path,
+                timeout: timeout,
+                node_id: node_id.into(),
+            }),
+            r @ _ => Err(Self::unexpected(r)),
+        }
+    }
+
+    fn request(path: &::std::path::Path, timeout: Duration, request: &SignerRequest) -> Result<SignerResponse, SignerError> {
+        use std::os::unix::net::UnixStream;
+        use std::io::Write;
+        use ::BinarySD;
+
+        let mut stream = UnixStream::connect(path)?;
+        stream.set_read_timeout(Some(timeout))?;
+        stream.set_write_timeout(Some(timeout))?;
+        BinarySD::serialize(&mut stream, request)?;
+        stream.flush()?;
+        BinarySD::deserialize(&mut stream).map_err(SignerError::Wire)
+    }
+
+    fn unexpected(response: SignerResponse) -> SignerError {
+        match response {
+            SignerResponse::Error(msg) => SignerError::Remote(msg),
+            r @ _ => SignerError::Remote(format!("unexpected response: {:?}", r)),
+        }
+    }
+}
+
+#[cfg(unix)]
+impl NodeSigner for SocketSigner {
+    fn node_id(&self) -> Secp256k1PublicKey {
+        self.node_id.clone()
+    }
+
+    fn ecdh(&self, public_key: &Secp256k1PublicKey) -> Result<[u8; 32], SignerError> {
+        match Self::request(&self.path, self.timeout, &SignerRequest::Ecdh(public_key.clone().into()))? {
+            SignerResponse::Ecdh(hash) => Ok(hash),
+            r @ _ => Err(Self::unexpected(r)),
+        }
+    }
+
+    fn sign(&self, hash: &Secp256k1Message) -> Result<Secp256k1Signature, SignerError> {
+        let mut data = [0; 32];
+        data.copy_from_slice(&hash[..]);
+        match Self::request(&self.path, self.timeout, &SignerRequest::Sign(data))? {
+            SignerResponse::Signature(signature) => {
+                // do not trust the signer blindly
+                Secp256k1::new().verify(hash, signature.as_ref(), &self.node_id)
+                    .map_err(SignerError::Crypto)?;
+                Ok(signature.as_ref().clone())
+            },
+            r @ _ => Err(Self::unexpected(r)),
+        }
+    }
+
+    fn is_blocking(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    use std::os::unix::net::UnixListener;
+    use std::{thread, env, fs, process};
+    use std::sync::Arc;
+
+    fn secret(byte: u8) -> Secp256k1SecretKey {
+        Secp256k1SecretKey::from_slice(&Secp256k1::new(), &[byte; 32]).unwrap()
+    }
+
+    #[test]
+    fn socket_signer() {
+        let path = env::temp_dir().join(format!("lpd-signer-{}.sock", process::id()));
+        let _ = fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).unwrap();
+
+        let local = Arc::new(InMemorySigner::new(secret(0x11)).unwrap());
+        let remote = local.clone();
+        let handle = thread::spawn(move || {
+            // node id, ecdh, sign and the malformed hash
+            for stream in listener.incoming().take(4) {
+                serve_signer_request(&*remote, stream.unwrap()).unwrap();
+            }
+        });
+
+        let signer = SocketSigner::connect(&path).unwrap();
+        assert_eq!(signer.node_id(), local.node_id());
+
+        let other = Secp256k1PublicKey::from_secret_key(&Secp256k1::new(), &secret(0x22)).unwrap();
+        assert_eq!(signer.ecdh(&other).unwrap(), local.ecdh(&other).unwrap());
+        // the ecdh is symmetric
+        let other_signer = InMemorySigner::new(secret(0x22)).unwrap();
+        assert_eq!(signer.ecdh(&other).unwrap(), other_signer.ecdh(&local.node_id()).unwrap());
+
+        let hash = Secp256k1Message::from_slice(&[0x33; 32]).unwrap();
+        let signature = signer.sign(&hash).unwrap();
+        Secp256k1::new().verify(&hash, &signature, &local.node_id()).unwrap();
+
+        // the zero hash is not a valid message, the signer tells why
+        match SocketSigner::request(&path, SIGNER_TIMEOUT, &SignerRequest::Sign([0; 32])).unwrap() {
+            SignerResponse::Error(_) => (),
+            r => panic!("unexpected response: {:?}", r),
+        }
+
+        handle.join().unwrap();
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn hung_signer() {
+        use std::time::Instant;
+
+        let path = env::temp_dir().join(format!("lpd-hung-signer-{}.sock", process::id()));
+        let _ = fs::remove_file(&path);
+        // the connection is queued, but nobody ever answers
+        let listener = UnixListener::bind(&path).unwrap();
+
+        let start = Instant::now();
+        assert!(SocketSigner::connect_with_timeout(&path, Duration::from_millis(50)).is_err());
+        assert!(start.elapsed() < Duration::from_secs(5));
+
+        drop(listener);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn sign_by() {
+        use message::{Signed, SignedData};
+        use message::types::SecretKey;
+        use rand;
+
+        let secret: SecretKey = rand::random();
+        let signer = InMemorySigner::new(secret.as_ref().clone()).unwrap();
+        let node_id = PublicKey::from(signer.node_id());
+
+        let by_signer = Signed::sign_by(SignedData(vec![1u8, 2, 3]), &signer).unwrap();
+        let by_key = Signed::sign(SignedData(vec![1u8, 2, 3]), &secret).unwrap();
+        assert_eq!(by_signer, by_key);
+        assert_eq!(by_signer.verify(&node_id).unwrap(), SignedData(vec![1, 2, 3]));
+    }
+}
@@ -11,6 +11,8 @@ use bincode::Error;
 use bincode::LengthSDOptions;
 use bincode::config;
 
+use serde_json;
+
 use super::message::MessageSize;
 
 pub type WireError = Error;
@@ -68,3 +70,27 @@ impl BinarySD {
         bc_config.deserialize_custom_length_from(r, LengthSD)
     }
 }
+
+pub type JsonError = serde_json::Error;
+
+/// Public facade object, provides human readable JSON representation,
+/// keys, signatures and hashes are hex, amounts are tagged with units
+pub struct JsonSD;
+
+impl JsonSD {
+    pub fn serialize<T: Serialize, W: io::Write>(w: W, value: &T) -> Result<(), JsonError> {
+        serde_json::to_writer_pretty(w, value)
+    }
+
+    pub fn deserialize<T: DeserializeOwned, R: io::Read>(r: R) -> Result<T, JsonError> {
+        serde_json::from_reader(r)
+    }
+
+    pub fn to_string<T: Serialize>(value: &T) -> Result<String, JsonError> {
+        serde_json::to_string_pretty(value)
+    }
+
+    pub fn from_str<T: DeserializeOwned>(s: &str) -> Result<T, JsonError> {
+        serde_json::from_str(s)
+    }
+}